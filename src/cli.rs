@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use crate::{config::ConfigOverrides, OutputFormat};
+
+// Command-line flags. Sandbox, timeout and step limits default to the nearest
+// .garden.toml and override it when given.
+#[derive(Debug, Parser)]
+#[command(name = "garden", version, about = "Live evaluation of s-expression files")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Files to evaluate; watching one file unless --tui is given
+    #[arg(value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+
+    /// Show evaluated definitions in a terminal UI, one tab per file
+    #[arg(long)]
+    pub tui: bool,
+
+    /// List the standard prelude's definitions in the TUI
+    #[arg(long, requires = "tui")]
+    pub show_prelude: bool,
+
+    /// Watch every .expr file in a directory
+    #[arg(long, value_name = "DIR")]
+    pub watch_dir: Option<PathBuf>,
+
+    /// Include subdirectories of --watch-dir
+    #[arg(long, requires = "watch_dir")]
+    pub recursive: bool,
+
+    /// Serve nREPL, on [nrepl] port from .garden.toml or any free port by default
+    #[arg(long, value_name = "PORT")]
+    pub nrepl: Option<Option<u16>>,
+
+    /// Serve the language server over stdio
+    #[arg(long)]
+    pub lsp: bool,
+
+    /// Start an interactive read-eval-print loop
+    #[arg(long)]
+    pub repl: bool,
+
+    /// Serve a live dashboard of results over WebSocket
+    #[arg(long, value_name = "PORT")]
+    pub ws_port: Option<u16>,
+
+    /// Serve JSON-RPC evaluation requests
+    #[arg(long, value_name = "PORT")]
+    pub jsonrpc_port: Option<u16>,
+
+    /// How results are printed when watching a file
+    #[arg(long, value_name = "FORMAT", default_value = "text", value_parser = |s: &str| s.parse::<OutputFormat>())]
+    pub format: OutputFormat,
+
+    /// Only keep the cache in memory for this run
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Start from scratch, then cache as usual
+    #[arg(long, conflicts_with = "no_cache")]
+    pub clear_cache: bool,
+
+    /// Recompute a memo-def value on this run; may be repeated
+    #[arg(long, value_name = "NAME")]
+    pub invalidate_memo: Vec<String>,
+
+    /// Refuse HTTP requests and file writes
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// Allow HTTP requests, even with --sandbox
+    #[arg(long)]
+    pub allow_http: bool,
+
+    /// Allow file writes, even with --sandbox
+    #[arg(long)]
+    pub allow_file: bool,
+
+    /// Let http.mock answer requests with canned responses
+    #[arg(long)]
+    pub allow_mocks: bool,
+
+    /// Report how long each expression took after every run
+    #[arg(long)]
+    pub benchmark: bool,
+
+    /// Longest a top-level expression may take, in milliseconds
+    #[arg(long, value_name = "MS")]
+    pub timeout_ms: Option<u64>,
+
+    /// Iteration limit for while loops
+    #[arg(long, value_name = "N")]
+    pub max_steps: Option<u64>,
+
+    /// How long to wait for more changes after a file changes, in milliseconds
+    #[arg(long, value_name = "MS")]
+    pub debounce_ms: Option<u64>,
+
+    /// Evaluate an expression and print its value; may be repeated
+    #[arg(long, value_name = "EXPR")]
+    pub eval: Vec<String>,
+
+    /// Definitions to evaluate before --eval expressions
+    #[arg(long, value_name = "FILE", requires = "eval")]
+    pub context_file: Option<PathBuf>,
+
+    /// Log each evaluation
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Only log errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Manage cached results
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheAction {
+    /// Delete a file's cached results
+    Clean { file: PathBuf },
+    /// Print a file's cached results
+    List { file: PathBuf },
+    /// Add the results in another cache file, such as one from another machine, to a
+    /// file's cached results. Where both have a result, the newer one is kept.
+    Merge { file: PathBuf, other: PathBuf },
+}
+
+impl Cli {
+    // Log filter for the chosen verbosity, or None to defer to RUST_LOG
+    pub fn log_level(&self) -> Option<&'static str> {
+        if self.quiet {
+            Some("error")
+        } else if self.verbose {
+            Some("garden=debug")
+        } else {
+            None
+        }
+    }
+
+    // Settings these flags override in every loaded .garden.toml
+    pub fn config_overrides(&self) -> ConfigOverrides {
+        let sandboxed = self.sandbox.then_some(false);
+        ConfigOverrides {
+            allow_http: if self.allow_http { Some(true) } else { sandboxed },
+            allow_file: if self.allow_file { Some(true) } else { sandboxed },
+            allow_mocks: self.allow_mocks.then_some(true),
+            timeout_ms: self.timeout_ms,
+            max_loop_iterations: self.max_steps,
+            debounce_ms: self.debounce_ms,
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::{fs, path::Path, sync::OnceLock, time::Duration};
+use serde::Deserialize;
+
+// Name of the per-project config file
+const CONFIG_FILE: &str = ".garden.toml";
+
+// Iterations a while loop may run before it is assumed to be stuck
+const DEFAULT_MAX_LOOP_ITERATIONS: u64 = 10_000;
+
+// Editors often save in several steps; changes this close together cause one re-evaluation
+const DEFAULT_DEBOUNCE_MS: u64 = 100;
+
+// Cached results are dropped after a week unless [cache] ttl_seconds says otherwise
+const DEFAULT_CACHE_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+// Command-line settings, applied over every .garden.toml that is loaded
+static OVERRIDES: OnceLock<ConfigOverrides> = OnceLock::new();
+
+// Settings from .garden.toml. Every section and key is optional; CLI flags override them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GardenConfig {
+    pub http: HttpConfig,
+    pub cache: CacheConfig,
+    pub nrepl: NreplConfig,
+    pub sandbox: SandboxConfig,
+    pub display: DisplayConfig,
+    pub eval: EvalConfig,
+    pub watch: WatchConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    // Per-request timeout for http.get
+    pub timeout_ms: Option<u64>,
+    // Whether http.mock may stand in for real responses
+    pub allow_mocks: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    // Keep at most this many cached results, dropping the oldest
+    pub max_entries: Option<usize>,
+    // Cached results older than this are re-evaluated; a week if unset
+    pub ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NreplConfig {
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    // When false, http.get fails instead of making requests
+    pub allow_http: bool,
+    // When false, operations that write to the filesystem, like http.download, fail
+    pub allow_file: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self { allow_http: true, allow_file: true }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    // TUI theme, "dark" or "light"
+    pub theme: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EvalConfig {
+    // Iteration limit for while loops; 10,000 if unset
+    pub max_loop_iterations: Option<u64>,
+    // Longest a top-level expression may take; no limit if unset
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WatchConfig {
+    // How long to wait after a file changes for more changes before re-evaluating; 100 if unset
+    pub debounce_ms: Option<u64>,
+}
+
+// Flags that win over .garden.toml. None leaves the file's setting alone.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub allow_http: Option<bool>,
+    pub allow_file: Option<bool>,
+    pub allow_mocks: Option<bool>,
+    pub timeout_ms: Option<u64>,
+    pub max_loop_iterations: Option<u64>,
+    pub debounce_ms: Option<u64>,
+}
+
+impl ConfigOverrides {
+    // Apply these to every config loaded from now on. Only the first call has any effect.
+    pub fn install(self) {
+        let _ = OVERRIDES.set(self);
+    }
+
+    fn apply(&self, config: &mut GardenConfig) {
+        if let Some(allow_http) = self.allow_http {
+            config.sandbox.allow_http = allow_http;
+        }
+        if let Some(allow_file) = self.allow_file {
+            config.sandbox.allow_file = allow_file;
+        }
+        if let Some(allow_mocks) = self.allow_mocks {
+            config.http.allow_mocks = allow_mocks;
+        }
+        if let Some(timeout_ms) = self.timeout_ms {
+            config.eval.timeout_ms = Some(timeout_ms);
+        }
+        if let Some(max_loop_iterations) = self.max_loop_iterations {
+            config.eval.max_loop_iterations = Some(max_loop_iterations);
+        }
+        if let Some(debounce_ms) = self.debounce_ms {
+            config.watch.debounce_ms = Some(debounce_ms);
+        }
+    }
+}
+
+impl GardenConfig {
+    // Find the nearest .garden.toml in `start_dir` or its parents, stopping at the
+    // repository root. A missing or unparsable file gives the defaults. Installed
+    // command-line overrides are applied on top.
+    pub fn load(start_dir: &Path) -> GardenConfig {
+        let mut config = Self::read(start_dir);
+        if let Some(overrides) = OVERRIDES.get() {
+            overrides.apply(&mut config);
+        }
+        config
+    }
+
+    fn read(start_dir: &Path) -> GardenConfig {
+        // Relative paths like "." have no parents to walk up to
+        let start_dir = start_dir.canonicalize().unwrap_or_else(|_| start_dir.to_path_buf());
+        for dir in start_dir.ancestors() {
+            let path = dir.join(CONFIG_FILE);
+            if path.is_file() {
+                let parsed = fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|src| toml::from_str(&src).map_err(|e| e.to_string()));
+                return match parsed {
+                    Ok(config) => config,
+                    Err(e) => {
+                        tracing::warn!("Ignoring {}: {}", path.display(), e);
+                        GardenConfig::default()
+                    }
+                };
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        GardenConfig::default()
+    }
+
+    // Config for a watched file, searched for from the file's directory
+    pub fn for_file(file_path: &Path) -> GardenConfig {
+        match file_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => Self::load(dir),
+            _ => Self::load(Path::new(".")),
+        }
+    }
+
+    pub fn http_timeout(&self) -> Option<Duration> {
+        self.http.timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn max_loop_iterations(&self) -> u64 {
+        self.eval.max_loop_iterations.unwrap_or(DEFAULT_MAX_LOOP_ITERATIONS)
+    }
+
+    pub fn eval_timeout(&self) -> Option<Duration> {
+        self.eval.timeout_ms.map(Duration::from_millis)
+    }
+    
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache.ttl_seconds.unwrap_or(DEFAULT_CACHE_TTL_SECONDS))
+    }
+    
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.watch.debounce_ms.unwrap_or(DEFAULT_DEBOUNCE_MS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A fresh directory marked as a repository root, holding `config` as its .garden.toml
+    fn project_with_config(config: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("garden-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(CONFIG_FILE), config).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_settings_from_the_nearest_config_file() {
+        let dir = project_with_config(
+            "[http]\ntimeout_ms = 250\n[cache]\nmax_entries = 10\nttl_seconds = 60\n\
+             [nrepl]\nport = 7888\n[sandbox]\nallow_http = false\n[display]\ntheme = \"light\"\n",
+        );
+        let nested = dir.join("src");
+        fs::create_dir_all(&nested).unwrap();
+
+        let config = GardenConfig::read(&nested);
+        assert_eq!(config.http_timeout(), Some(Duration::from_millis(250)));
+        assert_eq!(config.cache.max_entries, Some(10));
+        assert_eq!(config.cache_ttl(), Duration::from_secs(60));
+        assert_eq!(config.nrepl.port, Some(7888));
+        assert!(!config.sandbox.allow_http);
+        assert!(config.sandbox.allow_file);
+        assert_eq!(config.display.theme.as_deref(), Some("light"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unparsable_config_gives_defaults() {
+        let dir = project_with_config("[http\ntimeout_ms = ");
+        let config = GardenConfig::read(&dir);
+        assert_eq!(config.http_timeout(), None);
+        assert!(config.sandbox.allow_http);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flags_override_config_file() {
+        let dir = project_with_config("[sandbox]\nallow_http = false\n[eval]\ntimeout_ms = 5\n");
+        let mut config = GardenConfig::read(&dir);
+        let overrides = ConfigOverrides {
+            allow_http: Some(true),
+            timeout_ms: Some(1000),
+            debounce_ms: Some(20),
+            ..ConfigOverrides::default()
+        };
+        assert_eq!(config.debounce(), Duration::from_millis(DEFAULT_DEBOUNCE_MS));
+        overrides.apply(&mut config);
+        assert!(config.sandbox.allow_http);
+        assert_eq!(config.eval_timeout(), Some(Duration::from_millis(1000)));
+        assert_eq!(config.debounce(), Duration::from_millis(20));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
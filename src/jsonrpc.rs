@@ -0,0 +1,290 @@
+use std::rc::Rc;
+use serde_json::{json, Value as JsonValue};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+use crate::{config::GardenConfig, parser, Env, Error, Evaluator, Node, Value};
+
+// Standard JSON-RPC 2.0 error codes, plus -32000 for evaluation failures
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const EVAL_ERROR: i64 = -32000;
+
+const METHODS: [&str; 5] = ["eval", "get-var", "set-var", "get-context", "describe"];
+
+// The context shared by every client: an evaluator, its top-level environment and
+// every root node evaluated so far
+struct Session {
+    evaluator: Evaluator,
+    env: Env<'static>,
+    root_nodes: Vec<Rc<Node>>,
+}
+
+impl Session {
+    async fn eval(&mut self, code: &str) -> Result<Option<Value>, Error> {
+        let nodes = parser::parse(code)?;
+
+        self.evaluator.prepare_for_evaluation();
+        for node in &nodes {
+            self.evaluator.store_node(node.clone());
+        }
+        self.root_nodes.extend(nodes.iter().cloned());
+
+        self.evaluator.evaluate_sequence(&nodes, &mut self.env).await
+    }
+
+    async fn set_var(&mut self, name: &str, value: Value) -> Result<(), Error> {
+        let definition = self.evaluator.value_definition(name, value);
+        self.root_nodes.push(definition.clone());
+        self.evaluator.evaluate_sequence(&[definition], &mut self.env).await?;
+        Ok(())
+    }
+}
+
+// A JSON-RPC error: code and message
+type RpcError = (i64, String);
+
+fn error_response(id: JsonValue, (code, message): RpcError) -> JsonValue {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+// Start a JSON-RPC 2.0 server on the given port, one newline-delimited JSON message per line
+pub async fn serve(port: u16, config: GardenConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("JSON-RPC server started on port {}", listener.local_addr()?.port());
+
+    // Evaluators hold Rc nodes, so clients are served on a single-threaded LocalSet
+    let local = tokio::task::LocalSet::new();
+    local.run_until(accept_clients(listener, config)).await
+}
+
+async fn accept_clients(listener: TcpListener, config: GardenConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let session = Rc::new(Mutex::new(Session {
+        evaluator: Evaluator::with_config(config),
+        env: Env::new(),
+        root_nodes: Vec::new(),
+    }));
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let session = session.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = handle_client(stream, session).await {
+                tracing::warn!(client = %addr, "JSON-RPC client error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_client(stream: TcpStream, session: Rc<Mutex<Session>>) -> Result<(), Box<dyn std::error::Error>> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<JsonValue>(&line) {
+            Ok(JsonValue::Array(batch)) if batch.is_empty() => {
+                Some(error_response(JsonValue::Null, (INVALID_REQUEST, "Empty batch".to_string())))
+            }
+            Ok(JsonValue::Array(batch)) => {
+                let mut responses = Vec::new();
+                for request in batch {
+                    responses.extend(handle_request(request, &session).await);
+                }
+                // A batch of only notifications gets no reply at all
+                (!responses.is_empty()).then_some(JsonValue::Array(responses))
+            }
+            Ok(request) => handle_request(request, &session).await,
+            Err(e) => Some(error_response(JsonValue::Null, (PARSE_ERROR, format!("Parse error: {}", e)))),
+        };
+
+        if let Some(response) = response {
+            let mut bytes = serde_json::to_vec(&response)?;
+            bytes.push(b'\n');
+            writer.write_all(&bytes).await?;
+        }
+    }
+    Ok(())
+}
+
+// Handle one request object. Notifications (requests without an id) get no response.
+async fn handle_request(request: JsonValue, session: &Mutex<Session>) -> Option<JsonValue> {
+    let Some(object) = request.as_object() else {
+        return Some(error_response(JsonValue::Null, (INVALID_REQUEST, "Request must be an object".to_string())));
+    };
+    let id = object.get("id").cloned();
+    let Some(method) = object.get("method").and_then(JsonValue::as_str) else {
+        return Some(error_response(id.unwrap_or_default(), (INVALID_REQUEST, "Missing method".to_string())));
+    };
+    let params = object.get("params").cloned().unwrap_or_default();
+
+    let result = call(method, &params, session).await;
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+fn string_param<'a>(params: &'a JsonValue, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, format!("Missing string parameter '{}'", name)))
+}
+
+async fn call(method: &str, params: &JsonValue, session: &Mutex<Session>) -> Result<JsonValue, RpcError> {
+    match method {
+        "eval" => {
+            let code = string_param(params, "code")?;
+            let value = session.lock().await.eval(code).await.map_err(|e| (EVAL_ERROR, e.to_string()))?;
+            let result = value.map(|v| v.to_json_value()).transpose().map_err(|e| (EVAL_ERROR, e.to_string()))?;
+            Ok(json!({ "result": result }))
+        }
+        "get-var" => {
+            let name = string_param(params, "name")?;
+            let session = session.lock().await;
+            let context = session.evaluator.context(&session.root_nodes);
+            match context.get(name) {
+                Some(value) => {
+                    let value = value.to_json_value().map_err(|e| (EVAL_ERROR, e.to_string()))?;
+                    Ok(json!({ "name": name, "value": value }))
+                }
+                None => Err((EVAL_ERROR, format!("Undefined variable: {}", name))),
+            }
+        }
+        "set-var" => {
+            let name = string_param(params, "name")?;
+            let value = params
+                .get("value")
+                .cloned()
+                .ok_or_else(|| (INVALID_PARAMS, "Missing parameter 'value'".to_string()))?;
+            session
+                .lock()
+                .await
+                .set_var(name, Value::from_json_value(value.clone()))
+                .await
+                .map_err(|e| (EVAL_ERROR, e.to_string()))?;
+            Ok(json!({ "name": name, "value": value }))
+        }
+        "get-context" => {
+            let session = session.lock().await;
+            let context: serde_json::Map<String, JsonValue> = session
+                .evaluator
+                .context(&session.root_nodes)
+                .into_iter()
+                // Values without a JSON form, like functions, are given as their source
+                .map(|(name, value)| {
+                    let json = value.to_json_value().unwrap_or_else(|_| JsonValue::String(value.to_string()));
+                    (name, json)
+                })
+                .collect();
+            Ok(JsonValue::Object(context))
+        }
+        "describe" => Ok(json!({
+            "name": "garden",
+            "version": env!("CARGO_PKG_VERSION"),
+            "methods": METHODS,
+        })),
+        other => Err((METHOD_NOT_FOUND, format!("Method not found: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::Lines;
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::task::LocalSet;
+
+    struct Client {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl Client {
+        // Connect to a server on a free port, which runs for the rest of the test on its LocalSet
+        async fn start() -> Self {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::task::spawn_local(accept_clients(listener, GardenConfig::default()));
+            let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+            Self { lines: BufReader::new(reader).lines(), writer }
+        }
+
+        // Send one line and read the line that answers it
+        async fn call(&mut self, line: &str) -> JsonValue {
+            self.writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+            serde_json::from_str(&self.lines.next_line().await.unwrap().unwrap()).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluates_and_reads_back_definitions() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::start().await;
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 1, "method": "eval", "params": {"code": "(def x 20) (+ x 1)"}}"#).await;
+                assert_eq!(reply, json!({"jsonrpc": "2.0", "id": 1, "result": {"result": 21}}));
+
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 2, "method": "get-var", "params": {"name": "x"}}"#).await;
+                assert_eq!(reply["result"], json!({"name": "x", "value": 20}));
+
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": "s", "method": "set-var", "params": {"name": "y", "value": [1, "a"]}}"#).await;
+                assert_eq!(reply["id"], "s");
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 3, "method": "get-context"}"#).await;
+                assert_eq!(reply["result"], json!({"x": 20, "y": [1, "a"]}));
+
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 4, "method": "describe"}"#).await;
+                assert_eq!(reply["result"]["methods"].as_array().unwrap().len(), METHODS.len());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn batches_get_one_reply_per_request_with_an_id() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::start().await;
+                let reply = client
+                    .call(
+                        r#"[{"jsonrpc": "2.0", "id": 1, "method": "eval", "params": {"code": "(def a 1)"}},
+                            {"jsonrpc": "2.0", "method": "eval", "params": {"code": "(def b 2)"}},
+                            {"jsonrpc": "2.0", "id": 2, "method": "eval", "params": {"code": "(+ a b)"}}]"#
+                            .replace('\n', " ")
+                            .as_str(),
+                    )
+                    .await;
+                let replies = reply.as_array().unwrap();
+                assert_eq!(replies.len(), 2);
+                assert_eq!(replies[1], json!({"jsonrpc": "2.0", "id": 2, "result": {"result": 3}}));
+
+                let reply = client.call("[]").await;
+                assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn errors_use_json_rpc_codes() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::start().await;
+                assert_eq!(client.call("{not json").await["error"]["code"], PARSE_ERROR);
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 1, "method": "eval", "params": {"code": "(/ 1 0)"}}"#).await;
+                assert_eq!(reply["error"]["code"], EVAL_ERROR);
+                assert_eq!(reply["id"], 1);
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 2, "method": "get-var", "params": {"name": "nope"}}"#).await;
+                assert_eq!(reply["error"]["code"], EVAL_ERROR);
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 3, "method": "eval", "params": {}}"#).await;
+                assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+                let reply = client.call(r#"{"jsonrpc": "2.0", "id": 4, "method": "launch"}"#).await;
+                assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+            })
+            .await;
+    }
+}
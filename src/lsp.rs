@@ -0,0 +1,339 @@
+use std::{collections::HashMap, sync::Mutex};
+use indexmap::IndexMap;
+use tokio::sync::{mpsc, oneshot};
+use tower_lsp::jsonrpc::Result as RpcResult;
+use tower_lsp::lsp_types::*;
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+
+use std::rc::Rc;
+
+use crate::{config::GardenConfig, evaluate_source, Evaluator, Node, NodeKind, SourceSpan, Value};
+
+// What the server knows about a document after evaluating it
+#[derive(Debug, Default)]
+struct Analysis {
+    context: IndexMap<String, Value>,
+    // Where each variable's name appears in its def form
+    definitions: HashMap<String, Range>,
+    // The comments above def forms, by name
+    descriptions: HashMap<String, String>,
+    // Every expression with a value, for hovering over expressions that aren't names
+    values: Vec<(SourceSpan, Value)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+// A document to evaluate on the evaluator thread
+struct EvalRequest {
+    uri: Url,
+    text: String,
+    reply: oneshot::Sender<Analysis>,
+}
+
+// Evaluators hold Rc nodes and can't cross threads, while tower-lsp needs a Send
+// server, so all evaluation happens on one dedicated thread with its own runtime
+fn spawn_evaluator() -> mpsc::UnboundedSender<EvalRequest> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<EvalRequest>();
+    let thread = std::thread::Builder::new().stack_size(crate::EVAL_STACK_SIZE);
+    thread.spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Could not start the LSP evaluator runtime");
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&runtime, async move {
+            let mut evaluators: HashMap<Url, Evaluator> = HashMap::new();
+            while let Some(request) = rx.recv().await {
+                let evaluator = evaluators.entry(request.uri.clone()).or_insert_with(|| new_evaluator(&request.uri));
+                let analysis = analyze(&request.text, evaluator).await;
+                // The requester may have gone away, e.g. on shutdown
+                let _ = request.reply.send(analysis);
+            }
+        });
+    })
+    .expect("Could not start the LSP evaluator thread");
+    tx
+}
+
+// Evaluator for a document, seeded with the file's cache so hovers show values
+// without re-running slow expressions
+fn new_evaluator(uri: &Url) -> Evaluator {
+    let Ok(path) = uri.to_file_path() else {
+        return Evaluator::new();
+    };
+    let mut evaluator = Evaluator::with_config(GardenConfig::for_file(&path));
+    // A missing or corrupt cache just means evaluating from scratch
+    let _ = evaluator.load_cache(&path.with_extension("expr.cache"));
+    evaluator
+}
+
+async fn analyze(text: &str, evaluator: &mut Evaluator) -> Analysis {
+    let mut analysis = Analysis::default();
+    match evaluate_source(text, evaluator).await {
+        Ok((root_nodes, error)) => {
+            analysis.context = evaluator.context(&root_nodes);
+            for node in &root_nodes {
+                let Some(line) = node.metadata().get("line").and_then(|line| line.parse::<u32>().ok()) else {
+                    continue;
+                };
+                let line = line.saturating_sub(1);
+
+                if let (NodeKind::Definition | NodeKind::LetStatement, Some(NodeKind::Symbol(name))) =
+                    (node.kind(), node.children().get(1).map(|child| child.kind()))
+                {
+                    analysis.definitions.insert(name.clone(), symbol_range(text, line, name));
+                    if let Some(description) = node.metadata().get("description") {
+                        analysis.descriptions.insert(name.clone(), description.clone());
+                    }
+                }
+                if let Some(Err(e)) = evaluator.get_cached_result(node.id()) {
+                    let range = SourceSpan::of(node).map_or_else(|| line_range(text, line), |span| span_range(&span));
+                    analysis.diagnostics.push(diagnostic(range, e.to_string()));
+                }
+                collect_values(node, evaluator, &mut analysis.values);
+            }
+            // An error not tied to any root still gets reported, at the top of the file
+            if let (Some(e), true) = (error, analysis.diagnostics.is_empty()) {
+                analysis.diagnostics.push(diagnostic(line_range(text, 0), e.to_string()));
+            }
+        }
+        Err(e) => {
+            // LSP positions count from 0
+            let range = match e.location() {
+                Some((line, col)) => {
+                    let (line, col) = (line.saturating_sub(1) as u32, col.saturating_sub(1) as u32);
+                    Range::new(Position::new(line, col), Position::new(line, col + 1))
+                }
+                None => line_range(text, 0),
+            };
+            analysis.diagnostics.push(diagnostic(range, e.to_string()));
+        }
+    }
+    analysis
+}
+
+fn diagnostic(range: Range, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("garden".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+// The whole of a (zero-based) line
+// Record the span and value of `node` and every expression inside it that has a cached value
+fn collect_values(node: &Rc<Node>, evaluator: &Evaluator, values: &mut Vec<(SourceSpan, Value)>) {
+    if let (Some(span), Some(Ok(value))) = (SourceSpan::of(node), evaluator.get_cached_result(node.id())) {
+        values.push((span, value));
+    }
+    for child in node.children() {
+        collect_values(child, evaluator, values);
+    }
+}
+
+fn span_range(span: &SourceSpan) -> Range {
+    let position = |line: usize, col: usize| Position::new(line.saturating_sub(1) as u32, col.saturating_sub(1) as u32);
+    Range::new(position(span.line, span.column), position(span.end_line, span.end_col))
+}
+
+fn line_range(text: &str, line: u32) -> Range {
+    let len = text.lines().nth(line as usize).map_or(0, |l| l.chars().count() as u32);
+    Range::new(Position::new(line, 0), Position::new(line, len))
+}
+
+// The first occurrence of `name` on a line, after the def keyword
+fn symbol_range(text: &str, line: u32, name: &str) -> Range {
+    let line_text = text.lines().nth(line as usize).unwrap_or_default();
+    let skip = line_text.find("def").or_else(|| line_text.find("let")).map_or(0, |i| i + 3);
+    match line_text[skip..].find(name) {
+        Some(i) => {
+            let start = line_text[..skip + i].chars().count() as u32;
+            Range::new(Position::new(line, start), Position::new(line, start + name.chars().count() as u32))
+        }
+        None => line_range(text, line),
+    }
+}
+
+// The symbol the cursor is on, if any
+fn word_at(text: &str, position: Position) -> Option<String> {
+    let line: Vec<char> = text.lines().nth(position.line as usize)?.chars().collect();
+    let is_symbol_char = |c: &char| !c.is_whitespace() && !matches!(c, '(' | ')' | '"');
+    let cursor = (position.character as usize).min(line.len());
+
+    let start = line[..cursor].iter().rposition(|c| !is_symbol_char(c)).map_or(0, |i| i + 1);
+    let end = line[cursor..].iter().position(|c| !is_symbol_char(c)).map_or(line.len(), |i| cursor + i);
+    (start < end).then(|| line[start..end].iter().collect())
+}
+
+struct Backend {
+    client: Client,
+    evaluator: mpsc::UnboundedSender<EvalRequest>,
+    documents: Mutex<HashMap<Url, Document>>,
+}
+
+impl Backend {
+    // Evaluate a document's current text and publish its diagnostics
+    async fn evaluate(&self, uri: Url) {
+        let Some(text) = self.documents.lock().unwrap().get(&uri).map(|doc| doc.text.clone()) else {
+            return;
+        };
+
+        let (reply, analysis) = oneshot::channel();
+        let request = EvalRequest { uri: uri.clone(), text, reply };
+        if self.evaluator.send(request).is_err() {
+            self.client.log_message(MessageType::ERROR, "The garden evaluator thread has stopped").await;
+            return;
+        }
+        let Ok(analysis) = analysis.await else {
+            return;
+        };
+
+        let diagnostics = analysis.diagnostics.clone();
+        if let Some(doc) = self.documents.lock().unwrap().get_mut(&uri) {
+            doc.analysis = analysis;
+        }
+        self.client.publish_diagnostics(uri, diagnostics, None).await;
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, _: InitializeParams) -> RpcResult<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::FULL),
+                    save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions { include_text: Some(true) })),
+                    ..Default::default()
+                })),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                ..Default::default()
+            },
+            server_info: Some(ServerInfo {
+                name: "garden".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+        })
+    }
+
+    async fn shutdown(&self) -> RpcResult<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.lock().unwrap().insert(
+            uri.clone(),
+            Document { text: params.text_document.text, analysis: Analysis::default() },
+        );
+        self.evaluate(uri).await;
+    }
+
+    // Edits are only recorded; evaluation waits for a save so slow expressions like
+    // http.get don't run on every keystroke
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        if let (Some(change), Some(doc)) = (
+            params.content_changes.into_iter().last(),
+            self.documents.lock().unwrap().get_mut(&params.text_document.uri),
+        ) {
+            doc.text = change.text;
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if let Some(text) = params.text {
+            if let Some(doc) = self.documents.lock().unwrap().get_mut(&uri) {
+                doc.text = text;
+            }
+        }
+        self.evaluate(uri).await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.documents.lock().unwrap().remove(&params.text_document.uri);
+    }
+
+    async fn hover(&self, params: HoverParams) -> RpcResult<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let documents = self.documents.lock().unwrap();
+        let Some(doc) = documents.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        // A variable's value, or else the value of the innermost expression under the cursor
+        let (line, col) = (position.position.line as usize + 1, position.position.character as usize + 1);
+        let innermost = || {
+            doc.analysis.values
+                .iter()
+                .filter(|(span, _)| span.contains(line, col))
+                .min_by_key(|(span, _)| (span.end_line - span.line, span.original_text.len()))
+                .map(|(_, value)| value)
+        };
+        let name = word_at(&doc.text, position.position);
+        let Some(value) = name
+            .as_ref()
+            .and_then(|name| doc.analysis.context.get(name))
+            .or_else(innermost)
+        else {
+            return Ok(None);
+        };
+        let mut contents = format!("`{}`\n```\n{}\n```", value.type_name(), value);
+        if let Some(description) = name.and_then(|name| doc.analysis.descriptions.get(&name)) {
+            contents.push_str(&format!("\n\n{}", description));
+        }
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: contents,
+            }),
+            range: None,
+        }))
+    }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> RpcResult<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let documents = self.documents.lock().unwrap();
+        let Some(doc) = documents.get(&uri) else {
+            return Ok(None);
+        };
+
+        Ok(word_at(&doc.text, position.position)
+            .and_then(|name| doc.analysis.definitions.get(&name).copied())
+            .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri.clone(), range))))
+    }
+}
+
+// Serve the Language Server Protocol over stdin/stdout
+pub async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    let evaluator = spawn_evaluator();
+    let (service, socket) = LspService::new(|client| Backend {
+        client,
+        evaluator,
+        documents: Mutex::new(HashMap::new()),
+    });
+    Server::new(tokio::io::stdin(), tokio::io::stdout(), socket).serve(service).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn definitions_are_described_by_the_comments_above_them() {
+        let text = "; How many\n;; to fetch\n(def limit 10)\n\n(def other 1)\n";
+        let analysis = analyze(text, &mut Evaluator::new()).await;
+        assert_eq!(analysis.descriptions.get("limit").map(String::as_str), Some("How many\nto fetch"));
+        assert!(!analysis.descriptions.contains_key("other"));
+    }
+}
@@ -1,37 +1,114 @@
-use std::{collections::{HashMap, HashSet}, fs, path::Path, sync::mpsc, rc::Rc, hash::{Hash, Hasher}, time::{Instant, Duration}};
+use std::{collections::{HashMap, HashSet}, fs, path::{Path, PathBuf}, sync::{mpsc, Arc, Mutex}, rc::Rc, time::{Duration, Instant}};
 use serde::{Serialize, Deserialize};
 use serde_json::Value as JsonValue;
-use indexmap::IndexMap;
-use reqwest;
+use indexmap::IndexMap; // For ordered context display
 use futures::future::{BoxFuture, Future};
 use std::pin::Pin;
 use notify::{Watcher, RecursiveMode, recommended_watcher};
 use chrono::{self, DateTime, Utc};
-use blake3;
-use hex;
 use smallvec::SmallVec;
-
-// === Import chrono with serde features ===
-#[cfg(feature = "serde")]
-use chrono::{serde::ts_seconds, Duration};
+use regex::Regex;
+use tokio::io::AsyncWriteExt;
+use config::GardenConfig;
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use cli::{Cli, Command, CacheAction};
 
 // Add pest parser module
 mod parser;
+mod cli;
+mod config;
+mod lsp;
+mod nrepl;
+mod jsonrpc;
+mod repl;
+mod tui;
+mod ws;
+#[cfg(test)]
+mod tests;
 
 // === TYPES ===
 
+// Where an expression is in its source. Lines and columns count from 1, and the end
+// is just past the last character.
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceSpan {
     pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_col: usize,
     pub original_text: String, // Store the original source text
 }
 
+impl SourceSpan {
+    // The span recorded in a parsed node's metadata
+    pub fn of(node: &Node) -> Option<SourceSpan> {
+        let position = |key: &str| node.metadata().get(key).and_then(|value| value.parse().ok());
+        Some(SourceSpan {
+            line: position("line")?,
+            column: position("column")?,
+            end_line: position("end_line")?,
+            end_col: position("end_col")?,
+            original_text: node.code_snippet().to_string(),
+        })
+    }
+    
+    // Whether the character at `line` and `col` is inside the span
+    pub fn contains(&self, line: usize, col: usize) -> bool {
+        (self.line, self.column) <= (line, col) && (line, col) < (self.end_line, self.end_col)
+    }
+}
+
+// Where parsed expressions came from, so a value can be traced back to the code that
+// produced it
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    entries: Vec<SourceMapEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceMapEntry {
+    pub node_id: NodeId,
+    pub span: SourceSpan,
+    pub source_file: PathBuf,
+}
+
+impl SourceMap {
+    // Record the span of every parsed node under `roots`, identical code included
+    pub fn add(&mut self, roots: &[Rc<Node>], source_file: &Path) {
+        let mut stack: Vec<&Rc<Node>> = roots.iter().rev().collect();
+        while let Some(node) = stack.pop() {
+            if let Some(span) = SourceSpan::of(node) {
+                self.entries.push(SourceMapEntry {
+                    node_id: *node.id(),
+                    span,
+                    source_file: source_file.to_path_buf(),
+                });
+            }
+            stack.extend(node.children().iter().rev());
+        }
+    }
+    
+    // Where a node was most recently seen. Identical code elsewhere has the same id.
+    pub fn find_by_node_id(&self, id: &NodeId) -> Option<&SourceMapEntry> {
+        self.entries.iter().rev().find(|entry| &entry.node_id == id)
+    }
+    
+    // The innermost expression at `line` and `col`
+    pub fn find_at_position(&self, line: usize, col: usize) -> Option<&SourceMapEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.span.contains(line, col))
+            .max_by_key(|entry| (entry.span.line, entry.span.column))
+    }
+}
+
 type NodeId = [u8; 32]; // 32 bytes for BLAKE3 hash
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum NodeKind {
     Symbol(String),
     Number(i64),
+    Float(f64),
     String(String),
     List,
     // More specific operations
@@ -40,10 +117,108 @@ pub enum NodeKind {
     LetStatement,   // New: (let name value) - statement form, modifies current env
     Addition,
     Multiplication,
+    Subtraction,
+    Division,
+    Modulo,
     HttpGet,
     JsonParse,
     JsonGet,
     StringUpper,
+    StrToNum,
+    NumFormat,
+    NumParse,
+    StrPadLeft,
+    StrPadRight,
+    StrRepeat,
+    StrLines,
+    StrTrim,
+    StrSlice,
+    Vector,
+    Lambda,
+    Each,
+    Range,
+    Println,
+    Sort,
+    SortBy,
+    GroupBy,
+    Zip,
+    Unzip,
+    Distinct,
+    Frequencies,
+    Take,
+    Drop,
+    Partition,
+    StrConcat,
+    Apply,
+    Assoc,
+    Dissoc,
+    Keys,
+    Vals,
+    Merge,
+    For,
+    LessThan,
+    GreaterThan,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    While,
+    If,
+    Memoize,
+    TypeOf,
+    CsvParse,
+    CsvParseHeaders,
+    CsvStringify,
+    TomlParse,
+    YamlParse,
+    RegexMatch,
+    RegexCaptures,
+    RegexReplace,
+    Base64Encode,
+    Base64Decode,
+    HashSha256,
+    HashMd5,
+    HashBlake3,
+    HashHmacSha256,
+    UuidNew,
+    UuidParse,
+    UrlEncode,
+    UrlDecode,
+    UrlBuild,
+    Sleep,
+    HttpPostJson,
+    HttpGetResponse,
+    HttpSetCookieJar,
+    HttpClearCookies,
+    Watch,
+    NumOverflowMode,
+    AssertType,
+    CoerceTo,
+    WithContext,
+    StrInterpolate,
+    JsonValidate,
+    WithDefault,
+    Tap,
+    MemoizeTtl,
+    Parallel,
+    HttpDownload,
+    StrJoin,
+    StrSplitN,
+    Cond,
+    Match,
+    MemoDefinition,
+    MemoInvalidate,
+    JsonFlatten,
+    JsonUnflatten,
+    XmlParse,
+    DefinitionOnce,
+    JsonArrayMap,
+    JsonArrayFilter,
+    StrNumberQ,
+    StrBoolQ,
+    StrJsonQ,
+    HttpMock,
+    HttpUnmock,
+    Spread,
 }
 
 // Immutable computation tree node without cached_value (moved to EvaluationCache)
@@ -90,6 +265,10 @@ impl Node {
                 hasher.update(b"Number:");
                 hasher.update(&n.to_le_bytes());
             }
+            NodeKind::Float(n) => {
+                hasher.update(b"Float:");
+                hasher.update(&n.to_le_bytes());
+            }
             NodeKind::String(s) => {
                 hasher.update(b"String:");
                 hasher.update(s.as_bytes());
@@ -112,6 +291,15 @@ impl Node {
             NodeKind::Multiplication => {
                 hasher.update(b"Multiplication");
             }
+            NodeKind::Subtraction => {
+                hasher.update(b"Subtraction");
+            }
+            NodeKind::Division => {
+                hasher.update(b"Division");
+            }
+            NodeKind::Modulo => {
+                hasher.update(b"Modulo");
+            }
             NodeKind::HttpGet => {
                 hasher.update(b"HttpGet");
             }
@@ -124,6 +312,291 @@ impl Node {
             NodeKind::StringUpper => {
                 hasher.update(b"StringUpper");
             }
+            NodeKind::StrToNum => {
+                hasher.update(b"StrToNum");
+            }
+            NodeKind::NumFormat => {
+                hasher.update(b"NumFormat");
+            }
+            NodeKind::NumParse => {
+                hasher.update(b"NumParse");
+            }
+            NodeKind::StrPadLeft => {
+                hasher.update(b"StrPadLeft");
+            }
+            NodeKind::StrPadRight => {
+                hasher.update(b"StrPadRight");
+            }
+            NodeKind::StrRepeat => {
+                hasher.update(b"StrRepeat");
+            }
+            NodeKind::StrLines => {
+                hasher.update(b"StrLines");
+            }
+            NodeKind::StrTrim => {
+                hasher.update(b"StrTrim");
+            }
+            NodeKind::StrSlice => {
+                hasher.update(b"StrSlice");
+            }
+            NodeKind::Vector => {
+                hasher.update(b"Vector");
+            }
+            NodeKind::Lambda => {
+                hasher.update(b"Lambda");
+            }
+            NodeKind::Each => {
+                hasher.update(b"Each");
+            }
+            NodeKind::Range => {
+                hasher.update(b"Range");
+            }
+            NodeKind::Println => {
+                hasher.update(b"Println");
+            }
+            NodeKind::Sort => {
+                hasher.update(b"Sort");
+            }
+            NodeKind::SortBy => {
+                hasher.update(b"SortBy");
+            }
+            NodeKind::GroupBy => {
+                hasher.update(b"GroupBy");
+            }
+            NodeKind::Zip => {
+                hasher.update(b"Zip");
+            }
+            NodeKind::Unzip => {
+                hasher.update(b"Unzip");
+            }
+            NodeKind::Distinct => {
+                hasher.update(b"Distinct");
+            }
+            NodeKind::Frequencies => {
+                hasher.update(b"Frequencies");
+            }
+            NodeKind::Take => {
+                hasher.update(b"Take");
+            }
+            NodeKind::Drop => {
+                hasher.update(b"Drop");
+            }
+            NodeKind::Partition => {
+                hasher.update(b"Partition");
+            }
+            NodeKind::StrConcat => {
+                hasher.update(b"StrConcat");
+            }
+            NodeKind::Apply => {
+                hasher.update(b"Apply");
+            }
+            NodeKind::Assoc => {
+                hasher.update(b"Assoc");
+            }
+            NodeKind::Dissoc => {
+                hasher.update(b"Dissoc");
+            }
+            NodeKind::Keys => {
+                hasher.update(b"Keys");
+            }
+            NodeKind::Vals => {
+                hasher.update(b"Vals");
+            }
+            NodeKind::Merge => {
+                hasher.update(b"Merge");
+            }
+            NodeKind::For => {
+                hasher.update(b"For");
+            }
+            NodeKind::LessThan => {
+                hasher.update(b"LessThan");
+            }
+            NodeKind::GreaterThan => {
+                hasher.update(b"GreaterThan");
+            }
+            NodeKind::LessEqual => {
+                hasher.update(b"LessEqual");
+            }
+            NodeKind::GreaterEqual => {
+                hasher.update(b"GreaterEqual");
+            }
+            NodeKind::Equal => {
+                hasher.update(b"Equal");
+            }
+            NodeKind::While => {
+                hasher.update(b"While");
+            }
+            NodeKind::If => {
+                hasher.update(b"If");
+            }
+            NodeKind::Memoize => {
+                hasher.update(b"Memoize");
+            }
+            NodeKind::TypeOf => {
+                hasher.update(b"TypeOf");
+            }
+            NodeKind::CsvParse => {
+                hasher.update(b"CsvParse");
+            }
+            NodeKind::CsvParseHeaders => {
+                hasher.update(b"CsvParseHeaders");
+            }
+            NodeKind::CsvStringify => {
+                hasher.update(b"CsvStringify");
+            }
+            NodeKind::TomlParse => {
+                hasher.update(b"TomlParse");
+            }
+            NodeKind::YamlParse => {
+                hasher.update(b"YamlParse");
+            }
+            NodeKind::RegexMatch => {
+                hasher.update(b"RegexMatch");
+            }
+            NodeKind::RegexCaptures => {
+                hasher.update(b"RegexCaptures");
+            }
+            NodeKind::RegexReplace => {
+                hasher.update(b"RegexReplace");
+            }
+            NodeKind::Base64Encode => {
+                hasher.update(b"Base64Encode");
+            }
+            NodeKind::Base64Decode => {
+                hasher.update(b"Base64Decode");
+            }
+            NodeKind::HashSha256 => {
+                hasher.update(b"HashSha256");
+            }
+            NodeKind::HashMd5 => {
+                hasher.update(b"HashMd5");
+            }
+            NodeKind::HashBlake3 => {
+                hasher.update(b"HashBlake3");
+            }
+            NodeKind::HashHmacSha256 => {
+                hasher.update(b"HashHmacSha256");
+            }
+            NodeKind::UuidNew => {
+                hasher.update(b"UuidNew");
+            }
+            NodeKind::UuidParse => {
+                hasher.update(b"UuidParse");
+            }
+            NodeKind::UrlEncode => {
+                hasher.update(b"UrlEncode");
+            }
+            NodeKind::UrlDecode => {
+                hasher.update(b"UrlDecode");
+            }
+            NodeKind::UrlBuild => {
+                hasher.update(b"UrlBuild");
+            }
+            NodeKind::Sleep => {
+                hasher.update(b"Sleep");
+            }
+            NodeKind::HttpPostJson => {
+                hasher.update(b"HttpPostJson");
+            }
+            NodeKind::HttpGetResponse => {
+                hasher.update(b"HttpGetResponse");
+            }
+            NodeKind::HttpSetCookieJar => {
+                hasher.update(b"HttpSetCookieJar");
+            }
+            NodeKind::HttpClearCookies => {
+                hasher.update(b"HttpClearCookies");
+            }
+            NodeKind::Watch => {
+                hasher.update(b"Watch");
+            }
+            NodeKind::NumOverflowMode => {
+                hasher.update(b"NumOverflowMode");
+            }
+            NodeKind::AssertType => {
+                hasher.update(b"AssertType");
+            }
+            NodeKind::CoerceTo => {
+                hasher.update(b"CoerceTo");
+            }
+            NodeKind::WithContext => {
+                hasher.update(b"WithContext");
+            }
+            NodeKind::StrInterpolate => {
+                hasher.update(b"StrInterpolate");
+            }
+            NodeKind::JsonValidate => {
+                hasher.update(b"JsonValidate");
+            }
+            NodeKind::WithDefault => {
+                hasher.update(b"WithDefault");
+            }
+            NodeKind::Tap => {
+                hasher.update(b"Tap");
+            }
+            NodeKind::MemoizeTtl => {
+                hasher.update(b"MemoizeTtl");
+            }
+            NodeKind::Parallel => {
+                hasher.update(b"Parallel");
+            }
+            NodeKind::HttpDownload => {
+                hasher.update(b"HttpDownload");
+            }
+            NodeKind::StrJoin => {
+                hasher.update(b"StrJoin");
+            }
+            NodeKind::StrSplitN => {
+                hasher.update(b"StrSplitN");
+            }
+            NodeKind::Cond => {
+                hasher.update(b"Cond");
+            }
+            NodeKind::Match => {
+                hasher.update(b"Match");
+            }
+            NodeKind::MemoDefinition => {
+                hasher.update(b"MemoDefinition");
+            }
+            NodeKind::MemoInvalidate => {
+                hasher.update(b"MemoInvalidate");
+            }
+            NodeKind::JsonFlatten => {
+                hasher.update(b"JsonFlatten");
+            }
+            NodeKind::JsonUnflatten => {
+                hasher.update(b"JsonUnflatten");
+            }
+            NodeKind::XmlParse => {
+                hasher.update(b"XmlParse");
+            }
+            NodeKind::DefinitionOnce => {
+                hasher.update(b"DefinitionOnce");
+            }
+            NodeKind::JsonArrayMap => {
+                hasher.update(b"JsonArrayMap");
+            }
+            NodeKind::JsonArrayFilter => {
+                hasher.update(b"JsonArrayFilter");
+            }
+            NodeKind::StrNumberQ => {
+                hasher.update(b"StrNumberQ");
+            }
+            NodeKind::StrBoolQ => {
+                hasher.update(b"StrBoolQ");
+            }
+            NodeKind::StrJsonQ => {
+                hasher.update(b"StrJsonQ");
+            }
+            NodeKind::HttpMock => {
+                hasher.update(b"HttpMock");
+            }
+            NodeKind::HttpUnmock => {
+                hasher.update(b"HttpUnmock");
+            }
+            NodeKind::Spread => {
+                hasher.update(b"Spread");
+            }
         }
         
         // Add code snippet
@@ -162,13 +635,293 @@ impl Node {
     pub fn metadata(&self) -> &HashMap<String, String> {
         &self.metadata
     }
+    
+    // A copy of this node with one more metadata entry, such as a "description". Metadata
+    // isn't hashed, so the copy has the same id and shares cached results.
+    pub fn with_metadata(&self, key: &str, value: &str) -> Rc<Node> {
+        let mut node = self.clone();
+        node.metadata.insert(key.to_string(), value.to_string());
+        Rc::new(node)
+    }
+}
+
+// Every node under `root`, including it, with children before their parents. A node
+// reachable through several parents (or appearing twice, as identical code does) is
+// visited once.
+pub fn collect_all_nodes(root: &Rc<Node>) -> impl Iterator<Item = Rc<Node>> {
+    let mut seen = HashSet::new();
+    let mut order = Vec::new();
+    // An explicit stack, so deeply nested code can't overflow; `true` marks a node whose
+    // children have all been visited
+    let mut stack = vec![(root.clone(), false)];
+    while let Some((node, children_done)) = stack.pop() {
+        if children_done {
+            order.push(node);
+            continue;
+        }
+        if !seen.insert(*node.id()) {
+            continue;
+        }
+        stack.push((node.clone(), true));
+        for child in node.children().iter().rev() {
+            stack.push((child.clone(), false));
+        }
+    }
+    order.into_iter()
+}
+
+// The nodes under `root` that satisfy `predicate`, in the order of collect_all_nodes
+pub fn collect_all_nodes_filtered<F: Fn(&Node) -> bool>(root: &Rc<Node>, predicate: F) -> Vec<Rc<Node>> {
+    collect_all_nodes(root).filter(|node| predicate(node)).collect()
+}
+
+// The nodes under `root` matching `predicate`, in the order they start in the source
+fn find_in_source_order<F: Fn(&Node) -> bool>(root: &Rc<Node>, predicate: F) -> Vec<Rc<Node>> {
+    let mut nodes = collect_all_nodes_filtered(root, predicate);
+    nodes.sort_by_key(|node| SourceSpan::of(node).map(|span| (span.line, span.column)));
+    nodes
+}
+
+// Every node of one kind under `root`, such as all the HttpGet calls
+pub fn find_by_kind(root: &Rc<Node>, kind: &NodeKind) -> Vec<Rc<Node>> {
+    find_in_source_order(root, |node| node.kind() == kind)
+}
+
+// Every node under `root` whose metadata has `key` set to `value`
+#[allow(dead_code)] // For tools that query the tree
+pub fn find_by_metadata(root: &Rc<Node>, key: &str, value: &str) -> Vec<Rc<Node>> {
+    find_in_source_order(root, |node| node.metadata().get(key).is_some_and(|v| v == value))
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Number(i64),
+    Float(f64),
     String(String),
+    List(Vec<Value>),
+    // String keys in insertion order
+    Map(IndexMap<String, Value>),
     Json(JsonValue),
+    Lambda(Box<Lambda>),
+    Bool(bool),
+    Nil,
+}
+
+// A function value: its parameters, the body to evaluate and the environment it closed over
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lambda {
+    params: Vec<String>,
+    body: NodeId,
+    // The (fn ...) form, for display
+    source: String,
+    bindings: HashMap<String, NodeId>,
+    values: HashMap<String, Value>,
+    // For functions made by (memoize f): the memoize node, whose table of results it uses
+    #[serde(default)]
+    memo: Option<NodeId>,
+    // For (memoize-ttl f secs): how long a remembered result stays valid
+    #[serde(default)]
+    memo_ttl: Option<Duration>,
+}
+
+// Just the source; the body id and captured environment are noise in output
+impl std::fmt::Debug for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Lambda").field(&self.source).finish()
+    }
+}
+
+impl Value {
+    // Where the value's type comes in the order between types (see PartialOrd)
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Nil => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::Float(_) => 3,
+            Value::String(_) => 4,
+            Value::List(_) => 5,
+            Value::Map(_) => 6,
+            Value::Json(_) => 7,
+            Value::Lambda(_) => 8,
+        }
+    }
+    
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Lambda(_) => "lambda",
+            Value::Bool(_) => "bool",
+            Value::Nil => "nil",
+            Value::Json(_) => "json",
+        }
+    }
+    
+    // JSON for a value. Functions and floats without a JSON form (NaN, infinities) are errors.
+    pub fn to_json_value(&self) -> Result<JsonValue, Error> {
+        Ok(match self {
+            Value::Number(n) => JsonValue::Number((*n).into()),
+            Value::Float(f) => JsonValue::Number(serde_json::Number::from_f64(*f).ok_or_else(|| {
+                Error::EvalError(format!("{} has no JSON form", f))
+            })?),
+            Value::String(s) => JsonValue::String(s.clone()),
+            Value::List(items) => JsonValue::Array(items.iter().map(Value::to_json_value).collect::<Result<_, _>>()?),
+            Value::Map(entries) => JsonValue::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), value.to_json_value()?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Value::Lambda(lambda) => {
+                return Err(Error::EvalError(format!("A function has no JSON form: {}", lambda.source)))
+            }
+            Value::Bool(b) => JsonValue::Bool(*b),
+            Value::Nil => JsonValue::Null,
+            Value::Json(json) => json.clone(),
+        })
+    }
+    
+    // The garden value for JSON data: arrays become lists and objects maps, all the way down
+    pub fn from_json_value(json: JsonValue) -> Value {
+        match json {
+            JsonValue::Number(n) => match n.as_i64() {
+                Some(i) => Value::Number(i),
+                // Integers beyond i64 become floats
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            JsonValue::String(s) => Value::String(s),
+            JsonValue::Bool(b) => Value::Bool(b),
+            JsonValue::Null => Value::Nil,
+            JsonValue::Array(items) => Value::List(items.into_iter().map(Value::from_json_value).collect()),
+            JsonValue::Object(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, Value::from_json_value(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Values of different types order by type: nil, booleans, integers, floats, strings,
+// lists, maps, then JSON. Within a type, lists compare element by element, maps by size
+// and then by their entries in key order, JSON by type and then value. Functions have no
+// order, even with other types; one only equals itself.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        use std::cmp::Ordering;
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Some(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::List(a), Value::List(b)) => a.partial_cmp(b),
+            (Value::Map(a), Value::Map(b)) => match a.len().cmp(&b.len()) {
+                Ordering::Equal => {
+                    let mut a: Vec<_> = a.iter().collect();
+                    let mut b: Vec<_> = b.iter().collect();
+                    a.sort_by_key(|(key, _)| *key);
+                    b.sort_by_key(|(key, _)| *key);
+                    a.partial_cmp(&b)
+                }
+                unequal => Some(unequal),
+            },
+            (Value::Json(a), Value::Json(b)) => compare_json(a, b),
+            (Value::Lambda(a), Value::Lambda(b)) => (a == b).then_some(Ordering::Equal),
+            (Value::Lambda(_), _) | (_, Value::Lambda(_)) => None,
+            (a, b) => Some(a.type_rank().cmp(&b.type_rank())),
+        }
+    }
+}
+
+// The order of JSON values, by type (null, booleans, numbers, strings, arrays, then
+// objects) and then value, like Value's
+fn compare_json(a: &JsonValue, b: &JsonValue) -> Option<std::cmp::Ordering> {
+    use std::cmp::Ordering;
+    fn rank(value: &JsonValue) -> u8 {
+        match value {
+            JsonValue::Null => 0,
+            JsonValue::Bool(_) => 1,
+            JsonValue::Number(_) => 2,
+            JsonValue::String(_) => 3,
+            JsonValue::Array(_) => 4,
+            JsonValue::Object(_) => 5,
+        }
+    }
+    match (a, b) {
+        (JsonValue::Bool(a), JsonValue::Bool(b)) => Some(a.cmp(b)),
+        (JsonValue::Number(a), JsonValue::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            // Integers go before floats of the same value
+            _ => Some(a.as_f64()?.partial_cmp(&b.as_f64()?)?.then(a.is_f64().cmp(&b.is_f64()))),
+        },
+        (JsonValue::String(a), JsonValue::String(b)) => Some(a.cmp(b)),
+        (JsonValue::Array(a), JsonValue::Array(b)) => {
+            for (a, b) in a.iter().zip(b) {
+                match compare_json(a, b)? {
+                    Ordering::Equal => {}
+                    unequal => return Some(unequal),
+                }
+            }
+            Some(a.len().cmp(&b.len()))
+        }
+        (JsonValue::Object(a), JsonValue::Object(b)) => match a.len().cmp(&b.len()) {
+            Ordering::Equal => {
+                let mut a: Vec<_> = a.iter().collect();
+                let mut b: Vec<_> = b.iter().collect();
+                a.sort_by_key(|(key, _)| *key);
+                b.sort_by_key(|(key, _)| *key);
+                for ((a_key, a), (b_key, b)) in a.into_iter().zip(b) {
+                    match a_key.cmp(b_key).then(compare_json(a, b)?) {
+                        Ordering::Equal => {}
+                        unequal => return Some(unequal),
+                    }
+                }
+                Some(Ordering::Equal)
+            }
+            unequal => Some(unequal),
+        },
+        _ => Some(rank(a).cmp(&rank(b))),
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            // Debug formatting keeps the decimal point on whole floats, e.g. 2.0
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?} {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Json(json) => write!(f, "{}", json),
+            Value::Lambda(lambda) => write!(f, "{}", lambda.source),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -177,6 +930,8 @@ pub enum Error {
     EvalError(String),
     HttpError(String),
     JsonError(String),
+    ArityError(String),
+    TypeError(String),
 }
 
 impl std::fmt::Display for Error {
@@ -186,24 +941,74 @@ impl std::fmt::Display for Error {
             Error::EvalError(msg) => write!(f, "Evaluation Error: {}", msg),
             Error::HttpError(msg) => write!(f, "HTTP Error: {}", msg),
             Error::JsonError(msg) => write!(f, "JSON Error: {}", msg),
+            Error::ArityError(msg) => write!(f, "Arity Error: {}", msg),
+            Error::TypeError(msg) => write!(f, "Type Error: {}", msg),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    // Where in its source a parse error is, as line and column counting from 1
+    pub fn location(&self) -> Option<(usize, usize)> {
+        let Error::ParseError(message) = self else {
+            return None;
+        };
+        let (line, rest) = message.strip_prefix("line ")?.split_once(", column ")?;
+        let (column, _) = rest.split_once(':')?;
+        Some((line.parse().ok()?, column.parse().ok()?))
+    }
+}
+
+// An error under the line of `source` it is about, with a caret at its column, the way
+// rustc shows them. Errors with no location are shown on their own.
+pub fn format_error_with_context(error: &Error, source: &str) -> String {
+    match error.location() {
+        Some((line, column)) => show_in_source(source, line, column, &error.to_string()),
+        None => error.to_string(),
+    }
+}
+
+// `message` below line `line` of `source` with a caret under `column`
+fn show_in_source(source: &str, line: usize, column: usize, message: &str) -> String {
+    // Split on newlines rather than lines() so an error at the very end still has a line
+    let Some(text) = source.split('\n').nth(line.saturating_sub(1)).map(|text| text.trim_end_matches('\r')) else {
+        return message.to_string();
+    };
+    // Tabs are kept so the caret lines up however wide they are shown
+    let indent: String = text.chars().take(column.saturating_sub(1)).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let gutter = " ".repeat(line.to_string().len());
+    format!("{gutter} |\n{line} | {text}\n{gutter} | {indent}^\n{message}")
+}
+
 // Environment for lexical scoping
 #[derive(Debug, Clone)]
 pub struct Env<'parent> {
     bindings: HashMap<String, NodeId>,
+    // Function arguments, which are values rather than nodes
+    values: HashMap<String, Value>,
     parent: Option<&'parent Env<'parent>>,
 }
 
+// What a name resolves to: the node defining it, or a function argument
+pub enum Binding {
+    Node(NodeId),
+    Value(Value),
+}
+
+impl Default for Env<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'parent> Env<'parent> {
     // Create a new empty environment
     pub fn new() -> Self {
         Self {
             bindings: HashMap::new(),
+            values: HashMap::new(),
             parent: None,
         }
     }
@@ -212,28 +1017,63 @@ impl<'parent> Env<'parent> {
     pub fn with_parent(parent: &'parent Env<'parent>) -> Self {
         Self {
             bindings: HashMap::new(),
+            values: HashMap::new(),
             parent: Some(parent),
         }
     }
     
     // Resolve a symbol to its defining NodeId
     pub fn resolve(&self, name: &str) -> Option<NodeId> {
-        if let Some(node_id) = self.bindings.get(name) {
-            Some(*node_id)
+        match self.lookup(name)? {
+            Binding::Node(node_id) => Some(node_id),
+            Binding::Value(_) => None,
+        }
+    }
+    
+    // Resolve a symbol to its defining node or argument value, innermost scope first
+    pub fn lookup(&self, name: &str) -> Option<Binding> {
+        if let Some(value) = self.values.get(name) {
+            Some(Binding::Value(value.clone()))
+        } else if let Some(node_id) = self.bindings.get(name) {
+            Some(Binding::Node(*node_id))
         } else if let Some(parent) = self.parent {
-            parent.resolve(name)
+            parent.lookup(name)
         } else {
             None
         }
     }
     
+    // Bind a function argument
+    pub fn bind_value(&mut self, name: &str, value: Value) {
+        self.bindings.remove(name);
+        self.values.insert(name.to_string(), value);
+    }
+    
+    // Every visible binding flattened into one scope, for a closure to capture
+    fn captured(&self) -> (HashMap<String, NodeId>, HashMap<String, Value>) {
+        let (mut bindings, mut values) = match self.parent {
+            Some(parent) => parent.captured(),
+            None => (HashMap::new(), HashMap::new()),
+        };
+        for (name, node_id) in &self.bindings {
+            values.remove(name);
+            bindings.insert(name.clone(), *node_id);
+        }
+        for (name, value) in &self.values {
+            bindings.remove(name);
+            values.insert(name.clone(), value.clone());
+        }
+        (bindings, values)
+    }
+    
     // Add or update a binding
     pub fn bind(&mut self, name: &str, node_id: NodeId) {
+        self.values.remove(name);
         self.bindings.insert(name.to_string(), node_id);
     }
     
     // Create a new environment extending this one with new bindings
-    pub fn extend(&self, new_bindings: HashMap<String, NodeId>) -> Env {
+    pub fn extend(&self, new_bindings: HashMap<String, NodeId>) -> Env<'_> {
         let mut env = Env::with_parent(self);
         for (name, node_id) in new_bindings {
             env.bind(&name, node_id);
@@ -242,12 +1082,17 @@ impl<'parent> Env<'parent> {
     }
 }
 
-// Cached evaluation result with timestamp
+// Cached evaluation result with timestamps
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct CachedValue {
     result: Result<Value, Error>,
+    // When the result was last computed
     #[serde(with = "chrono::serde::ts_seconds")]
     timestamp: DateTime<Utc>,
+    // When the result last differed from the one before it. Caches written before
+    // this was tracked load with the epoch.
+    #[serde(default, with = "chrono::serde::ts_seconds")]
+    changed_at: DateTime<Utc>,
 }
 
 // Dependency graph to track relationships between nodes and optimize re-evaluation
@@ -274,6 +1119,11 @@ impl DepDag {
             return;
         }
 
+        // Evaluating a node again, e.g. on every call of a function, records the same edges
+        if self.forward.get(&parent).is_some_and(|children| children.contains(&child)) {
+            return;
+        }
+
         // Check for cycles
         if self.would_create_cycle(parent, child) {
             // Just skip creating this dependency - we could log a warning here
@@ -337,8 +1187,8 @@ impl DepDag {
             // Only process nodes that are in the dependency graph
             if self.forward.contains_key(&node_id) || self.reverse.contains_key(&node_id) {
                 let degree = self.forward
-                    .iter()
-                    .filter_map(|(_, children)| {
+                    .values()
+                    .filter_map(|children| {
                         if children.contains(&node_id) && dirty_nodes.contains(children.first().unwrap()) {
                             Some(1)
                         } else {
@@ -389,6 +1239,29 @@ impl DepDag {
     }
 }
 
+// Results that several evaluators can reuse, such as one per watched file. Only
+// results that depend on nothing but their code are kept here (see `is_shareable`).
+#[derive(Debug, Default)]
+pub struct SharedNodeCache {
+    results: HashMap<NodeId, Result<Value, Error>>,
+}
+
+// Whether a node's result depends only on its code, so another file's evaluator may
+// reuse it: it names no definitions, makes no functions and has no effects
+fn is_shareable(node: &Node) -> bool {
+    match node.kind() {
+        NodeKind::Symbol(_) => false,
+        NodeKind::HttpGet | NodeKind::HttpGetResponse | NodeKind::HttpPostJson | NodeKind::HttpSetCookieJar
+        | NodeKind::HttpClearCookies | NodeKind::Watch | NodeKind::UuidNew | NodeKind::Sleep | NodeKind::Println
+        | NodeKind::NumOverflowMode | NodeKind::Tap | NodeKind::HttpDownload | NodeKind::Lambda | NodeKind::Memoize
+        | NodeKind::MemoDefinition | NodeKind::MemoInvalidate | NodeKind::HttpMock | NodeKind::HttpUnmock => false,
+        // In a call the function is looked up like any other name
+        NodeKind::List | NodeKind::Vector => node.children().iter().all(|child| is_shareable(child)),
+        // Other forms start with their keyword
+        _ => node.children().iter().skip(1).all(|child| is_shareable(child)),
+    }
+}
+
 // The unified evaluation cache
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct EvaluationCache {
@@ -396,22 +1269,30 @@ pub struct EvaluationCache {
             deserialize_with = "node_id_map_serde::deserialize_cached_values_map")]
     cache: HashMap<NodeId, CachedValue>,
     
+    // Nodes whose value changed in the current evaluation cycle
     #[serde(skip)]
     changed_nodes: HashSet<NodeId>,
     
     #[serde(skip)]
     all_nodes: HashMap<NodeId, Rc<Node>>,
+    
+    // Where shareable results are published and looked up, if other caches take part
+    #[serde(skip)]
+    shared: Option<Arc<Mutex<SharedNodeCache>>>,
+    
+    // Values of memo-def definitions by name, kept until invalidated
+    #[serde(default)]
+    memos: HashMap<String, Value>,
 }
 
 // Serde helper module for NodeId maps
 mod node_id_map_serde {
     use serde::{
         de::Error as SerdeError, ser::SerializeMap, Deserializer, Serializer,
-        Serialize, Deserialize
+        Deserialize
     };
     use std::collections::HashMap;
-    use super::{NodeId, Value, Error as EvalError, CachedValue};
-    use hex;
+    use super::{NodeId, CachedValue};
     
     // For HashMap<NodeId, CachedValue>
     pub fn serialize_cached_values_map<S>(
@@ -452,9 +1333,28 @@ impl EvaluationCache {
             cache: HashMap::new(),
             changed_nodes: HashSet::new(),
             all_nodes: HashMap::new(),
+            shared: None,
+            memos: HashMap::new(),
+        }
+    }
+    
+    // Publish shareable results to `backend`, and take results for new nodes from it
+    pub fn with_shared_backend(self, backend: Arc<Mutex<SharedNodeCache>>) -> Self {
+        Self {
+            shared: Some(backend),
+            ..self
         }
     }
     
+    // How many nodes have a cached result
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+    
     // Get cached value for a node
     pub fn get(&self, id: &NodeId) -> Option<&Result<Value, Error>> {
         self.cache.get(id).map(|cached| &cached.result)
@@ -462,22 +1362,47 @@ impl EvaluationCache {
     
     // Insert a new evaluation result
     pub fn insert(&mut self, id: NodeId, result: Result<Value, Error>) {
+        if let Some(backend) = &self.shared {
+            if self.all_nodes.get(&id).is_some_and(|node| is_shareable(node)) {
+                if let Ok(mut backend) = backend.lock() {
+                    backend.results.insert(id, result.clone());
+                }
+            }
+        }
+        self.record(id, result);
+    }
+    
+    // Keep a result, noting whether it differs from the one before
+    fn record(&mut self, id: NodeId, result: Result<Value, Error>) {
         let is_changed = match self.cache.get(&id) {
             Some(old_cached) => {
                 let old_str = format!("{:?}", old_cached.result);
                 let new_str = format!("{:?}", &result);
-                old_str != new_str
+                // Functions display as their source, which stays the same when what they
+                // closed over changes
+                let closure_changed = matches!(
+                    (&old_cached.result, &result),
+                    (Ok(Value::Lambda(old)), Ok(Value::Lambda(new))) if old != new
+                );
+                old_str != new_str || closure_changed
             },
             None => true // New node
         };
         
+        let now = chrono::Utc::now();
+        let changed_at = match self.cache.get(&id) {
+            Some(old_cached) if !is_changed => old_cached.changed_at,
+            _ => now,
+        };
+        
         if is_changed {
             self.changed_nodes.insert(id);
         }
         
         self.cache.insert(id, CachedValue {
             result,
-            timestamp: chrono::Utc::now(),
+            timestamp: now,
+            changed_at,
         });
     }
     
@@ -486,19 +1411,84 @@ impl EvaluationCache {
         self.changed_nodes.contains(id)
     }
     
-    // Store a node in the all_nodes map
-    pub fn store_node(&mut self, node: Rc<Node>) {
-        self.all_nodes.insert(*node.id(), node);
+    // Nodes whose value changed strictly after `cutoff`, across evaluation cycles
+    pub fn changed_since(&self, cutoff: DateTime<Utc>) -> Vec<NodeId> {
+        self.cache
+            .iter()
+            .filter(|(_, cached)| cached.changed_at > cutoff)
+            .map(|(id, _)| *id)
+            .collect()
     }
     
-    // Get a node by ID
-    pub fn get_node(&self, id: &NodeId) -> Option<&Rc<Node>> {
-        self.all_nodes.get(id)
+    // When a node's value last changed
+    pub fn last_changed(&self, id: &NodeId) -> Option<DateTime<Utc>> {
+        self.cache.get(id).map(|cached| cached.changed_at)
     }
     
-    // Clear the changed_nodes set to prepare for a new evaluation cycle
-    pub fn prepare_for_evaluation(&mut self) {
-        self.changed_nodes.clear();
+    // Store a node in the all_nodes map, with the shared backend's result for it if
+    // this cache has none
+    pub fn store_node(&mut self, node: Rc<Node>) {
+        let id = *node.id();
+        if let Some(backend) = &self.shared {
+            if !self.cache.contains_key(&id) && is_shareable(&node) {
+                let shared = backend.lock().ok().and_then(|backend| backend.results.get(&id).cloned());
+                if let Some(result) = shared {
+                    self.record(id, result);
+                }
+            }
+        }
+        self.all_nodes.insert(id, node);
+    }
+    
+    // Results of this cache's nodes that don't depend on anything but their code, for
+    // other caches to import
+    #[allow(dead_code)] // For sharing results without a shared backend
+    pub fn export_shared(&self) -> HashMap<NodeId, Result<Value, Error>> {
+        self.cache
+            .iter()
+            .filter(|(id, _)| self.all_nodes.get(*id).is_some_and(|node| is_shareable(node)))
+            .map(|(id, cached)| (*id, cached.result.clone()))
+            .collect()
+    }
+    
+    // Take in results exported by another cache, keeping any this cache already has and
+    // leaving out nodes it knows can't be shared
+    #[allow(dead_code)] // For sharing results without a shared backend
+    pub fn import_shared(&mut self, shared: &HashMap<NodeId, Result<Value, Error>>) {
+        for (id, result) in shared {
+            if !self.cache.contains_key(id) && self.all_nodes.get(id).is_none_or(|node| is_shareable(node)) {
+                self.record(*id, result.clone());
+            }
+        }
+    }
+    
+    // The value memo-def keeps for `name`
+    pub fn memo(&self, name: &str) -> Option<&Value> {
+        self.memos.get(name)
+    }
+    
+    pub fn set_memo(&mut self, name: &str, value: Value) {
+        self.memos.insert(name.to_string(), value);
+    }
+    
+    // Forget the value memo-def keeps for `name`, returning whether there was one
+    pub fn invalidate_memo(&mut self, name: &str) -> bool {
+        self.memos.remove(name).is_some()
+    }
+    
+    // Get a node by ID
+    pub fn get_node(&self, id: &NodeId) -> Option<&Rc<Node>> {
+        self.all_nodes.get(id)
+    }
+    
+    // Start a new evaluation cycle, so only values that change from now on count as changed
+    pub fn prepare_for_evaluation(&mut self) {
+        self.changed_nodes.clear();
+    }
+    
+    // Drop the result of one node, so the next evaluation computes it again
+    pub fn forget(&mut self, id: &NodeId) {
+        self.cache.remove(id);
     }
     
     // Save cache to file
@@ -508,32 +1498,116 @@ impl EvaluationCache {
         Ok(())
     }
     
+    // Forget every result, keeping the known nodes (such as the prelude's)
+    fn clear_results(&mut self) {
+        self.cache.clear();
+        self.changed_nodes.clear();
+        self.memos.clear();
+    }
+    
     // Load cache from file
     pub fn load_from_file(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         if !path.exists() {
-            *self = EvaluationCache::default();
+            self.clear_results();
             return Ok(());
         }
         
         let json_str = fs::read_to_string(path)?;
         if json_str.trim().is_empty() {
-            *self = EvaluationCache::default();
+            self.clear_results();
             return Ok(());
         }
         
         match serde_json::from_str::<EvaluationCache>(&json_str) {
             Ok(loaded_cache) => {
                 self.cache = loaded_cache.cache;
-                // Ensure transient fields are correctly initialized after load
-                self.changed_nodes = HashSet::new();
+                self.memos = loaded_cache.memos;
             },
             Err(e) => {
-                eprintln!("Failed to load evaluation cache, reinitializing: {}", e);
-                *self = EvaluationCache::default();
+                tracing::warn!(cache = %path.display(), "Failed to load evaluation cache, reinitializing: {}", e);
+                self.clear_results();
             }
         }
         Ok(())
     }
+    
+    // Drop results computed more than `max_age` ago, returning how many were dropped
+    pub fn purge_stale(&mut self, max_age: std::time::Duration) -> usize {
+        let Ok(max_age) = chrono::Duration::from_std(max_age) else {
+            return 0;
+        };
+        let cutoff = Utc::now() - max_age;
+        let before = self.cache.len();
+        self.cache.retain(|_, cached| cached.timestamp >= cutoff);
+        before - self.cache.len()
+    }
+    
+    // Forget every node that can't be reached from `roots` through node children, with
+    // its result. Returns how many results were dropped.
+    pub fn prune_unreachable(&mut self, roots: &[NodeId]) -> usize {
+        let mut reachable: HashSet<NodeId> = roots.iter().copied().collect();
+        for root in roots {
+            if let Some(node) = self.all_nodes.get(root) {
+                reachable.extend(collect_all_nodes(node).map(|node| *node.id()));
+            }
+        }
+        
+        let before = self.cache.len();
+        self.cache.retain(|id, _| reachable.contains(id));
+        self.all_nodes.retain(|id, _| reachable.contains(id));
+        self.changed_nodes.retain(|id| reachable.contains(id));
+        before - self.cache.len()
+    }
+    
+    // Take in results from another cache; where both have a node, the newer result wins.
+    // Returns how many results were added or replaced.
+    pub fn merge(&mut self, other: &EvaluationCache) -> usize {
+        let mut taken = 0;
+        for (id, cached) in &other.cache {
+            match self.cache.get(id) {
+                Some(ours) if ours.timestamp >= cached.timestamp => {}
+                _ => {
+                    self.cache.insert(*id, cached.clone());
+                    taken += 1;
+                }
+            }
+        }
+        taken
+    }
+    
+    // Drop results older than `ttl`, then the oldest results beyond `max_entries`. A
+    // limit of 0 keeps nothing.
+    pub fn enforce_limits(&mut self, max_entries: Option<usize>, ttl: std::time::Duration) {
+        self.purge_stale(ttl);
+        if max_entries == Some(0) {
+            self.cache.clear();
+            return;
+        }
+        if let Some(max_entries) = max_entries {
+            if self.cache.len() > max_entries {
+                let mut timestamps: Vec<DateTime<Utc>> = self.cache.values().map(|cached| cached.timestamp).collect();
+                timestamps.sort_unstable_by(|a, b| b.cmp(a));
+                let cutoff = timestamps[max_entries - 1];
+                self.cache.retain(|_, cached| cached.timestamp >= cutoff);
+                // Entries sharing the cutoff timestamp can still leave too many
+                let excess = self.cache.len().saturating_sub(max_entries);
+                let extra: Vec<NodeId> = self.cache.iter().filter(|(_, cached)| cached.timestamp == cutoff).map(|(id, _)| *id).take(excess).collect();
+                for id in extra {
+                    self.cache.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+// Each node's cached result, in no particular order
+impl<'a> IntoIterator for &'a EvaluationCache {
+    type Item = (&'a NodeId, &'a Result<Value, Error>);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+    
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.cache.iter().map(|(id, cached)| (id, &cached.result)))
+    }
 }
 
 impl From<reqwest::Error> for Error {
@@ -550,26 +1624,528 @@ impl From<serde_json::Error> for Error {
 
 type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
 
+// What built-in functions get access to while they run
+pub type EvalContext = Evaluator;
+
+/// A function callable from garden code, registered from Rust with
+/// `Evaluator::register_builtin`. Arguments are evaluated before `eval` is called.
+/// The example below is also compiled and run as a test in tests.rs.
+///
+/// ```
+/// use futures::future::BoxFuture;
+///
+/// struct Greet;
+///
+/// impl BuiltinFn for Greet {
+///     fn name(&self) -> &str {
+///         "greet"
+///     }
+///
+///     fn eval(&self, args: Vec<Value>, _context: &mut EvalContext) -> BoxFuture<'_, Result<Value, Error>> {
+///         Box::pin(async move {
+///             match args.as_slice() {
+///                 [Value::String(name)] => Ok(Value::String(format!("Hello, {}!", name))),
+///                 _ => Err(Error::EvalError("'greet' expects 1 string argument".to_string())),
+///             }
+///         })
+///     }
+/// }
+///
+/// let mut evaluator = Evaluator::new();
+/// evaluator.register_builtin(Box::new(Greet));
+/// // (greet "garden") now evaluates to "Hello, garden!"
+/// ```
+pub trait BuiltinFn: Send + Sync {
+    fn name(&self) -> &str;
+    fn eval(&self, args: Vec<Value>, context: &mut EvalContext) -> BoxFuture<'_, Result<Value, Error>>;
+}
+
+// Time spent evaluating each node during one evaluation, for --benchmark. A node's
+// time includes its children's, and nodes evaluated more than once (such as function
+// bodies) add up. Results served from the cache aren't counted.
+#[derive(Debug, Default)]
+pub struct EvalTimings {
+    durations: HashMap<NodeId, Duration>,
+    // Nodes being evaluated, so recursive calls aren't counted twice
+    running: HashSet<NodeId>,
+    // Evaluations that got past the cache so far
+    evaluations: u64,
+}
+
+impl EvalTimings {
+    // Start timing `id`, unless an outer evaluation of it already is
+    fn start(&mut self, id: NodeId) -> Option<Instant> {
+        self.running.insert(id).then(Instant::now)
+    }
+    
+    // Stop timing `id`, adding the time if it was evaluated rather than served from the cache
+    fn finish(&mut self, id: NodeId, started: Option<Instant>, evaluated: bool) {
+        if let Some(started) = started {
+            self.running.remove(&id);
+            if evaluated {
+                *self.durations.entry(id).or_default() += started.elapsed();
+            }
+        }
+    }
+    
+    pub fn get(&self, id: &NodeId) -> Option<Duration> {
+        self.durations.get(id).copied()
+    }
+    
+    // Every timed node, slowest first
+    pub fn slowest(&self) -> Vec<(NodeId, Duration)> {
+        let mut timings: Vec<(NodeId, Duration)> = self.durations.iter().map(|(id, elapsed)| (*id, *elapsed)).collect();
+        timings.sort_by_key(|&(_, elapsed)| std::cmp::Reverse(elapsed));
+        timings
+    }
+}
+
+// What + - * / % do when a result doesn't fit in an i64, set by (num.overflow-mode ...)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum OverflowMode {
+    #[default]
+    Error,
+    Saturating,
+    Wrapping,
+}
+
+impl OverflowMode {
+    fn parse(name: &str) -> Option<OverflowMode> {
+        match name {
+            "error" => Some(OverflowMode::Error),
+            "saturating" => Some(OverflowMode::Saturating),
+            "wrapping" => Some(OverflowMode::Wrapping),
+            _ => None,
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            OverflowMode::Error => "error",
+            OverflowMode::Saturating => "saturating",
+            OverflowMode::Wrapping => "wrapping",
+        }
+    }
+    
+    // Apply the operator `op` to a and b. Dividing by zero is an error in every mode.
+    fn apply(self, op: char, a: i64, b: i64) -> Result<i64, Error> {
+        if matches!(op, '/' | '%') && b == 0 {
+            return Err(Error::EvalError("division by zero".to_string()));
+        }
+        let (checked, saturating, wrapping) = match op {
+            '+' => (a.checked_add(b), a.saturating_add(b), a.wrapping_add(b)),
+            '-' => (a.checked_sub(b), a.saturating_sub(b), a.wrapping_sub(b)),
+            '*' => (a.checked_mul(b), a.saturating_mul(b), a.wrapping_mul(b)),
+            '/' => (a.checked_div(b), a.saturating_div(b), a.wrapping_div(b)),
+            // Only i64::MIN % -1 overflows, and its remainder is 0 either way
+            _ => (a.checked_rem(b), a.wrapping_rem(b), a.wrapping_rem(b)),
+        };
+        match self {
+            OverflowMode::Error => checked.ok_or_else(|| Error::EvalError(format!("integer overflow in ({} {} {})", op, a, b))),
+            OverflowMode::Saturating => Ok(saturating),
+            OverflowMode::Wrapping => Ok(wrapping),
+        }
+    }
+}
+
+// Sends a modification event for a file, so its watcher re-evaluates it as if it had been saved
+type ChangeEvents = (mpsc::Sender<notify::Result<notify::Event>>, PathBuf);
+
+// How far an http.download has got, sent after every chunk written and once when it ends
+#[derive(Debug, Clone)]
+pub struct DownloadProgress {
+    pub url: String,
+    pub written: u64,
+    // The size the server announced, if it did
+    pub total: Option<u64>,
+    pub finished: bool,
+}
+
+// A URL polled in the background for (watch url interval-secs). Dropping it stops the polling.
+#[derive(Debug)]
+struct UrlWatch {
+    latest: Arc<Mutex<String>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for UrlWatch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// GET `url` with `client`, giving its "status", "headers" and "body". Header names are
+// lowercase, and repeated headers are joined with commas.
+async fn fetch_response(client: reqwest::Client, url: String) -> Result<IndexMap<String, Value>, Error> {
+    let response = client.get(&url).send().await?;
+    let status = Value::Number(response.status().as_u16() as i64);
+    let mut headers = IndexMap::new();
+    for (name, value) in response.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        headers
+            .entry(name.to_string())
+            .and_modify(|joined| {
+                if let Value::String(joined) = joined {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                }
+            })
+            .or_insert_with(|| Value::String(value.clone()));
+    }
+    let body = Value::String(response.text().await?);
+    Ok(IndexMap::from([
+        ("status".to_string(), status),
+        ("headers".to_string(), Value::Map(headers)),
+        ("body".to_string(), body),
+    ]))
+}
+
+// Fetch `url` every `interval`, keeping the latest body and reporting each change
+async fn poll_url(
+    client: reqwest::Client,
+    url: String,
+    interval: Duration,
+    latest: Arc<Mutex<String>>,
+    changes: Option<ChangeEvents>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick is immediate, and the first fetch was made when the watch started
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let body = match client.get(&url).send().await {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        };
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!(url = %url, "Could not poll watched URL: {}", e);
+                continue;
+            }
+        };
+        let changed = {
+            let mut latest = latest.lock().unwrap();
+            let changed = *latest != body;
+            *latest = body;
+            changed
+        };
+        if let (true, Some((tx, path))) = (changed, &changes) {
+            let event = notify::Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any)).add_path(path.clone());
+            if tx.send(Ok(event)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Registered built-ins by name. Rc so one can be called while the evaluator is borrowed.
+#[derive(Default, Clone)]
+struct Builtins(HashMap<String, Rc<dyn BuiltinFn>>);
+
+impl std::fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
 // The evaluator/runtime that manages evaluation of the node tree
 #[derive(Debug)]
 pub struct Evaluator {
     cache: EvaluationCache,
     depdag: DepDag,
     dirty_nodes: HashSet<NodeId>,
+    config: GardenConfig,
+    builtins: Builtins,
+    // Nodes inside function bodies, which depend on their arguments and so are never cached
+    function_nodes: HashSet<NodeId>,
+    // Arithmetic after a num.overflow-mode and requests after an http.mock in this
+    // evaluation. The same node can run under two modes or mocks, so these aren't cached
+    // either, until the source stops setting them.
+    mode_dependent_nodes: HashSet<NodeId>,
+    // Nodes whose result differs on every evaluation or that are run for their effect,
+    // like (uuid.new) and (sleep ms), and the nodes containing them. Their results only
+    // last for one evaluation.
+    volatile_nodes: HashSet<NodeId>,
+    // The definition each symbol node last resolved to, to tell when a cached result
+    // refers to a definition that has since been replaced
+    resolutions: HashMap<NodeId, NodeId>,
+    // Results computed or confirmed up to date during this evaluation
+    fresh_nodes: HashSet<NodeId>,
+    // Variables each top-level while loop redefined, with their final values
+    loop_assignments: HashMap<NodeId, IndexMap<String, Value>>,
+    // The (def name value) branch each top-level if, when or unless last took, by if node
+    branch_definitions: HashMap<NodeId, (String, Rc<Node>)>,
+    // Every top-level definition by name, so functions can call ones defined after
+    // them, including themselves, and definitions can use ones that come later
+    globals: HashMap<String, NodeId>,
+    // Definitions being evaluated because a name referred to them, to catch a definition
+    // that needs its own value
+    resolving: HashSet<NodeId>,
+    // Results of memoized functions, by memoize node and then by arguments, with when
+    // each was computed. Tokio's clock, so tests can move it forward.
+    memo_tables: HashMap<NodeId, HashMap<String, (Value, tokio::time::Instant)>>,
+    // Definitions from prelude.expr by name, used when nothing else defines a name
+    prelude: IndexMap<String, NodeId>,
+    // Every node of the prelude, which is left out of reports of what changed
+    prelude_nodes: HashSet<NodeId>,
+    // Compiled regex.* patterns, so each pattern string is only compiled once
+    regexes: HashMap<String, Regex>,
+    // Compiled json.validate schemas, by the hash of the schema string
+    schemas: HashMap<String, jsonschema::JSONSchema>,
+    // Per-node evaluation times, when benchmarking
+    timings: Option<EvalTimings>,
+    // Built on the first HTTP request and shared by the rest, which reuse its connections
+    http_client: Option<reqwest::Client>,
+    // Clients with their own cookie jars, by the name given to http.set-cookie-jar
+    cookie_jars: HashMap<String, reqwest::Client>,
+    // The jar later requests in this evaluation send and store cookies with
+    cookie_jar: Option<String>,
+    // Whether a cookie jar is set up in the source, which makes the requests after it
+    // depend on session state
+    uses_cookie_jars: bool,
+    // Bodies http.mock gave for URLs in this evaluation, returned instead of fetching them
+    http_mocks: HashMap<String, String>,
+    // Whether the source mocks URLs, which makes the requests after it depend on the mocks
+    uses_mocks: bool,
+    // Integer overflow handling for arithmetic after a (num.overflow-mode ...) in this evaluation
+    overflow_mode: OverflowMode,
+    // Whether the source sets an overflow mode, which makes the arithmetic after it depend on it
+    sets_overflow_mode: bool,
+    // URLs being polled for (watch ...) expressions, by watch node
+    watches: HashMap<NodeId, UrlWatch>,
+    // Where polls announce a changed response, if anything re-evaluates on file changes
+    change_events: Option<ChangeEvents>,
+    // Responses fetched ahead for (parallel ...), by URL, until the GET that wants them
+    prefetched: HashMap<String, Result<IndexMap<String, Value>, Error>>,
+    // Lines printed by println, when they are collected instead of written to stdout
+    output: Option<Vec<String>>,
+    // Where http.download reports how far along it is, if anything shows it
+    download_progress: Option<mpsc::Sender<DownloadProgress>>,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Evaluator {
     pub fn new() -> Self {
-        Self {
+        let mut evaluator = Self {
             cache: EvaluationCache::new(),
             depdag: DepDag::new(),
             dirty_nodes: HashSet::new(),
+            config: GardenConfig::default(),
+            builtins: Builtins::default(),
+            function_nodes: HashSet::new(),
+            mode_dependent_nodes: HashSet::new(),
+            volatile_nodes: HashSet::new(),
+            resolutions: HashMap::new(),
+            fresh_nodes: HashSet::new(),
+            timings: None,
+            loop_assignments: HashMap::new(),
+            branch_definitions: HashMap::new(),
+            globals: HashMap::new(),
+            resolving: HashSet::new(),
+            memo_tables: HashMap::new(),
+            prelude: IndexMap::new(),
+            prelude_nodes: HashSet::new(),
+            regexes: HashMap::new(),
+            schemas: HashMap::new(),
+            http_client: None,
+            cookie_jars: HashMap::new(),
+            cookie_jar: None,
+            uses_cookie_jars: false,
+            http_mocks: HashMap::new(),
+            uses_mocks: false,
+            overflow_mode: OverflowMode::Error,
+            sets_overflow_mode: false,
+            watches: HashMap::new(),
+            change_events: None,
+            prefetched: HashMap::new(),
+            output: None,
+            download_progress: None,
+        };
+        evaluator.load_prelude();
+        evaluator
+    }
+    
+    // Make the prelude's definitions available. They are evaluated when first used.
+    fn load_prelude(&mut self) {
+        let nodes = parser::parse(PRELUDE).expect("prelude.expr should parse");
+        for node in nodes {
+            self.prelude_nodes.extend(collect_all_nodes(&node).map(|node| *node.id()));
+            if let (NodeKind::LetStatement, Some(NodeKind::Symbol(name))) =
+                (node.kind(), node.children().get(1).map(|child| child.kind()))
+            {
+                self.prelude.insert(name.clone(), *node.children()[2].id());
+            }
+            self.store_node(node);
+        }
+    }
+    
+    // Evaluator using the HTTP, sandbox and cache settings from `config`
+    pub fn with_config(config: GardenConfig) -> Self {
+        Self {
+            config,
+            ..Self::new()
+        }
+    }
+    
+    // The shared HTTP client for a request to `url`, unless the sandbox forbids HTTP
+    fn http_client(&mut self, url: &str) -> Result<reqwest::Client, Error> {
+        if !self.config.sandbox.allow_http {
+            return Err(Error::HttpError(format!(
+                "HTTP is disabled by --sandbox or [sandbox] allow_http in .garden.toml, refusing to fetch {}",
+                url
+            )));
+        }
+        if let Some(client) = self.cookie_jar.as_ref().and_then(|name| self.cookie_jars.get(name)) {
+            return Ok(client.clone());
+        }
+        if let Some(client) = &self.http_client {
+            return Ok(client.clone());
+        }
+        let client = self.http_client_builder().build()?;
+        self.http_client = Some(client.clone());
+        Ok(client)
+    }
+    
+    fn http_client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder();
+        match self.config.http_timeout() {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        }
+    }
+    
+    // GET `url`, giving its "status", "headers" and "body". A mocked URL gives status 200,
+    // no headers and the mocked body.
+    async fn http_get_response(&mut self, url: &str) -> Result<IndexMap<String, Value>, Error> {
+        if let Some(body) = self.http_mocks.get(url) {
+            return Ok(IndexMap::from([
+                ("status".to_string(), Value::Number(200)),
+                ("headers".to_string(), Value::Map(IndexMap::new())),
+                ("body".to_string(), Value::String(body.clone())),
+            ]));
+        }
+        if let Some(response) = self.prefetched.remove(url) {
+            return response;
+        }
+        fetch_response(self.http_client(url)?, url.to_string()).await
+    }
+    
+    // Start the GET requests that the direct children of a (parallel ...) node will make,
+    // all at once, so that evaluating the children one by one finds their responses waiting
+    async fn prefetch_http_gets(&mut self, node: &Rc<Node>, env: &Env<'_>) {
+        let mut requests = Vec::new();
+        for child in node.children().iter().skip(1) {
+            if !matches!(child.kind(), NodeKind::HttpGet | NodeKind::HttpGetResponse)
+                || !matches!(child.children(), [_, url] if *url.kind() != NodeKind::Spread)
+            {
+                continue;
+            }
+            // A fresh cached response won't be fetched again
+            let cached = self.is_cacheable(child.id()) && self.cache.get(child.id()).is_some();
+            if cached && !self.is_stale(child, env) {
+                continue;
+            }
+            // Errors in the URL are left for the child's own evaluation to report
+            if let Ok(Value::String(url)) = self.eval_node(&child.children()[1], env).await {
+                if self.http_mocks.contains_key(&url) {
+                    continue;
+                }
+                if let Ok(client) = self.http_client(&url) {
+                    requests.push(async move {
+                        let response = fetch_response(client, url.clone()).await;
+                        (url, response)
+                    });
+                }
+            }
+        }
+        self.prefetched.extend(futures::future::join_all(requests).await);
+    }
+    
+    // Make a Rust function callable from garden code under its name, replacing any
+    // built-in registered under the same name
+    #[allow(dead_code)] // For embedders; the garden binary registers none itself
+    pub fn register_builtin(&mut self, builtin: Box<dyn BuiltinFn>) {
+        self.builtins.0.insert(builtin.name().to_string(), Rc::from(builtin));
+    }
+    
+    // Send the progress of every http.download to `tx`
+    pub fn report_downloads_to(&mut self, tx: mpsc::Sender<DownloadProgress>) {
+        self.download_progress = Some(tx);
+    }
+    
+    // Stream the body of `url` into the file at `path`, returning how many bytes were written
+    async fn download(&mut self, url: &str, path: &str) -> Result<u64, Error> {
+        if !self.config.sandbox.allow_file {
+            return Err(Error::EvalError(format!(
+                "File writes are disabled by --sandbox or [sandbox] allow_file in .garden.toml, refusing to write {}",
+                path
+            )));
+        }
+        let mut response = self.http_client(url)?.get(url).send().await?.error_for_status()?;
+        let total = response.content_length();
+        let write_error = |e: std::io::Error| Error::EvalError(format!("'http.download' could not write {}: {}", path, e));
+        let mut file = tokio::fs::File::create(path).await.map_err(write_error)?;
+        
+        let mut progress = DownloadProgress { url: url.to_string(), written: 0, total, finished: false };
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await.map_err(write_error)?;
+            progress.written += chunk.len() as u64;
+            if let Some(tx) = &self.download_progress {
+                let _ = tx.send(progress.clone());
+            }
+        }
+        file.flush().await.map_err(write_error)?;
+        
+        progress.finished = true;
+        if let Some(tx) = &self.download_progress {
+            let _ = tx.send(progress.clone());
         }
+        Ok(progress.written)
+    }
+    
+    // Collect what println prints from now on, for take_output, instead of writing it to stdout
+    pub fn capture_output(&mut self) {
+        self.output.get_or_insert_with(Vec::new);
+    }
+    
+    // Each line printed since the last call, newline included
+    pub fn take_output(&mut self) -> Vec<String> {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+    
+    // Reuse results other evaluators on `backend` computed for identical pure
+    // expressions, and offer them this evaluator's
+    pub fn share_results(&mut self, backend: Arc<Mutex<SharedNodeCache>>) {
+        self.cache = std::mem::take(&mut self.cache).with_shared_backend(backend);
+    }
+    
+    // Forget the value memo-def keeps for `name`, returning whether there was one
+    pub fn invalidate_memo(&mut self, name: &str) -> bool {
+        self.cache.invalidate_memo(name)
     }
     
     // Load cache from file
     pub fn load_cache(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-        self.cache.load_from_file(path)
+        self.cache.load_from_file(path)?;
+        self.cache.enforce_limits(self.config.cache.max_entries, self.config.cache_ttl());
+        Ok(())
+    }
+    
+    // Drop cached results of expressions no longer in the source, keeping the prelude's.
+    // Returns how many were dropped.
+    pub fn prune_unreachable(&mut self, root_nodes: &[Rc<Node>]) -> usize {
+        let roots: Vec<NodeId> = root_nodes
+            .iter()
+            .map(|node| *node.id())
+            .chain(self.prelude_nodes.iter().copied())
+            .collect();
+        self.cache.prune_unreachable(&roots)
     }
     
     // Save cache to file
@@ -580,17 +2156,74 @@ impl Evaluator {
     // Store a node in the cache
     pub fn store_node(&mut self, node: Rc<Node>) {
         self.cache.store_node(node.clone());
+        if *node.kind() == NodeKind::Lambda {
+            for child in node.children().iter().skip(2) {
+                self.mark_function_body(child);
+            }
+        }
+        // Loops are run for their effects, so they are re-run on every evaluation
+        if *node.kind() == NodeKind::While {
+            self.mark_function_body(&node);
+        }
         
         // Also store all children recursively
         for child in node.children() {
             self.store_node(child.clone());
         }
+        
+        // A function's body only runs when it is called, so it doesn't make the function volatile
+        if matches!(node.kind(), NodeKind::HttpSetCookieJar) {
+            self.uses_cookie_jars = true;
+        }
+        if matches!(node.kind(), NodeKind::NumOverflowMode) {
+            self.sets_overflow_mode = true;
+        }
+        if matches!(node.kind(), NodeKind::HttpMock | NodeKind::HttpUnmock) {
+            self.uses_mocks = true;
+        }
+        let with_cookies = self.uses_cookie_jars
+            && matches!(node.kind(), NodeKind::HttpGet | NodeKind::HttpGetResponse | NodeKind::HttpPostJson | NodeKind::HttpDownload);
+        let with_mocks = self.uses_mocks
+            && matches!(node.kind(), NodeKind::HttpGet | NodeKind::HttpGetResponse | NodeKind::HttpPostJson);
+        let with_overflow_mode = self.sets_overflow_mode
+            && matches!(node.kind(), NodeKind::Addition | NodeKind::Subtraction | NodeKind::Multiplication | NodeKind::Division | NodeKind::Modulo);
+        if with_overflow_mode || with_mocks {
+            self.mode_dependent_nodes.insert(*node.id());
+        }
+        // A memo-def runs every time to pick up values memo.invalidate forgot, and a
+        // def-once to see whether its name has been defined since
+        let volatile = matches!(node.kind(), NodeKind::UuidNew | NodeKind::Sleep | NodeKind::Tap | NodeKind::HttpSetCookieJar | NodeKind::HttpClearCookies | NodeKind::HttpMock | NodeKind::HttpUnmock | NodeKind::Watch | NodeKind::NumOverflowMode | NodeKind::MemoDefinition | NodeKind::DefinitionOnce)
+            || with_cookies
+            || with_mocks
+            || with_overflow_mode
+            || (*node.kind() != NodeKind::Lambda && node.children().iter().any(|child| self.volatile_nodes.contains(child.id())));
+        // Nodes are stored again on every evaluation, so one stops being volatile when
+        // the source no longer makes it so
+        if volatile {
+            self.volatile_nodes.insert(*node.id());
+        } else {
+            self.volatile_nodes.remove(node.id());
+        }
     }
     
     // Prepare for a new evaluation cycle
     pub fn prepare_for_evaluation(&mut self) {
         self.cache.prepare_for_evaluation();
+        for id in &self.volatile_nodes {
+            self.cache.forget(id);
+        }
+        self.uses_cookie_jars = false;
+        self.http_mocks.clear();
+        self.uses_mocks = false;
+        self.cookie_jar = None;
+        self.sets_overflow_mode = false;
+        self.overflow_mode = OverflowMode::Error;
+        self.mode_dependent_nodes.clear();
         self.dirty_nodes.clear();
+        self.fresh_nodes.clear();
+        if let Some(timings) = &mut self.timings {
+            *timings = EvalTimings::default();
+        }
         self.depdag.clear();
     }
     
@@ -602,61 +2235,470 @@ impl Evaluator {
     // Get a list of all nodes that changed in the last evaluation cycle
     pub fn get_changed_nodes(&self) -> Vec<Rc<Node>> {
         self.cache.changed_nodes.iter()
+            .filter(|id| !self.prelude_nodes.contains(*id))
             .filter_map(|id| self.cache.get_node(id).cloned())
             .collect()
     }
     
-    // Get cached result to avoid borrow issues
-    fn get_cached_result(&self, id: &NodeId) -> Option<Result<Value, Error>> {
-        self.cache.get(id).cloned()
+    // Whether a node's result may be kept for later evaluations
+    fn is_cacheable(&self, id: &NodeId) -> bool {
+        !self.function_nodes.contains(id) && !self.mode_dependent_nodes.contains(id)
     }
     
-    // Get node from cache
-    fn get_node(&self, id: &NodeId) -> Option<Rc<Node>> {
-        self.cache.get_node(id).cloned()
+    // Exclude a function body from caching, as its value depends on the arguments
+    fn mark_function_body(&mut self, node: &Rc<Node>) {
+        self.function_nodes.insert(*node.id());
+        for child in node.children() {
+            self.mark_function_body(child);
+        }
     }
     
-    // Evaluate a node asynchronously
-    pub fn eval_node<'a>(&'a mut self, node: &'a Rc<Node>, env: &'a Env<'a>) -> LocalBoxFuture<'a, Result<Value, Error>> {
+    // Whether the cached result of `node` may be out of date: a symbol in it now refers
+    // to a different definition, or to one whose result changed during this evaluation.
+    // Everything else about a node is part of its hash.
+    fn is_stale(&mut self, node: &Rc<Node>, env: &Env) -> bool {
+        // Also stops the search from going round recursive definitions
+        if !self.fresh_nodes.insert(*node.id()) {
+            return false;
+        }
+        let stale = match node.kind() {
+            NodeKind::Symbol(name) => match env.lookup(name) {
+                // Values bound directly, like loop variables, are looked up every time
+                Some(Binding::Value(_)) => true,
+                binding => {
+                    let current = match binding {
+                        Some(Binding::Node(id)) => Some(id),
+                        _ => self.globals.get(name).or_else(|| self.prelude.get(name)).copied(),
+                    };
+                    match current {
+                        _ if current != self.resolutions.get(node.id()).copied() => true,
+                        Some(id) => self.cache.was_changed(&id)
+                            || self.cache.get(&id).is_none()
+                            || self.get_node(&id).is_none_or(|definition| self.is_stale(&definition, env)),
+                        None => false,
+                    }
+                },
+            },
+            // The name a definition gives isn't a use of that name
+            NodeKind::Definition | NodeKind::LetStatement | NodeKind::MemoDefinition | NodeKind::DefinitionOnce
+                if node.children().len() == 3 =>
+            {
+                self.is_stale(&node.children()[2], env)
+            },
+            _ => node.children().iter().any(|child| self.is_stale(child, env)),
+        };
+        if stale {
+            self.fresh_nodes.remove(node.id());
+        }
+        stale
+    }
+    
+    // Evaluate a `for` body once per combination of the [name list ...] bindings,
+    // collecting the results
+    fn eval_for<'a>(
+        &'a mut self,
+        bindings: &'a [Rc<Node>],
+        body: &'a Rc<Node>,
+        env: &'a Env<'a>,
+        results: &'a mut Vec<Value>,
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
         Box::pin(async move {
-            // Get the node ID for easy reference
-            let node_id = *node.id();
-            
-            // Check if we have a cached value - avoid borrow issues by getting a clone before the mutable borrow
-            if let Some(cached_result) = self.get_cached_result(&node_id) {
-                return cached_result;
+            let [name_node, list_node, rest @ ..] = bindings else {
+                results.push(self.eval_node(body, env).await?);
+                return Ok(());
+            };
+            let NodeKind::Symbol(name) = name_node.kind() else {
+                return Err(Error::EvalError(format!(
+                    "'for' bindings must be symbols, got {}",
+                    name_node.code_snippet()
+                )));
+            };
+            for item in list_items(&self.eval_node(list_node, env).await?, "for")? {
+                let mut item_env = Env::with_parent(env);
+                item_env.bind_value(name, item);
+                self.eval_for(rest, body, &item_env, results).await?;
             }
-            
-            // For symbol nodes, we need to resolve and evaluate the defining node
-            if let NodeKind::Symbol(name) = node.kind() {
-                let result = match env.resolve(name) {
-                    Some(defining_node_id) => {
-                        // Record the dependency between the symbol node and its defining node
-                        self.depdag.add_dependency(node_id, defining_node_id);
-                        
-                        match self.get_node(&defining_node_id) {
-                            Some(defining_node) => self.eval_node(&defining_node, env).await,
-                            None => Err(Error::EvalError(format!("Internal error: Symbol {} resolved to unknown node", name)))
-                        }
-                    },
-                    None => Err(Error::EvalError(format!("Undefined symbol: {}", name)))
+            Ok(())
+        })
+    }
+    
+    // Call a function value with already evaluated arguments
+    async fn call_function(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, Error> {
+        let Value::Lambda(lambda) = function else {
+            return Err(Error::EvalError(format!("Expected a function, got {}", function)));
+        };
+        if args.len() != lambda.params.len() {
+            return Err(Error::EvalError(format!(
+                "{} expects {} arguments, got {}",
+                lambda.source,
+                lambda.params.len(),
+                args.len()
+            )));
+        }
+        let body = self.get_node(&lambda.body).ok_or_else(|| {
+            Error::EvalError(format!("The body of {} is no longer loaded", lambda.source))
+        })?;
+        
+        // Closures over different values share a memoize node, so those are part of the key
+        let memo_key = lambda.memo.map(|memo| {
+            let mut captured: Vec<String> = lambda.values.iter().map(|(name, value)| format!("{}={:?}", name, value)).collect();
+            captured.sort();
+            (memo, format!("{:?} {:?}", captured, args))
+        });
+        if let Some((memo, key)) = &memo_key {
+            if let Some((value, computed_at)) = self.memo_tables.get(memo).and_then(|table| table.get(key)) {
+                if lambda.memo_ttl.is_none_or(|ttl| computed_at.elapsed() < ttl) {
+                    return Ok(value.clone());
+                }
+            }
+        }
+        
+        let mut env = Env::new();
+        env.bindings = lambda.bindings.clone();
+        env.values = lambda.values.clone();
+        for (param, arg) in lambda.params.iter().zip(args) {
+            env.bind_value(param, arg);
+        }
+        let value = self.eval_node(&body, &env).await?;
+        
+        if let Some((memo, key)) = memo_key {
+            self.memo_tables.entry(memo).or_default().insert(key, (value.clone(), tokio::time::Instant::now()));
+        }
+        Ok(value)
+    }
+    
+    // Combine the evaluated arguments of an arithmetic node with `op`, from left to right,
+    // starting at `initial` or else at the first argument. Integers stay integers (with
+    // overflow_mode applied); any float argument promotes the whole computation to floats.
+    fn fold_arithmetic(&self, args: Vec<Value>, op: char, initial: Option<i64>) -> Result<Value, Error> {
+        for arg in &args {
+            if !matches!(arg, Value::Number(_) | Value::Float(_)) {
+                return Err(Error::EvalError(format!("'{}' requires all arguments to be numbers, got {} {}", op, arg.type_name(), arg)));
+            }
+        }
+        if args.iter().any(|arg| matches!(arg, Value::Float(_))) {
+            let mut numbers = args.iter().map(|arg| match arg {
+                Value::Float(f) => *f,
+                Value::Number(n) => *n as f64,
+                _ => unreachable!("arguments were checked above"),
+            });
+            let Some(mut result) = initial.map(|n| n as f64).or_else(|| numbers.next()) else {
+                return Err(Error::EvalError(format!("'{}' expects at least 1 argument", op)));
+            };
+            for n in numbers {
+                result = match op {
+                    '+' => result + n,
+                    '-' => result - n,
+                    '*' => result * n,
+                    '/' | '%' if n == 0.0 => return Err(Error::EvalError("division by zero".to_string())),
+                    '/' => result / n,
+                    _ => result % n,
                 };
-                self.cache.insert(node_id, result.clone());
-                return result;
             }
-            
-            // For other node types, proceed with normal evaluation
-            let result = match node.kind() {
-                NodeKind::Number(n) => {
-                    // Number literal
-                    Ok(Value::Number(*n))
-                },
-                NodeKind::String(s) => {
-                    // String literal
-                    Ok(Value::String(s.clone()))
-                },
-                NodeKind::Definition => {
-                    // Definition (def name value)
+            return Ok(Value::Float(result));
+        }
+        let mut numbers = args.into_iter().map(|arg| match arg {
+            Value::Number(n) => n,
+            _ => unreachable!("arguments were checked above"),
+        });
+        let Some(mut result) = initial.or_else(|| numbers.next()) else {
+            return Err(Error::EvalError(format!("'{}' expects at least 1 argument", op)));
+        };
+        for n in numbers {
+            result = self.overflow_mode.apply(op, result, n)?;
+        }
+        Ok(Value::Number(result))
+    }
+    
+    // Evaluate a call's arguments (every child after the operator) in order, recording
+    // the call's dependency on each. A (spread list) argument adds the list's elements.
+    async fn eval_args(&mut self, node: &Rc<Node>, env: &Env<'_>) -> Result<Vec<Value>, Error> {
+        let mut args = Vec::new();
+        for arg_node in node.children().iter().skip(1) {
+            match (arg_node.kind(), arg_node.children()) {
+                (NodeKind::Spread, [_, list_node]) => {
+                    self.depdag.add_dependency(*node.id(), *list_node.id());
+                    let list = self.eval_node(list_node, env).await?;
+                    args.extend(list_items(&list, "spread")?);
+                }
+                _ => {
+                    self.depdag.add_dependency(*node.id(), *arg_node.id());
+                    args.push(self.eval_node(arg_node, env).await?);
+                }
+            }
+        }
+        Ok(args)
+    }
+    
+    // Collect the named top-level values (def/let statements) in source order
+    pub fn context(&self, root_nodes: &[Rc<Node>]) -> IndexMap<String, Value> {
+        let mut context = IndexMap::new();
+        for node in root_nodes {
+            if !matches!(node.kind(), NodeKind::Definition | NodeKind::LetStatement | NodeKind::MemoDefinition | NodeKind::DefinitionOnce) || node.children().len() < 3 {
+                continue;
+            }
+            if let NodeKind::Symbol(name) = node.children()[1].kind() {
+                if let Some(Ok(value)) = self.cache.get(node.id()) {
+                    context.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        // Variables a loop redefined show their value after the loop, and a def in the
+        // branch an if took shows too
+        for node in root_nodes {
+            if let Some((name, definition)) = self.branch_definitions.get(node.id()) {
+                if let Some(Ok(value)) = self.cache.get(definition.id()) {
+                    context.insert(name.clone(), value.clone());
+                }
+            }
+            if let Some(assignments) = self.loop_assignments.get(node.id()) {
+                context.extend(assignments.clone());
+            }
+        }
+        context
+    }
+    
+    // The compiled form of `pattern`, compiling it on first use
+    fn regex(&mut self, pattern: &str, operation: &str) -> Result<Regex, Error> {
+        if let Some(regex) = self.regexes.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(pattern)
+            .map_err(|e| Error::EvalError(format!("'{}' got an invalid pattern: {}", operation, e)))?;
+        self.regexes.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+    
+    // What is wrong with `instance` according to the JSON Schema in `schema`, compiling
+    // the schema on first use. Empty when the instance conforms.
+    fn schema_violations(&mut self, schema: &str, instance: &JsonValue) -> Result<Vec<String>, Error> {
+        let key = blake3::hash(schema.as_bytes()).to_hex().to_string();
+        if !self.schemas.contains_key(&key) {
+            let parsed: JsonValue = serde_json::from_str(schema)?;
+            let compiled = jsonschema::JSONSchema::compile(&parsed)
+                .map_err(|e| Error::EvalError(format!("'json.validate' got an invalid schema: {}", e)))?;
+            self.schemas.insert(key.clone(), compiled);
+        }
+        Ok(match self.schemas[&key].validate(instance) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .map(|error| match error.instance_path.to_string() {
+                    path if path.is_empty() => error.to_string(),
+                    path => format!("{}: {}", path, error),
+                })
+                .collect(),
+        })
+    }
+    
+    // When a (watch ...) poll sees a new response, send a modification event for `path`
+    // to `tx`, the channel its file watcher reports to
+    pub fn notify_changes_to(&mut self, tx: mpsc::Sender<notify::Result<notify::Event>>, path: PathBuf) {
+        self.change_events = Some((tx, path));
+    }
+    
+    // Stop polling for (watch ...) expressions no longer under `root_nodes`
+    fn stop_removed_watches(&mut self, root_nodes: &[Rc<Node>]) {
+        if self.watches.is_empty() {
+            return;
+        }
+        let ids: HashSet<NodeId> = root_nodes.iter().flat_map(collect_all_nodes).map(|node| *node.id()).collect();
+        self.watches.retain(|id, _| ids.contains(id));
+    }
+    
+    // Time every node evaluation from now on; see timings
+    pub fn enable_timings(&mut self) {
+        self.timings.get_or_insert_with(EvalTimings::default);
+    }
+    
+    // How long each node took in the last evaluation, if timing is enabled
+    pub fn timings(&self) -> Option<&EvalTimings> {
+        self.timings.as_ref()
+    }
+    
+    // Evaluate every prelude definition, so prelude_context can show them all
+    pub async fn evaluate_prelude(&mut self) {
+        let ids: Vec<NodeId> = self.prelude.values().copied().collect();
+        for id in ids {
+            if let Some(node) = self.get_node(&id) {
+                let _ = self.eval_node(&node, &Env::new()).await;
+            }
+        }
+    }
+    
+    // The evaluated prelude definitions, in prelude.expr order
+    pub fn prelude_context(&self) -> IndexMap<String, Value> {
+        self.prelude
+            .iter()
+            .filter_map(|(name, id)| match self.cache.get(id) {
+                Some(Ok(value)) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    // Build a root node equivalent to `(def name <value>)` for a value that comes from
+    // outside the source, with the value pre-seeded in the cache. Evaluating it with
+    // evaluate_sequence binds `name` like any other definition.
+    pub fn value_definition(&mut self, name: &str, value: Value) -> Rc<Node> {
+        let literal = value.to_string();
+        let value_node = Node::new(NodeKind::List, literal.clone(), Vec::new(), HashMap::new());
+        self.cache.insert(*value_node.id(), Ok(value));
+        
+        let symbol = |text: &str| Node::new(NodeKind::Symbol(text.to_string()), text.to_string(), Vec::new(), HashMap::new());
+        let definition = Node::new(
+            NodeKind::LetStatement,
+            format!("(def {} {})", name, literal),
+            vec![symbol("def"), symbol(name), value_node],
+            HashMap::new(),
+        );
+        self.store_node(definition.clone());
+        
+        // Results that used an earlier value of `name` are recomputed when next needed,
+        // as `name` now refers to a different node; see is_stale
+        definition
+    }
+    
+    // Render the dependency graph of the last evaluation in Graphviz dot format, each
+    // node labelled with its source and value
+    pub fn export_dot(&self) -> String {
+        let mut ids: Vec<&NodeId> = self.depdag.forward.keys().chain(self.depdag.reverse.keys()).collect();
+        ids.sort();
+        ids.dedup();
+
+        let mut dot = String::from("digraph garden {\n");
+        for id in &ids {
+            let snippet = self.get_node(id).map(|node| node.code_snippet().to_string()).unwrap_or_default();
+            let label = match self.cache.get(id) {
+                Some(Ok(value)) => format!("{} => {}", snippet, value),
+                Some(Err(e)) => format!("{} => {}", snippet, e),
+                None => snippet,
+            };
+            dot.push_str(&format!("    \"{}\" [label={:?}];\n", hex::encode(&id[0..4]), label));
+        }
+        for id in &ids {
+            for child in self.depdag.forward.get(*id).into_iter().flatten() {
+                dot.push_str(&format!("    \"{}\" -> \"{}\";\n", hex::encode(&id[0..4]), hex::encode(&child[0..4])));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+    
+    // Get cached result to avoid borrow issues
+    fn get_cached_result(&self, id: &NodeId) -> Option<Result<Value, Error>> {
+        self.cache.get(id).cloned()
+    }
+    
+    // Get node from cache
+    fn get_node(&self, id: &NodeId) -> Option<Rc<Node>> {
+        self.cache.get_node(id).cloned()
+    }
+    
+    // Evaluate a node asynchronously
+    pub fn eval_node<'a>(&'a mut self, node: &'a Rc<Node>, env: &'a Env<'a>) -> LocalBoxFuture<'a, Result<Value, Error>> {
+        if self.timings.is_none() {
+            return self.eval_node_untimed(node, env);
+        }
+        Box::pin(async move {
+            let node_id = *node.id();
+            let (started, evaluations) = match &mut self.timings {
+                Some(timings) => (timings.start(node_id), timings.evaluations),
+                None => (None, 0),
+            };
+            let result = self.eval_node_untimed(node, env).await;
+            if let Some(timings) = &mut self.timings {
+                // Results served from the cache evaluate nothing
+                let evaluated = timings.evaluations != evaluations;
+                timings.finish(node_id, started, evaluated);
+            }
+            result
+        })
+    }
+    
+    fn eval_node_untimed<'a>(&'a mut self, node: &'a Rc<Node>, env: &'a Env<'a>) -> LocalBoxFuture<'a, Result<Value, Error>> {
+        Box::pin(async move {
+            // Get the node ID for easy reference
+            let node_id = *node.id();
+            
+            // Nodes in function bodies have a different value on every call
+            let cacheable = self.is_cacheable(&node_id);
+            
+            // Check if we have a cached value - avoid borrow issues by getting a clone before the mutable borrow
+            if let Some(cached_result) = self.get_cached_result(&node_id).filter(|_| cacheable) {
+                if !self.is_stale(node, env) {
+                    return cached_result;
+                }
+            }
+            if let Some(timings) = &mut self.timings {
+                timings.evaluations += 1;
+            }
+            
+            // For symbol nodes, we need to resolve and evaluate the defining node
+            if let NodeKind::Symbol(name) = node.kind() {
+                let binding = env.lookup(name).or_else(|| self.globals.get(name).map(|id| Binding::Node(*id)));
+                let result = match binding {
+                    // Prelude definitions don't see the user's environment
+                    None if self.prelude.contains_key(name) => {
+                        let defining_node_id = self.prelude[name];
+                        self.depdag.add_dependency(node_id, defining_node_id);
+                        self.resolutions.insert(node_id, defining_node_id);
+                        match self.get_node(&defining_node_id) {
+                            Some(defining_node) => self.eval_node(&defining_node, &Env::new()).await,
+                            None => Err(Error::EvalError(format!("Internal error: Symbol {} resolved to unknown node", name)))
+                        }
+                    },
+                    None if name == "true" => Ok(Value::Bool(true)),
+                    None if name == "false" => Ok(Value::Bool(false)),
+                    None if name == "nil" => Ok(Value::Nil),
+                    Some(Binding::Value(value)) => Ok(value),
+                    Some(Binding::Node(defining_node_id)) => {
+                        // Record the dependency between the symbol node and its defining node
+                        self.depdag.add_dependency(node_id, defining_node_id);
+                        self.resolutions.insert(node_id, defining_node_id);
+                        
+                        match self.get_node(&defining_node_id) {
+                            // Like (def a (+ b 1)) (def b (* a 2)), which has no value
+                            Some(_) if !self.resolving.insert(defining_node_id) => {
+                                Err(Error::EvalError("circular definition".to_string()))
+                            }
+                            Some(defining_node) => {
+                                let result = self.eval_node(&defining_node, env).await;
+                                self.resolving.remove(&defining_node_id);
+                                result
+                            }
+                            None => Err(Error::EvalError(format!("Internal error: Symbol {} resolved to unknown node", name)))
+                        }
+                    },
+                    None => Err(Error::EvalError(format!("Undefined symbol: {}", name)))
+                };
+                if cacheable {
+                    self.cache.insert(node_id, result.clone());
+                    self.fresh_nodes.insert(node_id);
+                }
+                return result;
+            }
+            
+            // Special forms take their arguments by position, so there is nowhere to splice a list
+            if takes_arguments_by_position(node.kind()) && node.children().iter().skip(1).any(|child| *child.kind() == NodeKind::Spread) {
+                return Err(Error::EvalError(format!("'{}' does not accept spread arguments", node.children()[0].code_snippet())));
+            }
+            
+            // For other node types, proceed with normal evaluation
+            let result = match node.kind() {
+                NodeKind::Number(n) => {
+                    // Number literal
+                    Ok(Value::Number(*n))
+                },
+                NodeKind::Float(n) => {
+                    // Float literal
+                    Ok(Value::Float(*n))
+                },
+                NodeKind::String(s) => {
+                    // String literal
+                    Ok(Value::String(s.clone()))
+                },
+                NodeKind::Definition => {
+                    // Definition (def name value)
                     // Children: 0: 'def' symbol, 1: name symbol, 2: value expression
                     if node.children().len() != 3 {
                         return Err(Error::EvalError(format!(
@@ -716,7 +2758,7 @@ impl Evaluator {
                     // Record dependency to value expression
                     self.depdag.add_dependency(node_id, *value_expr_node.id());
                     
-                    let value = self.eval_node(value_expr_node, env).await?;
+                    self.eval_node(value_expr_node, env).await?;
                     
                     // Create a new environment extending the current one with the new binding
                     let mut new_bindings = HashMap::new();
@@ -765,293 +2807,2108 @@ impl Evaluator {
                 },
                 NodeKind::Addition => {
                     // Addition (+ a b c ...)
-                    if node.children().len() < 2 {
-                        return Err(Error::EvalError("'+' requires at least 1 argument".to_string()));
-                    }
-                    
-                    // Record dependencies to all arguments
-                    for child in node.children().iter().skip(1) {
-                        self.depdag.add_dependency(node_id, *child.id());
-                    }
-                    
-                    let mut sum = 0;
-                    // Evaluate argument children (starting from index 1)
-                    for i in 1..node.children().len() {
-                        let arg_node = &node.children()[i];
-                        let val = self.eval_node(arg_node, env).await?;
-                        match val {
-                            Value::Number(n) => sum += n,
-                            _ => return Err(Error::EvalError(
-                                "'+' requires all arguments to be numbers".to_string(),
-                            )),
-                        }
-                    }
-                    Ok(Value::Number(sum))
+                    // With no arguments this is 0, so (apply + []) works
+                    let args = self.eval_args(node, env).await?;
+                    self.fold_arithmetic(args, '+', Some(0))
                 },
                 NodeKind::Multiplication => {
                     // Multiplication (* a b c ...)
-                    if node.children().len() < 2 {
-                        return Err(Error::EvalError("'*' requires at least 1 argument".to_string()));
-                    }
-                    
-                    // Record dependencies to all arguments
-                    for child in node.children().iter().skip(1) {
-                        self.depdag.add_dependency(node_id, *child.id());
+                    // With no arguments this is 1, so (apply * []) works
+                    let args = self.eval_args(node, env).await?;
+                    self.fold_arithmetic(args, '*', Some(1))
+                },
+                NodeKind::Subtraction => {
+                    // Subtraction (- a b c ...), or negation (- a). Counted after spreading, so
+                    // (- ...[10 2]) subtracts.
+                    let args = self.eval_args(node, env).await?;
+                    let initial = (args.len() == 1).then_some(0);
+                    self.fold_arithmetic(args, '-', initial)
+                },
+                NodeKind::Division => {
+                    // Integer division (/ a b c ...), truncating toward zero
+                    let args = self.eval_args(node, env).await?;
+                    if args.len() < 2 {
+                        return Err(Error::EvalError("'/' expects at least 2 arguments".into()));
                     }
-                    
-                    let mut product = 1;
-                    // Evaluate argument children (starting from index 1)
-                    for i in 1..node.children().len() {
-                        let arg_node = &node.children()[i];
-                        let val = self.eval_node(arg_node, env).await?;
-                        match val {
-                            Value::Number(n) => product *= n,
-                            _ => return Err(Error::EvalError(
-                                "'*' requires all arguments to be numbers".to_string(),
-                            )),
-                        }
+                    self.fold_arithmetic(args, '/', None)
+                },
+                NodeKind::Modulo => {
+                    // Remainder (% a b), with the sign of a
+                    let args = self.eval_args(node, env).await?;
+                    if args.len() != 2 {
+                        return Err(Error::EvalError("'%' expects 2 arguments".into()));
                     }
-                    Ok(Value::Number(product))
+                    self.fold_arithmetic(args, '%', None)
                 },
                 NodeKind::HttpGet => {
                     // HTTP GET (http.get url)
-                    // Children: 0: 'http.get' symbol, 1: url expression
-                    if node.children().len() != 2 {
-                        return Err(Error::EvalError(
-                            "'http.get' expects 1 argument (url), so 2 children in the node.".into(),
-                        ));
-                    }
-                    
-                    // Record dependency to URL argument
-                    let url_expr_node = &node.children()[1];
-                    self.depdag.add_dependency(node_id, *url_expr_node.id());
-                    
-                    match self.eval_node(url_expr_node, env).await? {
-                        Value::String(url) => {
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url)] => {
                             // Perform the HTTP GET request
-                            let body = reqwest::get(&url).await?.text().await?;
-                            Ok(Value::String(body))
+                            let mut response = self.http_get_response(url).await?;
+                            Ok(response.swap_remove("body").unwrap_or(Value::Nil))
                         }
-                        _ => Err(Error::EvalError(
+                        [_] => Err(Error::EvalError(
                             "'http.get' expects its argument to evaluate to a string URL".into(),
                         )),
+                        _ => Err(Error::EvalError(
+                            "'http.get' expects 1 argument (url)".into(),
+                        )),
                     }
                 },
                 NodeKind::JsonParse => {
                     // JSON Parse (json.parse json_string)
-                    // Children: 0: 'json.parse' symbol, 1: string expression
-                    if node.children().len() != 2 {
-                        return Err(Error::EvalError(
-                            "'json.parse' expects 1 argument (a string to parse)".into(),
-                        ));
-                    }
-                    
-                    // Record dependency to string argument
-                    let string_expr_node = &node.children()[1];
-                    self.depdag.add_dependency(node_id, *string_expr_node.id());
-                    
-                    match self.eval_node(string_expr_node, env).await? {
-                        Value::String(s) => {
-                            let json_data: JsonValue = serde_json::from_str(&s)?;
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => {
+                            let json_data: JsonValue = serde_json::from_str(s)?;
                             Ok(Value::Json(json_data))
                         }
-                        _ => Err(Error::EvalError(
+                        [_] => Err(Error::EvalError(
                             "'json.parse' expects its argument to evaluate to a string".into(),
                         )),
+                        _ => Err(Error::EvalError(
+                            "'json.parse' expects 1 argument (a string to parse)".into(),
+                        )),
                     }
                 },
                 NodeKind::JsonGet => {
-                    // JSON Get (get json_obj key_string)
-                    // Children: 0: 'get' symbol, 1: json_obj expression, 2: key_string expression
-                    if node.children().len() != 3 {
+                    // JSON Get (get json_obj key_string ...), following each key in turn
+                    let mut args = self.eval_args(node, env).await?;
+                    if args.len() < 2 {
                         return Err(Error::EvalError(
-                            "'get' expects 2 arguments (a JSON object, a string key)".into(),
+                            "'get' expects a JSON object and at least one string key".into(),
                         ));
                     }
-                    
-                    // Record dependencies to JSON object and key arguments
-                    let json_obj_expr_node = &node.children()[1];
-                    let key_string_expr_node = &node.children()[2];
-                    self.depdag.add_dependency(node_id, *json_obj_expr_node.id());
-                    self.depdag.add_dependency(node_id, *key_string_expr_node.id());
-                    
-                    let json_val = self.eval_node(json_obj_expr_node, env).await?;
-                    let key_val = self.eval_node(key_string_expr_node, env).await?;
-                    
-                    match (json_val, key_val) {
-                        (Value::Json(json_data), Value::String(key)) => {
-                            match json_data.get(&key) {
-                                Some(v) => convert_json_value(v.clone()), // convert_json_value handles errors for unsupported types
-                                None => Err(Error::EvalError(format!(
-                                    "Key '{}' not found in JSON object",
-                                    key
-                                ))),
+                    let keys = args.split_off(1);
+                    let key_count = keys.len();
+                    let mut json_val = args.remove(0);
+                    for (i, key_val) in keys.into_iter().enumerate() {
+                        json_val = match (json_val, key_val) {
+                            (Value::Map(entries), Value::String(key)) => match entries.get(&key) {
+                                Some(value) => value.clone(),
+                                None => return Err(Error::EvalError(format!("Key '{}' not found in map", key))),
+                            },
+                            (Value::Json(json_data), Value::String(key)) => {
+                                match json_data.get(&key) {
+                                    // Objects along the way stay JSON for the next key
+                                    Some(v) if i + 1 < key_count => Value::Json(v.clone()),
+                                    Some(v) => Value::from_json_value(v.clone()),
+                                    None => return Err(Error::EvalError(format!(
+                                        "Key '{}' not found in JSON object",
+                                        key
+                                    ))),
+                                }
                             }
-                        }
-                        (Value::Json(_), other_key_type) => Err(Error::EvalError(format!(
-                            "'get' expects the second argument (key) to be a string, got {:?}",
-                            other_key_type
-                        ))),
-                        (other_json_type, _) => Err(Error::EvalError(format!(
-                            "'get' expects the first argument to be a JSON object, got {:?}",
-                            other_json_type
-                        ))),
+                            (Value::Json(_), other_key_type) => return Err(Error::EvalError(format!(
+                                "'get' expects its keys to be strings, got {:?}",
+                                other_key_type
+                            ))),
+                            (other_json_type, _) => return Err(Error::EvalError(format!(
+                                "'get' expects the first argument to be a JSON object, got {:?}",
+                                other_json_type
+                            ))),
+                        };
                     }
+                    Ok(json_val)
                 },
                 NodeKind::StringUpper => {
                     // String to uppercase (str.upper string_expr)
-                    // Children: 0: 'str.upper' symbol, 1: string expression
-                    if node.children().len() != 2 {
-                        return Err(Error::EvalError(
-                            "'str.upper' expects 1 argument (a string)".into(),
-                        ));
-                    }
-                    
-                    // Record dependency to string argument
-                    let string_expr_node = &node.children()[1];
-                    self.depdag.add_dependency(node_id, *string_expr_node.id());
-                    
-                    match self.eval_node(string_expr_node, env).await? {
-                        Value::String(s) => Ok(Value::String(s.to_uppercase())),
-                        other_type => Err(Error::EvalError(format!(
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => Ok(Value::String(s.to_uppercase())),
+                        [other_type] => Err(Error::EvalError(format!(
                             "'str.upper' expects its argument to evaluate to a string, got {:?}",
                             other_type
                         ))),
+                        _ => Err(Error::EvalError(
+                            "'str.upper' expects 1 argument (a string)".into(),
+                        )),
                     }
                 },
-                NodeKind::List => {
-                    // Generic list or unknown function call
-                    if node.children().is_empty() {
-                        return Err(Error::EvalError("Cannot evaluate an empty list".to_string()));
+                NodeKind::StrToNum => {
+                    // String to number (str.to-num string_expr [radix_expr])
+                    let args = self.eval_args(node, env).await?;
+                    let (s, radix) = match args.as_slice() {
+                        [s] => (s, None),
+                        [s, radix] => (s, Some(radix)),
+                        _ => return Err(Error::EvalError(
+                            "'str.to-num' expects 1 or 2 arguments (a string, an optional radix)".into(),
+                        )),
+                    };
+                    let Value::String(s) = s else {
+                        return Err(Error::EvalError(format!(
+                            "'str.to-num' expects its first argument to evaluate to a string, got {:?}",
+                            s
+                        )));
+                    };
+                    let radix = match radix {
+                        Some(Value::Number(radix)) if (2..=36).contains(radix) => Some(*radix as u32),
+                        Some(other) => return Err(Error::EvalError(format!(
+                            "'str.to-num' expects a radix between 2 and 36, got {}",
+                            other
+                        ))),
+                        None => None,
+                    };
+                    parse_number(s, radix)
+                },
+                NodeKind::NumFormat => {
+                    // Format a number (num.format number_expr format_string_expr)
+                    // with a printf-style format string
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [number @ (Value::Number(_) | Value::Float(_)), Value::String(format)] => {
+                            format_number(number, format).map(Value::String)
+                        }
+                        [number, format] => Err(Error::EvalError(format!(
+                            "'num.format' expects a number and a format string, got {:?} and {:?}",
+                            number, format
+                        ))),
+                        _ => Err(Error::EvalError(
+                            "'num.format' expects 2 arguments (a number, a format string like \"%.2f\")".into(),
+                        )),
                     }
-                    
-                    // Record dependencies to all children
-                    for child in node.children() {
-                        self.depdag.add_dependency(node_id, *child.id());
+                },
+                NodeKind::NumParse => {
+                    // Parse a number (num.parse string_expr)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => {
+                            // Thousands separators and surrounding whitespace are ignored
+                            let cleaned: String = s.trim().chars().filter(|c| *c != ',').collect();
+                            match (cleaned.parse::<i64>(), cleaned.parse::<f64>()) {
+                                (Ok(n), _) => Ok(Value::Number(n)),
+                                (_, Ok(n)) => Ok(Value::Float(n)),
+                                _ => Err(Error::EvalError(format!("Could not parse {:?} as a number", s))),
+                            }
+                        }
+                        [other_type] => Err(Error::EvalError(format!(
+                            "'num.parse' expects its argument to evaluate to a string, got {:?}",
+                            other_type
+                        ))),
+                        _ => Err(Error::EvalError(
+                            "'num.parse' expects 1 argument (a string)".into(),
+                        )),
                     }
-                    
-                    // The first child of a List node (if not a special form handled above)
-                    // would be the function to call.
-                    let func_expr_node = &node.children()[0];
-                    
-                    // What is it? If it's a symbol, it's an attempt to call a function by that name.
-                    if let NodeKind::Symbol(func_name) = func_expr_node.kind() {
-                        Err(Error::EvalError(format!(
-                            "Attempted to call '{}' as a function, but it's either undefined or not a known built-in operation",
-                            func_name
-                        )))
+                },
+                NodeKind::StrPadLeft | NodeKind::StrPadRight => {
+                    // Pad a string (str.pad-left string width [char]) / (str.pad-right string width [char])
+                    // Strings already at least `width` characters wide are returned unchanged
+                    let name = if *node.kind() == NodeKind::StrPadLeft { "str.pad-left" } else { "str.pad-right" };
+                    let args = self.eval_args(node, env).await?;
+                    let (s, width, fill) = match args.as_slice() {
+                        [Value::String(s), Value::Number(width)] => (s, *width, ' '),
+                        [Value::String(s), Value::Number(width), Value::String(fill)] if fill.chars().count() == 1 => {
+                            (s, *width, fill.chars().next().unwrap_or(' '))
+                        }
+                        _ => return Err(Error::EvalError(format!(
+                            "'{}' expects a string, a width and an optional single-character string, got {:?}",
+                            name, args
+                        ))),
+                    };
+                    let count = usize::try_from(width.max(0)).unwrap_or(usize::MAX).saturating_sub(s.chars().count());
+                    check_built_size(name, count.checked_mul(fill.len_utf8()).and_then(|bytes| bytes.checked_add(s.len())))?;
+                    let padding: String = std::iter::repeat_n(fill, count).collect();
+                    if *node.kind() == NodeKind::StrPadLeft {
+                        Ok(Value::String(padding + s))
                     } else {
-                        Err(Error::EvalError(
-                            "The first element of a list to be evaluated as a function call must be a symbol".to_string()
-                        ))
+                        Ok(Value::String(s.clone() + &padding))
                     }
                 },
-                // Unexpected node types
-                NodeKind::Symbol(_) => {
-                    // Should be handled above already
-                    Err(Error::EvalError("Reached unreachable code: Symbol handling in match".to_string()))
-                }
-            };
-            
-            // Cache the result
-            self.cache.insert(node_id, result.clone());
-            
-            result
-        })
-    }
-
-    // Evaluate a sequence of nodes in order, updating the environment for definitions and let statements
-    pub async fn evaluate_sequence<'a>(
-        &'a mut self,
-        nodes: &'a [Rc<Node>],
-        env: &'a mut Env<'a>,
-    ) -> Result<Option<Value>, Error> {
-        let mut last_value = None;
-
-        for node in nodes {
-            let node_id = *node.id();
-            let result = self.eval_node(node, env).await;
-            
-            // For Definition and LetStatement nodes, also update the environment
-            match node.kind() {
-                NodeKind::Definition | NodeKind::LetStatement => {
-                    if node.children().len() >= 3 {
-                        if let NodeKind::Symbol(name) = node.children()[1].kind() {
-                            if result.is_ok() {
-                                // Bind the name to the value expression NodeId for future lookups
-                                env.bind(name, *node.children()[2].id());
-                            }
+                NodeKind::StrRepeat => {
+                    // Repeat a string (str.repeat string n)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s), Value::Number(n)] if *n >= 0 => {
+                            let n = usize::try_from(*n).unwrap_or(usize::MAX);
+                            check_built_size("str.repeat", s.len().checked_mul(n))?;
+                            Ok(Value::String(s.repeat(n)))
                         }
+                        args => Err(Error::EvalError(format!(
+                            "'str.repeat' expects a string and a non-negative count, got {:?}",
+                            args
+                        ))),
                     }
                 },
-                _ => {} // Other node types don't modify the environment
-            }
-            
-            // Remember the result of this node
-            if let Ok(value) = &result {
-                last_value = Some(value.clone());
-            }
-            
-            // If there was an error and it hasn't been inserted into the cache yet, insert it
-            if let Err(err) = &result {
-                self.cache.insert(node_id, Err(err.clone()));
-                return Err(err.clone());
-            }
+                NodeKind::StrLines => {
+                    // Split a string into lines (str.lines string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => Ok(Value::List(s.lines().map(|line| Value::String(line.to_string())).collect())),
+                        args => Err(Error::EvalError(format!("'str.lines' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::StrTrim => {
+                    // Drop whitespace from both ends of a string (str.trim string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => Ok(Value::String(s.trim().to_string())),
+                        args => Err(Error::EvalError(format!("'str.trim' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::StrSlice => {
+                    // Substring by character index (str.slice string start [end]). Negative indices
+                    // count from the end and out-of-range ones are clamped, as in Python.
+                    let args = self.eval_args(node, env).await?;
+                    let (s, start, end) = match args.as_slice() {
+                        [Value::String(s), Value::Number(start)] => (s, *start, None),
+                        [Value::String(s), Value::Number(start), Value::Number(end)] => (s, *start, Some(*end)),
+                        _ => return Err(Error::EvalError(format!(
+                            "'str.slice' expects a string, a start index and an optional end index, got {:?}",
+                            args
+                        ))),
+                    };
+                    let len = s.chars().count() as i64;
+                    let clamp = |i: i64| if i < 0 { (len + i).max(0) } else { i.min(len) };
+                    let (start, end) = (clamp(start), clamp(end.unwrap_or(len)));
+                    Ok(Value::String(s.chars().skip(start as usize).take((end - start).max(0) as usize).collect()))
+                },
+                NodeKind::Vector => {
+                    // List literal [a b c]
+                    for child in node.children() {
+                        self.depdag.add_dependency(node_id, *child.id());
+                    }
+                    let mut items = Vec::new();
+                    for child in node.children() {
+                        items.push(self.eval_node(child, env).await?);
+                    }
+                    Ok(Value::List(items))
+                },
+                NodeKind::Lambda => {
+                    // Function (fn [params...] body)
+                    // Children: 0: 'fn' symbol, 1: parameter vector, 2: body expression
+                    let params = match node.children().get(1).map(|params| params.kind()) {
+                        Some(NodeKind::Vector) if node.children().len() == 3 => node.children()[1]
+                            .children()
+                            .iter()
+                            .map(|param| match param.kind() {
+                                NodeKind::Symbol(name) => Ok(name.clone()),
+                                _ => Err(Error::EvalError(format!(
+                                    "'fn' parameters must be symbols, got {}",
+                                    param.code_snippet()
+                                ))),
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                        _ => return Err(Error::EvalError(
+                            "'fn' expects a parameter vector and a body, e.g. (fn [x] (+ x 1))".into(),
+                        )),
+                    };
+                    let body = &node.children()[2];
+                    self.mark_function_body(body);
+                    
+                    let (bindings, values) = env.captured();
+                    Ok(Value::Lambda(Box::new(Lambda {
+                        params,
+                        body: *body.id(),
+                        source: node.code_snippet().to_string(),
+                        bindings,
+                        values,
+                        memo: None,
+                        memo_ttl: None,
+                    })))
+                },
+                NodeKind::Each => {
+                    // Call a function on every element for its side effects (each list fn)
+                    let args = self.eval_args(node, env).await?;
+                    let [list, function] = args.as_slice() else {
+                        return Err(Error::EvalError("'each' expects 2 arguments (a list, a function)".into()));
+                    };
+                    for item in list_items(list, "each")? {
+                        self.call_function(function, vec![item]).await?;
+                    }
+                    Ok(Value::Nil)
+                },
+                NodeKind::Range => {
+                    // Numbers from start up to, not including, end (range [start] end)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::Number(end)] => Ok(Value::List((0..*end).map(Value::Number).collect())),
+                        [Value::Number(start), Value::Number(end)] => Ok(Value::List((*start..*end).map(Value::Number).collect())),
+                        args => Err(Error::EvalError(format!("'range' expects 1 or 2 numbers, got {:?}", args))),
+                    }
+                },
+                NodeKind::Println => {
+                    // Print the arguments separated by spaces, strings without quotes (println args...)
+                    let args = self.eval_args(node, env).await?;
+                    let line: Vec<String> = args
+                        .iter()
+                        .map(|arg| match arg {
+                            Value::String(s) => s.clone(),
+                            other => other.to_string(),
+                        })
+                        .collect();
+                    match &mut self.output {
+                        Some(output) => output.push(format!("{}\n", line.join(" "))),
+                        None => println!("{}", line.join(" ")),
+                    }
+                    Ok(Value::Nil)
+                },
+                NodeKind::Sort => {
+                    // Sort a list in natural order (sort list); see PartialOrd for Value
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [list] => {
+                            let mut items = list_items(list, "sort")?;
+                            items.sort_by(compare_values);
+                            Ok(Value::List(items))
+                        }
+                        args => Err(Error::EvalError(format!("'sort' expects 1 argument (a list), got {}", args.len()))),
+                    }
+                },
+                NodeKind::SortBy => {
+                    // Sort a list by the key a function gives each element (sort-by key-fn list)
+                    let args = self.eval_args(node, env).await?;
+                    let [key_fn, list] = args.as_slice() else {
+                        return Err(Error::EvalError("'sort-by' expects 2 arguments (a key function, a list)".into()));
+                    };
+                    let mut keyed = Vec::new();
+                    for item in list_items(list, "sort-by")? {
+                        keyed.push((self.call_function(key_fn, vec![item.clone()]).await?, item));
+                    }
+                    keyed.sort_by(|(a, _), (b, _)| compare_values(a, b));
+                    Ok(Value::List(keyed.into_iter().map(|(_, item)| item).collect()))
+                },
+                NodeKind::GroupBy => {
+                    // Group a list's elements by the string key a function gives each (group-by key-fn list)
+                    let args = self.eval_args(node, env).await?;
+                    let [key_fn, list] = args.as_slice() else {
+                        return Err(Error::EvalError("'group-by' expects 2 arguments (a key function, a list)".into()));
+                    };
+                    let mut groups: IndexMap<String, Value> = IndexMap::new();
+                    for item in list_items(list, "group-by")? {
+                        let key = match self.call_function(key_fn, vec![item.clone()]).await? {
+                            Value::String(key) => key,
+                            other => return Err(Error::EvalError(format!(
+                                "'group-by' key function must return a string, got {} for {}",
+                                other, item
+                            ))),
+                        };
+                        if let Value::List(group) = groups.entry(key).or_insert_with(|| Value::List(Vec::new())) {
+                            group.push(item);
+                        }
+                    }
+                    Ok(Value::Map(groups))
+                },
+                NodeKind::Zip => {
+                    // Pair up the elements of two lists, stopping at the shorter one (zip list1 list2)
+                    let args = self.eval_args(node, env).await?;
+                    let [first, second] = args.as_slice() else {
+                        return Err(Error::EvalError("'zip' expects 2 arguments (two lists)".into()));
+                    };
+                    let pairs = list_items(first, "zip")?
+                        .into_iter()
+                        .zip(list_items(second, "zip")?)
+                        .map(|(a, b)| Value::List(vec![a, b]))
+                        .collect();
+                    Ok(Value::List(pairs))
+                },
+                NodeKind::Unzip => {
+                    // Split a list of pairs into a list of firsts and a list of seconds (unzip pairs)
+                    let args = self.eval_args(node, env).await?;
+                    let [pairs] = args.as_slice() else {
+                        return Err(Error::EvalError("'unzip' expects 1 argument (a list of pairs)".into()));
+                    };
+                    let (mut firsts, mut seconds) = (Vec::new(), Vec::new());
+                    for pair in list_items(pairs, "unzip")? {
+                        match list_items(&pair, "unzip")?.as_slice() {
+                            [a, b] => {
+                                firsts.push(a.clone());
+                                seconds.push(b.clone());
+                            }
+                            _ => return Err(Error::EvalError(format!("'unzip' expects two-element pairs, got {}", pair))),
+                        }
+                    }
+                    Ok(Value::List(vec![Value::List(firsts), Value::List(seconds)]))
+                },
+                NodeKind::Distinct => {
+                    // Drop repeated elements, keeping the first of each (distinct list)
+                    let args = self.eval_args(node, env).await?;
+                    let [list] = args.as_slice() else {
+                        return Err(Error::EvalError("'distinct' expects 1 argument (a list)".into()));
+                    };
+                    let mut unique: Vec<Value> = Vec::new();
+                    for item in list_items(list, "distinct")? {
+                        if !unique.contains(&item) {
+                            unique.push(item);
+                        }
+                    }
+                    Ok(Value::List(unique))
+                },
+                NodeKind::Frequencies => {
+                    // Count how often each element occurs, keyed by its text (frequencies list)
+                    let args = self.eval_args(node, env).await?;
+                    let [list] = args.as_slice() else {
+                        return Err(Error::EvalError("'frequencies' expects 1 argument (a list)".into()));
+                    };
+                    let mut counts: IndexMap<String, Value> = IndexMap::new();
+                    for item in list_items(list, "frequencies")? {
+                        // Strings count under their contents rather than their quoted form
+                        let key = match item {
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        };
+                        if let Value::Number(count) = counts.entry(key).or_insert(Value::Number(0)) {
+                            *count += 1;
+                        }
+                    }
+                    Ok(Value::Map(counts))
+                },
+                NodeKind::Take | NodeKind::Drop | NodeKind::Partition => {
+                    // (take n list), (drop n list) and (partition n list)
+                    let name = match node.kind() {
+                        NodeKind::Take => "take",
+                        NodeKind::Drop => "drop",
+                        _ => "partition",
+                    };
+                    let args = self.eval_args(node, env).await?;
+                    let (n, list) = match args.as_slice() {
+                        [Value::Number(n), list] if *n >= 0 => (*n as usize, list_items(list, name)?),
+                        [Value::Number(n), _] => return Err(Error::EvalError(format!(
+                            "'{}' expects a non-negative count, got {}",
+                            name, n
+                        ))),
+                        _ => return Err(Error::EvalError(format!("'{}' expects 2 arguments (a count, a list)", name))),
+                    };
+                    match node.kind() {
+                        NodeKind::Take => Ok(Value::List(list.into_iter().take(n).collect())),
+                        NodeKind::Drop => Ok(Value::List(list.into_iter().skip(n).collect())),
+                        _ if n == 0 => Err(Error::EvalError("'partition' expects a chunk size of at least 1".into())),
+                        _ => Ok(Value::List(list.chunks(n).map(|chunk| Value::List(chunk.to_vec())).collect())),
+                    }
+                },
+                NodeKind::StrConcat => {
+                    // Join strings end to end (str.concat values...); other values are joined by
+                    // their display form, so (str.concat "n=" 3) is "n=3"
+                    let mut out = String::new();
+                    for arg in self.eval_args(node, env).await? {
+                        match arg {
+                            Value::String(s) => out.push_str(&s),
+                            other => out.push_str(&other.to_string()),
+                        }
+                    }
+                    Ok(Value::String(out))
+                },
+                NodeKind::Apply => {
+                    // Call a function or built-in operator with a list of arguments (apply f list)
+                    // Children: 0: 'apply' symbol, 1: function or operator, 2: list expression
+                    if node.children().len() != 3 {
+                        return Err(Error::EvalError("'apply' expects 2 arguments (a function, a list)".into()));
+                    }
+                    let func_expr_node = &node.children()[1];
+                    let list_expr_node = &node.children()[2];
+                    self.depdag.add_dependency(node_id, *list_expr_node.id());
+                    let args = list_items(&self.eval_node(list_expr_node, env).await?, "apply")?;
+                    
+                    match func_expr_node.kind() {
+                        // Built-in operators like + aren't values, so apply builds the call itself
+                        NodeKind::Symbol(op) if env.lookup(op).is_none() && !self.globals.contains_key(op) && !self.prelude.contains_key(op) => {
+                            if let Some(builtin) = self.builtins.0.get(op).cloned() {
+                                return builtin.eval(args, self).await;
+                            }
+                            let arg_names: Vec<String> = (0..args.len()).map(|i| format!("%{}", i)).collect();
+                            let call = parser::parse(&format!("({} {})", op, arg_names.join(" ")))?
+                                .pop()
+                                .ok_or_else(|| Error::EvalError(format!("'apply' could not call {}", op)))?;
+                            // The call's arguments differ every time, so it is never cached
+                            self.mark_function_body(&call);
+                            self.store_node(call.clone());
+                            
+                            let mut call_env = Env::with_parent(env);
+                            for (name, arg) in arg_names.iter().zip(args) {
+                                call_env.bind_value(name, arg);
+                            }
+                            self.eval_node(&call, &call_env).await
+                        }
+                        _ => {
+                            self.depdag.add_dependency(node_id, *func_expr_node.id());
+                            let function = self.eval_node(func_expr_node, env).await?;
+                            self.call_function(&function, args).await
+                        }
+                    }
+                },
+                NodeKind::Assoc => {
+                    // A copy of a map with a key set (assoc map key value)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [map, Value::String(key), value] => {
+                            let mut entries = map_entries(map, "assoc")?;
+                            entries.insert(key.clone(), value.clone());
+                            Ok(Value::Map(entries))
+                        }
+                        _ => Err(Error::EvalError("'assoc' expects 3 arguments (a map, a string key, a value)".into())),
+                    }
+                },
+                NodeKind::Dissoc => {
+                    // A copy of a map without a key (dissoc map key)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [map, Value::String(key)] => {
+                            let mut entries = map_entries(map, "dissoc")?;
+                            entries.shift_remove(key);
+                            Ok(Value::Map(entries))
+                        }
+                        _ => Err(Error::EvalError("'dissoc' expects 2 arguments (a map, a string key)".into())),
+                    }
+                },
+                NodeKind::Keys | NodeKind::Vals => {
+                    // A map's keys or values in insertion order (keys map) / (vals map)
+                    let name = if *node.kind() == NodeKind::Keys { "keys" } else { "vals" };
+                    let args = self.eval_args(node, env).await?;
+                    let [map] = args.as_slice() else {
+                        return Err(Error::EvalError(format!("'{}' expects 1 argument (a map)", name)));
+                    };
+                    let entries = map_entries(map, name)?;
+                    if *node.kind() == NodeKind::Keys {
+                        Ok(Value::List(entries.into_keys().map(Value::String).collect()))
+                    } else {
+                        Ok(Value::List(entries.into_values().collect()))
+                    }
+                },
+                NodeKind::Merge => {
+                    // Combine two maps; keys in the second override the first (merge map1 map2)
+                    let args = self.eval_args(node, env).await?;
+                    let [first, second] = args.as_slice() else {
+                        return Err(Error::EvalError("'merge' expects 2 arguments (two maps)".into()));
+                    };
+                    let mut entries = map_entries(first, "merge")?;
+                    entries.extend(map_entries(second, "merge")?);
+                    Ok(Value::Map(entries))
+                },
+                NodeKind::For => {
+                    // List comprehension (for [x list ...] body), nesting over several bindings
+                    // Children: 0: 'for' symbol, 1: binding vector, 2: body expression
+                    let bindings = match node.children().get(1) {
+                        Some(bindings) if node.children().len() == 3
+                            && *bindings.kind() == NodeKind::Vector
+                            && !bindings.children().is_empty()
+                            && bindings.children().len() % 2 == 0 => bindings.children().to_vec(),
+                        _ => return Err(Error::EvalError(
+                            "'for' expects a binding vector and a body, e.g. (for [x items] (* x x))".into(),
+                        )),
+                    };
+                    let body = &node.children()[2];
+                    // Everything after the first list can depend on the bindings, so is never cached
+                    for later in bindings.iter().skip(2) {
+                        self.mark_function_body(later);
+                    }
+                    self.mark_function_body(body);
+                    self.depdag.add_dependency(node_id, *bindings[1].id());
+                    
+                    let mut results = Vec::new();
+                    self.eval_for(&bindings, body, env, &mut results).await?;
+                    Ok(Value::List(results))
+                },
+                NodeKind::LessThan | NodeKind::GreaterThan | NodeKind::LessEqual | NodeKind::GreaterEqual => {
+                    // Comparison of values of one type, true if every neighbouring pair is in order (< a b ...)
+                    let (name, holds): (&str, fn(std::cmp::Ordering) -> bool) = match node.kind() {
+                        NodeKind::LessThan => ("<", std::cmp::Ordering::is_lt),
+                        NodeKind::GreaterThan => (">", std::cmp::Ordering::is_gt),
+                        NodeKind::LessEqual => ("<=", std::cmp::Ordering::is_le),
+                        _ => (">=", std::cmp::Ordering::is_ge),
+                    };
+                    let args = self.eval_args(node, env).await?;
+                    if args.len() < 2 {
+                        return Err(Error::EvalError(format!("'{}' expects at least 2 arguments", name)));
+                    }
+                    let mut in_order = true;
+                    for pair in args.windows(2) {
+                        // Values of different types have an order, but asking for it is likely a mistake
+                        let ordering = match (&pair[0], &pair[1]) {
+                            // 1 and 1.0 are the same number here, though they sort apart
+                            (Value::Number(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+                            (Value::Float(a), Value::Number(b)) => a.partial_cmp(&(*b as f64)),
+                            (a, b) if a.type_rank() == b.type_rank() => a.partial_cmp(b),
+                            _ => None,
+                        };
+                        let Some(ordering) = ordering else {
+                            return Err(Error::EvalError(format!(
+                                "'{}' can only compare values of the same type that have an order, got {} and {}",
+                                name, pair[0], pair[1]
+                            )));
+                        };
+                        in_order &= holds(ordering);
+                    }
+                    Ok(Value::Bool(in_order))
+                },
+                NodeKind::Equal => {
+                    // Equality of all arguments; integers and floats compare by value (= a b ...)
+                    let args = self.eval_args(node, env).await?;
+                    if args.len() < 2 {
+                        return Err(Error::EvalError("'=' expects at least 2 arguments".into()));
+                    }
+                    Ok(Value::Bool(args.windows(2).all(|pair| match (&pair[0], &pair[1]) {
+                        (Value::Number(a), Value::Float(b)) | (Value::Float(b), Value::Number(a)) => *a as f64 == *b,
+                        (a, b) => a == b,
+                    })))
+                },
+                NodeKind::While => {
+                    // Loop while a condition holds (while condition body). A def in the body
+                    // redefines the variable for the next iteration and for code after the loop.
+                    // Children: 0: 'while' symbol, 1: condition, 2: body
+                    if node.children().len() != 3 {
+                        return Err(Error::EvalError("'while' expects 2 arguments (a condition, a body)".into()));
+                    }
+                    let condition = &node.children()[1];
+                    let body = &node.children()[2];
+                    // Both change every iteration, so neither they nor the loop are cached
+                    self.mark_function_body(node);
+                    
+                    // A (def name value) body assigns instead of opening a new scope
+                    let assigned = match (body.kind(), body.children().get(1).map(|child| child.kind())) {
+                        (NodeKind::Definition | NodeKind::LetStatement, Some(NodeKind::Symbol(name))) if body.children().len() == 3 => {
+                            Some((name.clone(), body.children()[2].clone()))
+                        }
+                        _ => None,
+                    };
+                    
+                    let max_iterations = self.config.max_loop_iterations();
+                    let mut loop_env = Env::with_parent(env);
+                    let mut assignments = IndexMap::new();
+                    let mut last = Value::Nil;
+                    let mut iterations = 0;
+                    while is_truthy(&self.eval_node(condition, &loop_env).await?) {
+                        iterations += 1;
+                        if iterations > max_iterations {
+                            return Err(Error::EvalError("while loop exceeded maximum iteration count".into()));
+                        }
+                        last = match &assigned {
+                            Some((name, value_node)) => {
+                                let value = self.eval_node(value_node, &loop_env).await?;
+                                loop_env.bind_value(name, value.clone());
+                                assignments.insert(name.clone(), value.clone());
+                                value
+                            }
+                            None => self.eval_node(body, &loop_env).await?,
+                        };
+                    }
+                    self.loop_assignments.insert(node_id, assignments);
+                    Ok(last)
+                },
+                NodeKind::If => {
+                    // Conditional (if condition then [else]); only the chosen branch is evaluated
+                    // Children: 0: 'if' symbol, 1: condition, 2: then branch, 3: optional else branch
+                    if !(3..=4).contains(&node.children().len()) {
+                        return Err(Error::EvalError("'if' expects a condition, a then branch and an optional else branch".into()));
+                    }
+                    let condition = &node.children()[1];
+                    self.depdag.add_dependency(node_id, *condition.id());
+                    let branch = if is_truthy(&self.eval_node(condition, env).await?) {
+                        node.children().get(2)
+                    } else {
+                        node.children().get(3)
+                    };
+                    // A def branch, as in (when ready (def x 1)), defines its name for the code
+                    // after the if. Which branch is taken isn't cached, so the if always runs.
+                    let defines = |child: &Rc<Node>| {
+                        matches!(child.kind(), NodeKind::Definition | NodeKind::LetStatement)
+                            && matches!(child.children().get(1).map(|name| name.kind()), Some(NodeKind::Symbol(_)))
+                            && child.children().len() == 3
+                    };
+                    if node.children().iter().skip(2).any(defines) {
+                        self.function_nodes.insert(node_id);
+                    }
+                    match branch {
+                        Some(branch) => {
+                            self.depdag.add_dependency(node_id, *branch.id());
+                            let value = self.eval_node(branch, env).await;
+                            match branch.children().get(1).map(|name| name.kind()) {
+                                Some(NodeKind::Symbol(name)) if defines(branch) => {
+                                    self.branch_definitions.insert(node_id, (name.clone(), branch.clone()));
+                                }
+                                _ => {
+                                    self.branch_definitions.remove(&node_id);
+                                }
+                            }
+                            value
+                        }
+                        None => {
+                            self.branch_definitions.remove(&node_id);
+                            Ok(Value::Nil)
+                        }
+                    }
+                },
+                NodeKind::Memoize => {
+                    // A function that remembers its result for each set of arguments (memoize fn)
+                    let args = self.eval_args(node, env).await?;
+                    match args.as_slice() {
+                        [Value::Lambda(lambda)] => Ok(Value::Lambda(Box::new(Lambda {
+                            memo: Some(node_id),
+                            memo_ttl: None,
+                            ..(**lambda).clone()
+                        }))),
+                        _ => Err(Error::EvalError("'memoize' expects 1 argument (a function)".into())),
+                    }
+                },
+                NodeKind::TypeOf => {
+                    // Name of a value's type, e.g. "number" or "list" (type-of value)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [value] => Ok(Value::String(value.type_name().to_string())),
+                        args => Err(Error::EvalError(format!("'type-of' expects 1 argument, got {}", args.len()))),
+                    }
+                },
+                NodeKind::CsvParse | NodeKind::CsvParseHeaders => {
+                    // Parse CSV text into a list of rows, each a list of strings (csv.parse text),
+                    // or into a list of maps keyed by the header row (csv.parse-headers text)
+                    let op = if matches!(node.kind(), NodeKind::CsvParse) { "csv.parse" } else { "csv.parse-headers" };
+                    let text = match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(text)] => text.clone(),
+                        args => return Err(Error::EvalError(format!("'{}' expects 1 string argument, got {:?}", op, args))),
+                    };
+                    let mut reader = csv::ReaderBuilder::new()
+                        .has_headers(false)
+                        .flexible(true)
+                        .from_reader(text.as_bytes());
+                    let mut rows = Vec::new();
+                    for record in reader.records() {
+                        let record = record.map_err(|e| Error::EvalError(format!("'{}' failed: {}", op, e)))?;
+                        rows.push(record.iter().map(str::to_string).collect::<Vec<_>>());
+                    }
+                    if matches!(node.kind(), NodeKind::CsvParse) {
+                        return Ok(Value::List(rows.into_iter()
+                            .map(|row| Value::List(row.into_iter().map(Value::String).collect()))
+                            .collect()));
+                    }
+                    let mut rows = rows.into_iter();
+                    let headers = rows.next().unwrap_or_default();
+                    // Short rows leave their missing columns out of the map
+                    Ok(Value::List(rows
+                        .map(|row| Value::Map(headers.iter().cloned().zip(row.into_iter().map(Value::String)).collect()))
+                        .collect()))
+                },
+                NodeKind::CsvStringify => {
+                    // Write a list of rows as CSV text (csv.stringify rows). Cells that aren't
+                    // strings are written as they display.
+                    let rows = match self.eval_args(node, env).await?.as_slice() {
+                        [rows] => list_items(rows, "csv.stringify")?,
+                        args => return Err(Error::EvalError(format!("'csv.stringify' expects 1 list argument, got {:?}", args))),
+                    };
+                    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+                    for row in &rows {
+                        let cells: Vec<String> = list_items(row, "csv.stringify")?
+                            .iter()
+                            .map(|cell| match cell {
+                                Value::String(s) => s.clone(),
+                                other => other.to_string(),
+                            })
+                            .collect();
+                        writer.write_record(&cells).map_err(|e| Error::EvalError(format!("'csv.stringify' failed: {}", e)))?;
+                    }
+                    let bytes = writer.into_inner().map_err(|e| Error::EvalError(format!("'csv.stringify' failed: {}", e)))?;
+                    Ok(Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+                },
+                NodeKind::TomlParse => {
+                    // Parse a TOML document into a map (toml.parse text)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(text)] => toml::from_str::<toml::Table>(text)
+                            .map(|table| from_toml(toml::Value::Table(table)))
+                            .map_err(|e| Error::EvalError(format!("'toml.parse' failed: {}", e))),
+                        args => Err(Error::EvalError(format!("'toml.parse' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::YamlParse => {
+                    // Parse a YAML document into garden values (yaml.parse text)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(text)] => serde_yaml::from_str(text)
+                            .map(from_yaml)
+                            .map_err(|e| Error::EvalError(format!("'yaml.parse' failed: {}", e))),
+                        args => Err(Error::EvalError(format!("'yaml.parse' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::RegexMatch => {
+                    // Whether a pattern matches anywhere in a string (regex.match pattern string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(pattern), Value::String(s)] => Ok(Value::Bool(self.regex(pattern, "regex.match")?.is_match(s))),
+                        args => Err(Error::EvalError(format!("'regex.match' expects a pattern and a string, got {:?}", args))),
+                    }
+                },
+                NodeKind::RegexCaptures => {
+                    // The first match and its groups (regex.captures pattern string): a list of the
+                    // whole match followed by each group, nil for groups that didn't take part.
+                    // nil when the pattern doesn't match.
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(pattern), Value::String(s)] => Ok(match self.regex(pattern, "regex.captures")?.captures(s) {
+                            Some(captures) => Value::List(captures
+                                .iter()
+                                .map(|group| group.map_or(Value::Nil, |group| Value::String(group.as_str().to_string())))
+                                .collect()),
+                            None => Value::Nil,
+                        }),
+                        args => Err(Error::EvalError(format!("'regex.captures' expects a pattern and a string, got {:?}", args))),
+                    }
+                },
+                NodeKind::RegexReplace => {
+                    // Replace every match (regex.replace pattern replacement string). The replacement
+                    // can refer to groups as $1 or ${name}.
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(pattern), Value::String(replacement), Value::String(s)] => Ok(Value::String(
+                            self.regex(pattern, "regex.replace")?.replace_all(s, replacement.as_str()).into_owned()
+                        )),
+                        args => Err(Error::EvalError(format!(
+                            "'regex.replace' expects a pattern, a replacement and a string, got {:?}",
+                            args
+                        ))),
+                    }
+                },
+                NodeKind::Base64Encode | NodeKind::Base64Decode => {
+                    // (base64.encode string [url-safe]) and (base64.decode string [url-safe]), using
+                    // the standard alphabet with padding unless url-safe is true
+                    use base64::{engine::general_purpose::{STANDARD, URL_SAFE}, Engine};
+                    let op = if matches!(node.kind(), NodeKind::Base64Encode) { "base64.encode" } else { "base64.decode" };
+                    let args = self.eval_args(node, env).await?;
+                    let (s, url_safe) = match args.as_slice() {
+                        [Value::String(s)] => (s, false),
+                        [Value::String(s), url_safe] => (s, is_truthy(url_safe)),
+                        _ => return Err(Error::EvalError(format!("'{}' expects a string and an optional url-safe flag, got {:?}", op, args))),
+                    };
+                    let engine = if url_safe { &URL_SAFE } else { &STANDARD };
+                    if matches!(node.kind(), NodeKind::Base64Encode) {
+                        return Ok(Value::String(engine.encode(s)));
+                    }
+                    let bytes = engine.decode(s).map_err(|e| Error::EvalError(format!("'base64.decode' failed on {:?}: {}", s, e)))?;
+                    String::from_utf8(bytes)
+                        .map(Value::String)
+                        .map_err(|e| Error::EvalError(format!("'base64.decode' produced bytes that aren't UTF-8: {}", hex::encode(e.as_bytes()))))
+                },
+                NodeKind::HashSha256 | NodeKind::HashMd5 | NodeKind::HashBlake3 => {
+                    // Hex digest of a string (hash.sha256 s), (hash.md5 s) or (hash.blake3 s)
+                    use sha2::Digest;
+                    let op = match node.kind() {
+                        NodeKind::HashSha256 => "hash.sha256",
+                        NodeKind::HashMd5 => "hash.md5",
+                        _ => "hash.blake3",
+                    };
+                    let s = match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => s.clone(),
+                        args => return Err(Error::EvalError(format!("'{}' expects 1 string argument, got {:?}", op, args))),
+                    };
+                    Ok(Value::String(match node.kind() {
+                        NodeKind::HashSha256 => hex::encode(sha2::Sha256::digest(s.as_bytes())),
+                        NodeKind::HashMd5 => format!("{:x}", md5::compute(s.as_bytes())),
+                        _ => blake3::hash(s.as_bytes()).to_hex().to_string(),
+                    }))
+                },
+                NodeKind::HashHmacSha256 => {
+                    // Hex HMAC-SHA256 of a message (hash.hmac-sha256 key message)
+                    use hmac::Mac;
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(key), Value::String(message)] => {
+                            // HMAC accepts keys of any length
+                            let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key.as_bytes())
+                                .map_err(|e| Error::EvalError(format!("'hash.hmac-sha256' failed: {}", e)))?;
+                            mac.update(message.as_bytes());
+                            Ok(Value::String(hex::encode(mac.finalize().into_bytes())))
+                        },
+                        args => Err(Error::EvalError(format!("'hash.hmac-sha256' expects a key and a message, got {:?}", args))),
+                    }
+                },
+                NodeKind::UuidNew => {
+                    // A random (version 4) UUID, different on every evaluation (uuid.new)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [] => Ok(Value::String(uuid::Uuid::new_v4().to_string())),
+                        args => Err(Error::EvalError(format!("'uuid.new' expects no arguments, got {:?}", args))),
+                    }
+                },
+                NodeKind::UuidParse => {
+                    // Check a UUID and write it in lowercase hyphenated form (uuid.parse string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => uuid::Uuid::parse_str(s)
+                            .map(|uuid| Value::String(uuid.to_string()))
+                            .map_err(|e| Error::EvalError(format!("'uuid.parse' got an invalid UUID {:?}: {}", s, e))),
+                        args => Err(Error::EvalError(format!("'uuid.parse' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::UrlEncode => {
+                    // Percent-encode everything but letters, digits and -_.~ (url.encode string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => Ok(Value::String(urlencoding::encode(s).into_owned())),
+                        args => Err(Error::EvalError(format!("'url.encode' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::UrlDecode => {
+                    // Undo percent-encoding, treating + as a space as forms do (url.decode string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => urlencoding::decode(&s.replace('+', " "))
+                            .map(|decoded| Value::String(decoded.into_owned()))
+                            .map_err(|e| Error::EvalError(format!("'url.decode' failed on {:?}: {}", s, e))),
+                        args => Err(Error::EvalError(format!("'url.decode' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::UrlBuild => {
+                    // Append a map of query parameters to a URL (url.build base params). Values
+                    // that aren't strings are written as they display.
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(base), params] => {
+                            let query: Vec<String> = map_entries(params, "url.build")?
+                                .iter()
+                                .map(|(key, value)| {
+                                    let value = match value {
+                                        Value::String(s) => s.clone(),
+                                        other => other.to_string(),
+                                    };
+                                    format!("{}={}", urlencoding::encode(key), urlencoding::encode(&value))
+                                })
+                                .collect();
+                            if query.is_empty() {
+                                return Ok(Value::String(base.clone()));
+                            }
+                            let separator = if base.contains('?') { '&' } else { '?' };
+                            Ok(Value::String(format!("{}{}{}", base, separator, query.join("&"))))
+                        },
+                        args => Err(Error::EvalError(format!("'url.build' expects a URL and a map of parameters, got {:?}", args))),
+                    }
+                },
+                NodeKind::Sleep => {
+                    // Pause for a number of milliseconds, then give nil (sleep ms)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::Number(ms)] if *ms >= 0 => {
+                            tokio::time::sleep(Duration::from_millis(*ms as u64)).await;
+                            Ok(Value::Nil)
+                        },
+                        args => Err(Error::EvalError(format!("'sleep' expects a number of milliseconds, got {:?}", args))),
+                    }
+                },
+                NodeKind::HttpPostJson => {
+                    // POST a map or JSON value as JSON and parse the JSON reply (http.post-json url body)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url), body @ (Value::Map(_) | Value::Json(_))] => {
+                            if let Some(reply) = self.http_mocks.get(url) {
+                                return Ok(Value::Json(serde_json::from_str(reply)?));
+                            }
+                            let reply = self.http_client(url)?
+                                .post(url)
+                                .json(&body.to_json_value()?)
+                                .send()
+                                .await?
+                                .json::<JsonValue>()
+                                .await?;
+                            Ok(Value::Json(reply))
+                        },
+                        args => Err(Error::EvalError(format!("'http.post-json' expects a URL and a map or JSON body, got {:?}", args))),
+                    }
+                },
+                NodeKind::HttpGetResponse => {
+                    // GET a URL, giving a map of its status, headers and body (http.get-response url)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url)] => Ok(Value::Map(self.http_get_response(url).await?)),
+                        args => Err(Error::EvalError(format!("'http.get-response' expects a URL, got {:?}", args))),
+                    }
+                },
+                NodeKind::HttpSetCookieJar => {
+                    // Send and store cookies with the named jar in the requests that follow,
+                    // creating the jar if needed (http.set-cookie-jar name)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(name)] => {
+                            if !self.cookie_jars.contains_key(name) {
+                                let jar = std::sync::Arc::new(reqwest::cookie::Jar::default());
+                                let client = self.http_client_builder().cookie_provider(jar).build()?;
+                                self.cookie_jars.insert(name.clone(), client);
+                            }
+                            self.cookie_jar = Some(name.clone());
+                            Ok(Value::String(name.clone()))
+                        },
+                        args => Err(Error::EvalError(format!("'http.set-cookie-jar' expects a jar name, got {:?}", args))),
+                    }
+                },
+                NodeKind::HttpClearCookies => {
+                    // Discard the named jar and its cookies (http.clear-cookies name). Requests
+                    // that follow send no cookies until a jar is set again.
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(name)] => {
+                            self.cookie_jars.remove(name);
+                            if self.cookie_jar.as_ref() == Some(name) {
+                                self.cookie_jar = None;
+                            }
+                            Ok(Value::Nil)
+                        },
+                        args => Err(Error::EvalError(format!("'http.clear-cookies' expects a jar name, got {:?}", args))),
+                    }
+                },
+                NodeKind::Watch => {
+                    // Poll a URL every interval-secs seconds, giving its latest body. A changed
+                    // body re-evaluates the file (watch url interval-secs).
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url), Value::Number(secs)] if *secs > 0 => {
+                            if let Some(watch) = self.watches.get(&node_id) {
+                                return Ok(Value::String(watch.latest.lock().unwrap().clone()));
+                            }
+                            let client = self.http_client(url)?;
+                            let body = client.get(url).send().await?.text().await?;
+                            let latest = Arc::new(Mutex::new(body.clone()));
+                            let interval = Duration::from_secs(*secs as u64);
+                            let task = tokio::spawn(poll_url(client, url.clone(), interval, latest.clone(), self.change_events.clone()));
+                            self.watches.insert(node_id, UrlWatch { latest, task });
+                            Ok(Value::String(body))
+                        },
+                        args => Err(Error::EvalError(format!("'watch' expects a URL and a positive number of seconds, got {:?}", args))),
+                    }
+                },
+                NodeKind::NumOverflowMode => {
+                    // Set how the arithmetic after this handles overflow, giving the previous
+                    // mode (num.overflow-mode "error" | "saturating" | "wrapping")
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(name)] => match OverflowMode::parse(name) {
+                            Some(mode) => {
+                                let previous = std::mem::replace(&mut self.overflow_mode, mode);
+                                Ok(Value::String(previous.name().to_string()))
+                            },
+                            None => Err(Error::EvalError(format!(
+                                "'num.overflow-mode' expects \"error\", \"saturating\" or \"wrapping\", got {:?}",
+                                name
+                            ))),
+                        },
+                        args => Err(Error::EvalError(format!("'num.overflow-mode' expects a mode name, got {:?}", args))),
+                    }
+                },
+                NodeKind::AssertType => {
+                    // Give a value unchanged if it has the named type (assert-type val "number")
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [value, Value::String(expected)] if value.type_name() == expected => Ok(value.clone()),
+                        [value, Value::String(expected)] => Err(Error::TypeError(format!(
+                            "expected {}, got {} {}",
+                            expected, value.type_name(), value
+                        ))),
+                        args => Err(Error::EvalError(format!("'assert-type' expects a value and a type name, got {:?}", args))),
+                    }
+                },
+                NodeKind::CoerceTo => {
+                    // Convert a value to "number", "float" or "string" (coerce-to "number" val)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(target), value] => coerce_to(target, value),
+                        args => Err(Error::EvalError(format!("'coerce-to' expects a type name and a value, got {:?}", args))),
+                    }
+                },
+                NodeKind::WithContext => {
+                    // Evaluate a body with each entry of a map bound to a local name, shadowing
+                    // any outer binding (with-context map body)
+                    if node.children().len() != 3 {
+                        return Err(Error::EvalError("'with-context' expects a map and a body".into()));
+                    }
+                    let (map_node, body) = (&node.children()[1], &node.children()[2]);
+                    self.depdag.add_dependency(node_id, *map_node.id());
+                    self.depdag.add_dependency(node_id, *body.id());
+                    
+                    let entries = map_entries(&self.eval_node(map_node, env).await?, "with-context")?;
+                    // The body's value depends on the map, so only this node's result is cached
+                    self.mark_function_body(body);
+                    let mut context_env = Env::with_parent(env);
+                    for (name, value) in entries {
+                        context_env.bind_value(&name, value);
+                    }
+                    self.eval_node(body, &context_env).await
+                },
+                NodeKind::StrInterpolate => {
+                    // Fill ${key} placeholders from a map (str.interpolate template map)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(template), values] => {
+                            let entries = map_entries(values, "str.interpolate")?;
+                            Ok(Value::String(interpolate(template, &entries)?))
+                        },
+                        args => Err(Error::EvalError(format!("'str.interpolate' expects a template string and a map, got {:?}", args))),
+                    }
+                },
+                NodeKind::JsonValidate => {
+                    // Check a value against a JSON Schema (json.validate schema-string value): true,
+                    // or a list of what is wrong
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(schema), value] => {
+                            let violations = self.schema_violations(schema, &value.to_json_value()?)?;
+                            if violations.is_empty() {
+                                Ok(Value::Bool(true))
+                            } else {
+                                Ok(Value::List(violations.into_iter().map(Value::String).collect()))
+                            }
+                        },
+                        args => Err(Error::EvalError(format!("'json.validate' expects a schema string and a value, got {:?}", args))),
+                    }
+                },
+                NodeKind::WithDefault => {
+                    // A fallback for an expression that fails (with-default default body); the
+                    // default is only evaluated when the body gives an error
+                    // Children: 0: 'with-default' symbol, 1: default, 2: body
+                    let [_, default, body] = node.children() else {
+                        return Err(Error::EvalError("'with-default' expects a default value and an expression".into()));
+                    };
+                    self.depdag.add_dependency(node_id, *body.id());
+                    match self.eval_node(body, env).await {
+                        Ok(value) => Ok(value),
+                        Err(_) => {
+                            self.depdag.add_dependency(node_id, *default.id());
+                            self.eval_node(default, env).await
+                        }
+                    }
+                },
+                NodeKind::Tap => {
+                    // Call a function on a value for its effects and give back the value (tap fn value)
+                    // Children: 0: 'tap' symbol, 1: function, 2: value
+                    let [_, function, value_node] = node.children() else {
+                        return Err(Error::EvalError("'tap' expects a function and a value".into()));
+                    };
+                    self.depdag.add_dependency(node_id, *value_node.id());
+                    let value = self.eval_node(value_node, env).await?;
+                    
+                    // Operations like println aren't values, so they are called as (println value)
+                    let is_operation = matches!(function.kind(), NodeKind::Symbol(name)
+                        if env.lookup(name).is_none() && !self.globals.contains_key(name) && !self.prelude.contains_key(name));
+                    if is_operation {
+                        let call = parser::call_node(function.clone(), vec![value_node.clone()])?;
+                        self.store_node(call.clone());
+                        // The value node keeps its cached result, but the call runs every time
+                        self.function_nodes.insert(*call.id());
+                        self.eval_node(&call, env).await?;
+                    } else {
+                        self.depdag.add_dependency(node_id, *function.id());
+                        let function = self.eval_node(function, env).await?;
+                        self.call_function(&function, vec![value.clone()]).await?;
+                    }
+                    Ok(value)
+                },
+                NodeKind::MemoizeTtl => {
+                    // Like memoize, but a result is only remembered for some seconds (memoize-ttl fn secs)
+                    let args = self.eval_args(node, env).await?;
+                    let (lambda, secs) = match args.as_slice() {
+                        [Value::Lambda(lambda), Value::Number(n)] => (lambda, *n as f64),
+                        [Value::Lambda(lambda), Value::Float(f)] => (lambda, *f),
+                        _ => return Err(Error::EvalError("'memoize-ttl' expects 2 arguments (a function and a number of seconds)".into())),
+                    };
+                    let ttl = Duration::try_from_secs_f64(secs).map_err(|_| {
+                        Error::EvalError(format!("'memoize-ttl' expects a number of seconds that isn't negative, got {}", secs))
+                    })?;
+                    Ok(Value::Lambda(Box::new(Lambda {
+                        memo: Some(node_id),
+                        memo_ttl: Some(ttl),
+                        ..(**lambda).clone()
+                    })))
+                },
+                NodeKind::Parallel => {
+                    // Evaluate independent expressions into a list (parallel expr...). HTTP GETs among
+                    // them are sent together instead of one after another.
+                    self.prefetch_http_gets(node, env).await;
+                    let values = self.eval_args(node, env).await;
+                    // Responses a cached child didn't need aren't kept for later requests
+                    self.prefetched.clear();
+                    Ok(Value::List(values?))
+                },
+                NodeKind::HttpDownload => {
+                    // Save a response body to a file without holding it in memory (http.download url path),
+                    // giving the number of bytes written
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url), Value::String(path)] => Ok(Value::Number(self.download(url, path).await? as i64)),
+                        args => Err(Error::EvalError(format!("'http.download' expects a URL and a file path, got {:?}", args))),
+                    }
+                },
+                NodeKind::StrJoin => {
+                    // Join list items with a separator (str.join separator list). Strings are
+                    // joined as-is and other values by their display form.
+                    let args = self.eval_args(node, env).await?;
+                    let [sep, list] = args.as_slice() else {
+                        return Err(Error::EvalError("'str.join' expects a separator and a list".to_string()));
+                    };
+                    let Value::String(sep) = sep else {
+                        return Err(Error::EvalError(format!("'str.join' expects a string separator, got {}", sep)));
+                    };
+                    let parts: Vec<String> = list_items(list, "str.join")?
+                        .into_iter()
+                        .map(|item| match item {
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        })
+                        .collect();
+                    Ok(Value::String(parts.join(sep)))
+                },
+                NodeKind::StrSplitN => {
+                    // Split at most n times (str.split-n string separator n), giving at most n+1 parts
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s), Value::String(_), Value::Number(0)] => Ok(Value::List(vec![Value::String(s.clone())])),
+                        [Value::String(s), Value::String(sep), Value::Number(n)] if *n > 0 => Ok(Value::List(
+                            s.splitn(*n as usize + 1, sep.as_str()).map(|part| Value::String(part.to_string())).collect(),
+                        )),
+                        args => Err(Error::EvalError(format!(
+                            "'str.split-n' expects a string, a separator and a non-negative count, got {:?}",
+                            args
+                        ))),
+                    }
+                },
+                NodeKind::Cond => {
+                    // First value whose test holds (cond test value ... [default]); tests run in
+                    // order and only the chosen value is evaluated. Nil if nothing matches.
+                    let clauses = &node.children()[1..];
+                    for pair in clauses.chunks(2) {
+                        let branch = match pair {
+                            [test, value] => {
+                                self.depdag.add_dependency(node_id, *test.id());
+                                if !is_truthy(&self.eval_node(test, env).await?) {
+                                    continue;
+                                }
+                                value
+                            }
+                            [default] => default,
+                            _ => unreachable!(),
+                        };
+                        self.depdag.add_dependency(node_id, *branch.id());
+                        return self.eval_node(branch, env).await;
+                    }
+                    Ok(Value::Nil)
+                },
+                NodeKind::Match => {
+                    // Result for the first pattern the value fits (match value pattern result
+                    // ...). A pattern is _ for anything, (type "number") for a type,
+                    // (keys "a" "b") for a map or object with those keys, or an expression whose
+                    // value is matched by match_pattern. Keys a pattern checks are bound in its
+                    // result. Nil if nothing matches.
+                    let [_, subject, clauses @ ..] = node.children() else {
+                        return Err(Error::EvalError("'match' expects a value followed by pattern and result pairs".to_string()));
+                    };
+                    if clauses.len() % 2 != 0 {
+                        return Err(Error::EvalError("'match' expects a result for every pattern".to_string()));
+                    }
+                    self.depdag.add_dependency(node_id, *subject.id());
+                    let value = self.eval_node(subject, env).await?;
+                    for pair in clauses.chunks(2) {
+                        let [pattern, result] = pair else { unreachable!() };
+                        let bindings = match (pattern.kind(), pattern.children()) {
+                            (NodeKind::Symbol(name), _) if name == "_" => Some(IndexMap::new()),
+                            // (keys ...) parses as the keys operation, so go by the name
+                            (kind, [head, args @ ..])
+                                if *kind != NodeKind::Vector
+                                    && matches!(head.kind(), NodeKind::Symbol(name) if name == "type" || name == "keys") =>
+                            {
+                                let mut names = Vec::new();
+                                for arg in args {
+                                    match self.eval_node(arg, env).await? {
+                                        Value::String(name) => names.push(name),
+                                        other => return Err(Error::EvalError(format!("'match' expects string names in {}, got {}", pattern.code_snippet(), other))),
+                                    }
+                                }
+                                if matches!(head.kind(), NodeKind::Symbol(name) if name == "type") {
+                                    names.iter().any(|name| name == value.type_name()).then(IndexMap::new)
+                                } else {
+                                    map_entries(&value, "match").ok().and_then(|fields| {
+                                        names.iter().map(|name| Some((name.clone(), fields.get(name)?.clone()))).collect()
+                                    })
+                                }
+                            }
+                            _ => {
+                                self.depdag.add_dependency(node_id, *pattern.id());
+                                let pattern = self.eval_node(pattern, env).await?;
+                                match_pattern(&value, &pattern)
+                            }
+                        };
+                        let Some(bindings) = bindings else {
+                            continue;
+                        };
+                        self.depdag.add_dependency(node_id, *result.id());
+                        if bindings.is_empty() {
+                            return self.eval_node(result, env).await;
+                        }
+                        // The result depends on the matched value, so it isn't cached
+                        self.mark_function_body(result);
+                        let mut match_env = Env::with_parent(env);
+                        for (name, value) in bindings {
+                            match_env.bind_value(&name, value);
+                        }
+                        return self.eval_node(result, &match_env).await;
+                    }
+                    Ok(Value::Nil)
+                },
+                NodeKind::MemoDefinition => {
+                    // Definition computed once and then kept, even when its expression changes,
+                    // until memo.invalidate or --invalidate-memo forgets it (memo-def name value)
+                    let [_, name_node, value_node] = node.children() else {
+                        return Err(Error::EvalError(format!(
+                            "'memo-def' expects 2 arguments (name, value), got {} arguments",
+                            node.children().len() - 1
+                        )));
+                    };
+                    let NodeKind::Symbol(name) = name_node.kind() else {
+                        return Err(Error::EvalError("'memo-def' first argument must be a symbol naming the value".to_string()));
+                    };
+                    if let Some(value) = self.cache.memo(name) {
+                        return Ok(value.clone());
+                    }
+                    let value = self.eval_node(value_node, env).await?;
+                    self.cache.set_memo(name, value.clone());
+                    Ok(value)
+                },
+                NodeKind::MemoInvalidate => {
+                    // Forget a memo-def's value so the next evaluation computes it again
+                    // (memo.invalidate "name"). True if there was one.
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(name)] => Ok(Value::Bool(self.cache.invalidate_memo(name))),
+                        args => Err(Error::EvalError(format!("'memo.invalidate' expects a name, got {:?}", args))),
+                    }
+                },
+                NodeKind::JsonFlatten => {
+                    // One-level object of every leaf keyed by its path (json.flatten value
+                    // [separator]), e.g. {"a": {"b": [1]}} becomes {"a.b.0": 1}
+                    let args = self.eval_args(node, env).await?;
+                    let (value, separator) = match args.as_slice() {
+                        [value] => (value, "."),
+                        [value, Value::String(separator)] => (value, separator.as_str()),
+                        args => return Err(Error::EvalError(format!("'json.flatten' expects an object and an optional separator, got {:?}", args))),
+                    };
+                    let mut flat = serde_json::Map::new();
+                    match value.to_json_value()? {
+                        json @ (JsonValue::Object(_) | JsonValue::Array(_)) => flatten_json(&json, "", separator, &mut flat),
+                        _ => return Err(Error::EvalError(format!("'json.flatten' expects an object or array, got {}", value))),
+                    }
+                    Ok(Value::Json(JsonValue::Object(flat)))
+                },
+                NodeKind::JsonUnflatten => {
+                    // Nested structure from a json.flatten result (json.unflatten value [separator])
+                    let args = self.eval_args(node, env).await?;
+                    let (flat, separator) = match args.as_slice() {
+                        [flat] => (flat, "."),
+                        [flat, Value::String(separator)] if !separator.is_empty() => (flat, separator.as_str()),
+                        args => return Err(Error::EvalError(format!("'json.unflatten' expects an object and an optional separator, got {:?}", args))),
+                    };
+                    let JsonValue::Object(entries) = flat.to_json_value()? else {
+                        return Err(Error::EvalError(format!("'json.unflatten' expects an object, got {}", flat)));
+                    };
+                    Ok(Value::Json(unflatten_json(&entries, separator)?))
+                },
+                NodeKind::XmlParse => {
+                    // Parse XML into JSON (xml.parse string), also called xml.to-json
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(xml)] => Ok(Value::Json(xml_to_json(xml)?)),
+                        args => Err(Error::EvalError(format!("'xml.parse' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::DefinitionOnce => {
+                    // Definition that keeps an existing one (def-once name value): if name is
+                    // already defined, its value is used and value isn't evaluated
+                    let [_, name_node, value_node] = node.children() else {
+                        return Err(Error::EvalError(format!(
+                            "'def-once' expects 2 arguments (name, value), got {} arguments",
+                            node.children().len() - 1
+                        )));
+                    };
+                    let NodeKind::Symbol(name) = name_node.kind() else {
+                        return Err(Error::EvalError("'def-once' first argument must be a symbol naming the value".to_string()));
+                    };
+                    let defined = env.lookup(name).is_some() || self.globals.contains_key(name);
+                    let chosen = if defined { name_node } else { value_node };
+                    self.depdag.add_dependency(node_id, *chosen.id());
+                    self.eval_node(chosen, env).await
+                },
+                NodeKind::JsonArrayMap => {
+                    // Call a function on every element of a JSON array, giving a JSON array of the
+                    // results (json.array-map fn array)
+                    let args = self.eval_args(node, env).await?;
+                    let [function, Value::Json(JsonValue::Array(items))] = args.as_slice() else {
+                        return Err(Error::EvalError(format!("'json.array-map' expects a function and a JSON array, got {:?}", args)));
+                    };
+                    let mut mapped = Vec::with_capacity(items.len());
+                    for item in items {
+                        mapped.push(self.call_function(function, vec![Value::from_json_value(item.clone())]).await?.to_json_value()?);
+                    }
+                    Ok(Value::Json(JsonValue::Array(mapped)))
+                },
+                NodeKind::JsonArrayFilter => {
+                    // The elements of a JSON array a function is truthy for, as a JSON array
+                    // (json.array-filter fn array)
+                    let args = self.eval_args(node, env).await?;
+                    let [function, Value::Json(JsonValue::Array(items))] = args.as_slice() else {
+                        return Err(Error::EvalError(format!("'json.array-filter' expects a function and a JSON array, got {:?}", args)));
+                    };
+                    let mut kept = Vec::new();
+                    for item in items {
+                        if is_truthy(&self.call_function(function, vec![Value::from_json_value(item.clone())]).await?) {
+                            kept.push(item.clone());
+                        }
+                    }
+                    Ok(Value::Json(JsonValue::Array(kept)))
+                },
+                NodeKind::StrNumberQ => {
+                    // Whether a string is an integer or a finite decimal number (str.number? string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => {
+                            let s = s.trim();
+                            Ok(Value::Bool(s.parse::<i64>().is_ok() || s.parse::<f64>().is_ok_and(f64::is_finite)))
+                        },
+                        args => Err(Error::EvalError(format!("'str.number?' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::StrBoolQ => {
+                    // Whether a string is "true" or "false", in any case (str.bool? string)
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => Ok(Value::Bool(s.eq_ignore_ascii_case("true") || s.eq_ignore_ascii_case("false"))),
+                        args => Err(Error::EvalError(format!("'str.bool?' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::StrJsonQ => {
+                    // Whether json.parse would accept a string (str.json? string); the document is
+                    // checked without being built
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(s)] => Ok(Value::Bool(serde_json::from_str::<serde::de::IgnoredAny>(s).is_ok())),
+                        args => Err(Error::EvalError(format!("'str.json?' expects 1 string argument, got {:?}", args))),
+                    }
+                },
+                NodeKind::HttpMock => {
+                    // Answer requests for a URL with a canned body instead of fetching it, for the
+                    // rest of this evaluation (http.mock url body). Needs --allow-mocks.
+                    if !(cfg!(test) || self.config.http.allow_mocks) {
+                        return Err(Error::EvalError("'http.mock' needs --allow-mocks or [http] allow_mocks in .garden.toml".to_string()));
+                    }
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url), Value::String(body)] => {
+                            self.http_mocks.insert(url.clone(), body.clone());
+                            Ok(Value::String(url.clone()))
+                        },
+                        args => Err(Error::EvalError(format!("'http.mock' expects a URL and a response body, got {:?}", args))),
+                    }
+                },
+                NodeKind::HttpUnmock => {
+                    // Fetch a mocked URL for real again (http.unmock url). True if it was mocked.
+                    match self.eval_args(node, env).await?.as_slice() {
+                        [Value::String(url)] => Ok(Value::Bool(self.http_mocks.remove(url).is_some())),
+                        args => Err(Error::EvalError(format!("'http.unmock' expects a URL, got {:?}", args))),
+                    }
+                },
+                NodeKind::Spread => {
+                    // Splice a list into a call's arguments ((f (spread list)) or (f ...list));
+                    // eval_args expands it, so reaching here means it isn't an argument
+                    Err(Error::EvalError("'spread' can only be used as an argument in a call".to_string()))
+                },
+                NodeKind::List => {
+                    // Generic list or unknown function call
+                    if node.children().is_empty() {
+                        return Err(Error::EvalError("Cannot evaluate an empty list".to_string()));
+                    }
+                    
+                    // Record dependencies to all children
+                    for child in node.children() {
+                        self.depdag.add_dependency(node_id, *child.id());
+                    }
+                    
+                    // The first child of a List node (if not a special form handled above)
+                    // would be the function to call.
+                    let func_expr_node = &node.children()[0];
+                    
+                    // What is it? If it's a symbol, it's an attempt to call a function by that name.
+                    if let NodeKind::Symbol(func_name) = func_expr_node.kind() {
+                        // Built-ins registered from Rust are looked up at runtime
+                        if let Some(builtin) = self.builtins.0.get(func_name).cloned() {
+                            let args = self.eval_args(node, env).await?;
+                            builtin.eval(args, self).await
+                        } else if env.lookup(func_name).is_some() || self.globals.contains_key(func_name) || self.prelude.contains_key(func_name) {
+                            // A variable holding a function
+                            let function = self.eval_node(func_expr_node, env).await?;
+                            let args = self.eval_args(node, env).await?;
+                            self.call_function(&function, args).await
+                        } else {
+                            Err(Error::EvalError(format!(
+                                "Attempted to call '{}' as a function, but it's either undefined or not a known built-in operation",
+                                func_name
+                            )))
+                        }
+                    } else {
+                        // An expression producing a function, e.g. ((fn [x] ...) arg)
+                        let function = self.eval_node(func_expr_node, env).await?;
+                        let args = self.eval_args(node, env).await?;
+                        self.call_function(&function, args).await
+                    }
+                },
+                // Unexpected node types
+                NodeKind::Symbol(_) => {
+                    // Should be handled above already
+                    Err(Error::EvalError("Reached unreachable code: Symbol handling in match".to_string()))
+                }
+            };
+            
+            // Cache the result
+            if cacheable {
+                tracing::debug!(node = %hex::encode(&node_id[0..4]), code = %node.code_snippet, "evaluated");
+                self.cache.insert(node_id, result.clone());
+                self.fresh_nodes.insert(node_id);
+            }
+            
+            result
+        })
+    }
+
+    // Evaluate a node, giving up after `timeout`. Giving up cancels whatever the
+    // evaluation was waiting on, such as a sleep or an HTTP request.
+    pub async fn eval_with_timeout(&mut self, node: &Rc<Node>, env: &Env<'_>, timeout: Duration) -> Result<Value, Error> {
+        tokio::time::timeout(timeout, self.eval_node(node, env))
+            .await
+            .unwrap_or_else(|_| Err(Error::EvalError(EVAL_TIMED_OUT.to_string())))
+    }
+    
+    // Evaluate a sequence of nodes in order, updating the environment for definitions and let statements
+    pub async fn evaluate_sequence(
+        &mut self,
+        nodes: &[Rc<Node>],
+        env: &mut Env<'_>,
+    ) -> Result<Option<Value>, Error> {
+        let mut last_value = None;
+        
+        // A definition can use names defined further down; they are evaluated when first
+        // needed. The first definition of a name is the one used before it.
+        for node in nodes {
+            if let (NodeKind::Definition | NodeKind::LetStatement, [_, name, value]) = (node.kind(), node.children()) {
+                if let NodeKind::Symbol(name) = name.kind() {
+                    if env.lookup(name).is_none() {
+                        self.globals.entry(name.clone()).or_insert(*value.id());
+                    }
+                }
+            }
+        }
+
+        for node in nodes {
+            let node_id = *node.id();
+            let result = match self.config.eval_timeout() {
+                Some(timeout) => self.eval_with_timeout(node, env, timeout).await,
+                None => self.eval_node(node, env).await,
+            };
+            
+            // For Definition and LetStatement nodes, also update the environment
+            match node.kind() {
+                NodeKind::Definition | NodeKind::LetStatement if node.children().len() >= 3 => {
+                    if let NodeKind::Symbol(name) = node.children()[1].kind() {
+                        match &result {
+                            // The value expression may give something else under a later
+                            // overflow mode or mock, so the name keeps this value
+                            Ok(value) if self.mode_dependent_nodes.contains(node.children()[2].id()) => {
+                                env.bind_value(name, value.clone());
+                                self.globals.insert(name.clone(), *node.children()[2].id());
+                            }
+                            // Bind the name to the value expression NodeId for future lookups
+                            Ok(_) => {
+                                env.bind(name, *node.children()[2].id());
+                                self.globals.insert(name.clone(), *node.children()[2].id());
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                },
+                // So does a def in the branch an if took
+                NodeKind::If => {
+                    if let (Ok(_), Some((name, definition))) = (&result, self.branch_definitions.get(&node_id)) {
+                        env.bind(name, *definition.children()[2].id());
+                        self.globals.insert(name.clone(), *definition.children()[2].id());
+                    }
+                },
+                // A def-once only defines a name that isn't defined yet
+                NodeKind::DefinitionOnce if node.children().len() == 3 => {
+                    if let (Ok(_), NodeKind::Symbol(name)) = (&result, node.children()[1].kind()) {
+                        if env.lookup(name).is_none() && !self.globals.contains_key(name) {
+                            env.bind(name, *node.children()[2].id());
+                            self.globals.insert(name.clone(), *node.children()[2].id());
+                        }
+                    }
+                },
+                // A memo-def's name refers to the memo-def itself, which keeps the value
+                NodeKind::MemoDefinition if node.children().len() == 3 => {
+                    if let (Ok(_), NodeKind::Symbol(name)) = (&result, node.children()[1].kind()) {
+                        env.bind(name, node_id);
+                        self.globals.insert(name.clone(), node_id);
+                    }
+                },
+                // Variables redefined in a loop keep their final values afterwards
+                NodeKind::While => {
+                    for (name, value) in self.loop_assignments.get(&node_id).into_iter().flatten() {
+                        env.bind_value(name, value.clone());
+                    }
+                },
+                _ => {} // Other node types don't modify the environment
+            }
+            
+            // Remember the result of this node
+            if let Ok(value) = &result {
+                last_value = Some(value.clone());
+            }
+            
+            // If there was an error and it hasn't been inserted into the cache yet, insert it.
+            // An expression that timed out gets another chance next time.
+            if let Err(err) = &result {
+                if self.is_cacheable(&node_id) && !is_timeout(err) {
+                    self.cache.insert(node_id, Err(err.clone()));
+                }
+                return Err(err.clone());
+            }
+        }
+        
+        Ok(last_value)
+    }
+    
+    // Evaluate only dirty nodes in the proper order
+    pub async fn evaluate_dirty_nodes<'a>(
+        &'a mut self,
+        env: &'a mut Env<'a>,
+    ) -> Result<(), Error> {
+        // Get the set of dirty nodes
+        let dirty_node_ids = self.dirty_nodes.clone();
+        
+        // Get the nodes in topological order
+        let sorted_node_ids = self.depdag.topological_sort(&dirty_node_ids);
+        
+        // Evaluate each node in order
+        for node_id in sorted_node_ids {
+            if let Some(node) = self.get_node(&node_id) {
+                let _result = self.eval_node(&node, env).await;
+                // We don't need to do anything with the result here - it's already cached
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+// Message of the error for an evaluation that ran past its timeout
+const EVAL_TIMED_OUT: &str = "evaluation timed out";
+
+fn is_timeout(error: &Error) -> bool {
+    matches!(error, Error::EvalError(message) if message == EVAL_TIMED_OUT)
+}
+
+// Largest string, in bytes, that str.repeat and the str.pad-* operations will build
+const MAX_BUILT_STRING_BYTES: usize = 64 * 1024 * 1024;
+
+// Fail unless a string of `bytes` bytes (None if the size overflowed) may be built
+fn check_built_size(operation: &str, bytes: Option<usize>) -> Result<(), Error> {
+    match bytes {
+        Some(bytes) if bytes <= MAX_BUILT_STRING_BYTES => Ok(()),
+        _ => Err(Error::EvalError(format!(
+            "'{}' would build a string over the {} MiB limit",
+            operation,
+            MAX_BUILT_STRING_BYTES / (1024 * 1024)
+        ))),
+    }
+}
+
+// Whether a node kind reads its arguments one by one from its children, often without
+// evaluating them, rather than through eval_args
+fn takes_arguments_by_position(kind: &NodeKind) -> bool {
+    matches!(
+        kind,
+        NodeKind::Definition
+            | NodeKind::LetExpr
+            | NodeKind::LetStatement
+            | NodeKind::Lambda
+            | NodeKind::Apply
+            | NodeKind::For
+            | NodeKind::While
+            | NodeKind::If
+            | NodeKind::WithContext
+            | NodeKind::WithDefault
+            | NodeKind::Tap
+            | NodeKind::Cond
+            | NodeKind::Match
+            | NodeKind::MemoDefinition
+            | NodeKind::DefinitionOnce
+    )
+}
+
+// The elements of a list, or of a JSON array, for list operations such as `each`
+fn list_items(value: &Value, operation: &str) -> Result<Vec<Value>, Error> {
+    match value {
+        Value::List(items) => Ok(items.clone()),
+        Value::Json(JsonValue::Array(items)) => Ok(items.iter().cloned().map(Value::from_json_value).collect()),
+        other => Err(Error::EvalError(format!("'{}' expects a list, got {}", operation, other))),
+    }
+}
+
+// Whether a value counts as true in a condition: everything but false and nil does
+fn is_truthy(value: &Value) -> bool {
+    !matches!(value, Value::Bool(false) | Value::Nil)
+}
+
+// Value's order made total for sorting: NaN goes after every other float, and functions
+// go last, keeping their order among themselves
+fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+        (Value::List(a), Value::List(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| compare_values(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => a.partial_cmp(b).unwrap_or_else(|| a.type_rank().cmp(&b.type_rank())),
+    }
+}
+
+// The entries of a map, or of a JSON object, for map operations such as `assoc`
+fn map_entries(value: &Value, operation: &str) -> Result<IndexMap<String, Value>, Error> {
+    match value {
+        Value::Map(entries) => Ok(entries.clone()),
+        Value::Json(JsonValue::Object(entries)) => Ok(entries
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::from_json_value(value.clone())))
+            .collect()),
+        other => Err(Error::EvalError(format!("'{}' expects a map, got {}", operation, other))),
+    }
+}
+
+// Convert parsed TOML to garden values. Dates and times become strings.
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(n) => Value::Number(n),
+        toml::Value::Float(n) => Value::Float(n),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(items) => Value::List(items.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Map(table.into_iter().map(|(key, value)| (key, from_toml(value))).collect()),
+    }
+}
+
+// Convert parsed YAML to garden values. Map keys that aren't strings are written as
+// they display, and tags are dropped.
+fn from_yaml(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Null => Value::Nil,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(n) => Value::Number(n),
+            None => Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(items) => Value::List(items.into_iter().map(from_yaml).collect()),
+        serde_yaml::Value::Mapping(mapping) => Value::Map(mapping
+            .into_iter()
+            .map(|(key, value)| {
+                let key = match from_yaml(key) {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                (key, from_yaml(value))
+            })
+            .collect()),
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+// Convert `value` to the type named `target` where that can be done without guessing:
+// strings are parsed, floats truncated toward zero and booleans counted as 1 and 0
+fn coerce_to(target: &str, value: &Value) -> Result<Value, Error> {
+    let incompatible = || Error::TypeError(format!("cannot coerce {} {} to {}", value.type_name(), value, target));
+    match (target, value) {
+        ("number", Value::Number(n)) => Ok(Value::Number(*n)),
+        ("number", Value::String(s)) => s.trim().parse().map(Value::Number).map_err(|_| incompatible()),
+        // The range check also rejects NaN
+        ("number", Value::Float(f)) if (i64::MIN as f64..=i64::MAX as f64).contains(f) => Ok(Value::Number(f.trunc() as i64)),
+        ("number", Value::Bool(b)) => Ok(Value::Number(*b as i64)),
+        ("float", Value::Float(f)) => Ok(Value::Float(*f)),
+        ("float", Value::Number(n)) => Ok(Value::Float(*n as f64)),
+        ("float", Value::String(s)) => s.trim().parse().map(Value::Float).map_err(|_| incompatible()),
+        ("float", Value::Bool(b)) => Ok(Value::Float(*b as i64 as f64)),
+        ("string", Value::String(s)) => Ok(Value::String(s.clone())),
+        ("string", Value::Number(_) | Value::Float(_) | Value::Bool(_)) => Ok(Value::String(value.to_string())),
+        _ => Err(incompatible()),
+    }
+}
+
+// Replace each ${key} in `template` with the entry's value, strings without quotes.
+// ${{text}} is an escape for a literal ${text}.
+fn interpolate(template: &str, entries: &IndexMap<String, Value>) -> Result<String, Error> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        if let Some(escaped) = after.strip_prefix('{') {
+            let Some(end) = escaped.find("}}") else {
+                return Err(Error::EvalError(format!("'str.interpolate' found an unclosed ${{{{ in {:?}", template)));
+            };
+            out.push_str("${");
+            out.push_str(&escaped[..end]);
+            out.push('}');
+            rest = &escaped[end + 2..];
+            continue;
+        }
+        let Some(end) = after.find('}') else {
+            return Err(Error::EvalError(format!("'str.interpolate' found an unclosed ${{ in {:?}", template)));
+        };
+        let key = &after[..end];
+        match entries.get(key) {
+            Some(Value::String(s)) => out.push_str(s),
+            Some(value) => out.push_str(&value.to_string()),
+            None => return Err(Error::EvalError(format!("'str.interpolate' has no value for key '{}'", key))),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// The bindings a match of `value` against the literal `pattern` captures, or None if it
+// doesn't match. A map or JSON object pattern matches any map or object that has each of
+// its keys with a matching value, capturing those keys; anything else must equal `value`.
+fn match_pattern(value: &Value, pattern: &Value) -> Option<IndexMap<String, Value>> {
+    let is_object = |value: &Value| matches!(value, Value::Map(_) | Value::Json(JsonValue::Object(_)));
+    if is_object(pattern) {
+        if !is_object(value) {
+            return None;
+        }
+        let fields = map_entries(value, "match").ok()?;
+        let mut bindings = IndexMap::new();
+        for (key, expected) in map_entries(pattern, "match").ok()? {
+            let field = fields.get(&key)?;
+            bindings.extend(match_pattern(field, &expected)?);
+            bindings.insert(key, field.clone());
+        }
+        return Some(bindings);
+    }
+    let equal = match (value, pattern) {
+        (Value::Number(a), Value::Float(b)) | (Value::Float(b), Value::Number(a)) => *a as f64 == *b,
+        (a, b) => a == b,
+    };
+    equal.then(IndexMap::new)
+}
+
+// Add the leaves of `json` to `flat`, keyed by their path from the root joined with
+// `separator`. Array elements are keyed by index; empty objects and arrays are leaves.
+fn flatten_json(json: &JsonValue, path: &str, separator: &str, flat: &mut serde_json::Map<String, JsonValue>) {
+    let key = |segment: &str| if path.is_empty() { segment.to_string() } else { format!("{}{}{}", path, separator, segment) };
+    match json {
+        JsonValue::Object(entries) if !entries.is_empty() => {
+            for (name, value) in entries {
+                flatten_json(value, &key(name), separator, flat);
+            }
+        }
+        JsonValue::Array(items) if !items.is_empty() => {
+            for (index, value) in items.iter().enumerate() {
+                flatten_json(value, &key(&index.to_string()), separator, flat);
+            }
+        }
+        leaf => {
+            flat.insert(path.to_string(), leaf.clone());
+        }
+    }
+}
+
+// Rebuild the nested structure of flattened `entries`, splitting keys at `separator`.
+// An object whose keys are exactly 0, 1, 2, ... becomes an array.
+fn unflatten_json(entries: &serde_json::Map<String, JsonValue>, separator: &str) -> Result<JsonValue, Error> {
+    let mut root = JsonValue::Object(serde_json::Map::new());
+    for (key, value) in entries {
+        let (parents, last) = match key.rsplit_once(separator) {
+            Some((parents, last)) => (parents.split(separator).collect(), last),
+            None => (Vec::new(), key.as_str()),
+        };
+        let mut target = &mut root;
+        for segment in parents {
+            let JsonValue::Object(fields) = target else {
+                return Err(Error::EvalError(format!("'json.unflatten' can't nest {} under a key that already has a value", key)));
+            };
+            target = fields.entry(segment).or_insert_with(|| JsonValue::Object(serde_json::Map::new()));
+        }
+        let JsonValue::Object(fields) = target else {
+            return Err(Error::EvalError(format!("'json.unflatten' can't nest {} under a key that already has a value", key)));
+        };
+        fields.insert(last.to_string(), value.clone());
+    }
+    Ok(arrays_from_indices(root))
+}
+
+fn arrays_from_indices(json: JsonValue) -> JsonValue {
+    match json {
+        JsonValue::Object(fields) => {
+            let mut indexed: Vec<(usize, JsonValue)> = Vec::new();
+            for (key, value) in &fields {
+                match key.parse::<usize>() {
+                    Ok(index) if key == &index.to_string() => indexed.push((index, value.clone())),
+                    _ => break,
+                }
+            }
+            indexed.sort_by_key(|(index, _)| *index);
+            let is_array = !fields.is_empty()
+                && indexed.len() == fields.len()
+                && indexed.iter().enumerate().all(|(position, (index, _))| position == *index);
+            if is_array {
+                JsonValue::Array(indexed.into_iter().map(|(_, value)| arrays_from_indices(value)).collect())
+            } else {
+                JsonValue::Object(fields.into_iter().map(|(key, value)| (key, arrays_from_indices(value))).collect())
+            }
         }
-        
-        Ok(last_value)
+        other => other,
     }
+}
+
+// Convert an XML document to JSON by the BadgerFish convention: each element is an object
+// under its name, attributes are "@name" keys, text is under "$", and an element that
+// appears more than once in a parent is an array of them
+fn xml_to_json(xml: &str) -> Result<JsonValue, Error> {
+    use quick_xml::events::{BytesStart, Event};
     
-    // Evaluate only dirty nodes in the proper order
-    pub async fn evaluate_dirty_nodes<'a>(
-        &'a mut self,
-        env: &'a mut Env<'a>,
-    ) -> Result<(), Error> {
-        // Get the set of dirty nodes
-        let dirty_node_ids = self.dirty_nodes.clone();
-        
-        // Get the nodes in topological order
-        let sorted_node_ids = self.depdag.topological_sort(&dirty_node_ids);
+    let invalid = |e: &dyn std::fmt::Display| Error::EvalError(format!("'xml.parse' got invalid XML: {}", e));
+    let element = |start: &BytesStart| -> Result<(String, serde_json::Map<String, JsonValue>), Error> {
+        let mut fields = serde_json::Map::new();
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| invalid(&e))?;
+            let value = attribute.unescape_value().map_err(|e| invalid(&e))?;
+            fields.insert(format!("@{}", String::from_utf8_lossy(attribute.key.as_ref())), JsonValue::String(value.into_owned()));
+        }
+        Ok((String::from_utf8_lossy(start.name().as_ref()).into_owned(), fields))
+    };
+    // A finished element goes into its parent, turning repeats into an array
+    let add_child = |parent: &mut serde_json::Map<String, JsonValue>, name: String, child: JsonValue| {
+        match parent.get_mut(&name) {
+            Some(JsonValue::Array(siblings)) => siblings.push(child),
+            Some(sibling) => *sibling = JsonValue::Array(vec![sibling.take(), child]),
+            None => {
+                parent.insert(name, child);
+            }
+        }
+    };
+    
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    // Open elements with their name, fields and text so far; the first is the document
+    let mut open = vec![(String::new(), serde_json::Map::new(), String::new())];
+    loop {
+        match reader.read_event().map_err(|e| invalid(&e))? {
+            Event::Start(start) => {
+                let (name, fields) = element(&start)?;
+                open.push((name, fields, String::new()));
+            }
+            Event::Empty(start) => {
+                let (name, fields) = element(&start)?;
+                let parent = open.last_mut().map(|(_, fields, _)| fields).ok_or_else(|| invalid(&"unbalanced tags"))?;
+                add_child(parent, name, JsonValue::Object(fields));
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(|e| invalid(&e))?;
+                if let Some((_, _, content)) = open.last_mut() {
+                    content.push_str(&text);
+                }
+            }
+            Event::CData(data) => {
+                if let Some((_, _, content)) = open.last_mut() {
+                    content.push_str(&String::from_utf8_lossy(&data));
+                }
+            }
+            Event::End(_) => {
+                let (name, mut fields, content) = open.pop().ok_or_else(|| invalid(&"unbalanced tags"))?;
+                if !content.is_empty() {
+                    fields.insert("$".to_string(), JsonValue::String(content));
+                }
+                let parent = open.last_mut().map(|(_, fields, _)| fields).ok_or_else(|| invalid(&"unbalanced tags"))?;
+                add_child(parent, name, JsonValue::Object(fields));
+            }
+            Event::Eof => break,
+            // Declarations, comments and processing instructions carry no data
+            _ => {}
+        }
+    }
+    match open.pop() {
+        Some((_, document, _)) if open.is_empty() && !document.is_empty() => Ok(JsonValue::Object(document)),
+        Some(_) if !open.is_empty() => Err(invalid(&"unclosed element")),
+        _ => Err(invalid(&"no root element")),
+    }
+}
+
+// Parse an integer in the given radix. Without one, a 0x or 0b prefix selects hex or
+// binary and anything else is decimal. The prefix is also accepted with a matching radix.
+fn parse_number(s: &str, radix: Option<u32>) -> Result<Value, Error> {
+    let trimmed = s.trim();
+    let (negative, unsigned) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+    let prefixed = |prefix: &str| {
+        unsigned.strip_prefix(prefix).or_else(|| unsigned.strip_prefix(&prefix.to_uppercase()))
+    };
+    let (digits, radix) = match (radix, prefixed("0x"), prefixed("0b")) {
+        (None | Some(16), Some(hex), _) => (hex, 16),
+        (None | Some(2), _, Some(bin)) => (bin, 2),
+        (radix, _, _) => (unsigned, radix.unwrap_or(10)),
+    };
+    let signed = if negative { format!("-{}", digits) } else { digits.to_string() };
+    i64::from_str_radix(&signed, radix).map(Value::Number).map_err(|e| {
+        Error::EvalError(format!("Could not parse {:?} as a number in radix {}: {}", s, radix, e))
+    })
+}
+
+// Format a number with a printf-style format string. Supports %d, %f, %e, %x and %%,
+// with optional flags (`-` left-align, `0` zero-pad, `+` sign, `,` thousands separators),
+// a width and a precision, e.g. "%,.2f" or "%08d".
+fn format_number(number: &Value, format: &str) -> Result<String, Error> {
+    let invalid = |reason: &str| Error::EvalError(format!("Invalid format string {:?}: {}", format, reason));
+    let as_float = match number {
+        Value::Number(n) => *n as f64,
+        Value::Float(n) => *n,
+        _ => return Err(Error::EvalError(format!("Cannot format {:?} as a number", number))),
+    };
+    
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
         
-        // Evaluate each node in order
-        for node_id in sorted_node_ids {
-            if let Some(node) = self.get_node(&node_id) {
-                let _result = self.eval_node(&node, env).await;
-                // We don't need to do anything with the result here - it's already cached
+        let (mut left, mut zero, mut plus, mut thousands) = (false, false, false, false);
+        while let Some(flag) = chars.next_if(|c| matches!(c, '-' | '0' | '+' | ',')) {
+            match flag {
+                '-' => left = true,
+                '0' => zero = true,
+                '+' => plus = true,
+                _ => thousands = true,
             }
         }
+        let mut width = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width.push(digit);
+        }
+        let precision = if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            Some(digits.parse::<usize>().map_err(|_| invalid("missing precision after '.'"))?)
+        } else {
+            None
+        };
         
-        Ok(())
+        let mut body = match chars.next() {
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            Some('d') => match number {
+                Value::Number(n) => n.to_string(),
+                _ => format!("{:.0}", as_float.trunc()),
+            },
+            Some('f') => format!("{:.*}", precision.unwrap_or(6), as_float),
+            Some('e') => format!("{:.*e}", precision.unwrap_or(6), as_float),
+            Some('x') => match number {
+                Value::Number(n) => format!("{:x}", n),
+                _ => return Err(invalid("%x needs an integer")),
+            },
+            Some(other) => return Err(invalid(&format!("unknown conversion '%{}'", other))),
+            None => return Err(invalid("ends with an incomplete '%'")),
+        };
+        
+        if thousands {
+            body = group_thousands(&body);
+        }
+        if plus && !body.starts_with('-') {
+            body.insert(0, '+');
+        }
+        let width: usize = width.parse().unwrap_or(0);
+        let padding = width.saturating_sub(body.chars().count());
+        if left {
+            body.push_str(&" ".repeat(padding));
+        } else if zero {
+            // Zeros go between the sign and the digits
+            let sign_len = if body.starts_with(['-', '+']) { 1 } else { 0 };
+            body.insert_str(sign_len, &"0".repeat(padding));
+        } else {
+            body.insert_str(0, &" ".repeat(padding));
+        }
+        out.push_str(&body);
     }
+    Ok(out)
 }
 
-pub fn convert_json_value(json_val: JsonValue) -> Result<Value, Error> {
-    match json_val {
-        JsonValue::String(s) => Ok(Value::String(s)),
-        JsonValue::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                Ok(Value::Number(i))
-            } else {
-                Err(Error::EvalError(format!(
-                    "Unsupported number type from JSON: {}",
-                    n
-                )))
-            }
+// Insert commas every three digits in the integer part of a formatted number
+fn group_thousands(formatted: &str) -> String {
+    let (sign, rest) = match formatted.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", formatted),
+    };
+    let split = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let (integer, fraction) = rest.split_at(split);
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
         }
-        JsonValue::Bool(b) => Err(Error::EvalError(format!(
-            "Boolean JSON value ({}) not yet supported as primitive",
-            b
-        ))),
-        JsonValue::Null => Err(Error::EvalError(
-            "Null JSON value not yet supported as primitive".to_string(),
-        )),
-        JsonValue::Array(_) => Err(Error::EvalError(
-            "Array JSON value not yet supported as primitive".to_string(),
-        )),
-        JsonValue::Object(_) => Err(Error::EvalError(
-            "Nested JSON objects not directly supported as primitive values".to_string(),
-        )),
+        grouped.push(digit);
     }
+    format!("{}{}{}", sign, grouped, fraction)
 }
 
 // New struct for display
@@ -1063,78 +4920,313 @@ struct DisplayInfo {
     value_str: String,    // String representation of the Value or Error
 }
 
+// How run_once reports results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    // Coloured listing of the expressions that changed
+    #[default]
+    Text,
+    // A JSON object of {symbol: value}
+    Json,
+    // symbol,value rows
+    Csv,
+    // Graphviz dependency graph
+    Dot,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "dot" => Ok(OutputFormat::Dot),
+            other => Err(format!("Unknown output format '{}' (expected text, json, csv or dot)", other)),
+        }
+    }
+}
+
+// Render the defined variables in the given format, without any ANSI escapes.
+// The context carries no dependency edges, so Dot only lists the variables; run_once
+// uses Evaluator::export_dot for the full graph.
+pub fn format_output(context: &IndexMap<String, Value>, fmt: OutputFormat) -> String {
+    match fmt {
+        OutputFormat::Text => context
+            .iter()
+            .map(|(name, value)| format!("{} = {}\n", name, value))
+            .collect(),
+        OutputFormat::Json => {
+            let object: IndexMap<&str, JsonValue> = context
+                .iter()
+                // Values without a JSON form, like functions, are given as their source
+                .map(|(name, value)| (name.as_str(), value.to_json_value().unwrap_or_else(|_| JsonValue::String(value.to_string()))))
+                .collect();
+            let mut json = serde_json::to_string_pretty(&object).unwrap_or_default();
+            json.push('\n');
+            json
+        }
+        OutputFormat::Csv => {
+            let mut csv = String::from("symbol,value\n");
+            for (name, value) in context {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                csv.push_str(&format!("{},{}\n", csv_field(name), csv_field(&value)));
+            }
+            csv
+        }
+        OutputFormat::Dot => {
+            let mut dot = String::from("digraph garden {\n");
+            for (name, value) in context {
+                dot.push_str(&format!("    {:?} [label={:?}];\n", name, format!("{} = {}", name, value)));
+            }
+            dot.push_str("}\n");
+            dot
+        }
+    }
+}
+
+// Quote a CSV field if it contains a separator, quote or newline
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Garden definitions available to every program
+const PRELUDE: &str = include_str!("prelude.expr");
+
+// Stack for threads that evaluate garden code. Each call of a recursive garden
+// function nests several evaluation frames, which overflow the default stack.
+pub const EVAL_STACK_SIZE: usize = 256 * 1024 * 1024;
+
 // Main function
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
+fn main() {
+    let evaluation = std::thread::Builder::new()
+        .stack_size(EVAL_STACK_SIZE)
+        .spawn(|| {
+            let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+            // Reported here, as the error can't leave this thread
+            if let Err(e) = runtime.block_on(run()) {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+            Ok::<(), std::io::Error>(())
+        })
+        .expect("Could not start the evaluation thread");
+    match evaluation.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(1);
+        }
+        // The panic message has already been printed
+        Err(_) => std::process::exit(101),
+    }
+}
+
+// Diagnostics go to stderr, filtered by `level`, else RUST_LOG, else warnings
+// plus garden's own info messages.
+fn init_logging(level: Option<&str>) {
+    let filter = match level {
+        Some(level) => tracing_subscriber::EnvFilter::new(level),
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn,garden=info")),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    init_logging(cli.log_level());
+    cli.config_overrides().install();
+    
+    if let Some(Command::Cache { action }) = &cli.command {
+        match action {
+            CacheAction::Clean { file } => {
+                let cache_path = file.with_extension("expr.cache");
+                if remove_cache(&cache_path)? {
+                    println!("Removed {}", cache_path.display());
+                } else {
+                    println!("No cache at {}", cache_path.display());
+                }
+            }
+            CacheAction::List { file } => {
+                let mut cache = EvaluationCache::new();
+                cache.load_from_file(&file.with_extension("expr.cache"))?;
+                let src = fs::read_to_string(file).unwrap_or_default();
+                print!("{}", describe_cache(&cache, &src));
+            }
+            CacheAction::Merge { file, other } => {
+                let cache_path = file.with_extension("expr.cache");
+                let added = merge_cache_file(&cache_path, other)?;
+                println!("Merged {} results from {} into {}", added, other.display(), cache_path.display());
+            }
+        }
+        return Ok(());
+    }
     
-    if args.len() < 2 {
-        eprintln!("Usage: garden <file.expr>");
+    if !cli.eval.is_empty() {
+        if let Err(e) = eval_expressions(cli.context_file.as_deref(), &cli.eval).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
         return Ok(());
     }
     
-    let file_path = Path::new(&args[1]);
-    let cache_path = file_path.with_extension("expr.cache");
+    if let Some(port) = cli.jsonrpc_port {
+        return jsonrpc::serve(port, GardenConfig::load(Path::new("."))).await;
+    }
+    
+    if cli.lsp {
+        return lsp::serve().await;
+    }
+    
+    if cli.repl {
+        return repl::run(GardenConfig::load(Path::new("."))).await;
+    }
+    
+    if let Some(port) = cli.nrepl {
+        let config = GardenConfig::load(Path::new("."));
+        let port = port.or(config.nrepl.port).unwrap_or(0);
+        return nrepl::serve(port, config).await;
+    }
+    
+    // A dashboard can be served alongside the file watcher or the TUI
+    let broadcaster = match cli.ws_port {
+        Some(port) => Some(ws::serve(port).await?),
+        None => None,
+    };
+    
+    if let Some(dir) = &cli.watch_dir {
+        let dir = dir.canonicalize()?;
+        let files = expr_files(&dir, cli.recursive)?;
+        if cli.tui {
+            if files.is_empty() {
+                eprintln!("No .expr files in {}", dir.display());
+                return Ok(());
+            }
+            return tui::run(&files, broadcaster, Some((&dir, cli.recursive)), cli.show_prelude, cli.benchmark).await;
+        }
+        return watch_dir(&dir, cli.recursive, files, broadcaster.as_ref()).await;
+    }
+    
+    if cli.tui {
+        if cli.files.is_empty() {
+            Cli::command().error(ErrorKind::MissingRequiredArgument, "--tui needs at least one FILE").exit();
+        }
+        return tui::run(&cli.files, broadcaster, None, cli.show_prelude, cli.benchmark).await;
+    }
+    
+    let file_path = match cli.files.as_slice() {
+        [file] => file.as_path(),
+        [] => {
+            Cli::command().print_help()?;
+            return Ok(());
+        }
+        _ => Cli::command().error(ErrorKind::TooManyValues, "only one FILE can be watched without --tui").exit(),
+    };
+    let cache_path = (!cli.no_cache).then(|| file_path.with_extension("expr.cache"));
+    
+    if let (true, Some(cache_path)) = (cli.clear_cache, &cache_path) {
+        remove_cache(cache_path)?;
+    }
     
-    // Initialize the evaluator
-    let mut evaluator = Evaluator::new();
+    // Initialize the evaluator with the nearest .garden.toml
+    let mut evaluator = Evaluator::with_config(GardenConfig::for_file(file_path));
+    if cli.benchmark {
+        evaluator.enable_timings();
+    }
     
     // Try to load previous cache
-    if let Err(e) = evaluator.load_cache(&cache_path) {
-        eprintln!("Warning: Could not load cached values: {}", e);
+    if let Some(cache_path) = &cache_path {
+        if let Err(e) = evaluator.load_cache(cache_path) {
+            tracing::warn!(cache = %cache_path.display(), "Could not load cached values: {}", e);
+        }
+    }
+    for name in &cli.invalidate_memo {
+        if !evaluator.invalidate_memo(name) {
+            tracing::warn!("No memo-def value named {} to invalidate", name);
+        }
     }
     
     // Create a channel to receive file change events
     let (tx, rx) = mpsc::channel();
     
+    evaluator.notify_changes_to(tx.clone(), file_path.to_path_buf());
+    
     // Create a file watcher
     let mut watcher = recommended_watcher(tx)?;
     
     // Watch the target file
     watcher.watch(file_path, RecursiveMode::NonRecursive)?;
     
-    println!("Garden is watching {}...", file_path.display());
-    println!("(Press Ctrl+C to exit)");
+    // Keep stdout clean for machine-readable formats
+    if cli.format == OutputFormat::Text {
+        println!("Garden is watching {}...", file_path.display());
+        println!("(Press Ctrl+C to exit)");
+    }
     
     // Initial run
-    if let Err(e) = run_once(file_path, &mut evaluator).await {
-        eprintln!("Error: {}", e);
+    if let Err(e) = run_once(file_path, &mut evaluator, cli.format, broadcaster.as_ref(), None).await {
+        tracing::error!(file = %file_path.display(), "{}", e);
     }
     
     // Save cache
-    if let Err(e) = evaluator.save_cache(&cache_path) {
-        eprintln!("Warning: Could not save cache: {}", e);
+    if let Some(cache_path) = &cache_path {
+        if let Err(e) = evaluator.save_cache(cache_path) {
+            tracing::warn!(cache = %cache_path.display(), "Could not save cache: {}", e);
+        }
     }
     
     // Event loop
-    for res in rx {
-        match res {
-            Ok(_) => {
-                if let Err(e) = run_once(file_path, &mut evaluator).await {
-                    eprintln!("Error: {}", e);
-                } else {
-                    // Save cache after successful run
-                    if let Err(e) = evaluator.save_cache(&cache_path) {
-                        eprintln!("Warning: Could not save cache: {}", e);
-                    }
-                }
+    let debounce = evaluator.config.debounce();
+    while let Some(events) = next_changes(&rx, debounce) {
+        let mut changed = false;
+        for res in events {
+            match res {
+                // Our own reads of the file fire access events; only react to real changes
+                Ok(event) => changed |= event.kind.is_modify() || event.kind.is_create(),
+                Err(e) => tracing::error!("Watch error: {:?}", e),
+            }
+        }
+        if !changed {
+            continue;
+        }
+        if let Err(e) = run_once(file_path, &mut evaluator, cli.format, broadcaster.as_ref(), None).await {
+            tracing::error!(file = %file_path.display(), "{}", e);
+        } else if let Some(cache_path) = &cache_path {
+            // Save cache after successful run
+            if let Err(e) = evaluator.save_cache(cache_path) {
+                tracing::warn!(cache = %cache_path.display(), "Could not save cache: {}", e);
             }
-            Err(e) => eprintln!("Watch error: {:?}", e),
         }
     }
     
     Ok(())
 }
 
-async fn run_once(path: &Path, evaluator: &mut Evaluator) -> Result<(), Box<dyn std::error::Error>> {
-    println!("\nRevaluating expressions in {}...", path.display());
-    
+// Parse and evaluate source text, returning the root nodes. Parse errors are returned
+// as Err; an evaluation error is returned alongside the nodes so callers can still
+// show whatever was evaluated before it.
+pub async fn evaluate_source(src: &str, evaluator: &mut Evaluator) -> Result<(Vec<Rc<Node>>, Option<Error>), Error> {
     evaluator.prepare_for_evaluation();
-    
-    let src = fs::read_to_string(path)?;
+    // Definitions removed from the source must not linger
+    evaluator.globals.clear();
     
     // Parse the source file into a vector of root nodes
-    let root_nodes = parser::parse(&src)?;
+    let root_nodes = parser::parse(src)?;
     
     // Create a top-level environment
     let mut env = Env::new();
@@ -1144,34 +5236,291 @@ async fn run_once(path: &Path, evaluator: &mut Evaluator) -> Result<(), Box<dyn
         evaluator.store_node(node.clone());
     }
     
+    evaluator.stop_removed_watches(&root_nodes);
+    
     // First, evaluate the sequence of root nodes to build up the dependency graph
     if let Err(e) = evaluator.evaluate_sequence(&root_nodes, &mut env).await {
-        eprintln!("Evaluation error: {}", e);
-    } else {
-        // Now mark all changed nodes as dirty
-        for node in &root_nodes {
-            evaluator.mark_dirty(*node.id());
+        return Ok((root_nodes, Some(e)));
+    }
+    
+    // Now mark all changed nodes as dirty
+    for node in &root_nodes {
+        evaluator.mark_dirty(*node.id());
+    }
+    
+    // Create a new environment for the second evaluation pass
+    let mut new_env = Env::new();
+    
+    // Evaluate dirty nodes
+    let incremental_error = evaluator.evaluate_dirty_nodes(&mut new_env).await.err();
+    
+    Ok((root_nodes, incremental_error))
+}
+
+// Evaluate each expression in order in one shared environment, printing each result.
+// Definitions from `context_file` are loaded into the environment first.
+async fn eval_expressions(context_file: Option<&Path>, expressions: &[String]) -> Result<(), Error> {
+    let config = match context_file {
+        Some(path) => GardenConfig::for_file(path),
+        None => GardenConfig::load(Path::new(".")),
+    };
+    let mut evaluator = Evaluator::with_config(config);
+    let mut env = Env::new();
+
+    if let Some(path) = context_file {
+        let src = fs::read_to_string(path)
+            .map_err(|e| Error::EvalError(format!("Could not read {}: {}", path.display(), e)))?;
+        let nodes = parser::parse(&src)?;
+        for node in &nodes {
+            evaluator.store_node(node.clone());
+        }
+        evaluator.evaluate_sequence(&nodes, &mut env).await?;
+    }
+
+    for expr in expressions {
+        let nodes = parser::parse(expr)?;
+        evaluator.prepare_for_evaluation();
+        for node in &nodes {
+            evaluator.store_node(node.clone());
+        }
+        match evaluator.evaluate_sequence(&nodes, &mut env).await? {
+            Some(value) => println!("{}", value),
+            None => println!("nil"),
+        }
+    }
+    Ok(())
+}
+
+// Delete a cache file, returning whether there was one
+fn remove_cache(cache_path: &Path) -> std::io::Result<bool> {
+    match fs::remove_file(cache_path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// One line per cached result, with the code it's the result of where `src` has it
+fn describe_cache(cache: &EvaluationCache, src: &str) -> String {
+    if cache.is_empty() {
+        return "No cached results\n".to_string();
+    }
+    let snippets: HashMap<NodeId, String> = parser::parse(src)
+        .unwrap_or_default()
+        .iter()
+        .flat_map(collect_all_nodes)
+        .map(|node| (*node.id(), node.code_snippet().to_string()))
+        .collect();
+    let mut lines: Vec<String> = cache
+        .into_iter()
+        .map(|(id, result)| {
+            let result = match result {
+                Ok(value) => value.to_string(),
+                Err(e) => format!("Error: {}", e),
+            };
+            match snippets.get(id) {
+                Some(snippet) => format!("{} {} => {}", hex::encode(&id[0..4]), snippet, result),
+                None => format!("{} => {}", hex::encode(&id[0..4]), result),
+            }
+        })
+        .collect();
+    lines.sort();
+    format!("{} cached results\n{}\n", cache.len(), lines.join("\n"))
+}
+
+// Add the results cached in `other` to those in `cache_path`, returning how many were
+// added or replaced
+fn merge_cache_file(cache_path: &Path, other: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    if !other.exists() {
+        return Err(format!("No cache at {}", other.display()).into());
+    }
+    let mut theirs = EvaluationCache::new();
+    theirs.load_from_file(other)?;
+    let mut ours = EvaluationCache::new();
+    ours.load_from_file(cache_path)?;
+    let added = ours.merge(&theirs);
+    ours.save_to_file(cache_path)?;
+    Ok(added)
+}
+
+// All .expr files in `dir` (and its subdirectories when `recursive`), sorted by path
+fn expr_files(dir: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                files.extend(expr_files(&path, true)?);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "expr") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Watch every .expr file in `dir`, evaluating each independently with its own cache.
+// Files created later are picked up as they appear.
+async fn watch_dir(
+    dir: &Path,
+    recursive: bool,
+    files: Vec<PathBuf>,
+    broadcaster: Option<&ws::Broadcaster>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut evaluators: HashMap<PathBuf, Evaluator> = HashMap::new();
+    // Files often repeat expressions; each is only evaluated by the first file to need it
+    let shared = Arc::new(Mutex::new(SharedNodeCache::default()));
+    
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = recommended_watcher(tx.clone())?;
+    let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    watcher.watch(dir, mode)?;
+    
+    println!("Garden is watching {} .expr files in {}...", files.len(), dir.display());
+    println!("(Press Ctrl+C to exit)");
+    
+    let debounce = GardenConfig::load(dir).debounce();
+    let mut pending = files;
+    loop {
+        for path in pending.drain(..) {
+            let evaluator = evaluators.entry(path.clone()).or_insert_with(|| {
+                let mut evaluator = Evaluator::with_config(GardenConfig::for_file(&path));
+                if let Err(e) = evaluator.load_cache(&path.with_extension("expr.cache")) {
+                    tracing::warn!(file = %path.display(), "Could not load cached values: {}", e);
+                }
+                evaluator.notify_changes_to(tx.clone(), path.clone());
+                evaluator.share_results(shared.clone());
+                evaluator
+            });
+            
+            let label = path.strip_prefix(dir).unwrap_or(&path).display().to_string();
+            if let Err(e) = run_once(&path, evaluator, OutputFormat::Text, broadcaster, Some(&label)).await {
+                tracing::error!(file = %label, "{}", e);
+            } else if let Err(e) = evaluator.save_cache(&path.with_extension("expr.cache")) {
+                tracing::warn!(file = %label, "Could not save cache: {}", e);
+            }
         }
         
-        // Create a new environment for the second evaluation pass
-        let mut new_env = Env::new();
-        
-        // Evaluate dirty nodes
-        if let Err(e) = evaluator.evaluate_dirty_nodes(&mut new_env).await {
-            eprintln!("Evaluation error during incremental update: {}", e);
+        let Some(events) = next_changes(&rx, debounce) else {
+            return Ok(());
+        };
+        for res in events {
+            match res {
+                // Our own reads of the files fire access events; only react to real changes
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    for path in event.paths {
+                        let is_expr = path.extension().is_some_and(|ext| ext == "expr") && path.is_file();
+                        if is_expr && !pending.contains(&path) {
+                            pending.push(path);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Watch error: {:?}", e),
+            }
+        }
+    }
+}
+
+// Wait for the next watcher event, then for `debounce` more so the several events of one
+// save arrive together. Returns every event received, or None once the watcher is gone.
+fn next_changes(rx: &mpsc::Receiver<notify::Result<notify::Event>>, debounce: Duration) -> Option<Vec<notify::Result<notify::Event>>> {
+    let mut events = vec![rx.recv().ok()?];
+    let deadline = Instant::now() + debounce;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match rx.recv_timeout(remaining) {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    Some(events)
+}
+
+async fn run_once(
+    path: &Path,
+    evaluator: &mut Evaluator,
+    format: OutputFormat,
+    broadcaster: Option<&ws::Broadcaster>,
+    label: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // When watching several files, each line says which file it is about
+    let prefix = label.map(|label| format!("[{}] ", label)).unwrap_or_default();
+    if format == OutputFormat::Text {
+        println!("\n{}Revaluating expressions in {}...", prefix, path.display());
+    }
+    
+    let src = fs::read_to_string(path)?;
+    
+    let (root_nodes, error) = match evaluate_source(&src, evaluator).await {
+        Ok(evaluated) => evaluated,
+        Err(e) => {
+            eprintln!("{}{}", prefix, format_error_with_context(&e, &src));
+            return Ok(());
+        }
+    };
+    if let Some(e) = error {
+        // The first root whose cached result is an error is where evaluation stopped
+        let message = format!("{}Evaluation error: {}", prefix, e);
+        let failed = root_nodes
+            .iter()
+            .find(|node| matches!(evaluator.get_cached_result(node.id()), Some(Err(_))))
+            .and_then(|node| SourceSpan::of(node));
+        match failed {
+            Some(span) => eprintln!("{}", show_in_source(&src, span.line, span.column, &message)),
+            None => eprintln!("{}", message),
+        }
+    }
+    // Results of edited or deleted expressions would otherwise stay in the cache file forever
+    evaluator.prune_unreachable(&root_nodes);
+    
+    if let Some(broadcaster) = broadcaster {
+        broadcaster.publish(path, &evaluator.context(&root_nodes));
+    }
+    
+    match format {
+        OutputFormat::Text => {}
+        OutputFormat::Dot => {
+            print!("{}", evaluator.export_dot());
+            return Ok(());
+        }
+        OutputFormat::Json | OutputFormat::Csv => {
+            print!("{}", format_output(&evaluator.context(&root_nodes), format));
+            return Ok(());
+        }
+    }
+    
+    let display_items = collect_display_info(evaluator, &evaluator.get_changed_nodes());
+    
+    println!("{}Changed expressions:", prefix);
+    if display_items.is_empty() {
+        println!("{}No expressions changed in this evaluation.", prefix);
+    } else {
+        for item in display_items {
+            println!("\x1B[2K{}\x1B[0;1m{:>3}|\x1B[0m {} \x1B[0;36m[{}]\x1B[0m \x1B[0;32m=> {}\x1B[0m", 
+                    prefix, item.line, item.code_snippet, item.id_hex_short, item.value_str);
         }
     }
     
-    // Get all changed nodes for display
-    let changed_nodes = evaluator.get_changed_nodes();
+    if let Some(timings) = evaluator.timings() {
+        print_timings(evaluator, timings, &prefix);
+    }
     
-    // Convert to DisplayInfo
+    Ok(())
+}
+
+// What to show for each changed node, in line order
+fn collect_display_info(evaluator: &Evaluator, changed_nodes: &[Rc<Node>]) -> Vec<DisplayInfo> {
     let mut display_items: Vec<DisplayInfo> = Vec::new();
-    for node in &changed_nodes {
-        let line_str = node.metadata().get("line")
-            .expect("Node metadata should contain 'line' information");
-        let line = line_str.parse::<usize>()
-            .expect("Line metadata should be a parsable usize");
+    for node in changed_nodes {
+        // Nodes built outside the parser may have no line; they are listed first
+        let line = match node.metadata().get("line").map(|line| line.parse::<usize>()) {
+            Some(Ok(line)) => line,
+            Some(Err(_)) | None => {
+                tracing::warn!(node = %hex::encode(&node.id()[0..4]), "Changed node has no line number");
+                0
+            }
+        };
         
         let id_hex_short = hex::encode(&node.id()[0..4]); // First 4 bytes for display
         
@@ -1191,18 +5540,33 @@ async fn run_once(path: &Path, evaluator: &mut Evaluator) -> Result<(), Box<dyn
     
     // Sort by line number for ordered output
     display_items.sort_by_key(|item| item.line);
-    
-    println!("Changed expressions:");
-    if display_items.is_empty() {
-        println!("No expressions changed in this evaluation.");
-    } else {
-        for item in display_items {
-            println!("\x1B[2K\x1B[0;1m{:>3}|\x1B[0m {} \x1B[0;36m[{}]\x1B[0m \x1B[0;32m=> {}\x1B[0m", 
-                    item.line, item.code_snippet, item.id_hex_short, item.value_str);
-        }
+    display_items
+}
+
+// Rows of the --benchmark report
+const BENCHMARK_ROWS: usize = 20;
+
+// Print the slowest node evaluations of the last run as a table
+fn print_timings(evaluator: &Evaluator, timings: &EvalTimings, prefix: &str) {
+    let slowest = timings.slowest();
+    println!("{}Slowest evaluations:", prefix);
+    if slowest.is_empty() {
+        println!("{}Nothing was evaluated; every result came from the cache.", prefix);
+    }
+    for (id, elapsed) in slowest.iter().take(BENCHMARK_ROWS) {
+        let snippet = evaluator
+            .get_node(id)
+            .map(|node| node.code_snippet().split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+        let snippet = match snippet.char_indices().nth(50) {
+            Some((end, _)) => format!("{}...", &snippet[..end]),
+            None => snippet,
+        };
+        println!("{}{:<53} | {:>10.3} ms | {}", prefix, snippet, elapsed.as_secs_f64() * 1000.0, hex::encode(&id[0..4]));
+    }
+    if slowest.len() > BENCHMARK_ROWS {
+        println!("{}... and {} faster", prefix, slowest.len() - BENCHMARK_ROWS);
     }
-    
-    Ok(())
 }
 
 
@@ -0,0 +1,851 @@
+use std::{cell::RefCell, collections::{hash_map::Entry, HashMap, HashSet}, path::Path, rc::Rc};
+use bytes::BytesMut;
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::{config::GardenConfig, format_error_with_context, parser, Env, Error, Evaluator, NodeId, NodeKind, SourceMap, Value};
+
+// Upper bound for a single framed message; anything larger is treated as malformed
+const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+// === FRAMING ===
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    // The bytes can never form a valid bencode message, no matter what arrives next
+    Malformed(String),
+    // A declared string length or the message itself exceeds MAX_MESSAGE_BYTES
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Malformed(msg) => write!(f, "malformed bencode: {}", msg),
+            FrameError::TooLarge(len) => write!(f, "message of {} bytes exceeds the {} byte limit", len, MAX_MESSAGE_BYTES),
+        }
+    }
+}
+
+// Find the end of the first complete bencode dict in `buf`.
+// Returns Ok(Some(len)) when `buf[..len]` holds a whole message, Ok(None) when more
+// data is needed, and Err when the buffer can never become a valid message.
+pub fn frame_len(buf: &[u8]) -> Result<Option<usize>, FrameError> {
+    match buf.first() {
+        None => return Ok(None),
+        Some(b'd') => {}
+        Some(other) => {
+            return Err(FrameError::Malformed(format!(
+                "messages must be dicts, got leading byte {:?}",
+                *other as char
+            )))
+        }
+    }
+
+    let mut depth = 0usize;
+    let mut pos = 0usize;
+
+    while pos < buf.len() {
+        if pos > MAX_MESSAGE_BYTES {
+            return Err(FrameError::TooLarge(pos));
+        }
+
+        match buf[pos] {
+            b'd' | b'l' => {
+                depth += 1;
+                pos += 1;
+            }
+            b'e' => {
+                if depth == 0 {
+                    return Err(FrameError::Malformed(format!("unexpected 'e' at byte {}", pos)));
+                }
+                depth -= 1;
+                pos += 1;
+                if depth == 0 {
+                    return Ok(Some(pos));
+                }
+            }
+            b'i' => {
+                // Integer: i<digits>e
+                let start = pos + 1;
+                let mut end = start;
+                while end < buf.len() && buf[end] != b'e' {
+                    let c = buf[end];
+                    if !(c.is_ascii_digit() || (c == b'-' && end == start)) {
+                        return Err(FrameError::Malformed(format!("invalid integer byte {:?} at {}", c as char, end)));
+                    }
+                    end += 1;
+                }
+                if end == buf.len() {
+                    return Ok(None);
+                }
+                if end == start {
+                    return Err(FrameError::Malformed(format!("empty integer at byte {}", pos)));
+                }
+                pos = end + 1;
+            }
+            b'0'..=b'9' => {
+                // Byte string: <len>:<bytes>
+                let start = pos;
+                let mut colon = start;
+                while colon < buf.len() && buf[colon].is_ascii_digit() {
+                    colon += 1;
+                }
+                if colon == buf.len() {
+                    return Ok(None);
+                }
+                if buf[colon] != b':' {
+                    return Err(FrameError::Malformed(format!(
+                        "expected ':' after string length at byte {}",
+                        colon
+                    )));
+                }
+                let len: usize = std::str::from_utf8(&buf[start..colon])
+                    .ok()
+                    .and_then(|digits| digits.parse().ok())
+                    .ok_or_else(|| FrameError::Malformed(format!("invalid string length at byte {}", start)))?;
+                if len > MAX_MESSAGE_BYTES {
+                    return Err(FrameError::TooLarge(len));
+                }
+                let end = colon + 1 + len;
+                if end > buf.len() {
+                    return Ok(None);
+                }
+                pos = end;
+            }
+            other => {
+                return Err(FrameError::Malformed(format!(
+                    "unexpected byte {:?} at {}",
+                    other as char, pos
+                )))
+            }
+        }
+    }
+
+    // Ran out of bytes inside the dict
+    Ok(None)
+}
+
+// === MESSAGES ===
+
+#[derive(Debug, Deserialize)]
+struct NreplRequest {
+    op: String,
+    id: Option<String>,
+    session: Option<String>,
+    code: Option<String>,
+    // The file `code` comes from, if the client sent it from one
+    file: Option<String>,
+    // Symbol to look up
+    sym: Option<String>,
+    // Where in code evaluated in the session to look up the symbol, when `sym` isn't given
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct NreplResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<String>,
+    #[serde(rename = "new-session", skip_serializing_if = "Option::is_none")]
+    new_session: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    out: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ns: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    err: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    col: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sessions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ops: Option<HashMap<String, HashMap<String, String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    versions: Option<HashMap<String, HashMap<String, String>>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    status: Vec<String>,
+}
+
+impl NreplResponse {
+    // Start a response that echoes the request's id and session
+    fn reply_to(request: &NreplRequest) -> Self {
+        Self {
+            id: request.id.clone(),
+            session: request.session.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn done(mut self) -> Self {
+        self.status.push("done".to_string());
+        self
+    }
+
+    fn error(mut self, err: &str) -> Self {
+        self.err = Some(err.to_string());
+        self.status = vec!["error".to_string(), "done".to_string()];
+        self
+    }
+
+    // Output printed while evaluating, sent ahead of the result
+    fn with_out(mut self, output: &str) -> Self {
+        self.out = Some(output.to_string());
+        self
+    }
+
+    // The request can't be handled, for the reason in `status`
+    fn failed(mut self, status: &str) -> Self {
+        self.status = vec!["error".to_string(), status.to_string(), "done".to_string()];
+        self
+    }
+
+    // The request named a session that was closed or never existed
+    fn unknown_session(self) -> Self {
+        self.failed("unknown-session")
+    }
+}
+
+// === SESSIONS ===
+
+// Each session owns an evaluator and a top-level environment that persists between evals
+struct Session {
+    evaluator: Evaluator,
+    env: Env<'static>,
+    // Where everything evaluated in the session was written
+    source_map: SourceMap,
+    // The defining form of each name defined at the top level, latest first
+    definitions: HashMap<String, NodeId>,
+}
+
+impl Session {
+    fn new(config: &GardenConfig) -> Self {
+        let mut evaluator = Evaluator::with_config(config.clone());
+        evaluator.capture_output();
+        Self {
+            evaluator,
+            env: Env::new(),
+            source_map: SourceMap::default(),
+            definitions: HashMap::new(),
+        }
+    }
+
+    async fn eval(&mut self, code: &str, file: &Path) -> Result<Option<Value>, Error> {
+        let nodes = parser::parse(code)?;
+        self.source_map.add(&nodes, file);
+        for node in &nodes {
+            if let (NodeKind::Definition | NodeKind::LetStatement, Some(NodeKind::Symbol(name))) =
+                (node.kind(), node.children().get(1).map(|child| child.kind()))
+            {
+                self.definitions.insert(name.clone(), *node.id());
+            }
+        }
+
+        self.evaluator.prepare_for_evaluation();
+        for node in &nodes {
+            self.evaluator.store_node(node.clone());
+        }
+
+        self.evaluator.evaluate_sequence(&nodes, &mut self.env).await
+    }
+}
+
+// Live sessions, plus the config each new session starts with
+struct SessionStore {
+    // Each session is locked while it evaluates, so evals sent to a busy session wait
+    // their turn
+    sessions: RefCell<HashMap<String, Rc<Mutex<Session>>>>,
+    // Ids of the eval requests each session is running or has queued, to tell a resent
+    // request from a new one
+    in_progress: RefCell<HashMap<String, HashSet<String>>>,
+    config: GardenConfig,
+}
+
+impl SessionStore {
+    fn new(config: GardenConfig) -> Self {
+        Self {
+            sessions: RefCell::new(HashMap::new()),
+            in_progress: RefCell::new(HashMap::new()),
+            config,
+        }
+    }
+
+    // Start a session, giving its id
+    fn open(&self) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.sessions.borrow_mut().insert(id.clone(), Rc::new(Mutex::new(Session::new(&self.config))));
+        id
+    }
+
+    fn get(&self, id: &str) -> Option<Rc<Mutex<Session>>> {
+        self.sessions.borrow().get(id).cloned()
+    }
+
+    // Note that eval request `message_id` is running in a session, returning false if
+    // one with that id already is
+    fn start_eval(&self, session_id: &str, message_id: &str) -> bool {
+        self.in_progress.borrow_mut().entry(session_id.to_string()).or_default().insert(message_id.to_string())
+    }
+
+    fn finish_eval(&self, session_id: &str, message_id: &str) {
+        if let Entry::Occupied(mut running) = self.in_progress.borrow_mut().entry(session_id.to_string()) {
+            running.get_mut().remove(message_id);
+            if running.get().is_empty() {
+                running.remove();
+            }
+        }
+    }
+}
+
+// What one client connection shares between the requests it has in flight
+struct Connection {
+    sessions: Rc<SessionStore>,
+    // Session used for requests that don't name one, until the client closes it
+    current_session_id: RefCell<Option<String>>,
+    // Sessions this connection opened, which are closed when it disconnects
+    owned_sessions: RefCell<HashSet<String>>,
+    writer: Mutex<OwnedWriteHalf>,
+}
+
+// === SERVER ===
+
+// Start an nREPL server on the given port (0 picks a free port)
+pub async fn serve(port: u16, config: GardenConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("nREPL server started on port {}", listener.local_addr()?.port());
+
+    // Evaluators hold Rc nodes, so clients are served on a single-threaded LocalSet
+    let local = tokio::task::LocalSet::new();
+    local.run_until(accept_clients(listener, config)).await
+}
+
+async fn accept_clients(listener: TcpListener, config: GardenConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let sessions = Rc::new(SessionStore::new(config));
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let sessions = sessions.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = handle_client(stream, sessions).await {
+                tracing::warn!(client = %addr, "nREPL client error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_client(stream: TcpStream, sessions: Rc<SessionStore>) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut reader, writer) = stream.into_split();
+    let id = sessions.open();
+    let connection = Rc::new(Connection {
+        sessions,
+        current_session_id: RefCell::new(Some(id.clone())),
+        owned_sessions: RefCell::new(HashSet::from([id])),
+        writer: Mutex::new(writer),
+    });
+
+    let mut requests = JoinSet::new();
+    let result = read_requests(&mut reader, &connection, &mut requests).await;
+    // Let requests already running finish before their sessions go
+    while requests.join_next().await.is_some() {}
+    let mut store = connection.sessions.sessions.borrow_mut();
+    for id in connection.owned_sessions.borrow().iter() {
+        store.remove(id);
+    }
+    result
+}
+
+// Read messages until the client disconnects, handling each as its own task so that a
+// long evaluation doesn't hold up the requests after it
+async fn read_requests(
+    reader: &mut OwnedReadHalf,
+    connection: &Rc<Connection>,
+    requests: &mut JoinSet<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut buffer = BytesMut::with_capacity(4096);
+
+    loop {
+        // Handle every complete message currently buffered before reading more
+        loop {
+            match frame_len(&buffer) {
+                Ok(Some(len)) => {
+                    let frame = buffer.split_to(len);
+                    let connection = connection.clone();
+                    requests.spawn_local(async move {
+                        let mut responses = Vec::new();
+                        let response = match serde_bencode::from_bytes::<NreplRequest>(&frame) {
+                            Ok(request) => handle_request(request, &connection, &mut responses).await,
+                            Err(e) => NreplResponse::default().error(&format!("Could not decode message: {}", e)),
+                        };
+                        responses.push(response);
+                        if let Err(e) = send(&connection, &responses).await {
+                            tracing::warn!("Could not send nREPL response: {}", e);
+                        }
+                    });
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    // The stream is out of sync; report and drop the connection
+                    send(connection, &[NreplResponse::default().error(&e.to_string())]).await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        if reader.read_buf(&mut buffer).await? == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// Write responses to the client together, so other requests' responses don't land
+// between them
+async fn send(connection: &Connection, responses: &[NreplResponse]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = connection.writer.lock().await;
+    for response in responses {
+        writer.write_all(&serde_bencode::to_bytes(response)?).await?;
+    }
+    Ok(())
+}
+
+// The final response to `request`. Responses to send before it, such as printed output,
+// are added to `preceding`.
+async fn handle_request(request: NreplRequest, connection: &Connection, preceding: &mut Vec<NreplResponse>) -> NreplResponse {
+    let sessions = &connection.sessions;
+    // The session the request names, else the connection's current one
+    let session_id = request.session.clone().or_else(|| connection.current_session_id.borrow().clone());
+    match request.op.as_str() {
+        "clone" => {
+            let id = sessions.open();
+            connection.owned_sessions.borrow_mut().insert(id.clone());
+            let mut response = NreplResponse::reply_to(&request);
+            response.new_session = Some(id);
+            response.done()
+        }
+        "close" => {
+            let Some(session_id) = session_id else {
+                return NreplResponse::reply_to(&request).unknown_session();
+            };
+            if sessions.sessions.borrow_mut().remove(&session_id).is_none() {
+                return NreplResponse::reply_to(&request).unknown_session();
+            }
+            connection.owned_sessions.borrow_mut().remove(&session_id);
+            let mut current_session_id = connection.current_session_id.borrow_mut();
+            if current_session_id.as_ref() == Some(&session_id) {
+                *current_session_id = None;
+            }
+            NreplResponse::reply_to(&request).done()
+        }
+        "ls-sessions" => {
+            let mut ids: Vec<String> = sessions.sessions.borrow().keys().cloned().collect();
+            ids.sort();
+            let mut response = NreplResponse::reply_to(&request);
+            response.sessions = Some(ids);
+            response.done()
+        }
+        "describe" => {
+            let ops = ["clone", "close", "describe", "eval", "format-code", "lookup", "ls-sessions"]
+                .iter()
+                .map(|op| (op.to_string(), HashMap::new()))
+                .collect();
+            let mut garden_version = HashMap::new();
+            garden_version.insert("version-string".to_string(), env!("CARGO_PKG_VERSION").to_string());
+            let mut versions = HashMap::new();
+            versions.insert("garden".to_string(), garden_version);
+
+            let mut response = NreplResponse::reply_to(&request);
+            response.ops = Some(ops);
+            response.versions = Some(versions);
+            response.done()
+        }
+        "eval" => {
+            let Some(code) = request.code.clone() else {
+                return NreplResponse::reply_to(&request).error("'eval' requires a 'code' field");
+            };
+            let Some((session_id, session)) = session_id.and_then(|id| Some((id.clone(), sessions.get(&id)?))) else {
+                return NreplResponse::reply_to(&request).unknown_session();
+            };
+
+            // A request resent while the first is still queued or running is answered once
+            let message_id = request.id.clone().unwrap_or_default();
+            if request.id.is_some() && !sessions.start_eval(&session_id, &message_id) {
+                return NreplResponse::reply_to(&request).failed("duplicate-id");
+            }
+            let (result, output) = {
+                let mut session = session.lock().await;
+                let file = request.file.as_deref().unwrap_or("<nrepl>");
+                let result = session.eval(&code, Path::new(file)).await;
+                (result, session.evaluator.take_output())
+            };
+            if request.id.is_some() {
+                sessions.finish_eval(&session_id, &message_id);
+            }
+
+            for line in output {
+                let mut response = NreplResponse::reply_to(&request).with_out(&line);
+                response.session = Some(session_id.clone());
+                preceding.push(response);
+            }
+            let mut response = NreplResponse::reply_to(&request);
+            response.session = Some(session_id);
+            match result {
+                Ok(value) => {
+                    response.value = Some(value.map(|v| v.to_string()).unwrap_or_else(|| "nil".to_string()));
+                    response.ns = Some("user".to_string());
+                    response.done()
+                }
+                Err(e) => response.error(&format_error_with_context(&e, &code)),
+            }
+        }
+        "format-code" => {
+            let Some(code) = &request.code else {
+                return NreplResponse::reply_to(&request).error("'format-code' requires a 'code' field");
+            };
+            match parser::format_code(code) {
+                Ok(formatted) => {
+                    let mut response = NreplResponse::reply_to(&request);
+                    response.formatted = Some(formatted);
+                    response.done()
+                }
+                Err(e) => NreplResponse::reply_to(&request).error(&e.to_string()),
+            }
+        }
+        "lookup" => {
+            let position = request.line.zip(request.column);
+            if request.sym.is_none() && position.is_none() {
+                return NreplResponse::reply_to(&request).error("'lookup' requires a 'sym' field, or 'line' and 'column'");
+            }
+            let session = session_id.and_then(|id| sessions.get(&id));
+            let session = match &session {
+                Some(session) => Some(session.lock().await),
+                None => None,
+            };
+            let entry = session.as_ref().and_then(|session| {
+                let sym = match (&request.sym, position) {
+                    (Some(sym), _) => sym.clone(),
+                    (None, Some((line, column))) => session.source_map.find_at_position(line, column)?.span.original_text.clone(),
+                    (None, None) => return None,
+                };
+                let id = session.definitions.get(&sym)?;
+                session.source_map.find_by_node_id(id)
+            });
+
+            let mut response = NreplResponse::reply_to(&request);
+            match entry {
+                Some(entry) => {
+                    response.file = Some(entry.source_file.display().to_string());
+                    response.line = Some(entry.span.line);
+                    response.col = Some(entry.span.column);
+                    response.done()
+                }
+                None => {
+                    response.status = vec!["no-info".to_string(), "done".to_string()];
+                    response
+                }
+            }
+        }
+        other => {
+            let mut response = NreplResponse::reply_to(&request);
+            response.status = vec!["error".to_string(), "unknown-op".to_string(), "done".to_string()];
+            response.err = Some(format!("Unknown op: {}", other));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::BTreeMap, net::SocketAddr};
+    use tokio::task::LocalSet;
+
+    #[derive(Debug, Deserialize)]
+    struct Reply {
+        id: Option<String>,
+        #[serde(rename = "new-session")]
+        new_session: Option<String>,
+        value: Option<String>,
+        out: Option<String>,
+        err: Option<String>,
+        formatted: Option<String>,
+        sessions: Option<Vec<String>>,
+        file: Option<String>,
+        line: Option<usize>,
+        col: Option<usize>,
+        #[serde(default)]
+        status: Vec<String>,
+    }
+
+    // A lookup by position, whose line and column are bencoded as integers
+    #[derive(Serialize)]
+    struct LookupAt<'a> {
+        op: &'a str,
+        id: &'a str,
+        line: usize,
+        column: usize,
+    }
+
+    struct Client {
+        stream: TcpStream,
+        buffer: BytesMut,
+    }
+
+    impl Client {
+        async fn connect(addr: SocketAddr) -> Self {
+            Self { stream: TcpStream::connect(addr).await.unwrap(), buffer: BytesMut::new() }
+        }
+
+        async fn send(&mut self, message: &[(&str, &str)]) {
+            let message: BTreeMap<&str, &str> = message.iter().copied().collect();
+            self.send_message(&message).await;
+        }
+
+        async fn send_message<T: Serialize>(&mut self, message: &T) {
+            self.stream.write_all(&serde_bencode::to_bytes(message).unwrap()).await.unwrap();
+        }
+
+        async fn reply(&mut self) -> Reply {
+            loop {
+                if let Some(len) = frame_len(&self.buffer).unwrap() {
+                    return serde_bencode::from_bytes(&self.buffer.split_to(len)).unwrap();
+                }
+                assert!(self.stream.read_buf(&mut self.buffer).await.unwrap() > 0, "server hung up");
+            }
+        }
+
+        // Replies up to and including the one that finishes request `id`
+        async fn replies_to(&mut self, id: &str) -> Vec<Reply> {
+            let mut replies = Vec::new();
+            loop {
+                let reply = self.reply().await;
+                let done = reply.id.as_deref() == Some(id) && reply.status.iter().any(|status| status == "done");
+                replies.push(reply);
+                if done {
+                    return replies;
+                }
+            }
+        }
+
+        async fn sessions(&mut self) -> Vec<String> {
+            self.send(&[("op", "ls-sessions"), ("id", "ls")]).await;
+            self.replies_to("ls").await.pop().unwrap().sessions.unwrap()
+        }
+    }
+
+    // Serve on a free port for the rest of the test, which must run on a LocalSet
+    async fn start_server() -> SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn_local(accept_clients(listener, GardenConfig::default()));
+        addr
+    }
+
+    #[tokio::test]
+    async fn printed_output_comes_before_the_value() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                client.send(&[("op", "eval"), ("id", "1"), ("code", r#"(println "hello") (+ 1 2)"#)]).await;
+                let replies = client.replies_to("1").await;
+                assert_eq!(replies.len(), 2);
+                assert_eq!(replies[0].out.as_deref(), Some("hello\n"));
+                assert!(replies[0].status.is_empty());
+                assert_eq!(replies[1].value.as_deref(), Some("3"));
+                assert_eq!(replies[1].status, vec!["done"]);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn parse_errors_point_at_the_code() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                client.send(&[("op", "eval"), ("id", "1"), ("code", "(+ 1 ])")]).await;
+                let reply = client.replies_to("1").await.pop().unwrap();
+                assert!(reply.status.contains(&"error".to_string()));
+                assert!(reply.err.unwrap().starts_with("  |\n1 | (+ 1 ])\n  |      ^\n"));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn resent_eval_is_rejected_while_the_first_runs() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                let eval = [("op", "eval"), ("id", "7"), ("code", "(sleep 200) 42")];
+                client.send(&eval).await;
+                client.send(&eval).await;
+
+                let rejected = client.reply().await;
+                assert_eq!(rejected.id.as_deref(), Some("7"));
+                assert_eq!(rejected.status, vec!["error", "duplicate-id", "done"]);
+                let answered = client.reply().await;
+                assert_eq!(answered.value.as_deref(), Some("42"));
+
+                // Once it has finished, the id can be used again
+                client.send(&eval).await;
+                assert_eq!(client.reply().await.value.as_deref(), Some("42"));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn evals_in_one_session_run_in_order() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                client.send(&[("op", "eval"), ("id", "1"), ("code", "(sleep 100) (def x 1)")]).await;
+                client.send(&[("op", "eval"), ("id", "2"), ("code", "(+ x 1)")]).await;
+                assert_eq!(client.replies_to("1").await.pop().unwrap().value.as_deref(), Some("1"));
+                assert_eq!(client.replies_to("2").await.pop().unwrap().value.as_deref(), Some("2"));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn sessions_a_client_opened_close_when_it_disconnects() {
+        LocalSet::new()
+            .run_until(async {
+                let addr = start_server().await;
+                let mut observer = Client::connect(addr).await;
+                let mut client = Client::connect(addr).await;
+                client.send(&[("op", "clone"), ("id", "c")]).await;
+                let cloned = client.replies_to("c").await.pop().unwrap().new_session.unwrap();
+                assert!(observer.sessions().await.contains(&cloned));
+                assert_eq!(observer.sessions().await.len(), 3);
+
+                drop(client);
+                for _ in 0..50 {
+                    if observer.sessions().await.len() == 1 {
+                        break;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                assert_eq!(observer.sessions().await.len(), 1);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn closed_sessions_are_no_longer_listed_or_usable() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                client.send(&[("op", "clone"), ("id", "a")]).await;
+                let kept = client.replies_to("a").await.pop().unwrap().new_session.unwrap();
+                client.send(&[("op", "clone"), ("id", "b")]).await;
+                let closed = client.replies_to("b").await.pop().unwrap().new_session.unwrap();
+
+                client.send(&[("op", "close"), ("id", "c"), ("session", &closed)]).await;
+                assert_eq!(client.replies_to("c").await.pop().unwrap().status, vec!["done"]);
+                let sessions = client.sessions().await;
+                assert!(sessions.contains(&kept) && !sessions.contains(&closed), "{:?}", sessions);
+
+                client.send(&[("op", "eval"), ("id", "d"), ("code", "1"), ("session", &closed)]).await;
+                assert_eq!(client.replies_to("d").await.pop().unwrap().status, vec!["error", "unknown-session", "done"]);
+                client.send(&[("op", "eval"), ("id", "e"), ("code", "1"), ("session", &kept)]).await;
+                assert_eq!(client.replies_to("e").await.pop().unwrap().value.as_deref(), Some("1"));
+
+                // Closing the connection's own session leaves it with none
+                client.send(&[("op", "close"), ("id", "f")]).await;
+                assert_eq!(client.replies_to("f").await.pop().unwrap().status, vec!["done"]);
+                client.send(&[("op", "eval"), ("id", "g"), ("code", "1")]).await;
+                assert_eq!(client.replies_to("g").await.pop().unwrap().status, vec!["error", "unknown-session", "done"]);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn lookup_finds_definitions_by_name_or_position() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                let code = "(def base 10)\n(def total (+ base 5))";
+                client.send(&[("op", "eval"), ("id", "1"), ("code", code), ("file", "calc.expr")]).await;
+                client.replies_to("1").await;
+
+                client.send(&[("op", "lookup"), ("id", "2"), ("sym", "total")]).await;
+                let by_name = client.replies_to("2").await.pop().unwrap();
+                assert_eq!((by_name.file.as_deref(), by_name.line, by_name.col), (Some("calc.expr"), Some(2), Some(1)));
+
+                // The cursor on `base` in the second line
+                client.send_message(&LookupAt { op: "lookup", id: "3", line: 2, column: 16 }).await;
+                let by_position = client.replies_to("3").await.pop().unwrap();
+                assert_eq!((by_position.line, by_position.col), (Some(1), Some(1)));
+
+                client.send_message(&LookupAt { op: "lookup", id: "4", line: 9, column: 1 }).await;
+                assert_eq!(client.replies_to("4").await.pop().unwrap().status, vec!["no-info", "done"]);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn format_code_replies_with_the_formatted_code() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                client.send(&[("op", "format-code"), ("id", "1"), ("code", "( +  1   2 )")]).await;
+                assert_eq!(client.replies_to("1").await.pop().unwrap().formatted.as_deref(), Some("(+ 1 2)"));
+
+                client.send(&[("op", "format-code"), ("id", "2"), ("code", "(+ 1")]).await;
+                assert!(client.replies_to("2").await.pop().unwrap().status.contains(&"error".to_string()));
+            })
+            .await;
+    }
+
+    #[test]
+    fn frames_end_where_the_outer_dict_closes() {
+        let message = b"d4:code13:(def e \"d:l\")2:idi3e4:listl1:aee";
+        assert_eq!(frame_len(message).unwrap(), Some(message.len()));
+        // Anything shorter is an incomplete message, not an error
+        for end in 0..message.len() {
+            assert_eq!(frame_len(&message[..end]).unwrap(), None, "prefix of {} bytes", end);
+        }
+        let mut two = message.to_vec();
+        two.extend_from_slice(b"d2:op5:clonee");
+        assert_eq!(frame_len(&two).unwrap(), Some(message.len()));
+    }
+
+    #[test]
+    fn malformed_frames_are_errors() {
+        for bad in [&b"l2:ope"[..], b"d2:opx", b"d2:opi12x3ee", b"d2:opiee", b"d2x:ope"] {
+            assert!(matches!(frame_len(bad), Err(FrameError::Malformed(_))), "{:?}", String::from_utf8_lossy(bad));
+        }
+        let huge = format!("d{}:", MAX_MESSAGE_BYTES + 1);
+        assert!(matches!(frame_len(huge.as_bytes()), Err(FrameError::TooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn message_split_across_two_writes_is_handled() {
+        LocalSet::new()
+            .run_until(async {
+                let mut client = Client::connect(start_server().await).await;
+                let message = b"d4:code7:(+ 1 2)2:id1:12:op4:evale";
+                let (first, second) = message.split_at(15);
+                client.stream.write_all(first).await.unwrap();
+                client.stream.flush().await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                client.stream.write_all(second).await.unwrap();
+                assert_eq!(client.replies_to("1").await.pop().unwrap().value.as_deref(), Some("3"));
+
+                // The connection is still usable afterwards
+                client.send(&[("op", "eval"), ("id", "2"), ("code", "(* 2 3)")]).await;
+                assert_eq!(client.replies_to("2").await.pop().unwrap().value.as_deref(), Some("6"));
+            })
+            .await;
+    }
+}
@@ -1,10 +1,11 @@
 use pest::Parser;
 use pest::iterators::Pair;
+use pest::error::LineColLocation;
 use pest_derive::Parser;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::{Error, SourceSpan, Node, NodeKind};
+use crate::{Error, Node, NodeKind};
 
 // Define the grammar using pest's procedural macro
 #[derive(Parser)]
@@ -14,8 +15,12 @@ pub struct ExprParser;
 // Main parsing function that returns a vector of Nodes
 pub fn parse(source: &str) -> Result<Vec<Rc<Node>>, Error> {
     // Parse the input using pest
-    let top_level_pairs = ExprParser::parse(Rule::program, source)
-        .map_err(|e| Error::ParseError(e.to_string()))?;
+    let top_level_pairs = ExprParser::parse(Rule::program, source).map_err(|e| {
+        let (line, column) = match e.line_col {
+            LineColLocation::Pos(position) | LineColLocation::Span(position, _) => position,
+        };
+        Error::ParseError(format!("line {}, column {}: {}", line, column, e.variant.message()))
+    })?;
     
     // Process all top-level expressions into nodes
     let mut nodes = Vec::new();
@@ -25,10 +30,13 @@ pub fn parse(source: &str) -> Result<Vec<Rc<Node>>, Error> {
             for pair in program_level_pair.into_inner() { // Iterate over SOI, (symbol|number|string|list)*, EOI
                 match pair.as_rule() {
                     // Since `expr` is a silent rule `_{...}`, `pair.as_rule()` here will directly be
-                    // `Rule::symbol`, `Rule::number`, `Rule::string`, or `Rule::list` for expressions.
-                    Rule::symbol | Rule::number | Rule::string | Rule::list => {
-                        let node = parse_expr(pair, source)?;
-                        nodes.push(node);
+                    // `Rule::symbol`, `Rule::float`, `Rule::number`, `Rule::string`, `Rule::list` or `Rule::vector` for expressions.
+                    Rule::symbol | Rule::float | Rule::number | Rule::string | Rule::list | Rule::vector => {
+                        let node = parse_expr(pair)?;
+                        nodes.push(match comment_above(source, &node) {
+                            Some(description) => node.with_metadata("description", &description),
+                            None => node,
+                        });
                     }
                     Rule::EOI => {
                         // These are structural tokens from the `program` rule, ignore.
@@ -53,14 +61,31 @@ pub fn parse(source: &str) -> Result<Vec<Rc<Node>>, Error> {
     Ok(nodes)
 }
 
+// The comment lines directly above a top-level expression, which describe it
+fn comment_above(source: &str, node: &Node) -> Option<String> {
+    let line: usize = node.metadata().get("line")?.parse().ok()?;
+    let lines: Vec<&str> = source.lines().take(line.saturating_sub(1)).collect();
+    let comments: Vec<&str> = lines
+        .iter()
+        .rev()
+        .map_while(|line| line.trim().strip_prefix(';'))
+        .map(|comment| comment.trim_start_matches(';').trim())
+        .collect();
+    (!comments.is_empty()).then(|| comments.into_iter().rev().collect::<Vec<_>>().join("\n"))
+}
+
 // Parse a single expression
-fn parse_expr(pair: Pair<Rule>, source: &str) -> Result<Rc<Node>, Error> {
-    let line = pair.line_col().0;
+fn parse_expr(pair: Pair<Rule>) -> Result<Rc<Node>, Error> {
+    let (line, column) = pair.line_col();
+    let (end_line, end_col) = pair.as_span().end_pos().line_col();
     let span_text = pair.as_str().to_string();
     
-    // Create basic metadata for the node
+    // Create basic metadata for the node; see SourceSpan
     let mut metadata = HashMap::new();
     metadata.insert("line".to_string(), line.to_string());
+    metadata.insert("column".to_string(), column.to_string());
+    metadata.insert("end_line".to_string(), end_line.to_string());
+    metadata.insert("end_col".to_string(), end_col.to_string());
     
     match pair.as_rule() {
         Rule::symbol => {
@@ -73,6 +98,17 @@ fn parse_expr(pair: Pair<Rule>, source: &str) -> Result<Rc<Node>, Error> {
                 metadata
             ))
         },
+        Rule::float => {
+            let num = pair.as_str().parse::<f64>()
+                .map_err(|e| Error::ParseError(format!("Failed to parse number: {}", e)))?;
+            metadata.insert("source_type".to_string(), "float".to_string());
+            Ok(Node::new(
+                NodeKind::Float(num),
+                span_text,
+                Vec::new(),
+                metadata
+            ))
+        },
         Rule::number => {
             let num_str = pair.as_str();
             let num = num_str.parse::<i64>()
@@ -89,7 +125,7 @@ fn parse_expr(pair: Pair<Rule>, source: &str) -> Result<Rc<Node>, Error> {
             // Remove the quotes from the string literal
             let s = pair.as_str();
             let content = if s.len() >= 2 {
-                s[1..s.len()-1].to_string()
+                unescape(&s[1..s.len()-1])
             } else {
                 return Err(Error::ParseError("Malformed string literal".to_string()));
             };
@@ -110,82 +146,19 @@ fn parse_expr(pair: Pair<Rule>, source: &str) -> Result<Rc<Node>, Error> {
                 // Since `expr` is silent (`_{...}`), `inner_pair.as_rule()` will directly be
                 // `Rule::symbol`, `Rule::number`, `Rule::string`, or `Rule::list`.
                 // The `parse_expr` function is designed to handle these directly.
-                let child_node = parse_expr(inner_pair, source)?;
+                let child_node = parse_expr(inner_pair)?;
                 children.push(child_node);
             }
             
-            if children.is_empty() {
-                metadata.insert("source_type".to_string(), "empty_list".to_string());
-                return Ok(Node::new(
-                    NodeKind::List,
-                    original_text,
-                    Vec::new(),
-                    metadata
-                ));
-            }
-            
-            // Check if the first element is a symbol to determine the operation type
-            if let Some(first_child) = children.first() {
-                if let NodeKind::Symbol(op) = &first_child.kind {
-                    let node_kind = match op.as_str() {
-                        "def" => {
-                            metadata.insert("source_type".to_string(), "let_statement".to_string());
-                            NodeKind::LetStatement
-                        },
-                        "let" => {
-                            // Determine if this is a let statement or expression based on the number of children
-                            // (let name value) -> LetStatement
-                            // (let name value body) -> LetExpr
-                            if children.len() == 3 {
-                                metadata.insert("source_type".to_string(), "let_statement".to_string());
-                                NodeKind::LetStatement
-                            } else if children.len() == 4 {
-                                metadata.insert("source_type".to_string(), "let_expr".to_string());
-                                NodeKind::LetExpr
-                            } else {
-                                // Default to LetExpr for backward compatibility
-                                metadata.insert("source_type".to_string(), "let_expr".to_string());
-                                NodeKind::LetExpr
-                            }
-                        },
-                        "+" => {
-                            metadata.insert("source_type".to_string(), "addition".to_string());
-                            NodeKind::Addition
-                        },
-                        "*" => {
-                            metadata.insert("source_type".to_string(), "multiplication".to_string());
-                            NodeKind::Multiplication
-                        },
-                        "http.get" => {
-                            metadata.insert("source_type".to_string(), "http_get".to_string());
-                            NodeKind::HttpGet
-                        },
-                        "json.parse" => {
-                            metadata.insert("source_type".to_string(), "json_parse".to_string());
-                            NodeKind::JsonParse
-                        },
-                        "get" => {
-                            metadata.insert("source_type".to_string(), "json_get".to_string());
-                            NodeKind::JsonGet
-                        },
-                        "str.upper" => {
-                            metadata.insert("source_type".to_string(), "string_upper".to_string());
-                            NodeKind::StringUpper
-                        },
-                        _ => {
-                            metadata.insert("source_type".to_string(), "function_call".to_string());
-                            metadata.insert("function_name".to_string(), op.clone());
-                            NodeKind::List
-                        }
-                    };
-                    
-                    return Ok(Node::new(node_kind, original_text, children, metadata));
-                }
-            }
-            
-            // Generic list
-            metadata.insert("source_type".to_string(), "list".to_string());
-            Ok(Node::new(NodeKind::List, original_text, children, metadata))
+            let children = spread_shorthand(children, &metadata)?;
+            list_node(original_text, children, metadata)
+        },
+        Rule::vector => {
+            // [a b c] evaluates to a list of its elements
+            let original_text = pair.as_str().to_string();
+            let children = pair.into_inner().map(parse_expr).collect::<Result<Vec<_>, _>>()?;
+            metadata.insert("source_type".to_string(), "vector".to_string());
+            Ok(Node::new(NodeKind::Vector, original_text, children, metadata))
         },
         Rule::expr => {
             // This case should ideally be unreachable if 'expr' is a silent rule in the grammar
@@ -200,4 +173,738 @@ fn parse_expr(pair: Pair<Rule>, source: &str) -> Result<Rc<Node>, Error> {
         },
         _ => Err(Error::ParseError(format!("Unexpected rule in parse_expr: {:?}", pair.as_rule()))),
     }
-} 
\ No newline at end of file
+} 
+
+// Build the node for a parsed list, choosing its kind from the operation it starts with
+fn list_node(original_text: String, children: Vec<Rc<Node>>, mut metadata: HashMap<String, String>) -> Result<Rc<Node>, Error> {
+    if children.is_empty() {
+        metadata.insert("source_type".to_string(), "empty_list".to_string());
+        return Ok(Node::new(
+            NodeKind::List,
+            original_text,
+            Vec::new(),
+            metadata
+        ));
+    }
+    
+    // Check if the first element is a symbol to determine the operation type
+    if let Some(first_child) = children.first() {
+        if let NodeKind::Symbol(op) = &first_child.kind {
+            if op == "def-fn" {
+                return expand_def_fn(children, metadata);
+            }
+            if op == "pipe" || op == "pipe->" {
+                return expand_pipe(children, metadata);
+            }
+            if op == "when" || op == "unless" {
+                return expand_when(op == "unless", children, metadata);
+            }
+            let node_kind = match op.as_str() {
+                "def" => {
+                    metadata.insert("source_type".to_string(), "let_statement".to_string());
+                    NodeKind::LetStatement
+                },
+                "let" => {
+                    // Determine if this is a let statement or expression based on the number of children
+                    // (let name value) -> LetStatement
+                    // (let name value body) -> LetExpr
+                    if children.len() == 3 {
+                        metadata.insert("source_type".to_string(), "let_statement".to_string());
+                        NodeKind::LetStatement
+                    } else if children.len() == 4 {
+                        metadata.insert("source_type".to_string(), "let_expr".to_string());
+                        NodeKind::LetExpr
+                    } else {
+                        // Default to LetExpr for backward compatibility
+                        metadata.insert("source_type".to_string(), "let_expr".to_string());
+                        NodeKind::LetExpr
+                    }
+                },
+                "+" => {
+                    metadata.insert("source_type".to_string(), "addition".to_string());
+                    NodeKind::Addition
+                },
+                "*" => {
+                    metadata.insert("source_type".to_string(), "multiplication".to_string());
+                    NodeKind::Multiplication
+                },
+                "-" => {
+                    metadata.insert("source_type".to_string(), "subtraction".to_string());
+                    NodeKind::Subtraction
+                },
+                "/" => {
+                    metadata.insert("source_type".to_string(), "division".to_string());
+                    NodeKind::Division
+                },
+                "%" => {
+                    metadata.insert("source_type".to_string(), "modulo".to_string());
+                    NodeKind::Modulo
+                },
+                "http.get" => {
+                    metadata.insert("source_type".to_string(), "http_get".to_string());
+                    NodeKind::HttpGet
+                },
+                "json.parse" => {
+                    metadata.insert("source_type".to_string(), "json_parse".to_string());
+                    NodeKind::JsonParse
+                },
+                "get" => {
+                    metadata.insert("source_type".to_string(), "json_get".to_string());
+                    NodeKind::JsonGet
+                },
+                "str.upper" => {
+                    metadata.insert("source_type".to_string(), "string_upper".to_string());
+                    NodeKind::StringUpper
+                },
+                "str.to-num" => {
+                    metadata.insert("source_type".to_string(), "str_to_num".to_string());
+                    NodeKind::StrToNum
+                },
+                "num.format" => {
+                    metadata.insert("source_type".to_string(), "num_format".to_string());
+                    NodeKind::NumFormat
+                },
+                "num.parse" => {
+                    metadata.insert("source_type".to_string(), "num_parse".to_string());
+                    NodeKind::NumParse
+                },
+                "str.pad-left" => {
+                    metadata.insert("source_type".to_string(), "str_pad_left".to_string());
+                    NodeKind::StrPadLeft
+                },
+                "str.pad-right" => {
+                    metadata.insert("source_type".to_string(), "str_pad_right".to_string());
+                    NodeKind::StrPadRight
+                },
+                "str.repeat" => {
+                    metadata.insert("source_type".to_string(), "str_repeat".to_string());
+                    NodeKind::StrRepeat
+                },
+                "str.lines" => {
+                    metadata.insert("source_type".to_string(), "str_lines".to_string());
+                    NodeKind::StrLines
+                },
+                "str.trim" => {
+                    metadata.insert("source_type".to_string(), "str_trim".to_string());
+                    NodeKind::StrTrim
+                },
+                "str.slice" => {
+                    metadata.insert("source_type".to_string(), "str_slice".to_string());
+                    NodeKind::StrSlice
+                },
+                "fn" => {
+                    metadata.insert("source_type".to_string(), "lambda".to_string());
+                    NodeKind::Lambda
+                },
+                "each" => {
+                    metadata.insert("source_type".to_string(), "each".to_string());
+                    NodeKind::Each
+                },
+                "range" => {
+                    metadata.insert("source_type".to_string(), "range".to_string());
+                    NodeKind::Range
+                },
+                "println" => {
+                    metadata.insert("source_type".to_string(), "println".to_string());
+                    NodeKind::Println
+                },
+                "sort" => {
+                    metadata.insert("source_type".to_string(), "sort".to_string());
+                    NodeKind::Sort
+                },
+                "sort-by" => {
+                    metadata.insert("source_type".to_string(), "sort_by".to_string());
+                    NodeKind::SortBy
+                },
+                "group-by" => {
+                    metadata.insert("source_type".to_string(), "group_by".to_string());
+                    NodeKind::GroupBy
+                },
+                "zip" => {
+                    metadata.insert("source_type".to_string(), "zip".to_string());
+                    NodeKind::Zip
+                },
+                "unzip" => {
+                    metadata.insert("source_type".to_string(), "unzip".to_string());
+                    NodeKind::Unzip
+                },
+                "distinct" => {
+                    metadata.insert("source_type".to_string(), "distinct".to_string());
+                    NodeKind::Distinct
+                },
+                "frequencies" => {
+                    metadata.insert("source_type".to_string(), "frequencies".to_string());
+                    NodeKind::Frequencies
+                },
+                "take" => {
+                    metadata.insert("source_type".to_string(), "take".to_string());
+                    NodeKind::Take
+                },
+                "drop" => {
+                    metadata.insert("source_type".to_string(), "drop".to_string());
+                    NodeKind::Drop
+                },
+                "partition" => {
+                    metadata.insert("source_type".to_string(), "partition".to_string());
+                    NodeKind::Partition
+                },
+                "str.concat" => {
+                    metadata.insert("source_type".to_string(), "str_concat".to_string());
+                    NodeKind::StrConcat
+                },
+                "apply" | "apply-spread" => {
+                    metadata.insert("source_type".to_string(), "apply".to_string());
+                    NodeKind::Apply
+                },
+                "assoc" => {
+                    metadata.insert("source_type".to_string(), "assoc".to_string());
+                    NodeKind::Assoc
+                },
+                "dissoc" => {
+                    metadata.insert("source_type".to_string(), "dissoc".to_string());
+                    NodeKind::Dissoc
+                },
+                "keys" => {
+                    metadata.insert("source_type".to_string(), "keys".to_string());
+                    NodeKind::Keys
+                },
+                "vals" => {
+                    metadata.insert("source_type".to_string(), "vals".to_string());
+                    NodeKind::Vals
+                },
+                "merge" => {
+                    metadata.insert("source_type".to_string(), "merge".to_string());
+                    NodeKind::Merge
+                },
+                "for" => {
+                    metadata.insert("source_type".to_string(), "for".to_string());
+                    NodeKind::For
+                },
+                "<" => {
+                    metadata.insert("source_type".to_string(), "less_than".to_string());
+                    NodeKind::LessThan
+                },
+                ">" => {
+                    metadata.insert("source_type".to_string(), "greater_than".to_string());
+                    NodeKind::GreaterThan
+                },
+                "<=" => {
+                    metadata.insert("source_type".to_string(), "less_equal".to_string());
+                    NodeKind::LessEqual
+                },
+                ">=" => {
+                    metadata.insert("source_type".to_string(), "greater_equal".to_string());
+                    NodeKind::GreaterEqual
+                },
+                "=" => {
+                    metadata.insert("source_type".to_string(), "equal".to_string());
+                    NodeKind::Equal
+                },
+                "while" => {
+                    metadata.insert("source_type".to_string(), "while".to_string());
+                    NodeKind::While
+                },
+                "if" => {
+                    metadata.insert("source_type".to_string(), "if".to_string());
+                    NodeKind::If
+                },
+                "memoize" => {
+                    metadata.insert("source_type".to_string(), "memoize".to_string());
+                    NodeKind::Memoize
+                },
+                "type-of" => {
+                    metadata.insert("source_type".to_string(), "type_of".to_string());
+                    NodeKind::TypeOf
+                },
+                "csv.parse" => {
+                    metadata.insert("source_type".to_string(), "csv_parse".to_string());
+                    NodeKind::CsvParse
+                },
+                "csv.parse-headers" => {
+                    metadata.insert("source_type".to_string(), "csv_parse_headers".to_string());
+                    NodeKind::CsvParseHeaders
+                },
+                "csv.stringify" => {
+                    metadata.insert("source_type".to_string(), "csv_stringify".to_string());
+                    NodeKind::CsvStringify
+                },
+                "toml.parse" => {
+                    metadata.insert("source_type".to_string(), "toml_parse".to_string());
+                    NodeKind::TomlParse
+                },
+                "yaml.parse" => {
+                    metadata.insert("source_type".to_string(), "yaml_parse".to_string());
+                    NodeKind::YamlParse
+                },
+                "regex.match" => {
+                    metadata.insert("source_type".to_string(), "regex_match".to_string());
+                    NodeKind::RegexMatch
+                },
+                "regex.captures" => {
+                    metadata.insert("source_type".to_string(), "regex_captures".to_string());
+                    NodeKind::RegexCaptures
+                },
+                "regex.replace" => {
+                    metadata.insert("source_type".to_string(), "regex_replace".to_string());
+                    NodeKind::RegexReplace
+                },
+                "base64.encode" => {
+                    metadata.insert("source_type".to_string(), "base64_encode".to_string());
+                    NodeKind::Base64Encode
+                },
+                "base64.decode" => {
+                    metadata.insert("source_type".to_string(), "base64_decode".to_string());
+                    NodeKind::Base64Decode
+                },
+                "hash.sha256" => {
+                    metadata.insert("source_type".to_string(), "hash_sha256".to_string());
+                    NodeKind::HashSha256
+                },
+                "hash.md5" => {
+                    metadata.insert("source_type".to_string(), "hash_md5".to_string());
+                    NodeKind::HashMd5
+                },
+                "hash.blake3" => {
+                    metadata.insert("source_type".to_string(), "hash_blake3".to_string());
+                    NodeKind::HashBlake3
+                },
+                "hash.hmac-sha256" => {
+                    metadata.insert("source_type".to_string(), "hash_hmac_sha256".to_string());
+                    NodeKind::HashHmacSha256
+                },
+                "uuid.new" => {
+                    metadata.insert("source_type".to_string(), "uuid_new".to_string());
+                    NodeKind::UuidNew
+                },
+                "uuid.parse" => {
+                    metadata.insert("source_type".to_string(), "uuid_parse".to_string());
+                    NodeKind::UuidParse
+                },
+                "url.encode" => {
+                    metadata.insert("source_type".to_string(), "url_encode".to_string());
+                    NodeKind::UrlEncode
+                },
+                "url.decode" => {
+                    metadata.insert("source_type".to_string(), "url_decode".to_string());
+                    NodeKind::UrlDecode
+                },
+                "url.build" => {
+                    metadata.insert("source_type".to_string(), "url_build".to_string());
+                    NodeKind::UrlBuild
+                },
+                "sleep" => {
+                    metadata.insert("source_type".to_string(), "sleep".to_string());
+                    NodeKind::Sleep
+                },
+                "http.post-json" => {
+                    metadata.insert("source_type".to_string(), "http_post_json".to_string());
+                    NodeKind::HttpPostJson
+                },
+                "http.get-response" => {
+                    metadata.insert("source_type".to_string(), "http_get_response".to_string());
+                    NodeKind::HttpGetResponse
+                },
+                "http.set-cookie-jar" => {
+                    metadata.insert("source_type".to_string(), "http_set_cookie_jar".to_string());
+                    NodeKind::HttpSetCookieJar
+                },
+                "http.clear-cookies" => {
+                    metadata.insert("source_type".to_string(), "http_clear_cookies".to_string());
+                    NodeKind::HttpClearCookies
+                },
+                "watch" => {
+                    metadata.insert("source_type".to_string(), "watch".to_string());
+                    NodeKind::Watch
+                },
+                "num.overflow-mode" => {
+                    metadata.insert("source_type".to_string(), "num_overflow_mode".to_string());
+                    NodeKind::NumOverflowMode
+                },
+                "assert-type" => {
+                    metadata.insert("source_type".to_string(), "assert_type".to_string());
+                    NodeKind::AssertType
+                },
+                "coerce-to" => {
+                    metadata.insert("source_type".to_string(), "coerce_to".to_string());
+                    NodeKind::CoerceTo
+                },
+                "with-context" => {
+                    metadata.insert("source_type".to_string(), "with_context".to_string());
+                    NodeKind::WithContext
+                },
+                "str.interpolate" => {
+                    metadata.insert("source_type".to_string(), "str_interpolate".to_string());
+                    NodeKind::StrInterpolate
+                },
+                "json.validate" => {
+                    metadata.insert("source_type".to_string(), "json_validate".to_string());
+                    NodeKind::JsonValidate
+                },
+                "with-default" => {
+                    metadata.insert("source_type".to_string(), "with_default".to_string());
+                    NodeKind::WithDefault
+                },
+                "tap" => {
+                    metadata.insert("source_type".to_string(), "tap".to_string());
+                    NodeKind::Tap
+                },
+                "memoize-ttl" => {
+                    metadata.insert("source_type".to_string(), "memoize_ttl".to_string());
+                    NodeKind::MemoizeTtl
+                },
+                "parallel" => {
+                    metadata.insert("source_type".to_string(), "parallel".to_string());
+                    NodeKind::Parallel
+                },
+                "http.download" => {
+                    metadata.insert("source_type".to_string(), "http_download".to_string());
+                    NodeKind::HttpDownload
+                },
+                "str.join" => {
+                    metadata.insert("source_type".to_string(), "str_join".to_string());
+                    NodeKind::StrJoin
+                },
+                "str.split-n" => {
+                    metadata.insert("source_type".to_string(), "str_split_n".to_string());
+                    NodeKind::StrSplitN
+                },
+                "cond" => {
+                    metadata.insert("source_type".to_string(), "cond".to_string());
+                    NodeKind::Cond
+                },
+                "match" => {
+                    metadata.insert("source_type".to_string(), "match".to_string());
+                    NodeKind::Match
+                },
+                "memo-def" => {
+                    metadata.insert("source_type".to_string(), "memo_definition".to_string());
+                    NodeKind::MemoDefinition
+                },
+                "memo.invalidate" => {
+                    metadata.insert("source_type".to_string(), "memo_invalidate".to_string());
+                    NodeKind::MemoInvalidate
+                },
+                "json.flatten" => {
+                    metadata.insert("source_type".to_string(), "json_flatten".to_string());
+                    NodeKind::JsonFlatten
+                },
+                "json.unflatten" => {
+                    metadata.insert("source_type".to_string(), "json_unflatten".to_string());
+                    NodeKind::JsonUnflatten
+                },
+                "xml.parse" | "xml.to-json" => {
+                    metadata.insert("source_type".to_string(), "xml_parse".to_string());
+                    NodeKind::XmlParse
+                },
+                "def-once" => {
+                    metadata.insert("source_type".to_string(), "definition_once".to_string());
+                    NodeKind::DefinitionOnce
+                },
+                "json.array-map" => {
+                    metadata.insert("source_type".to_string(), "json_array_map".to_string());
+                    NodeKind::JsonArrayMap
+                },
+                "json.array-filter" => {
+                    metadata.insert("source_type".to_string(), "json_array_filter".to_string());
+                    NodeKind::JsonArrayFilter
+                },
+                "str.number?" => {
+                    metadata.insert("source_type".to_string(), "str_number_q".to_string());
+                    NodeKind::StrNumberQ
+                },
+                "str.bool?" => {
+                    metadata.insert("source_type".to_string(), "str_bool_q".to_string());
+                    NodeKind::StrBoolQ
+                },
+                "str.json?" => {
+                    metadata.insert("source_type".to_string(), "str_json_q".to_string());
+                    NodeKind::StrJsonQ
+                },
+                "http.mock" => {
+                    metadata.insert("source_type".to_string(), "http_mock".to_string());
+                    NodeKind::HttpMock
+                },
+                "http.unmock" => {
+                    metadata.insert("source_type".to_string(), "http_unmock".to_string());
+                    NodeKind::HttpUnmock
+                },
+                "spread" => {
+                    metadata.insert("source_type".to_string(), "spread".to_string());
+                    NodeKind::Spread
+                },
+                _ => {
+                    metadata.insert("source_type".to_string(), "function_call".to_string());
+                    metadata.insert("function_name".to_string(), op.clone());
+                    NodeKind::List
+                }
+            };
+            
+            return Ok(Node::new(node_kind, original_text, children, metadata));
+        }
+    }
+    
+    // Generic list
+    metadata.insert("source_type".to_string(), "list".to_string());
+    Ok(Node::new(NodeKind::List, original_text, children, metadata))
+}
+
+// Rewrite (def-fn name [params] body) as (def name (fn [params] body))
+fn expand_def_fn(children: Vec<Rc<Node>>, metadata: HashMap<String, String>) -> Result<Rc<Node>, Error> {
+    let [_, name, params, body] = children.as_slice() else {
+        return Err(Error::ArityError(format!(
+            "'def-fn' expects 3 arguments (name, parameter vector, body), got {}",
+            children.len() - 1
+        )));
+    };
+    if !matches!(name.kind, NodeKind::Symbol(_)) || params.kind != NodeKind::Vector {
+        return Err(Error::ParseError(
+            "'def-fn' expects a name and a parameter vector, e.g. (def-fn double [x] (* x 2))".to_string(),
+        ));
+    }
+    
+    let symbol = |text: &str| Node::new(NodeKind::Symbol(text.to_string()), text.to_string(), Vec::new(), metadata.clone());
+    let mut lambda_metadata = metadata.clone();
+    lambda_metadata.insert("source_type".to_string(), "lambda".to_string());
+    let lambda = Node::new(
+        NodeKind::Lambda,
+        format!("(fn {} {})", params.code_snippet(), body.code_snippet()),
+        vec![symbol("fn"), params.clone(), body.clone()],
+        lambda_metadata,
+    );
+    
+    let mut metadata = metadata.clone();
+    metadata.insert("source_type".to_string(), "let_statement".to_string());
+    Ok(Node::new(
+        NodeKind::LetStatement,
+        format!("(def {} {})", name.code_snippet(), lambda.code_snippet()),
+        vec![symbol("def"), name.clone(), lambda],
+        metadata,
+    ))
+}
+
+// Rewrite (when cond body) as (if cond body nil) and (unless cond body) as (if cond nil body)
+fn expand_when(unless: bool, children: Vec<Rc<Node>>, metadata: HashMap<String, String>) -> Result<Rc<Node>, Error> {
+    let op = if unless { "unless" } else { "when" };
+    let [_, condition, body] = children.as_slice() else {
+        return Err(Error::ArityError(format!(
+            "'{}' expects 2 arguments (condition, body), got {}",
+            op,
+            children.len() - 1
+        )));
+    };
+    
+    let symbol = |text: &str| Node::new(NodeKind::Symbol(text.to_string()), text.to_string(), Vec::new(), metadata.clone());
+    let (then_branch, else_branch) = if unless {
+        (symbol("nil"), body.clone())
+    } else {
+        (body.clone(), symbol("nil"))
+    };
+    let mut metadata = metadata.clone();
+    metadata.insert("source_type".to_string(), "if".to_string());
+    Ok(Node::new(
+        NodeKind::If,
+        format!("(if {} {} {})", condition.code_snippet(), then_branch.code_snippet(), else_branch.code_snippet()),
+        vec![symbol("if"), condition.clone(), then_branch, else_branch],
+        metadata,
+    ))
+}
+
+// Turn `...expr` among a list's elements into (spread expr). The parser reads `...` before
+// a list or vector as a symbol of its own, and `...name` as one symbol.
+fn spread_shorthand(children: Vec<Rc<Node>>, metadata: &HashMap<String, String>) -> Result<Vec<Rc<Node>>, Error> {
+    let symbol = |text: &str| Node::new(NodeKind::Symbol(text.to_string()), text.to_string(), Vec::new(), metadata.clone());
+    let spread = |list: Rc<Node>| {
+        let mut metadata = metadata.clone();
+        metadata.insert("source_type".to_string(), "spread".to_string());
+        Node::new(NodeKind::Spread, format!("...{}", list.code_snippet()), vec![symbol("spread"), list], metadata)
+    };
+    
+    let mut result = Vec::with_capacity(children.len());
+    let mut children = children.into_iter();
+    while let Some(child) = children.next() {
+        match child.kind() {
+            NodeKind::Symbol(name) if name == "..." => {
+                let list = children
+                    .next()
+                    .ok_or_else(|| Error::ParseError("'...' must be followed by the list to spread".to_string()))?;
+                result.push(spread(list));
+            }
+            NodeKind::Symbol(name) if name.strip_prefix("...").and_then(|rest| rest.chars().next()).is_some_and(|c| !c.is_ascii_digit()) => {
+                result.push(spread(symbol(&name[3..])));
+            }
+            _ => result.push(child),
+        }
+    }
+    Ok(result)
+}
+
+// A call of `function` with `args`, built outside the parser
+pub fn call_node(function: Rc<Node>, args: Vec<Rc<Node>>) -> Result<Rc<Node>, Error> {
+    let metadata = function.metadata.clone();
+    let children: Vec<Rc<Node>> = std::iter::once(function).chain(args).collect();
+    let text = format!("({})", children.iter().map(|child| child.code_snippet()).collect::<Vec<_>>().join(" "));
+    list_node(text, children, metadata)
+}
+
+// Rewrite (pipe value step ...) as nested calls, so (pipe x f g) is (g (f x)). A step
+// that is a call with _ among its arguments, like (get _ "name"), gets the value in
+// place of each _; any other step is called with the value as its only argument.
+fn expand_pipe(children: Vec<Rc<Node>>, metadata: HashMap<String, String>) -> Result<Rc<Node>, Error> {
+    let Some((value, steps)) = children[1..].split_first() else {
+        return Err(Error::ArityError(format!("'{}' expects a value and the steps to pass it through", children[0].code_snippet())));
+    };
+    let is_hole = |node: &Rc<Node>| matches!(&node.kind, NodeKind::Symbol(name) if name == "_");
+    
+    let mut current = value.clone();
+    for step in steps {
+        let call = if step.kind != NodeKind::Vector && step.children.iter().any(is_hole) {
+            step.children
+                .iter()
+                .map(|child| if is_hole(child) { current.clone() } else { child.clone() })
+                .collect()
+        } else {
+            vec![step.clone(), current]
+        };
+        let text = format!("({})", call.iter().map(|child| child.code_snippet()).collect::<Vec<_>>().join(" "));
+        current = list_node(text, call, metadata.clone())?;
+    }
+    Ok(current)
+}
+
+// Widest a form may print on one line before its arguments are split over several lines
+const FORMAT_WIDTH: usize = 80;
+
+// Reformat source with canonical spacing. A form that fits in FORMAT_WIDTH columns goes
+// on one line; a longer one keeps its operator after the opening paren and puts each
+// argument on its own line, indented two spaces, with the closing paren on a line of
+// its own. Comments and single blank lines between top-level forms are kept, and a form
+// with a comment inside it is left as it was written.
+pub fn format_code(source: &str) -> Result<String, Error> {
+    let program = ExprParser::parse(Rule::program, source)
+        .map_err(|e| Error::ParseError(e.to_string()))?;
+    let mut out = String::new();
+    let mut last_end = 0;
+    for pair in program.flat_map(|program| program.into_inner()).filter(|pair| pair.as_rule() != Rule::EOI) {
+        let span = pair.as_span();
+        push_gap(&mut out, &source[last_end..span.start()], true);
+        if has_comment(pair.as_str()) {
+            out.push_str(pair.as_str());
+        } else {
+            out.push_str(&format_form(pair, 0));
+        }
+        last_end = span.end();
+    }
+    push_gap(&mut out, &source[last_end..], false);
+    Ok(out)
+}
+
+// Carry the comments in the whitespace between two top-level forms over to `out`: one
+// on the line a form ended on stays there, the others go on lines of their own, and
+// runs of blank lines become one. With `form_follows`, ends with the line break the
+// next form starts after.
+fn push_gap(out: &mut String, gap: &str, form_follows: bool) {
+    let mut lines = gap.split('\n');
+    let trailing = lines.next().unwrap_or_default().trim();
+    if !trailing.is_empty() {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(trailing);
+    }
+    // The last piece is the indentation of the line the next form starts on
+    let lines: Vec<&str> = lines.collect();
+    let mut blank = false;
+    for line in lines.iter().take(lines.len().saturating_sub(1)).map(|line| line.trim()) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank { "\n\n" } else { "\n" });
+        }
+        out.push_str(line);
+        blank = false;
+    }
+    if form_follows && !out.is_empty() {
+        out.push_str(if blank { "\n\n" } else { "\n" });
+    }
+}
+
+// Whether a form's source has a ; comment outside its strings
+fn has_comment(text: &str) -> bool {
+    let mut in_string = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            ';' if !in_string => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// A form laid out starting at column `indent`
+fn format_form(pair: Pair<Rule>, indent: usize) -> String {
+    let flat = format_flat(pair.clone());
+    let (open, close) = match pair.as_rule() {
+        Rule::list => ('(', ')'),
+        Rule::vector => ('[', ']'),
+        _ => return flat,
+    };
+    if indent + flat.chars().count() <= FORMAT_WIDTH {
+        return flat;
+    }
+    
+    let child_indent = indent + 2;
+    let mut items = pair.into_inner();
+    let mut out = String::from(open);
+    if open == '(' {
+        if let Some(operator) = items.next() {
+            out.push_str(&format_form(operator, indent + 1));
+        }
+    }
+    for item in items {
+        out.push('\n');
+        out.push_str(&" ".repeat(child_indent));
+        out.push_str(&format_form(item, child_indent));
+    }
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+    out.push(close);
+    out
+}
+
+// A form on one line, single spaces between items
+fn format_flat(pair: Pair<Rule>) -> String {
+    let (open, close) = match pair.as_rule() {
+        Rule::list => ("(", ")"),
+        Rule::vector => ("[", "]"),
+        _ => return pair.as_str().to_string(),
+    };
+    let items: Vec<String> = pair.into_inner().map(format_flat).collect();
+    format!("{}{}{}", open, items.join(" "), close)
+}
+
+// Resolve the escapes the grammar allows in string literals: \n, \t, \\ and \"
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
@@ -0,0 +1,163 @@
+use std::{path::PathBuf, rc::Rc};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{config::GardenConfig, parser, Env, Error, Evaluator, Node, Value};
+
+// History file kept in the home directory
+const HISTORY_FILE: &str = ".garden_history";
+
+// Prompts for a fresh expression and for a continuation line
+const PROMPT: &str = "garden> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+// Everything entered so far: the evaluator, its top-level environment and the root
+// nodes, which `:ctx` reads the current values of
+struct Repl {
+    evaluator: Evaluator,
+    env: Env<'static>,
+    root_nodes: Vec<Rc<Node>>,
+}
+
+impl Repl {
+    fn new(config: GardenConfig) -> Self {
+        Self {
+            evaluator: Evaluator::with_config(config),
+            env: Env::new(),
+            root_nodes: Vec::new(),
+        }
+    }
+
+    async fn eval(&mut self, code: &str) -> Result<Option<Value>, Error> {
+        let nodes = parser::parse(code)?;
+
+        self.evaluator.prepare_for_evaluation();
+        for node in &nodes {
+            self.evaluator.store_node(node.clone());
+        }
+        self.root_nodes.extend(nodes.iter().cloned());
+
+        self.evaluator.evaluate_sequence(&nodes, &mut self.env).await
+    }
+
+    // Print every defined variable with its current value
+    fn print_context(&self) {
+        let context = self.evaluator.context(&self.root_nodes);
+        if context.is_empty() {
+            println!("(no definitions)");
+        }
+        for (name, value) in context {
+            println!("{} = {}", name, value);
+        }
+    }
+}
+
+// How many more parens and brackets are opened than closed in `src`, ignoring string
+// literals and ; comments
+fn paren_depth(src: &str) -> i64 {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut escaped = false;
+    for c in src.chars() {
+        match c {
+            '\n' if in_comment => in_comment = false,
+            _ if in_comment => {}
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            ';' => in_comment = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn history_path() -> PathBuf {
+    std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default().join(HISTORY_FILE)
+}
+
+// Read expressions from stdin and print their values until Ctrl+D
+pub async fn run(config: GardenConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    // No history yet on first use
+    let _ = editor.load_history(&history);
+
+    let mut repl = Repl::new(config);
+    let mut input = String::new();
+
+    loop {
+        let prompt = if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl+C drops the expression being typed
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if input.is_empty() && line.trim() == ":ctx" {
+            editor.add_history_entry(line.as_str())?;
+            repl.print_context();
+            continue;
+        }
+
+        input.push_str(&line);
+        input.push('\n');
+        if input.trim().is_empty() {
+            input.clear();
+            continue;
+        }
+        // Keep reading until every open paren and bracket is closed
+        if paren_depth(&input) > 0 {
+            continue;
+        }
+
+        let code = std::mem::take(&mut input);
+        editor.add_history_entry(code.trim_end())?;
+
+        // Ctrl+C while evaluating abandons the evaluation, e.g. a slow http.get
+        tokio::select! {
+            result = repl.eval(&code) => match result {
+                Ok(Some(value)) => println!("{}", value),
+                Ok(None) => println!("nil"),
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            _ = tokio::signal::ctrl_c() => eprintln!("Interrupted"),
+        }
+    }
+
+    editor.save_history(&history)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paren_depth_counts_open_lists_and_vectors() {
+        assert_eq!(paren_depth("(+ 1 2)\n"), 0);
+        assert_eq!(paren_depth("(def x\n"), 1);
+        assert_eq!(paren_depth("[1\n"), 1);
+        assert_eq!(paren_depth("[1\n2]\n"), 0);
+        assert_eq!(paren_depth("(def xs [[1 2] [3\n"), 3);
+        assert_eq!(paren_depth("(+ 1 2))\n"), -1);
+    }
+
+    #[test]
+    fn paren_depth_skips_strings_and_comments() {
+        assert_eq!(paren_depth("(str.concat \"(\" \"[\"\n"), 1);
+        assert_eq!(paren_depth("(str.concat \"\\\")\" \n"), 1);
+        assert_eq!(paren_depth("(str.concat \"a\\\\\")\n"), 0);
+        assert_eq!(paren_depth("(def x 1) ; closes (later\n"), 0);
+        assert_eq!(paren_depth("(def x ; ) not yet\n  1)\n"), 0);
+        assert_eq!(paren_depth("(println \"; not a comment (\")\n"), 0);
+    }
+}
@@ -0,0 +1,1807 @@
+use super::*;
+
+// Evaluate `src` as a file would be, giving the last expression's value
+async fn eval(src: &str) -> Result<Value, Error> {
+    let mut evaluator = Evaluator::new();
+    eval_with(&mut evaluator, src).await
+}
+
+// Evaluate `src` with an evaluator kept between calls, as on each save of a watched file
+async fn eval_with(evaluator: &mut Evaluator, src: &str) -> Result<Value, Error> {
+    let nodes = parser::parse(src)?;
+    evaluator.prepare_for_evaluation();
+    for node in &nodes {
+        evaluator.store_node(node.clone());
+    }
+    let mut env = Env::new();
+    Ok(evaluator.evaluate_sequence(&nodes, &mut env).await?.unwrap_or(Value::Nil))
+}
+
+// The displayed value of `src`, which must evaluate without error
+async fn show(src: &str) -> String {
+    match eval(src).await {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{} failed: {}", src, e),
+    }
+}
+
+// Like `show`, but on a thread with the stack garden evaluates on, for deep recursion
+fn show_on_eval_stack(src: &str) -> String {
+    let src = src.to_string();
+    std::thread::Builder::new()
+        .stack_size(EVAL_STACK_SIZE)
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(show(&src))
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+// The message of the error `src` fails with
+async fn eval_error(src: &str) -> String {
+    match eval(src).await {
+        Ok(value) => panic!("{} should fail, gave {}", src, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+fn cache_with_entries(count: u8) -> EvaluationCache {
+    let mut cache = EvaluationCache::new();
+    let start = Utc::now() - chrono::Duration::seconds(60);
+    for i in 0..count {
+        cache.insert([i; 32], Ok(Value::Number(i as i64)));
+        cache.cache.get_mut(&[i; 32]).unwrap().timestamp = start + chrono::Duration::seconds(i as i64);
+    }
+    cache
+}
+
+#[test]
+fn cache_limit_keeps_newest_entries() {
+    let mut cache = cache_with_entries(5);
+    cache.enforce_limits(Some(2), Duration::from_secs(3600));
+    let mut kept: Vec<u8> = cache.cache.keys().map(|id| id[0]).collect();
+    kept.sort();
+    assert_eq!(kept, vec![3, 4]);
+}
+
+#[test]
+fn cache_limit_of_zero_empties_the_cache() {
+    let mut cache = cache_with_entries(3);
+    cache.enforce_limits(Some(0), Duration::from_secs(3600));
+    assert!(cache.cache.is_empty());
+}
+
+#[test]
+fn cache_ttl_drops_old_entries() {
+    let mut cache = cache_with_entries(3);
+    cache.enforce_limits(None, Duration::from_secs(1));
+    assert!(cache.cache.is_empty());
+}
+
+#[test]
+fn purge_stale_drops_exactly_the_expired_entries() {
+    // Entries were computed 60, 59, 58, 57 and 56 seconds ago
+    let mut cache = cache_with_entries(5);
+    assert_eq!(cache.purge_stale(Duration::from_secs(3600)), 0);
+    assert_eq!(cache.purge_stale(Duration::from_secs(57) + Duration::from_millis(500)), 3);
+    let mut kept: Vec<u8> = cache.cache.keys().map(|id| id[0]).collect();
+    kept.sort();
+    assert_eq!(kept, vec![3, 4]);
+}
+
+#[test]
+fn merging_keeps_the_newer_result_of_each_node() {
+    // Ours has nodes 0-2, computed 60-58 seconds ago
+    let mut ours = cache_with_entries(3);
+    let mut theirs = EvaluationCache::new();
+    theirs.insert([0; 32], Ok(Value::String("newer".into())));
+    theirs.insert([1; 32], Ok(Value::String("older".into())));
+    theirs.cache.get_mut(&[1; 32]).unwrap().timestamp = Utc::now() - chrono::Duration::seconds(3600);
+    theirs.insert([7; 32], Ok(Value::Number(7)));
+
+    assert_eq!(ours.merge(&theirs), 2);
+    assert!(matches!(ours.get(&[0; 32]), Some(Ok(Value::String(s))) if s == "newer"));
+    assert!(matches!(ours.get(&[1; 32]), Some(Ok(Value::Number(1)))));
+    assert!(matches!(ours.get(&[2; 32]), Some(Ok(Value::Number(2)))));
+    assert!(matches!(ours.get(&[7; 32]), Some(Ok(Value::Number(7)))));
+}
+
+#[test]
+fn cache_files_merge_into_a_files_cache() {
+    let dir = std::env::temp_dir().join(format!("garden-merge-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let ours = dir.join("main.expr.cache");
+    let theirs = dir.join("other.expr.cache");
+    cache_with_entries(2).save_to_file(&ours).unwrap();
+    let mut other = EvaluationCache::new();
+    other.insert([5; 32], Ok(Value::Number(5)));
+    other.save_to_file(&theirs).unwrap();
+
+    assert_eq!(merge_cache_file(&ours, &theirs).unwrap(), 1);
+    let mut merged = EvaluationCache::new();
+    merged.load_from_file(&ours).unwrap();
+    assert_eq!(merged.cache.len(), 3);
+    assert!(merge_cache_file(&ours, &dir.join("missing.expr.cache")).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn pads_strings_to_a_width() {
+    assert_eq!(show(r#"(str.pad-left "7" 3 "0")"#).await, r#""007""#);
+    assert_eq!(show(r#"(str.pad-right "ab" 4)"#).await, r#""ab  ""#);
+    assert_eq!(show(r#"(str.pad-left "wide" 2)"#).await, r#""wide""#);
+    assert_eq!(show(r#"(str.pad-right "x" -3)"#).await, r#""x""#);
+}
+
+#[tokio::test]
+async fn repeats_and_splits_strings() {
+    assert_eq!(show(r#"(str.repeat "ab" 3)"#).await, r#""ababab""#);
+    assert_eq!(show(r#"(str.repeat "ab" 0)"#).await, r#""""#);
+    assert_eq!(show(r#"(str.lines "a\nb\n\nc")"#).await, r#"("a" "b" "" "c")"#);
+}
+
+#[tokio::test]
+async fn slice_clamps_and_counts_negative_indices_from_the_end() {
+    assert_eq!(show(r#"(str.slice "hello" 1 3)"#).await, r#""el""#);
+    assert_eq!(show(r#"(str.slice "hello" -3)"#).await, r#""llo""#);
+    assert_eq!(show(r#"(str.slice "hello" 2 100)"#).await, r#""llo""#);
+    assert_eq!(show(r#"(str.slice "hello" -100 2)"#).await, r#""he""#);
+    assert_eq!(show(r#"(str.slice "hello" 4 1)"#).await, r#""""#);
+}
+
+#[tokio::test]
+async fn huge_repeats_and_pads_are_errors() {
+    assert!(eval_error(r#"(str.repeat "ab" 9223372036854775807)"#).await.contains("limit"));
+    assert!(eval_error(r#"(str.repeat "x" 100000000)"#).await.contains("limit"));
+    assert!(eval_error(r#"(str.pad-left "x" 9223372036854775807)"#).await.contains("limit"));
+    assert!(eval_error(r#"(str.pad-right "x" 100000000 "é")"#).await.contains("limit"));
+}
+
+#[tokio::test]
+async fn values_order_by_type_then_value() {
+    let function = eval("(fn [x] x)").await.unwrap();
+    let ascending = vec![
+        Value::Nil,
+        Value::Bool(false),
+        Value::Bool(true),
+        Value::Number(-5),
+        Value::Number(10),
+        Value::Float(f64::NEG_INFINITY),
+        Value::Float(1.5),
+        Value::String("a".into()),
+        Value::String("b".into()),
+        Value::List(vec![Value::Number(1)]),
+        Value::List(vec![Value::Number(1), Value::Number(0)]),
+        Value::List(vec![Value::Number(2)]),
+        Value::Map(IndexMap::from([("z".to_string(), Value::Number(1))])),
+        Value::Map(IndexMap::from([("a".to_string(), Value::Number(1)), ("b".to_string(), Value::Number(1))])),
+        Value::Json(serde_json::json!(null)),
+        Value::Json(serde_json::json!(3)),
+        Value::Json(serde_json::json!("s")),
+    ];
+    for (i, a) in ascending.iter().enumerate() {
+        for (j, b) in ascending.iter().enumerate() {
+            assert_eq!(a.partial_cmp(b), Some(i.cmp(&j)), "{} against {}", a, b);
+        }
+    }
+    // Transitivity, checked over every triple
+    for a in &ascending {
+        for b in &ascending {
+            for c in &ascending {
+                if a < b && b < c {
+                    assert!(a < c, "{} < {} < {}", a, b, c);
+                }
+            }
+        }
+    }
+
+    assert_eq!(function.partial_cmp(&function), Some(std::cmp::Ordering::Equal));
+    assert_eq!(function.partial_cmp(&Value::Number(1)), None);
+    assert_eq!(Value::Number(1).partial_cmp(&function), None);
+}
+
+#[tokio::test]
+async fn integers_sort_before_floats() {
+    assert_eq!(show("(sort [2.5 3 1 0.5])").await, "(1 3 0.5 2.5)");
+    assert_eq!(show("(< 1 1.5 2)").await, "true");
+}
+
+#[tokio::test]
+async fn values_convert_to_and_from_json() {
+    let json = serde_json::json!({"name": "ada", "tags": ["a", 1, 2.5, true, null], "nested": {"x": 1}});
+    let value = Value::from_json_value(json.clone());
+    let Value::Map(entries) = &value else {
+        panic!("objects should become maps, got {}", value);
+    };
+    assert!(matches!(&entries["tags"], Value::List(items) if items.len() == 5));
+    assert!(matches!(&entries["nested"], Value::Map(_)));
+    assert_eq!(value.to_json_value().unwrap(), json);
+
+    assert_eq!(Value::Json(json.clone()).to_json_value().unwrap(), json);
+    assert!(Value::Float(f64::NAN).to_json_value().is_err());
+    let function = eval("(fn [x] x)").await.unwrap();
+    assert!(Value::List(vec![Value::Number(1), function]).to_json_value().is_err());
+}
+
+#[tokio::test]
+async fn json_arrays_give_garden_values_to_list_operations() {
+    assert_eq!(
+        show(r#"(sort-by (fn [p] (get p "age")) (json.parse "[{\"age\": 40}, {\"age\": 7}]"))"#).await,
+        r#"({"age" 7} {"age" 40})"#
+    );
+}
+
+// An evaluator that refuses to make HTTP requests
+fn offline_evaluator() -> Evaluator {
+    let mut config = GardenConfig::default();
+    config.sandbox.allow_http = false;
+    Evaluator::with_config(config)
+}
+
+// The node of `src`'s last expression
+fn last_node(src: &str) -> Rc<Node> {
+    parser::parse(src).unwrap().pop().unwrap()
+}
+
+#[tokio::test]
+async fn same_arithmetic_follows_each_overflow_mode_it_runs_under() {
+    let src = r#"
+        (num.overflow-mode "wrapping")
+        (def a (+ 9223372036854775807 1))
+        (num.overflow-mode "saturating")
+        (def b (+ 9223372036854775807 1))
+        [a b]"#;
+    assert_eq!(show(src).await, "(-9223372036854775808 9223372036854775807)");
+}
+
+#[tokio::test]
+async fn overflowing_arithmetic_is_an_error_by_default() {
+    let error = eval_error("(+ 9223372036854775807 1)").await;
+    assert!(error.contains("overflow") && error.contains("9223372036854775807"), "{}", error);
+    assert!(eval_error("(* 9223372036854775807 2)").await.contains("overflow"));
+    assert!(eval_error("(- -9223372036854775807 2)").await.contains("overflow"));
+    assert!(eval_error("(/ (- -9223372036854775807 1) -1)").await.contains("overflow"));
+    assert!(eval_error("(% 7 0)").await.contains("division by zero"));
+    assert_eq!(show("[(- 10 3 2) (/ 20 2 5) (% 17 5) (- 4)]").await, "(5 2 2 -4)");
+    assert_eq!(show(r#"(num.overflow-mode "wrapping") (num.overflow-mode "error")"#).await, r#""wrapping""#);
+}
+
+#[tokio::test]
+async fn arithmetic_promotes_to_float_when_any_argument_is_a_float() {
+    assert_eq!(show("[(+ 1.5 2) (* 2 0.5) (- 1.5) (/ 7.0 2) (% 7.5 2) (+ 1 2)]").await, "(3.5 1.0 -1.5 3.5 1.5 3)");
+    assert!(eval_error("(/ 1.5 0)").await.contains("division by zero"));
+    let error = eval_error(r#"(+ 1.5 "2")"#).await;
+    assert!(error.contains("requires all arguments to be numbers") && error.contains(r#"string "2""#), "{}", error);
+}
+
+#[tokio::test]
+async fn arithmetic_is_cached_again_once_no_overflow_mode_is_set() {
+    let mut evaluator = Evaluator::new();
+    let sum = "(+ 9223372036854775806 1)";
+    eval_with(&mut evaluator, &format!("(num.overflow-mode \"wrapping\") {}", sum)).await.unwrap();
+    let id = *last_node(sum).id();
+    assert!(!evaluator.is_cacheable(&id));
+
+    eval_with(&mut evaluator, sum).await.unwrap();
+    assert!(evaluator.is_cacheable(&id));
+    assert!(!evaluator.volatile_nodes.contains(&id));
+    assert!(evaluator.cache.get(&id).is_some());
+}
+
+#[tokio::test]
+async fn requests_are_cached_again_once_their_url_is_no_longer_mocked() {
+    let mut evaluator = offline_evaluator();
+    let get = r#"(http.get "http://example.invalid/")"#;
+    let mocked = format!(r#"(http.mock "http://example.invalid/" "canned") {}"#, get);
+    assert_eq!(eval_with(&mut evaluator, &mocked).await.unwrap(), Value::String("canned".into()));
+    let id = *last_node(get).id();
+    assert!(!evaluator.is_cacheable(&id));
+
+    let error = eval_with(&mut evaluator, get).await.unwrap_err().to_string();
+    assert!(error.contains("HTTP is disabled"), "{}", error);
+    assert!(evaluator.is_cacheable(&id));
+}
+
+// The example from BuiltinFn's documentation
+struct Greet;
+
+impl BuiltinFn for Greet {
+    fn name(&self) -> &str {
+        "greet"
+    }
+
+    fn eval(&self, args: Vec<Value>, _context: &mut EvalContext) -> BoxFuture<'_, Result<Value, Error>> {
+        Box::pin(async move {
+            match args.as_slice() {
+                [Value::String(name)] => Ok(Value::String(format!("Hello, {}!", name))),
+                _ => Err(Error::EvalError("'greet' expects 1 string argument".to_string())),
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn registered_builtins_can_be_called_from_garden() {
+    let mut evaluator = Evaluator::new();
+    evaluator.register_builtin(Box::new(Greet));
+    let greeting = eval_with(&mut evaluator, r#"(def who "garden") (greet who)"#).await.unwrap();
+    assert_eq!(greeting, Value::String("Hello, garden!".into()));
+    let applied = eval_with(&mut evaluator, r#"(apply greet ["world"])"#).await.unwrap();
+    assert_eq!(applied, Value::String("Hello, world!".into()));
+    let error = eval_with(&mut evaluator, "(greet 1)").await.unwrap_err().to_string();
+    assert!(error.contains("'greet' expects 1 string argument"), "{}", error);
+}
+
+#[tokio::test]
+async fn unregistered_names_are_still_unknown() {
+    assert!(eval_error(r#"(greet "garden")"#).await.contains("greet"));
+}
+
+#[test]
+fn changed_since_lists_nodes_changed_after_the_cutoff() {
+    let mut cache = cache_with_entries(4);
+    let start = Utc::now() - chrono::Duration::seconds(60);
+    for i in 0..4u8 {
+        cache.cache.get_mut(&[i; 32]).unwrap().changed_at = start + chrono::Duration::seconds(i as i64 * 10);
+    }
+    let mut changed: Vec<u8> = cache.changed_since(start + chrono::Duration::seconds(10)).iter().map(|id| id[0]).collect();
+    changed.sort();
+    assert_eq!(changed, vec![2, 3]);
+    assert_eq!(cache.last_changed(&[1; 32]), Some(start + chrono::Duration::seconds(10)));
+    assert_eq!(cache.last_changed(&[9; 32]), None);
+
+    // Computing the same value again leaves the time it last changed alone
+    let cutoff = Utc::now();
+    cache.insert([3; 32], Ok(Value::Number(3)));
+    cache.insert([1; 32], Ok(Value::Number(100)));
+    assert_eq!(cache.changed_since(cutoff), vec![[1; 32]]);
+}
+
+#[tokio::test]
+async fn only_values_that_changed_count_as_changed_in_a_cycle() {
+    let mut evaluator = Evaluator::new();
+    eval_with(&mut evaluator, "(def a 1) (def b 2)").await.unwrap();
+    let id = |src: &str| *last_node(src).id();
+    assert!(evaluator.cache.was_changed(&id("(def a 1)")));
+
+    eval_with(&mut evaluator, "(def a 1) (def b 3)").await.unwrap();
+    assert!(!evaluator.cache.was_changed(&id("(def a 1)")));
+    assert!(evaluator.cache.was_changed(&id("(def b 3)")));
+    let changed: Vec<NodeId> = evaluator.get_changed_nodes().iter().map(|node| *node.id()).collect();
+    assert!(changed.contains(&id("(def b 3)")));
+    assert!(!changed.contains(&id("(def a 1)")));
+}
+
+#[test]
+fn changes_in_a_cycle_do_not_depend_on_the_clock() {
+    let mut cache = cache_with_entries(2);
+    cache.prepare_for_evaluation();
+    // Changed in an earlier cycle, at a time the clock has since gone back past
+    cache.cache.get_mut(&[0; 32]).unwrap().changed_at = Utc::now() + chrono::Duration::hours(1);
+    assert!(!cache.was_changed(&[0; 32]));
+
+    // Recorded right as the cycle starts, within the same clock tick
+    cache.insert([1; 32], Ok(Value::String("new".into())));
+    cache.insert([5; 32], Ok(Value::Number(5)));
+    assert!(cache.was_changed(&[1; 32]) && cache.was_changed(&[5; 32]));
+    cache.prepare_for_evaluation();
+    assert!(!cache.was_changed(&[1; 32]) && !cache.was_changed(&[5; 32]));
+}
+
+#[test]
+fn annotated_nodes_keep_their_id() {
+    let node = last_node("(+ 1 2)");
+    let annotated = node.with_metadata("description", "three");
+    assert_eq!(annotated.id(), node.id());
+    assert_eq!(annotated.metadata().get("description").map(String::as_str), Some("three"));
+    assert!(!node.metadata().contains_key("description"));
+    assert_eq!(annotated.metadata().get("line"), node.metadata().get("line"));
+}
+
+#[test]
+fn comments_above_top_level_expressions_describe_them() {
+    let nodes = parser::parse("; Base rate\n(def rate 3)\n(def tax (* rate 2)) ; not this one\n").unwrap();
+    assert_eq!(nodes[0].metadata().get("description").map(String::as_str), Some("Base rate"));
+    assert!(!nodes[1].metadata().contains_key("description"));
+    assert_eq!(nodes[0].id(), last_node("(def rate 3)").id());
+}
+
+#[test]
+fn find_by_kind_gives_matching_nodes_in_source_order() {
+    let root = last_node(
+        r#"(+ (http.get "http://a.invalid/")
+              (* 2 (http.get "http://b.invalid/"))
+              (- 1 (+ 3 (http.get "http://c.invalid/"))))"#,
+    );
+    let found = find_by_kind(&root, &NodeKind::HttpGet);
+    let urls: Vec<String> = found.iter().map(|node| node.children()[1].code_snippet().to_string()).collect();
+    assert_eq!(urls, vec![r#""http://a.invalid/""#, r#""http://b.invalid/""#, r#""http://c.invalid/""#]);
+    assert_eq!(find_by_kind(&root, &NodeKind::Multiplication).len(), 1);
+}
+
+#[test]
+fn find_by_metadata_gives_nodes_with_that_value() {
+    let root = last_node(
+        r#"(+ (http.get "http://a.invalid/")
+              (* 2 (http.get "http://b.invalid/"))
+              (- 1 3))"#,
+    );
+    let on_line_two = find_by_metadata(&root, "line", "2");
+    assert_eq!(on_line_two[0].code_snippet(), r#"(* 2 (http.get "http://b.invalid/"))"#);
+    assert!(on_line_two.iter().any(|node| node.kind() == &NodeKind::HttpGet));
+    assert!(on_line_two.iter().all(|node| node.metadata()["line"] == "2" && !node.code_snippet().contains("a.invalid")));
+    assert!(find_by_metadata(&root, "line", "9").is_empty());
+    assert!(find_by_metadata(&root, "no-such-key", "2").is_empty());
+}
+
+#[tokio::test]
+async fn files_sharing_a_backend_evaluate_common_expressions_once() {
+    let shared = Arc::new(Mutex::new(SharedNodeCache::default()));
+    let mut first = Evaluator::new();
+    first.share_results(shared.clone());
+    let mut second = Evaluator::new();
+    second.share_results(shared.clone());
+    let sum = *last_node("(+ 1 2)").id();
+
+    assert_eq!(eval_with(&mut first, "(def a (+ 1 2)) a").await.unwrap(), Value::Number(3));
+    // The second file has the sum's result as soon as it knows the node, before evaluating
+    second.prepare_for_evaluation();
+    for node in parser::parse("(def b (+ 1 2))").unwrap() {
+        second.store_node(node);
+    }
+    assert!(matches!(second.cache.get(&sum), Some(Ok(Value::Number(3)))));
+    assert_eq!(eval_with(&mut second, "(def b (+ 1 2)) b").await.unwrap(), Value::Number(3));
+
+    // Names depend on each file's definitions, so they aren't shared
+    let name = *last_node("a").id();
+    assert!(shared.lock().unwrap().results.contains_key(&sum));
+    assert!(!shared.lock().unwrap().results.contains_key(&name));
+}
+
+#[tokio::test]
+async fn exported_results_leave_out_impure_nodes() {
+    let mut first = Evaluator::new();
+    let src = "(def a (+ 1 2)) (def id (uuid.new)) (def b (+ a 1)) b";
+    eval_with(&mut first, src).await.unwrap();
+    let exported = first.cache.export_shared();
+    let sum = *last_node("(+ 1 2)").id();
+    assert!(matches!(exported.get(&sum), Some(Ok(Value::Number(3)))));
+    for impure in ["(uuid.new)", "a", "(+ a 1)", "(def id (uuid.new))"] {
+        assert!(!exported.contains_key(last_node(impure).id()), "{} was exported", impure);
+    }
+
+    let mut second = Evaluator::new();
+    let kept = *last_node("(* 2 2)").id();
+    eval_with(&mut second, "(* 2 2)").await.unwrap();
+    second.store_node(last_node("(uuid.new)"));
+    let uuid = *last_node("(uuid.new)").id();
+    let mut with_others = exported.clone();
+    with_others.insert(kept, Ok(Value::Number(5)));
+    with_others.insert(uuid, Ok(Value::String("not fresh".into())));
+    second.cache.import_shared(&with_others);
+    assert!(matches!(second.cache.get(&sum), Some(Ok(Value::Number(3)))));
+    assert!(matches!(second.cache.get(&kept), Some(Ok(Value::Number(4)))));
+    assert!(second.cache.get(&uuid).is_none());
+}
+
+#[test]
+fn iterating_a_cache_gives_the_same_results_as_get() {
+    let cache = cache_with_entries(4);
+    assert_eq!(cache.len(), 4);
+    let mut seen = Vec::new();
+    for (id, result) in &cache {
+        assert_eq!(format!("{:?}", Some(result)), format!("{:?}", cache.get(id)));
+        seen.push(id[0]);
+    }
+    seen.sort();
+    assert_eq!(seen, vec![0, 1, 2, 3]);
+    assert!(EvaluationCache::new().is_empty());
+    assert_eq!((&EvaluationCache::new()).into_iter().count(), 0);
+}
+
+#[tokio::test]
+async fn cache_listings_show_each_result_with_its_code() {
+    let mut evaluator = Evaluator::new();
+    let src = "(def total (+ 1 2))";
+    eval_with(&mut evaluator, src).await.unwrap();
+    let listing = describe_cache(&evaluator.cache, src);
+    assert!(listing.starts_with(&format!("{} cached results\n", evaluator.cache.len())), "{}", listing);
+    let sum = hex::encode(&last_node("(+ 1 2)").id()[0..4]);
+    assert!(listing.contains(&format!("{} (+ 1 2) => 3\n", sum)), "{}", listing);
+    assert_eq!(describe_cache(&EvaluationCache::new(), src), "No cached results\n");
+}
+
+#[tokio::test]
+async fn trims_whitespace_from_both_ends() {
+    assert_eq!(show(r#"(str.trim "  a b \n")"#).await, r#""a b""#);
+    assert!(eval_error("(str.trim 1)").await.contains("'str.trim'"));
+}
+
+#[tokio::test]
+async fn pipe_threads_a_value_through_each_function() {
+    assert_eq!(show(r#"(pipe "  hello  " str.trim str.upper)"#).await, r#""HELLO""#);
+    assert_eq!(show(r#"(pipe-> "  hello  " str.trim str.upper)"#).await, r#""HELLO""#);
+    let data = r#"(def body (json.parse "{\"user\": {\"name\": \" ada \"}}"))"#;
+    let piped = show(&format!(r#"{} (pipe body (get _ "user") (get _ "name") str.trim)"#, data)).await;
+    let nested = show(&format!(r#"{} (str.trim (get (get body "user") "name"))"#, data)).await;
+    assert_eq!(piped, r#""ada""#);
+    assert_eq!(piped, nested);
+}
+
+#[tokio::test]
+async fn json_output_has_every_definition() {
+    let mut evaluator = Evaluator::new();
+    let src = r#"(def n 2) (def name "ada") (def items [1 2.5 "x"]) (def square (fn [x] (* x x)))"#;
+    let (root_nodes, error) = evaluate_source(src, &mut evaluator).await.unwrap();
+    assert!(error.is_none());
+    let context = evaluator.context(&root_nodes);
+
+    let json = format_output(&context, OutputFormat::Json);
+    assert!(!json.contains('\x1b'));
+    let parsed: JsonValue = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed["n"], serde_json::json!(2));
+    assert_eq!(parsed["name"], serde_json::json!("ada"));
+    assert_eq!(parsed["items"], serde_json::json!([1, 2.5, "x"]));
+    assert!(parsed["square"].is_string());
+    assert_eq!(parsed.as_object().unwrap().len(), 4);
+
+    assert_eq!(
+        format_output(&context, OutputFormat::Csv).lines().take(3).collect::<Vec<_>>(),
+        vec!["symbol,value", "n,2", "name,ada"]
+    );
+    assert!(format_output(&context, OutputFormat::Text).starts_with("n = 2\nname = \"ada\"\n"));
+}
+
+#[tokio::test]
+async fn parses_numbers_in_other_radixes() {
+    assert_eq!(show(r#"(str.to-num "0b11111111")"#).await, "255");
+    assert_eq!(show(r#"(str.to-num "0xFF")"#).await, "255");
+    assert_eq!(show(r#"(str.to-num "ff" 16)"#).await, "255");
+    assert_eq!(show(r#"(str.to-num "-42")"#).await, "-42");
+    let error = eval_error(r#"(str.to-num "hello")"#).await;
+    assert!(error.contains("hello") && error.contains("10"), "{}", error);
+    let error = eval_error(r#"(str.to-num "12" 37)"#).await;
+    assert!(error.contains("37"), "{}", error);
+}
+
+#[test]
+fn the_radix_is_part_of_a_parses_identity() {
+    assert_ne!(last_node(r#"(str.to-num "10" 2)"#).id(), last_node(r#"(str.to-num "10" 16)"#).id());
+    assert_ne!(last_node(r#"(str.to-num "10")"#).id(), last_node(r#"(str.to-num "10" 10)"#).id());
+}
+
+#[tokio::test]
+async fn formats_numbers_with_printf_style_strings() {
+    assert_eq!(show(r#"(num.format 3.14159 "%.2f")"#).await, r#""3.14""#);
+    assert_eq!(show(r#"(num.format 42 "%d")"#).await, r#""42""#);
+    assert_eq!(show(r#"(num.format 1234567 "%,d")"#).await, r#""1,234,567""#);
+    assert_eq!(show(r#"(num.format 1500 "%.1e")"#).await, r#""1.5e3""#);
+    let error = eval_error(r#"(num.format 1 "%q")"#).await;
+    assert!(error.contains("Invalid format string"), "{}", error);
+    assert!(eval_error(r#"(num.format 1 "%.")"#).await.contains("Invalid format string"));
+}
+
+#[tokio::test]
+async fn parses_numbers_written_for_people() {
+    assert_eq!(show(r#"(num.parse "1,000")"#).await, "1000");
+    assert_eq!(show(r#"(num.parse "  42  ")"#).await, "42");
+    assert_eq!(show(r#"(= (num.parse "1.5e3") 1500.0)"#).await, "true");
+    assert!(eval_error(r#"(num.parse "forty")"#).await.contains("forty"));
+}
+
+#[tokio::test]
+async fn each_calls_the_function_for_every_element_and_gives_nil() {
+    let mut evaluator = Evaluator::new();
+    evaluator.capture_output();
+    let value = eval_with(&mut evaluator, "(each (range 1 4) (fn [i] (println i)))").await.unwrap();
+    assert_eq!(value, Value::Nil);
+    assert_eq!(evaluator.take_output(), vec!["1\n", "2\n", "3\n"]);
+
+    let value = eval_with(&mut evaluator, "(each [] (fn [i] (println i)))").await.unwrap();
+    assert_eq!(value, Value::Nil);
+    assert!(evaluator.take_output().is_empty());
+}
+
+#[tokio::test]
+async fn sort_orders_mixed_lists_by_type_then_value() {
+    assert_eq!(show("(sort [3 1 2])").await, "(1 2 3)");
+    assert_eq!(show(r#"(sort ["b" [1] 2.5 "a" 1])"#).await, r#"(1 2.5 "a" "b" (1))"#);
+    assert_eq!(show("(sort [])").await, "()");
+}
+
+#[tokio::test]
+async fn sort_by_orders_by_each_elements_key() {
+    let people = r#"(def people (json.parse "[{\"name\": \"ann\", \"age\": 40}, {\"name\": \"bo\", \"age\": 12}]"))"#;
+    let sorted = show(&format!(r#"{} (sort-by (fn [x] (get x "age")) people)"#, people)).await;
+    assert_eq!(sorted, r#"({"age" 12, "name" "bo"} {"age" 40, "name" "ann"})"#);
+    let original = show(&format!(r#"{} (def sorted (sort-by (fn [x] (get x "age")) people)) people"#, people)).await;
+    assert_eq!(original, r#"[{"age":40,"name":"ann"},{"age":12,"name":"bo"}]"#);
+}
+
+#[tokio::test]
+async fn group_by_gives_a_group_per_distinct_key() {
+    let items = r#"(def items (json.parse "[{\"id\": 1, \"category\": \"fruit\"}, {\"id\": 2, \"category\": \"veg\"}, {\"id\": 3, \"category\": \"fruit\"}]"))"#;
+    let groups = eval(&format!(r#"{} (group-by (fn [x] (get x "category")) items)"#, items)).await.unwrap();
+    let Value::Map(groups) = groups else { panic!("expected a map, got {}", groups) };
+    assert_eq!(groups.keys().collect::<Vec<_>>(), vec!["fruit", "veg"]);
+    assert!(matches!(&groups["fruit"], Value::List(group) if group.len() == 2));
+    assert!(matches!(&groups["veg"], Value::List(group) if group.len() == 1));
+
+    assert_eq!(eval("(group-by (fn [x] x) [])").await.unwrap(), Value::Map(IndexMap::new()));
+    let error = eval_error(r#"(group-by (fn [x] (str.upper x)) ["a" 1])"#).await;
+    assert!(error.contains("'str.upper'"), "{}", error);
+}
+
+#[tokio::test]
+async fn zip_pairs_lists_and_unzip_splits_them() {
+    assert_eq!(show(r#"(zip [1 2 3] ["a" "b" "c"])"#).await, r#"((1 "a") (2 "b") (3 "c"))"#);
+    assert_eq!(show(r#"(zip [1 2 3] ["a"])"#).await, r#"((1 "a"))"#);
+    assert_eq!(show("(zip [] [])").await, "()");
+    assert_eq!(show(r#"(unzip [[1 "a"] [2 "b"]])"#).await, r#"((1 2) ("a" "b"))"#);
+    assert_eq!(show(r#"(unzip (zip [1 2] ["a" "b"]))"#).await, r#"((1 2) ("a" "b"))"#);
+}
+
+#[tokio::test]
+async fn distinct_keeps_the_first_of_each_value() {
+    assert_eq!(show("(distinct [1 2 1 3 2])").await, "(1 2 3)");
+    assert_eq!(show("(distinct [])").await, "()");
+    let distinct = eval(r#"(distinct (json.parse "[{\"a\": [1, 2]}, {\"a\": [1, 2]}, {\"a\": [2]}]"))"#).await.unwrap();
+    assert!(matches!(&distinct, Value::List(items) if items.len() == 2), "{}", distinct);
+}
+
+#[tokio::test]
+async fn frequencies_counts_each_value() {
+    let counts = eval(r#"(frequencies ["a" "b" "a"])"#).await.unwrap();
+    let expected = IndexMap::from([("a".to_string(), Value::Number(2)), ("b".to_string(), Value::Number(1))]);
+    assert_eq!(counts, Value::Map(expected));
+    assert_eq!(show(r#"(get (frequencies [1 1 2]) "1")"#).await, "2");
+}
+
+#[tokio::test]
+async fn take_drop_and_partition_slice_lists() {
+    assert_eq!(show("(take 2 [1 2 3 4])").await, "(1 2)");
+    assert_eq!(show("(take 9 [1 2])").await, "(1 2)");
+    assert_eq!(show("(drop 2 [1 2 3 4])").await, "(3 4)");
+    assert_eq!(show("(drop 9 [1 2])").await, "()");
+    assert_eq!(show("(partition 2 [1 2 3 4 5])").await, "((1 2) (3 4) (5))");
+    for src in ["(take -1 [1])", "(drop -1 [1])", "(partition 0 [1])", r#"(take 1 "abc")"#, "(drop 1 5)"] {
+        eval_error(src).await;
+    }
+}
+
+#[tokio::test]
+async fn apply_spreads_a_list_into_arguments() {
+    assert_eq!(show("(apply + [1 2 3])").await, "6");
+    assert_eq!(show("(apply + [])").await, "0");
+    assert_eq!(show(r#"(apply str.concat ["hello" " " "world"])"#).await, r#""hello world""#);
+    assert_eq!(show("(def add (fn [a b] (+ a b))) (apply add [2 5])").await, "7");
+}
+
+#[tokio::test]
+async fn map_operations_leave_the_original_map_alone() {
+    let m = r#"(def m (json.parse "{\"x\": 1, \"z\": 3}"))"#;
+    assert_eq!(show(&format!(r#"{} (assoc (dissoc m "x") "y" 99)"#, m)).await, r#"{"z" 3, "y" 99}"#);
+    assert_eq!(show(&format!(r#"{} (def changed (assoc m "x" 5)) m"#, m)).await, r#"{"x":1,"z":3}"#);
+    assert_eq!(show(&format!("{} (keys m)", m)).await, r#"("x" "z")"#);
+    assert_eq!(show(&format!("{} (vals m)", m)).await, "(1 3)");
+    assert_eq!(show(&format!(r#"{} (merge m (assoc m "w" 0))"#, m)).await, r#"{"x" 1, "z" 3, "w" 0}"#);
+    assert!(eval_error(r#"(assoc [1] "x" 1)"#).await.contains("'assoc'"));
+    assert!(eval_error(r#"(dissoc 5 "x")"#).await.contains("'dissoc'"));
+}
+
+#[tokio::test]
+async fn for_collects_the_body_for_each_element() {
+    assert_eq!(show("(for [x [1 2 3]] (* x x))").await, "(1 4 9)");
+    assert_eq!(show("(for [x []] x)").await, "()");
+    assert_eq!(show("(def x 10) (def squares (for [x [1 2]] (* x x))) x").await, "10");
+    let error = eval_error("(for [y [1 2]] y) y").await;
+    assert!(error.contains("y"), "{}", error);
+}
+
+#[tokio::test]
+async fn while_loops_until_its_condition_is_false() {
+    let mut evaluator = Evaluator::new();
+    let (root_nodes, error) = evaluate_source("(def i 0) (while (< i 5) (def i (+ i 1)))", &mut evaluator).await.unwrap();
+    assert!(error.is_none());
+    assert_eq!(evaluator.context(&root_nodes)["i"], Value::Number(5));
+    assert_eq!(show("(while false 1)").await, "nil");
+}
+
+#[tokio::test]
+async fn while_stops_at_the_configured_iteration_limit() {
+    let mut config = GardenConfig::default();
+    config.eval.max_loop_iterations = Some(3);
+    let mut evaluator = Evaluator::with_config(config);
+    let error = eval_with(&mut evaluator, "(def i 0) (while (< i 5) (def i (+ i 1)))").await.unwrap_err();
+    assert_eq!(error.to_string(), "Evaluation Error: while loop exceeded maximum iteration count");
+    let error = eval_with(&mut Evaluator::new(), "(while true 1)").await.unwrap_err();
+    assert!(error.to_string().contains("maximum iteration count"));
+}
+
+#[tokio::test]
+async fn def_fn_defines_a_named_function() {
+    assert_eq!(show("(def-fn double [x] (* x 2)) (double 5)").await, "10");
+    let mut evaluator = Evaluator::new();
+    let (root_nodes, error) = evaluate_source("(def-fn double [x] (* x 2))", &mut evaluator).await.unwrap();
+    assert!(error.is_none());
+    assert!(matches!(evaluator.context(&root_nodes)["double"], Value::Lambda(_)));
+    assert_eq!(root_nodes[0].code_snippet(), "(def double (fn [x] (* x 2)))");
+    assert!(matches!(parser::parse("(def-fn double [x])"), Err(Error::ArityError(_))));
+}
+
+#[test]
+fn memoized_recursion_reuses_earlier_calls() {
+    let started = std::time::Instant::now();
+    let fib = "(def fib (memoize (fn [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))";
+    assert_eq!(show_on_eval_stack(&format!("{} (fib 30)", fib)), "832040");
+    assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+    // Wrapping a function is not part of its identity
+    assert_eq!(last_node("(fn [n] n)").id(), last_node("(fn [n] n)").id());
+    assert_ne!(last_node("(memoize (fn [n] n))").id(), last_node("(fn [n] n)").id());
+}
+
+#[tokio::test]
+async fn type_of_names_each_kind_of_value() {
+    let cases = [
+        ("1", "number"),
+        ("1.5", "float"),
+        (r#""a""#, "string"),
+        ("true", "bool"),
+        ("(when false 1)", "nil"),
+        ("[1]", "list"),
+        (r#"(assoc (json.parse "{}") "a" 1)"#, "map"),
+        (r#"(json.parse "{}")"#, "json"),
+        ("(fn [x] x)", "lambda"),
+    ];
+    for (value, name) in cases {
+        assert_eq!(show(&format!("(type-of {})", value)).await, format!("\"{}\"", name), "{}", value);
+    }
+    assert_eq!(show("(number? 3)").await, "true");
+    assert_eq!(show(r#"(string? 3)"#).await, "false");
+    assert_eq!(show("(list? [])").await, "true");
+    assert_eq!(show("(nil? (when false 1))").await, "true");
+    assert_eq!(show("(fn? inc)").await, "true");
+    assert!(eval_error("(type-of 1 2)").await.contains("'type-of'"));
+}
+
+#[tokio::test]
+async fn prelude_definitions_are_available_and_can_be_overridden() {
+    assert_eq!(show("(inc 5)").await, "6");
+    assert_eq!(show("((compose inc dec) 3)").await, "3");
+    assert_eq!(show("(def inc (fn [x] (* x 10))) (inc 5)").await, "50");
+    assert_eq!(show("(def-fn not [x] 42) (not true)").await, "42");
+
+    // Prelude definitions aren't part of the program's own variables
+    let mut evaluator = Evaluator::new();
+    let (root_nodes, error) = evaluate_source("(def n (inc 1))", &mut evaluator).await.unwrap();
+    assert!(error.is_none());
+    assert_eq!(evaluator.context(&root_nodes).keys().collect::<Vec<_>>(), vec!["n"]);
+    evaluator.evaluate_prelude().await;
+    assert!(evaluator.prelude_context().contains_key("inc"));
+}
+
+#[tokio::test]
+async fn csv_round_trips_quoted_fields() {
+    let text = r#"(def text "name,note\r\n\"Lovelace, Ada\",\"said \"\"hi\"\"\"\r\nTuring,plain\r\n")"#;
+    assert_eq!(
+        show(&format!("{} (csv.parse text)", text)).await,
+        r#"(("name" "note") ("Lovelace, Ada" "said \"hi\"") ("Turing" "plain"))"#
+    );
+    let round_trip = format!("{} (= (csv.parse (csv.stringify (csv.parse text))) (csv.parse text))", text);
+    assert_eq!(show(&round_trip).await, "true");
+    assert_eq!(
+        show(&format!("{} (csv.parse-headers text)", text)).await,
+        r#"({"name" "Lovelace, Ada", "note" "said \"hi\""} {"name" "Turing", "note" "plain"})"#
+    );
+    assert!(eval_error("(csv.parse 1)").await.contains("'csv.parse'"));
+}
+
+#[tokio::test]
+async fn toml_and_yaml_parse_into_nested_maps_and_lists() {
+    let manifest = r#"(def manifest (toml.parse "
+name = 'garden'
+version = 3
+ratio = 0.5
+enabled = true
+ports = [80, 443]
+
+[owner]
+name = 'ada'
+
+[[servers]]
+host = 'alpha'
+
+[[servers]]
+host = 'beta'
+"))"#;
+    assert_eq!(
+        show(&format!("{} (keys manifest)", manifest)).await,
+        r#"("enabled" "name" "owner" "ports" "ratio" "servers" "version")"#
+    );
+    assert_eq!(show(&format!("{} (get manifest \"ports\")", manifest)).await, "(80 443)");
+    assert_eq!(show(&format!("{} (get (get manifest \"owner\") \"name\")", manifest)).await, r#""ada""#);
+    assert_eq!(
+        show(&format!("{} (for [s (get manifest \"servers\")] (get s \"host\"))", manifest)).await,
+        r#"("alpha" "beta")"#
+    );
+    assert_eq!(show(&format!("{} (type-of (get manifest \"ratio\"))", manifest)).await, r#""float""#);
+
+    let yaml = "(yaml.parse \"
+service: api
+replicas: 2
+debug: false
+tags: [a, b]
+limits:
+  memory: 512
+missing: ~
+\")";
+    assert_eq!(
+        show(yaml).await,
+        r#"{"service" "api", "replicas" 2, "debug" false, "tags" ("a" "b"), "limits" {"memory" 512}, "missing" nil}"#
+    );
+    assert_eq!(show(r#"(yaml.parse "- 1\n- 2.5")"#).await, "(1 2.5)");
+    assert!(eval_error(r#"(toml.parse "name = ")"#).await.contains("toml.parse"));
+}
+
+#[tokio::test]
+async fn regex_matches_captures_and_replaces() {
+    assert_eq!(show(r#"(regex.match "[0-9]+" "abc123")"#).await, "true");
+    assert_eq!(show(r#"(regex.match "^[0-9]+$" "abc123")"#).await, "false");
+    assert_eq!(show(r#"(regex.replace "(\\d+)" "[$1]" "abc 42 def 7")"#).await, r#""abc [42] def [7]""#);
+    assert_eq!(show(r#"(regex.captures "(\\w+)@(\\w+)" "mail ada@garden")"#).await, r#"("ada@garden" "ada" "garden")"#);
+    assert_eq!(show(r#"(regex.captures "x(\\d)" "abc")"#).await, "nil");
+    assert!(eval_error(r#"(regex.match "(" "abc")"#).await.contains("regex.match"));
+
+    // Each pattern is compiled once however often it's used
+    let mut evaluator = Evaluator::new();
+    let src = r#"(for [s ["a1" "b" "c3"]] (regex.match "[0-9]" s)) (regex.match "[0-9]" "z")"#;
+    eval_with(&mut evaluator, src).await.unwrap();
+    assert_eq!(evaluator.regexes.len(), 1);
+}
+
+#[tokio::test]
+async fn base64_round_trips_in_both_alphabets() {
+    assert_eq!(show(r#"(base64.encode "Hello, World!")"#).await, r#""SGVsbG8sIFdvcmxkIQ==""#);
+    assert_eq!(show(r#"(base64.decode (base64.encode "Hello, World!"))"#).await, r#""Hello, World!""#);
+    assert_eq!(show(r#"(base64.encode "??>")"#).await, r#""Pz8+""#);
+    assert_eq!(show(r#"(base64.encode "??>" true)"#).await, r#""Pz8-""#);
+    assert_eq!(show(r#"(base64.decode "Pz8-" true)"#).await, r#""??>""#);
+    let error = eval_error(r#"(base64.decode "not*base64")"#).await;
+    assert!(error.contains("'base64.decode'") && error.contains("not*base64"), "{}", error);
+}
+
+#[tokio::test]
+async fn hashes_match_known_digests() {
+    assert_eq!(
+        show(r#"(hash.sha256 "")"#).await,
+        r#""e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855""#
+    );
+    assert_eq!(show(r#"(hash.md5 "")"#).await, r#""d41d8cd98f00b204e9800998ecf8427e""#);
+    assert_eq!(
+        show(r#"(hash.blake3 "")"#).await,
+        r#""af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262""#
+    );
+    // RFC 4231 test case 2
+    assert_eq!(
+        show(r#"(hash.hmac-sha256 "Jefe" "what do ya want for nothing?")"#).await,
+        r#""5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843""#
+    );
+    assert!(eval_error("(hash.sha256 1)").await.contains("'hash.sha256'"));
+}
+
+#[tokio::test]
+async fn uuid_new_is_fresh_on_every_evaluation() {
+    let shape = Regex::new("^\"[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}\"$").unwrap();
+    let mut evaluator = Evaluator::new();
+    let first = eval_with(&mut evaluator, "(def id (uuid.new)) id").await.unwrap().to_string();
+    let second = eval_with(&mut evaluator, "(def id (uuid.new)) id").await.unwrap().to_string();
+    assert!(shape.is_match(&first), "{}", first);
+    assert_ne!(first, second);
+    assert_eq!(
+        show(r#"(uuid.parse "67E55044-10B1-426F-9247-BB680E5FE0C8")"#).await,
+        r#""67e55044-10b1-426f-9247-bb680e5fe0c8""#
+    );
+    assert!(eval_error(r#"(uuid.parse "invalid")"#).await.contains("'uuid.parse'"));
+}
+
+#[tokio::test]
+async fn url_encoding_round_trips_and_builds_query_strings() {
+    assert_eq!(show(r#"(url.encode "hello world & foo=bar")"#).await, r#""hello%20world%20%26%20foo%3Dbar""#);
+    assert_eq!(show(r#"(url.decode (url.encode "a/b?c=d é"))"#).await, r#""a/b?c=d é""#);
+    let params = r#"(assoc (assoc (json.parse "{}") "q" "rust lang") "limit" "10")"#;
+    assert_eq!(
+        show(&format!(r#"(url.build "https://api.example.com/search" {})"#, params)).await,
+        r#""https://api.example.com/search?q=rust%20lang&limit=10""#
+    );
+    assert_eq!(
+        show(&format!(r#"(url.build "https://example.com/?page=2" (assoc {} "limit" 5))"#, params)).await,
+        r#""https://example.com/?page=2&q=rust%20lang&limit=5""#
+    );
+    assert!(eval_error(r#"(url.decode "%FF")"#).await.contains("'url.decode'"));
+}
+
+#[tokio::test]
+async fn changing_one_definition_reevaluates_only_that_definition() {
+    let source = |changed: i64| {
+        (0..100)
+            .map(|i| format!("(def x{} (* {} 2))", i, if i == 42 { changed } else { i }))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let mut evaluator = Evaluator::new();
+    evaluator.enable_timings();
+    eval_with(&mut evaluator, &source(42)).await.unwrap();
+
+    let edited = source(1000);
+    eval_with(&mut evaluator, &edited).await.unwrap();
+    let timings = evaluator.timings().unwrap();
+    let reevaluated: Vec<String> = parser::parse(&edited)
+        .unwrap()
+        .iter()
+        .filter(|node| timings.get(node.id()).is_some())
+        .map(|node| node.code_snippet().to_string())
+        .collect();
+    assert_eq!(reevaluated, vec!["(def x42 (* 1000 2))"]);
+
+    // A definition using the changed one is evaluated again too
+    let dependent = format!("{}\n(def total (+ x42 1))", source(7));
+    eval_with(&mut evaluator, &dependent).await.unwrap();
+    let timings = evaluator.timings().unwrap();
+    let reevaluated = parser::parse(&dependent).unwrap().iter().filter(|node| timings.get(node.id()).is_some()).count();
+    assert_eq!(reevaluated, 2);
+}
+
+// A registered built-in that takes 10ms
+struct Nap;
+
+impl BuiltinFn for Nap {
+    fn name(&self) -> &str {
+        "nap"
+    }
+
+    fn eval(&self, _args: Vec<Value>, _context: &mut EvalContext) -> BoxFuture<'_, Result<Value, Error>> {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(Value::Nil)
+        })
+    }
+}
+
+#[tokio::test]
+async fn timings_put_the_slowest_evaluation_first() {
+    let mut evaluator = Evaluator::new();
+    evaluator.enable_timings();
+    evaluator.register_builtin(Box::new(Nap));
+    let src = "(def fast (+ 1 2)) (def slow (nap)) (def also-fast (* fast 3))";
+    eval_with(&mut evaluator, src).await.unwrap();
+    let slowest = evaluator.timings().unwrap().slowest();
+    let (id, elapsed) = slowest[0];
+    assert!(evaluator.get_node(&id).unwrap().code_snippet().contains("(nap)"));
+    let call = *last_node("(nap)").id();
+    assert!(evaluator.timings().unwrap().get(&call).unwrap() >= Duration::from_millis(10));
+    assert!(elapsed >= Duration::from_millis(10), "{:?}", elapsed);
+    let fast = *last_node("(def fast (+ 1 2))").id();
+    assert!(evaluator.timings().unwrap().get(&fast).unwrap() < elapsed);
+
+    // Nothing is timed when every result comes from the cache
+    eval_with(&mut evaluator, "(def fast (+ 1 2))").await.unwrap();
+    assert!(evaluator.timings().unwrap().slowest().is_empty());
+}
+
+#[test]
+fn pruning_keeps_only_results_reachable_from_the_roots() {
+    let mut cache = EvaluationCache::new();
+    let nodes: Vec<Rc<Node>> = (1..=5).map(|i| last_node(&format!("(+ {} 1)", i))).collect();
+    for (i, node) in nodes.iter().enumerate() {
+        cache.store_node(node.clone());
+        cache.insert(*node.id(), Ok(Value::Number(i as i64 + 2)));
+    }
+    let roots: Vec<NodeId> = nodes[..3].iter().map(|node| *node.id()).collect();
+    assert_eq!(cache.prune_unreachable(&roots), 2);
+    assert_eq!(cache.len(), 3);
+    assert!(cache.get(nodes[2].id()).is_some());
+    assert!(cache.get(nodes[3].id()).is_none());
+    assert!(cache.get_node(nodes[4].id()).is_none());
+}
+
+#[tokio::test]
+async fn evaluator_pruning_keeps_the_prelude_and_current_source() {
+    let mut evaluator = Evaluator::new();
+    eval_with(&mut evaluator, "(def a (+ 1 2)) (def b (inc 4))").await.unwrap();
+    let root_nodes = parser::parse("(def b (inc 4))").unwrap();
+    assert!(evaluator.prune_unreachable(&root_nodes) > 0);
+    assert!(evaluator.cache.get(last_node("(def a (+ 1 2))").id()).is_none());
+    assert!(evaluator.prelude_context().contains_key("inc"));
+    assert_eq!(eval_with(&mut evaluator, "(def b (inc 4)) b").await.unwrap(), Value::Number(5));
+}
+
+#[test]
+fn spans_cover_each_expression_from_start_to_end() {
+    let nodes = parser::parse("(def a 1)\n\n(+ 1 2)\n(str.concat \"x\"\n  \"y\")").unwrap();
+    let span = SourceSpan::of(&nodes[1]).unwrap();
+    assert_eq!((span.line, span.column, span.end_line, span.end_col), (3, 1, 3, 8));
+    assert_eq!(span.original_text, "(+ 1 2)");
+    assert!(span.contains(3, 1) && span.contains(3, 7));
+    assert!(!span.contains(3, 8) && !span.contains(2, 4));
+
+    let multi_line = SourceSpan::of(&nodes[2]).unwrap();
+    assert_eq!((multi_line.line, multi_line.end_line, multi_line.end_col), (4, 5, 7));
+    assert!(multi_line.contains(5, 1));
+    let inner = SourceSpan::of(&nodes[1].children()[2]).unwrap();
+    assert_eq!((inner.line, inner.column, inner.end_col), (3, 6, 7));
+}
+
+#[tokio::test]
+async fn sleep_pauses_and_is_cut_short_by_the_timeout() {
+    let started = Instant::now();
+    assert_eq!(show("(sleep 50)").await, "nil");
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_millis(500), "{:?}", elapsed);
+
+    // Sleeping isn't cached, so it pauses on every evaluation
+    let mut evaluator = Evaluator::new();
+    eval_with(&mut evaluator, "(sleep 30)").await.unwrap();
+    let started = Instant::now();
+    eval_with(&mut evaluator, "(sleep 30)").await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(30));
+
+    let started = Instant::now();
+    let result = evaluator.eval_with_timeout(&last_node("(sleep 100)"), &Env::new(), Duration::from_millis(20)).await;
+    assert!(matches!(&result, Err(Error::EvalError(message)) if message == "evaluation timed out"), "{:?}", result);
+    assert!(started.elapsed() < Duration::from_millis(100));
+    assert!(eval_error("(sleep -1)").await.contains("'sleep'"));
+}
+
+#[tokio::test]
+async fn definitions_using_a_changed_name_see_its_new_value() {
+    let mut evaluator = Evaluator::new();
+    assert_eq!(eval_with(&mut evaluator, "(def x 5) (def y x) y").await.unwrap(), Value::Number(5));
+    assert_eq!(eval_with(&mut evaluator, "(def x 10) (def y x) y").await.unwrap(), Value::Number(10));
+    // Through a function and a chain of definitions
+    let src = |n: i64| format!("(def base {}) (def-fn twice [v] (* v 2)) (def mid (twice base)) (def top (+ mid 1)) top", n);
+    assert_eq!(eval_with(&mut evaluator, &src(1)).await.unwrap(), Value::Number(3));
+    assert_eq!(eval_with(&mut evaluator, &src(4)).await.unwrap(), Value::Number(9));
+    // Back to a value seen before
+    assert_eq!(eval_with(&mut evaluator, "(def x 5) (def y x) y").await.unwrap(), Value::Number(5));
+}
+
+// Log output written while the guard from `capture_logs` is held
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+fn capture_logs(level: &str) -> (CapturedLogs, tracing::subscriber::DefaultGuard) {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(level))
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
+
+#[tokio::test]
+async fn evaluation_logs_each_evaluated_node_at_debug_level() {
+    let (logs, _guard) = capture_logs(Cli::parse_from(["garden", "--verbose"]).log_level().unwrap());
+    eval("(def answer (* 6 7))").await.unwrap();
+    let text = logs.text();
+    assert!(text.contains("DEBUG") && text.contains("evaluated"), "{}", text);
+    assert!(text.contains("code=(def answer (* 6 7))"), "{}", text);
+    let node = hex::encode(&last_node("(def answer (* 6 7))").id()[0..4]);
+    assert!(text.contains(&format!("node={}", node)), "{}", text);
+
+    let (quiet_logs, _guard) = capture_logs(Cli::parse_from(["garden", "--quiet"]).log_level().unwrap());
+    eval("(def answer (* 6 7))").await.unwrap();
+    assert_eq!(quiet_logs.text(), "");
+    assert_eq!(Cli::parse_from(["garden"]).log_level(), None);
+}
+
+// A local HTTP server giving each response in `responses` to one request in turn, with
+// the requests it got
+async fn serve_responses(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+    serve_responses_after(Duration::ZERO, responses).await
+}
+
+// Like serve_responses, but waiting `delay` before each response
+async fn serve_responses_after(delay: Duration, responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+    use tokio::io::AsyncReadExt;
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // Read the headers, then as much body as they announce
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let text = String::from_utf8_lossy(&request).to_lowercase();
+            let header_end = text.find("\r\n\r\n").unwrap() + 4;
+            let length: usize = text
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            while request.len() < header_end + length {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            received.lock().unwrap().push(String::from_utf8_lossy(&request).into_owned());
+            tokio::time::sleep(delay).await;
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+    (url, requests)
+}
+
+// An HTTP response with `headers` (each ending in \r\n) and a body
+fn http_response(status: &str, headers: &str, body: &str) -> String {
+    format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}", status, headers, body.len(), body)
+}
+
+#[tokio::test]
+async fn post_json_sends_and_parses_json() {
+    let reply = http_response("200 OK", "Content-Type: application/json\r\n", r#"{"id": 7, "ok": true}"#);
+    let (url, requests) = serve_responses(vec![reply]).await;
+    let src = format!(r#"(http.post-json "{}/items" (assoc (json.parse "{{}}") "name" "ada"))"#, url);
+    let reply = eval(&src).await.unwrap();
+    assert_eq!(reply, Value::Json(serde_json::json!({"id": 7, "ok": true})));
+
+    let request = requests.lock().unwrap()[0].clone();
+    assert!(request.starts_with("POST /items "), "{}", request);
+    assert!(request.to_lowercase().contains("content-type: application/json"), "{}", request);
+    assert!(request.ends_with(r#"{"name":"ada"}"#), "{}", request);
+
+    // The body is part of the request's identity
+    let post = |body: &str| *last_node(&format!(r#"(http.post-json "{}" (json.parse "{}"))"#, url, body)).id();
+    assert_ne!(post(r#"{\"a\": 1}"#), post(r#"{\"a\": 2}"#));
+    assert!(eval_error(r#"(http.post-json "http://x.invalid/" 5)"#).await.contains("'http.post-json'"));
+}
+
+#[tokio::test]
+async fn get_response_gives_status_headers_and_body() {
+    let responses = vec![
+        http_response("200 OK", "Content-Type: text/plain\r\nX-Request-Id: abc\r\n", "hello"),
+        http_response("404 Not Found", "", "missing"),
+        http_response("200 OK", "", "plain body"),
+    ];
+    let (url, _) = serve_responses(responses).await;
+    let response = format!(r#"(def response (http.get-response "{}/a"))"#, url);
+    let src = format!(r#"{} [(get response "status") (get response "headers" "content-type") (get response "body")]"#, response);
+    assert_eq!(show(&src).await, r#"(200 "text/plain" "hello")"#);
+    assert_eq!(show(&format!(r#"(get (http.get-response "{}/b") "status")"#, url)).await, "404");
+    assert_eq!(show(&format!(r#"(http.get "{}/c")"#, url)).await, r#""plain body""#);
+}
+
+#[tokio::test]
+async fn cookie_jars_send_cookies_from_earlier_responses() {
+    let responses = vec![
+        http_response("200 OK", "Set-Cookie: session=s3cret; Path=/\r\n", "logged in"),
+        http_response("200 OK", "", "profile"),
+        http_response("200 OK", "", "anonymous"),
+    ];
+    let (url, requests) = serve_responses(responses).await;
+    let src = format!(
+        r#"(http.set-cookie-jar "site")
+           (def login (http.get "{0}/login"))
+           (def profile (http.get "{0}/profile"))
+           (http.clear-cookies "site")
+           (def later (http.get "{0}/later"))
+           later"#,
+        url
+    );
+    assert_eq!(show(&src).await, r#""anonymous""#);
+    let requests = requests.lock().unwrap();
+    assert!(!requests[0].to_lowercase().contains("cookie:"), "{}", requests[0]);
+    assert!(requests[1].contains("session=s3cret"), "{}", requests[1]);
+    assert!(!requests[2].contains("session=s3cret"), "{}", requests[2]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watch_reevaluates_each_time_the_response_changes() {
+    let responses = ["v1", "v2", "v3"].iter().map(|body| http_response("200 OK", "", body)).collect();
+    let (url, requests) = serve_responses(responses).await;
+    let (tx, rx) = mpsc::channel();
+    let mut evaluator = Evaluator::new();
+    evaluator.notify_changes_to(tx, PathBuf::from("watched.expr"));
+    let src = format!(r#"(def latest (watch "{}" 1)) latest"#, url);
+    assert_eq!(eval_with(&mut evaluator, &src).await.unwrap(), Value::String("v1".into()));
+
+    for expected in ["v2", "v3"] {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let event = loop {
+            match rx.try_recv() {
+                Ok(event) => break event.unwrap(),
+                Err(_) if Instant::now() < deadline => tokio::time::sleep(Duration::from_millis(20)).await,
+                Err(e) => panic!("no change reported for {}: {}", expected, e),
+            }
+        };
+        assert_eq!(event.paths, vec![PathBuf::from("watched.expr")]);
+        assert_eq!(eval_with(&mut evaluator, &src).await.unwrap(), Value::String(expected.into()));
+    }
+    // Re-evaluating uses the latest response rather than fetching again
+    assert_eq!(requests.lock().unwrap().len(), 3);
+
+    // Removing the expression stops the polling
+    evaluator.stop_removed_watches(&parser::parse("(def other 1)").unwrap());
+    assert!(evaluator.watches.is_empty());
+}
+
+#[test]
+fn collecting_a_dag_visits_shared_children_once() {
+    let shared = last_node("(* 2 3)");
+    let left = Node::new(NodeKind::Addition, "(+ 1 (* 2 3))".into(), vec![last_node("1"), shared.clone()], HashMap::new());
+    let right = Node::new(NodeKind::Addition, "(+ (* 2 3) 4)".into(), vec![shared.clone(), last_node("4")], HashMap::new());
+    let root = Node::new(NodeKind::List, "[left right]".into(), vec![left.clone(), right.clone()], HashMap::new());
+
+    let visited: Vec<Rc<Node>> = collect_all_nodes(&root).collect();
+    let ids: HashSet<NodeId> = visited.iter().map(|node| *node.id()).collect();
+    assert_eq!(ids.len(), visited.len());
+    assert_eq!(visited.iter().filter(|node| node.id() == shared.id()).count(), 1);
+    // Children come before their parents
+    let position = |node: &Rc<Node>| visited.iter().position(|visited| visited.id() == node.id()).unwrap();
+    assert!(position(&shared) < position(&left) && position(&left) < position(&root));
+    assert_eq!(position(&root), visited.len() - 1);
+
+    let additions = collect_all_nodes_filtered(&root, |node| *node.kind() == NodeKind::Addition);
+    assert_eq!(additions.iter().map(|node| node.code_snippet()).collect::<Vec<_>>(), vec!["(+ 1 (* 2 3))", "(+ (* 2 3) 4)"]);
+}
+
+#[tokio::test]
+async fn assert_type_and_coerce_to_check_and_convert_values() {
+    assert_eq!(show(r#"(assert-type 5 "number")"#).await, "5");
+    assert!(matches!(eval(r#"(assert-type "5" "number")"#).await, Err(Error::TypeError(_))));
+
+    let valid = [
+        (r#""number" " 42 ""#, "42"),
+        (r#""number" 3.9"#, "3"),
+        (r#""number" -3.9"#, "-3"),
+        (r#""number" true"#, "1"),
+        (r#""number" false"#, "0"),
+        (r#""float" 2"#, "2.0"),
+        (r#""float" "2.5""#, "2.5"),
+        (r#""float" true"#, "1.0"),
+        (r#""string" 7"#, r#""7""#),
+        (r#""string" false"#, r#""false""#),
+    ];
+    for (args, expected) in valid {
+        assert_eq!(show(&format!("(coerce-to {})", args)).await, expected, "{}", args);
+    }
+    let invalid = [
+        r#""number" "4x""#,
+        r#""number" [1]"#,
+        r#""float" "pi""#,
+        r#""string" [1]"#,
+        r#""list" 1"#,
+    ];
+    for args in invalid {
+        let result = eval(&format!("(coerce-to {})", args)).await;
+        assert!(matches!(result, Err(Error::TypeError(_))), "{}: {:?}", args, result);
+    }
+    assert!(matches!(coerce_to("number", &Value::Float(f64::NAN)), Err(Error::TypeError(_))));
+    assert!(matches!(coerce_to("number", &Value::Float(1e300)), Err(Error::TypeError(_))));
+}
+
+#[tokio::test]
+async fn with_context_bindings_stay_inside_the_form() {
+    let person = r#"(def person (assoc (assoc (json.parse "{}") "name" "Alice") "age" 30))"#;
+    assert_eq!(
+        show(&format!(r#"{} (with-context person (str.concat "Hello, " name "!"))"#, person)).await,
+        r#""Hello, Alice!""#
+    );
+    let src = format!(r#"(def age 99) {} (def inner (with-context person (+ age 1))) [inner age]"#, person);
+    assert_eq!(show(&src).await, "(31 99)");
+    let error = eval_error(&format!("{} (def greeting (with-context person name)) name", person)).await;
+    assert!(error.contains("name"), "{}", error);
+    // The body follows the map when it changes
+    let mut evaluator = Evaluator::new();
+    let src = |n: i64| format!(r#"(def m (assoc (json.parse "{{}}") "n" {})) (with-context m (* n 2))"#, n);
+    assert_eq!(eval_with(&mut evaluator, &src(2)).await.unwrap(), Value::Number(4));
+    assert_eq!(eval_with(&mut evaluator, &src(5)).await.unwrap(), Value::Number(10));
+}
+
+#[tokio::test]
+async fn format_code_normalises_spacing_and_wraps_long_forms() {
+    assert_eq!(parser::format_code("( +  1   2 )").unwrap(), "(+ 1 2)");
+    assert_eq!(parser::format_code("(def  x\n  [1  2])  (+ x\t1)").unwrap(), "(def x [1 2])\n(+ x 1)");
+
+    let long = r#"(def report (str.concat "a fairly long heading, " (str.upper "and some shouting") (str.trim "   padded   ")))"#;
+    let expected = r#"(def
+  report
+  (str.concat
+    "a fairly long heading, "
+    (str.upper "and some shouting")
+    (str.trim "   padded   ")
+  )
+)"#;
+    assert_eq!(parser::format_code(long).unwrap(), expected);
+    // Formatting doesn't change what the code means
+    let formatted = parser::format_code(long).unwrap();
+    assert_eq!(show(&format!("{} report", formatted)).await, show(&format!("{} report", long)).await);
+    assert!(matches!(parser::format_code("(+ 1"), Err(Error::ParseError(_))));
+
+    // Comments and blank lines between forms are kept, and a form with a comment inside
+    // is left alone
+    let commented = "; rates\n(def  rate 2) ; per hour\n\n\n; total\n(def total\n  ; twice the rate\n  (*  rate 2))\n(+  1 \";not a comment\")\n";
+    let expected = "; rates\n(def rate 2) ; per hour\n\n; total\n(def total\n  ; twice the rate\n  (*  rate 2))\n(+ 1 \";not a comment\")";
+    assert_eq!(parser::format_code(commented).unwrap(), expected);
+    assert_eq!(parser::format_code(expected).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn interpolate_fills_placeholders_from_a_map() {
+    let values = r#"(def values (assoc (assoc (json.parse "{}") "name" "Ada") "count" 3))"#;
+    let fill = |template: &str| format!(r#"{} (str.interpolate "{}" values)"#, values, template);
+    assert_eq!(show(&fill("Hello, ${name}! You have ${count} messages.")).await, r#""Hello, Ada! You have 3 messages.""#);
+    assert_eq!(show(&fill("costs ${{price}} in ${name}'s shop")).await, r#""costs ${price} in Ada's shop""#);
+    assert_eq!(show(&fill("")).await, r#""""#);
+    assert_eq!(show(&fill("no placeholders")).await, r#""no placeholders""#);
+    let error = eval_error(&fill("Dear ${title} ${name}")).await;
+    assert!(error.contains("title"), "{}", error);
+    assert!(eval_error(&fill("${name")).await.contains("'str.interpolate'"));
+}
+
+#[tokio::test]
+async fn changed_nodes_without_a_line_are_listed_first() {
+    let mut evaluator = Evaluator::new();
+    eval_with(&mut evaluator, "\n\n(def a (+ 1 2))").await.unwrap();
+    let built = Node::new(NodeKind::Number(5), "5".into(), vec![], HashMap::new());
+    evaluator.store_node(built.clone());
+    evaluator.cache.insert(*built.id(), Ok(Value::Number(5)));
+    let changed = evaluator.get_changed_nodes();
+    assert!(changed.iter().any(|node| node.id() == built.id()));
+
+    let items = collect_display_info(&evaluator, &changed);
+    assert_eq!((items[0].line, items[0].code_snippet.as_str(), items[0].value_str.as_str()), (0, "5", "Number(5)"));
+    assert!(items[1..].iter().all(|item| item.line == 3));
+}
+
+#[tokio::test]
+async fn json_validate_describes_each_violation() {
+    let schema = r#"(def schema "{\"type\": \"object\", \"required\": [\"name\"], \"properties\": {\"age\": {\"type\": \"integer\"}}}")"#;
+    let validate = |value: &str| format!("{} (json.validate schema (json.parse {}))", schema, value);
+    assert_eq!(show(&validate(r#""{\"name\": \"ada\", \"age\": 36}""#)).await, "true");
+
+    let violations = match eval(&validate(r#""{\"age\": \"old\"}""#)).await.unwrap() {
+        Value::List(violations) => violations,
+        other => panic!("expected violations, got {}", other),
+    };
+    assert_eq!(
+        violations,
+        vec![
+            Value::String(r#"/age: "old" is not of type "integer""#.into()),
+            Value::String(r#""name" is a required property"#.into()),
+        ]
+    );
+
+    // The schema is compiled once for every value checked against it
+    let mut evaluator = Evaluator::new();
+    let src = format!(r#"{} (for [v ["{{}}" "[]" "{{\"name\": 1}}"]] (json.validate schema (json.parse v)))"#, schema);
+    eval_with(&mut evaluator, &src).await.unwrap();
+    assert_eq!(evaluator.schemas.len(), 1);
+    assert!(eval_error(r#"(json.validate "{\"type\": 5}" 1)"#).await.contains("invalid schema"));
+}
+
+#[tokio::test]
+async fn with_default_falls_back_only_on_errors() {
+    let data = r#"(def data (json.parse "{\"present\": 5}"))"#;
+    assert_eq!(show(&format!(r#"{} (with-default 0 (get data "missing-key"))"#, data)).await, "0");
+    assert_eq!(show(&format!(r#"{} (with-default 0 (get data "present"))"#, data)).await, "5");
+    assert_eq!(show(r#"(with-default "fallback" (http.get "http://localhost:0"))"#).await, r#""fallback""#);
+    // The default is only evaluated when needed
+    assert_eq!(show("(with-default (/ 1 0) 7)").await, "7");
+    assert!(eval_error("(with-default (/ 1 0) (/ 2 0))").await.contains("division by zero"));
+}
+
+#[tokio::test]
+async fn tap_runs_the_function_and_gives_the_value() {
+    let mut evaluator = Evaluator::new();
+    evaluator.capture_output();
+    assert_eq!(eval_with(&mut evaluator, "(tap println 42)").await.unwrap(), Value::Number(42));
+    assert_eq!(evaluator.take_output(), vec!["42\n"]);
+    let piped = r#"(pipe "  hi  " str.trim (tap println _) str.upper)"#;
+    assert_eq!(eval_with(&mut evaluator, piped).await.unwrap(), Value::String("HI".into()));
+    assert_eq!(evaluator.take_output(), vec!["hi\n"]);
+
+    // Its effects happen on every evaluation, and its result is the value, not the function's
+    assert_eq!(eval_with(&mut evaluator, "(def v (tap (fn [x] (* x 100)) 42)) v").await.unwrap(), Value::Number(42));
+    eval_with(&mut evaluator, "(tap println 42)").await.unwrap();
+    assert_eq!(evaluator.take_output(), vec!["42\n"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn memoize_ttl_calls_again_once_a_result_expires() {
+    let mut evaluator = Evaluator::new();
+    evaluator.capture_output();
+    // Differently spaced calls are different nodes, so each one reaches the function
+    let f = "(def f (memoize-ttl (fn [x] (tap println (* x 10))) 0.2))";
+    assert_eq!(eval_with(&mut evaluator, &format!("{} [(f 1) (f  1) (f 2)]", f)).await.unwrap().to_string(), "(10 10 20)");
+    assert_eq!(evaluator.take_output(), vec!["10\n", "20\n"]);
+
+    tokio::time::advance(Duration::from_millis(150)).await;
+    assert_eq!(eval_with(&mut evaluator, &format!("{} (f   1)", f)).await.unwrap(), Value::Number(10));
+    assert!(evaluator.take_output().is_empty());
+    tokio::time::advance(Duration::from_millis(100)).await;
+    assert_eq!(eval_with(&mut evaluator, &format!("{} (f    1)", f)).await.unwrap(), Value::Number(10));
+    assert_eq!(evaluator.take_output(), vec!["10\n"]);
+    assert!(eval_error("(memoize-ttl (fn [x] x) -1)").await.contains("'memoize-ttl'"));
+}
+
+#[tokio::test]
+async fn parallel_requests_wait_for_the_slowest_rather_than_the_sum() {
+    let delay = Duration::from_millis(200);
+    let (first, _) = serve_responses_after(delay, vec![http_response("200 OK", "", "one")]).await;
+    let (second, _) = serve_responses_after(delay, vec![http_response("200 OK", "", "two")]).await;
+    let started = Instant::now();
+    let src = format!(r#"(parallel (http.get "{}") (http.get "{}") (+ 1 2))"#, first, second);
+    assert_eq!(show(&src).await, r#"("one" "two" 3)"#);
+    let elapsed = started.elapsed();
+    assert!(elapsed >= delay && elapsed < delay * 2, "{:?}", elapsed);
+
+    let error = eval_error(r#"(parallel 1 (/ 1 0) (get 5 "x"))"#).await;
+    assert!(error.contains("division by zero"), "{}", error);
+    assert_ne!(last_node("(parallel 1 2)").id(), last_node("(parallel 2 1)").id());
+}
+
+#[tokio::test]
+async fn download_streams_the_body_into_a_file() {
+    let body: String = (0..10240).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    let (url, _) = serve_responses(vec![http_response("200 OK", "", &body)]).await;
+    let path = std::env::temp_dir().join(format!("garden-download-{}.bin", uuid::Uuid::new_v4()));
+    let (tx, rx) = mpsc::channel();
+    let mut evaluator = Evaluator::new();
+    evaluator.report_downloads_to(tx);
+    let src = format!(r#"(http.download "{}/file" "{}")"#, url, path.display());
+    assert_eq!(eval_with(&mut evaluator, &src).await.unwrap(), Value::Number(10240));
+    assert_eq!(fs::read(&path).unwrap(), body.as_bytes());
+    fs::remove_file(&path).unwrap();
+
+    let progress: Vec<DownloadProgress> = rx.try_iter().collect();
+    let last = progress.last().unwrap();
+    assert!(last.finished && last.written == 10240 && last.total == Some(10240), "{:?}", last);
+    assert!(progress[..progress.len() - 1].iter().all(|update| !update.finished));
+
+    let mut config = GardenConfig::default();
+    config.sandbox.allow_file = false;
+    let error = eval_with(&mut Evaluator::with_config(config), &src).await.unwrap_err().to_string();
+    assert!(error.contains("File writes are disabled"), "{}", error);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn join_and_split_n_build_and_break_up_strings() {
+    assert_eq!(show(r#"(str.join ", " ["a" "b" "c"])"#).await, r#""a, b, c""#);
+    assert_eq!(show(r#"(str.join "/" ["usr" 1 2.5 true])"#).await, r#""usr/1/2.5/true""#);
+    assert_eq!(show(r#"(str.join "-" [])"#).await, r#""""#);
+    assert_eq!(show(r#"(str.split-n "a=b=c" "=" 1)"#).await, r#"("a" "b=c")"#);
+    assert_eq!(show(r#"(str.split-n "a=b=c" "=" 5)"#).await, r#"("a" "b" "c")"#);
+    assert_eq!(show(r#"(str.split-n "a=b=c" "=" 0)"#).await, r#"("a=b=c")"#);
+    assert!(eval_error(r#"(str.join 1 ["a"])"#).await.contains("'str.join'"));
+    assert!(eval_error(r#"(str.split-n "a" "=" -1)"#).await.contains("'str.split-n'"));
+}
+
+#[tokio::test]
+async fn when_and_unless_give_nil_for_the_branch_they_leave_out() {
+    assert_eq!(show("(when true 42)").await, "42");
+    assert_eq!(show("(when false 42)").await, "nil");
+    assert_eq!(show("(unless false 42)").await, "42");
+    assert_eq!(show("(unless true 42)").await, "nil");
+    assert_eq!(show("(when true (def x 99)) x").await, "99");
+
+    let mut evaluator = Evaluator::new();
+    let (root_nodes, error) = evaluate_source("(when (< 1 2) (def y 7))", &mut evaluator).await.unwrap();
+    assert!(error.is_none());
+    assert_eq!(evaluator.context(&root_nodes).get("y"), Some(&Value::Number(7)));
+    assert_eq!(last_node("(when c b)").kind(), &NodeKind::If);
+}
+
+#[tokio::test]
+async fn cond_gives_the_first_true_branch_and_skips_the_rest() {
+    assert_eq!(show("(cond false 1 false 2 true 3 4)").await, "3");
+    assert_eq!(show("(cond false 1 false 2 4)").await, "4");
+    assert_eq!(show("(cond false 1 (= 1 2) 2)").await, "nil");
+    assert_eq!(show("(cond)").await, "nil");
+
+    let (url, requests) = serve_responses(vec![http_response("200 OK", "", "fetched")]).await;
+    let src = format!(r#"(cond false (http.get "{0}/a") (< 1 2) "cheap" (http.get "{0}/b"))"#, url);
+    assert_eq!(show(&src).await, r#""cheap""#);
+    assert!(requests.lock().unwrap().is_empty());
+    let src = format!(r#"(cond false "cheap" (http.get "{}/c"))"#, url);
+    assert_eq!(show(&src).await, r#""fetched""#);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn match_checks_types_keys_and_values_in_order() {
+    let describe = r#"(def-fn describe [v] (match v (type "number") "is a number" (type "string") "is a string" _ "unknown"))"#;
+    assert_eq!(show(&format!("{} (describe 1)", describe)).await, r#""is a number""#);
+    assert_eq!(show(&format!(r#"{} (describe "a")"#, describe)).await, r#""is a string""#);
+    assert_eq!(show(&format!("{} (describe true)", describe)).await, r#""unknown""#);
+
+    let user = r#"(json.parse "{\"name\": \"ada\", \"age\": 36}")"#;
+    assert_eq!(show(&format!(r#"(match {} (keys "email") "has email" (keys "name" "age") (str.join " " [name age]))"#, user)).await, r#""ada 36""#);
+    assert_eq!(show(&format!(r#"(match {} (json.parse "{{\"name\": \"bob\"}}") 1 (json.parse "{{\"name\": \"ada\"}}") name)"#, user)).await, r#""ada""#);
+
+    assert_eq!(show("(match 2 1 \"one\" 2.0 \"two\" _ \"many\")").await, r#""two""#);
+    assert_eq!(show("(match 5 _ \"anything\")").await, r#""anything""#);
+    assert_eq!(show("(match 5 1 \"one\" (type \"string\") \"text\")").await, "nil");
+    assert!(eval_error("(match 5 1)").await.contains("'match'"));
+}
+
+#[tokio::test]
+async fn memo_def_keeps_its_value_until_invalidated() {
+    let (url, requests) = serve_responses(vec![
+        http_response("200 OK", "", "first"),
+        http_response("200 OK", "", "second"),
+        http_response("200 OK", "", "third"),
+    ])
+    .await;
+    let mut evaluator = Evaluator::new();
+    let first = format!(r#"(memo-def token (http.get "{}/a")) token"#, url);
+    assert_eq!(eval_with(&mut evaluator, &first).await.unwrap().to_string(), r#""first""#);
+
+    // A changed expression still gives the kept value without running it
+    let changed = format!(r#"(memo-def token (str.upper (http.get "{}/b"))) token"#, url);
+    assert_eq!(eval_with(&mut evaluator, &changed).await.unwrap().to_string(), r#""first""#);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    assert!(evaluator.invalidate_memo("token"));
+    assert!(!evaluator.invalidate_memo("token"));
+    assert_eq!(eval_with(&mut evaluator, &changed).await.unwrap().to_string(), r#""SECOND""#);
+    assert_eq!(eval_with(&mut evaluator, &changed).await.unwrap().to_string(), r#""SECOND""#);
+    assert_eq!(requests.lock().unwrap().len(), 2);
+
+    let invalidated = eval_with(&mut evaluator, r#"(memo.invalidate "token")"#).await.unwrap();
+    assert_eq!(invalidated, Value::Bool(true));
+    // The same http.get would come from the evaluation cache, so it asks for another path
+    let refetched = format!(r#"(memo-def token (http.get "{}/c")) token"#, url);
+    assert_eq!(eval_with(&mut evaluator, &refetched).await.unwrap().to_string(), r#""third""#);
+    assert_eq!(requests.lock().unwrap().len(), 3);
+    assert_eq!(eval_with(&mut evaluator, r#"(memo.invalidate "missing")"#).await.unwrap(), Value::Bool(false));
+}
+
+#[tokio::test]
+async fn json_flatten_and_unflatten_round_trip() {
+    let nested = r#"(json.parse "{\"service\": {\"name\": \"api\", \"ports\": [80, 443], \"routes\": [{\"path\": \"/\", \"tags\": [\"a\", \"b\"]}, {\"path\": \"/health\", \"tags\": []}]}, \"debug\": false}")"#;
+    let flat = eval(&format!("(json.flatten {})", nested)).await.unwrap();
+    let Value::Json(JsonValue::Object(keys)) = &flat else { panic!("{}", flat) };
+    assert_eq!(keys["service.ports.1"], JsonValue::from(443));
+    assert_eq!(keys["service.routes.0.tags.1"], JsonValue::from("b"));
+    assert_eq!(keys["service.routes.1.tags"], JsonValue::Array(vec![]));
+    assert_eq!(keys.len(), 9);
+
+    let round_trip = format!(r#"(= (json.unflatten (json.flatten {0}) ".") {0})"#, nested);
+    assert_eq!(show(&round_trip).await, "true");
+    let round_trip = format!(r#"(= (json.unflatten (json.flatten {0} "/") "/") {0})"#, nested);
+    assert_eq!(show(&round_trip).await, "true");
+
+    // Indices past 9 still rebuild an array in order
+    let long = r#"(json.parse "[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]")"#;
+    assert_eq!(show(&format!("(= (json.unflatten (json.flatten {0})) {0})", long)).await, "true");
+    assert!(eval_error(r#"(json.unflatten (json.parse "{\"a\": 1, \"a.b\": 2}"))"#).await.contains("'json.unflatten'"));
+    assert!(eval_error("(json.flatten 1)").await.contains("'json.flatten'"));
+}
+
+#[tokio::test]
+async fn xml_parse_follows_badgerfish() {
+    let parsed = eval(r#"(xml.parse "<root><item id=\"1\">Hello</item><item id=\"2\">World</item></root>")"#).await.unwrap();
+    let expected = serde_json::json!({
+        "root": {"item": [{"@id": "1", "$": "Hello"}, {"@id": "2", "$": "World"}]}
+    });
+    assert_eq!(parsed, Value::Json(expected));
+
+    let single = eval(r#"(xml.to-json "<?xml version=\"1.0\"?><a><b x=\"y\"/><!-- note --><c><![CDATA[1 < 2]]></c></a>")"#).await.unwrap();
+    assert_eq!(single, Value::Json(serde_json::json!({"a": {"b": {"@x": "y"}, "c": {"$": "1 < 2"}}})));
+    assert!(eval_error(r#"(xml.parse "<a><b></a>")"#).await.contains("'xml.parse'"));
+    assert!(eval_error(r#"(xml.parse "<a>")"#).await.contains("'xml.parse'"));
+}
+
+#[test]
+fn rapid_watcher_events_are_handled_together() {
+    let (tx, rx) = mpsc::channel();
+    let event = || Ok(notify::Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any)));
+    // A save written in three steps
+    for _ in 0..3 {
+        tx.send(event()).unwrap();
+    }
+    let debounce = Duration::from_millis(100);
+    let started = Instant::now();
+    assert_eq!(next_changes(&rx, debounce).map(|events| events.len()), Some(3));
+    assert!(started.elapsed() >= debounce);
+
+    // Events during the wait join the same batch, and a later one starts another
+    let sender = tx.clone();
+    let late = std::thread::spawn(move || {
+        sender.send(event()).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        sender.send(event()).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        sender.send(event()).unwrap();
+    });
+    assert_eq!(next_changes(&rx, debounce).map(|events| events.len()), Some(2));
+    assert_eq!(next_changes(&rx, debounce).map(|events| events.len()), Some(1));
+    late.join().unwrap();
+    drop(tx);
+    assert!(next_changes(&rx, debounce).is_none());
+}
+
+#[tokio::test]
+async fn def_once_keeps_an_existing_binding() {
+    assert_eq!(show(r#"(def-once api-base-url "https://default.example.com") api-base-url"#).await, r#""https://default.example.com""#);
+    assert_eq!(show(r#"(def api-base-url "https://ours") (def-once api-base-url "https://default.example.com") api-base-url"#).await, r#""https://ours""#);
+
+    // The default isn't evaluated when the name exists
+    let (url, requests) = serve_responses(vec![http_response("200 OK", "", "fetched")]).await;
+    let src = format!(r#"(def token "kept") (def-once token (http.get "{0}/a")) (def-once fresh (http.get "{0}/b")) (str.join " " [token fresh])"#, url);
+    assert_eq!(show(&src).await, r#""kept fetched""#);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert!(requests.lock().unwrap()[0].contains("/b"));
+    assert!(eval_error("(def-once 1 2)").await.contains("'def-once'"));
+}
+
+#[tokio::test]
+async fn json_array_map_and_filter_give_json_arrays() {
+    let doubled = eval(r#"(json.array-map (fn [x] (* x 2)) (json.parse "[1, 2, 3]"))"#).await.unwrap();
+    assert_eq!(doubled, Value::Json(serde_json::json!([2, 4, 6])));
+    let names = eval(r#"(json.array-map (fn [user] (get user "name")) (json.parse "[{\"name\": \"ada\"}, {\"name\": \"bob\"}]"))"#).await.unwrap();
+    assert_eq!(names, Value::Json(serde_json::json!(["ada", "bob"])));
+
+    let big = eval(r#"(json.array-filter (fn [x] (> x 1)) (json.parse "[1, 2, 3]"))"#).await.unwrap();
+    assert_eq!(big, Value::Json(serde_json::json!([2, 3])));
+    let none = eval(r#"(json.array-filter (fn [x] false) (json.parse "[{\"a\": 1}]"))"#).await.unwrap();
+    assert_eq!(none, Value::Json(serde_json::json!([])));
+
+    assert!(eval_error("(json.array-map (fn [x] x) [1 2])").await.contains("'json.array-map'"));
+    assert!(eval_error(r#"(json.array-filter (fn [x] x) (json.parse "{}"))"#).await.contains("'json.array-filter'"));
+}
+
+#[tokio::test]
+async fn string_probes_check_without_failing() {
+    assert_eq!(show(r#"(str.number? "3.14")"#).await, "true");
+    assert_eq!(show(r#"(str.number? " -42 ")"#).await, "true");
+    assert_eq!(show(r#"(str.number? "hello")"#).await, "false");
+    assert_eq!(show(r#"(str.number? "NaN")"#).await, "false");
+    assert_eq!(show(r#"(str.number? "")"#).await, "false");
+    assert_eq!(show(r#"(str.bool? "TRUE")"#).await, "true");
+    assert_eq!(show(r#"(str.bool? "false")"#).await, "true");
+    assert_eq!(show(r#"(str.bool? "yes")"#).await, "false");
+    assert_eq!(show(r#"(str.json? "{}")"#).await, "true");
+    assert_eq!(show(r#"(str.json? "[1, {\"a\": null}]")"#).await, "true");
+    assert_eq!(show(r#"(str.json? "{bad")"#).await, "false");
+    assert_eq!(show(r#"(str.json? "{} {}")"#).await, "false");
+    assert!(eval_error("(str.number? 1)").await.contains("'str.number?'"));
+}
+
+#[tokio::test]
+async fn http_mocks_answer_without_contacting_the_server() {
+    let (url, requests) = serve_responses(vec![http_response("200 OK", "", "real")]).await;
+    let src = format!(
+        r#"(http.mock "{0}/users" "[\"ada\"]")
+           (http.mock "{0}/items" "{{\"id\": 1}}")
+           [(http.get "{0}/users") (get (http.post-json "{0}/items" (json.parse "{{}}")) "id")]"#,
+        url
+    );
+    assert_eq!(show(&src).await, r#"("[\"ada\"]" 1)"#);
+    assert!(requests.lock().unwrap().is_empty());
+
+    let src = format!(r#"(http.mock "{0}/users" "canned") (def unmocked (http.unmock "{0}/users")) [unmocked (http.get "{0}/users")]"#, url);
+    assert_eq!(show(&src).await, r#"(true "real")"#);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert_eq!(show(r#"(http.unmock "http://example.invalid/")"#).await, "false");
+}
+
+#[tokio::test]
+async fn definitions_can_use_names_defined_later() {
+    assert_eq!(show("(def y (+ x 1))\n(def x 5)\ny").await, "6");
+    assert_eq!(show("(def c (* b 2)) (def b (+ a 1)) (def a 1) c").await, "4");
+    // Functions can call each other in either order, as their bodies run later
+    let src = "(def answer (even? 10))
+               (def-fn even? [n] (if (= n 0) true (odd? (- n 1))))
+               (def-fn odd? [n] (if (= n 0) false (even? (- n 1))))
+               answer";
+    assert_eq!(show_on_eval_stack(src), "true");
+
+    assert!(eval_error("(def a (+ b 1)) (def b (+ a 1))").await.contains("circular definition"));
+    assert!(eval_error("(def a (+ a 1))").await.contains("circular definition"));
+    assert!(eval_error("(def y (+ missing 1))").await.contains("Undefined symbol: missing"));
+
+    let mut evaluator = Evaluator::new();
+    eval_with(&mut evaluator, "(def y (+ x 1)) (def x 5)").await.unwrap();
+    assert_eq!(eval_with(&mut evaluator, "(def y (+ x 1)) (def x 9) y").await.unwrap(), Value::Number(10));
+}
+
+#[tokio::test]
+async fn spread_splices_a_list_into_call_arguments() {
+    assert_eq!(show("(apply-spread + [1 2 3])").await, "6");
+    assert_eq!(show(r#"(str.concat "prefix-" ...(range 0 3))"#).await, r#""prefix-012""#);
+    assert_eq!(show("(+ ...[1 2 3])").await, "6");
+    assert_eq!(show("(* 2 (spread [3 4]) 5)").await, "120");
+    assert_eq!(show("(+ 1 ...[])").await, "1");
+    assert_eq!(show("(def-fn f [a b] (- a b)) (def xs [5 2]) (f ...xs)").await, "3");
+    assert_eq!(last_node("(+ ...xs)").children()[1].kind(), &NodeKind::Spread);
+
+    // Arity is counted after spreading
+    assert_eq!(show("[(- ...[10 2]) (- ...[4]) (- 10 ...[2 3])]").await, "(8 -4 5)");
+    assert_eq!(show("[(/ (spread [10 2])) (% ...[7 2])]").await, "(5 1)");
+    assert!(eval_error("(% ...[7 2 1])").await.contains("'%' expects 2 arguments"));
+    assert!(eval_error("(/ ...[7])").await.contains("'/' expects at least 2 arguments"));
+
+    // Built-ins with fixed arguments take them spread as well
+    assert_eq!(show(r#"(str.upper ...["a"])"#).await, r#""A""#);
+    assert_eq!(show(r#"(json.parse ...["[1]"])"#).await, "[1]");
+    assert_eq!(show(r#"(get ...[(json.parse "{\"a\": {\"b\": 2}}") "a" "b"])"#).await, "2");
+    assert_eq!(show(r#"(str.to-num "ff" ...[16])"#).await, "255");
+    assert_eq!(show(r#"(num.format ...[3.14159 "%.2f"])"#).await, r#""3.14""#);
+    assert_eq!(show(r#"(num.parse ...["1,000"])"#).await, "1000");
+    assert_eq!(show(r#"(str.join ", " ...[["a" "b"]])"#).await, r#""a, b""#);
+    let (url, requests) = serve_responses(vec![http_response("200 OK", "", "fetched")]).await;
+    assert_eq!(show(&format!(r#"(http.get ...["{}/a"])"#, url)).await, r#""fetched""#);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert!(eval_error(r#"(str.upper ...["a" "b"])"#).await.contains("'str.upper' expects 1 argument"));
+
+    // Special forms don't
+    for (src, form) in [("(if ...[true 1 2])", "if"), ("(def x ...[1])", "def"), ("(apply + ...[[1 2]])", "apply"), ("(cond ...[true 1])", "cond")] {
+        let error = eval_error(src).await;
+        assert!(error.contains(&format!("'{}' does not accept spread arguments", form)), "{}", error);
+    }
+
+    assert!(eval_error("(spread [1])").await.contains("'spread' can only be used as an argument in a call"));
+    assert!(eval_error("[0 (spread [1 2])]").await.contains("'spread'"));
+    assert!(eval_error("(+ (spread 1))").await.contains("'spread' expects a list"));
+}
+
+#[test]
+fn errors_point_at_their_column_in_the_source() {
+    let source = "(def x 1)\n(+ x ])";
+    let error = parser::parse(source).unwrap_err();
+    assert_eq!(error.location(), Some((2, 6)));
+    let shown = format_error_with_context(&error, source);
+    let lines: Vec<&str> = shown.lines().collect();
+    assert_eq!(lines[..3], ["  |", "2 | (+ x ])", "  |      ^"]);
+    assert_eq!(lines[3..].join("\n"), error.to_string());
+
+    // Tabs stay tabs, so the caret lines up however they are shown
+    let source = "\t(+ 1 ]";
+    let shown = format_error_with_context(&parser::parse(source).unwrap_err(), source);
+    assert!(shown.contains("\n  | \t     ^\n"), "{}", shown);
+
+    let error = Error::EvalError("Undefined symbol: x".to_string());
+    assert_eq!(error.location(), None);
+    assert_eq!(format_error_with_context(&error, source), error.to_string());
+}
+
+#[tokio::test]
+async fn integer_division_truncates_toward_zero() {
+    assert_eq!(show("[(/ 7 2) (/ -7 2) (/ 7 -2) (/ -7 -2)]").await, "(3 -3 -3 3)");
+    assert_eq!(show("[(% 7 3) (% -7 3) (% 7 -3) (% -7 -3)]").await, "(1 -1 1 -1)");
+    assert_eq!(show("[(- -3 -5) (- 0 7) (- -9)]").await, "(2 -7 9)");
+    assert_eq!(show("(/ 100 -3 2)").await, "-16");
+
+    for src in ["(/ 1 0)", "(% -5 0)", "(/ 10 2 0)"] {
+        assert!(matches!(eval(src).await, Err(Error::EvalError(message)) if message == "division by zero"), "{}", src);
+    }
+    assert_eq!(last_node("(% a b)").kind(), &NodeKind::Modulo);
+}
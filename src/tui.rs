@@ -0,0 +1,1652 @@
+use std::{collections::{HashMap, HashSet}, fs::{self, File}, io::{self, BufWriter}, panic::AssertUnwindSafe, path::{Path, PathBuf}, rc::Rc, sync::mpsc, time::{Duration, Instant}};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use indexmap::IndexMap;
+use serde_json::Value as JsonValue;
+use tokio::task::JoinHandle;
+use notify::{recommended_watcher, RecommendedWatcher, RecursiveMode, Watcher};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    crossterm::{
+        event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Tabs},
+    Frame, Terminal,
+};
+
+use crate::{config::GardenConfig, evaluate_source, find_by_kind, ws::Broadcaster, parser, DownloadProgress, Error, EvalTimings, Evaluator, Node, NodeKind, Value};
+
+// How often the event loop wakes up to check for file changes
+const TICK: Duration = Duration::from_millis(100);
+
+// How long the event loop sleeps between input checks, yielding to the evaluation task
+const INPUT_POLL: Duration = Duration::from_millis(20);
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+// How long the status bar shows the result of an export
+const EXPORT_MESSAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// File in the home directory remembering the last chosen theme
+const THEME_FILE: &str = ".garden-theme";
+
+// What a background evaluation hands back: the evaluator it borrowed, the source it
+// evaluated and the result of evaluating it
+type EvalOutcome = (Evaluator, String, Result<(Vec<Rc<Node>>, Option<Error>), Error>);
+
+// State for one watched file
+pub struct FileApp {
+    file_path: PathBuf,
+    cache_path: PathBuf,
+    // None while a background evaluation has it
+    evaluator: Option<Evaluator>,
+    context: IndexMap<String, Value>,
+    // Context as it was before the latest re-evaluation, for showing what changed
+    previous_context: IndexMap<String, Value>,
+    last_error: Option<String>,
+    // Index of the highlighted row among the visible (filtered) entries
+    selected: usize,
+    // Index of the first visible row in the variable list
+    scroll_offset: usize,
+    // Number of rows the variable list showed on the last draw, used for paging
+    page_size: usize,
+    // Where the variable list and status bar were drawn last, for mapping mouse clicks
+    list_area: Rect,
+    status_area: Rect,
+    // Value shown in the right-hand inspector pane
+    inspector_value: Option<Value>,
+    show_inspector: bool,
+    // Case-insensitive filter applied to variable names and values
+    filter_text: String,
+    // Whether the search bar is open and capturing keystrokes
+    filter_active: bool,
+    // Every value each variable had after each evaluation cycle, oldest first
+    history: HashMap<String, Vec<(DateTime<Utc>, Value)>>,
+    show_history: bool,
+    // Number of newest history entries scrolled past in the history panel
+    history_offset: usize,
+    // Raw source of the watched file as of the last evaluation
+    source_text: String,
+    show_source: bool,
+    // 1-based line the last error points at, if it could be located
+    error_line: Option<usize>,
+    // 1-based line of each variable's definition
+    definition_lines: HashMap<String, usize>,
+    // Evaluation running in the background; it owns the evaluator until it finishes
+    eval_task: Option<JoinHandle<EvalOutcome>>,
+    // Variables whose definitions have no cached value yet and are being evaluated
+    in_flight: HashSet<String>,
+    // How many http.get calls the running evaluation has no cached response for
+    pending_requests: usize,
+    // How long each definition took in the last evaluation, with --benchmark
+    timings: HashMap<String, Duration>,
+    // The file changed again while an evaluation was running
+    rerun_requested: bool,
+    // Advanced every TICK while an evaluation is running
+    spinner_frame: usize,
+    // Watcher for this file and the channel it reports changes on; dropping the
+    // watcher stops it
+    watcher: Option<(RecommendedWatcher, mpsc::Receiver<notify::Result<notify::Event>>)>,
+    // When the file first changed since the last re-evaluation; changes within the
+    // debounce time after it are handled by the same re-evaluation
+    changed_at: Option<Instant>,
+    debounce: Duration,
+    // Result of the last export and when it happened, shown briefly in the status bar
+    export_message: Option<(String, Instant)>,
+    // Dashboard to send each evaluation's results to
+    broadcaster: Option<Broadcaster>,
+    // Progress reports from http.download, and the latest one, shown in the status bar
+    downloads: mpsc::Receiver<DownloadProgress>,
+    download: Option<DownloadProgress>,
+    // List the prelude's definitions along with the file's own
+    show_prelude: bool,
+    should_quit: bool,
+}
+
+impl FileApp {
+    pub fn new(file_path: &Path) -> Self {
+        let config = GardenConfig::for_file(file_path);
+        let debounce = config.debounce();
+        let mut evaluator = Evaluator::with_config(config);
+        let cache_path = file_path.with_extension("expr.cache");
+        if let Err(e) = evaluator.load_cache(&cache_path) {
+            tracing::warn!(cache = %cache_path.display(), "Could not load cached values: {}", e);
+        }
+        let (download_tx, downloads) = mpsc::channel();
+        evaluator.report_downloads_to(download_tx);
+
+        Self {
+            file_path: file_path.to_path_buf(),
+            cache_path,
+            evaluator: Some(evaluator),
+            context: IndexMap::new(),
+            previous_context: IndexMap::new(),
+            last_error: None,
+            selected: 0,
+            scroll_offset: 0,
+            page_size: 1,
+            list_area: Rect::default(),
+            status_area: Rect::default(),
+            inspector_value: None,
+            show_inspector: false,
+            filter_text: String::new(),
+            filter_active: false,
+            history: HashMap::new(),
+            show_history: false,
+            history_offset: 0,
+            source_text: String::new(),
+            show_source: false,
+            error_line: None,
+            definition_lines: HashMap::new(),
+            eval_task: None,
+            in_flight: HashSet::new(),
+            pending_requests: 0,
+            timings: HashMap::new(),
+            rerun_requested: false,
+            spinner_frame: 0,
+            watcher: None,
+            changed_at: None,
+            debounce,
+            export_message: None,
+            broadcaster: None,
+            downloads,
+            download: None,
+            show_prelude: false,
+            should_quit: false,
+        }
+    }
+
+    // Start watching the file for changes
+    pub fn watch(&mut self) -> notify::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        if let Some(evaluator) = &mut self.evaluator {
+            evaluator.notify_changes_to(tx.clone(), self.file_path.clone());
+        }
+        let mut watcher = recommended_watcher(tx)?;
+        watcher.watch(&self.file_path, RecursiveMode::NonRecursive)?;
+        self.watcher = Some((watcher, rx));
+        Ok(())
+    }
+
+    // Re-evaluate if the watcher reported changes since the last check
+    fn check_for_changes(&mut self) {
+        let Some((_, rx)) = &self.watcher else {
+            return;
+        };
+        // Collapse all watcher events within the debounce time into a single re-evaluation
+        let mut file_changed = false;
+        while let Ok(res) = rx.try_recv() {
+            // Ignore access events, which our own reads of the file trigger
+            file_changed |= matches!(res, Ok(event) if event.kind.is_modify() || event.kind.is_create());
+        }
+        if file_changed && self.changed_at.is_none() {
+            self.changed_at = Some(Instant::now());
+        }
+        if self.changed_at.is_some_and(|changed_at| changed_at.elapsed() >= self.debounce) {
+            self.changed_at = None;
+            self.request_evaluation();
+        }
+    }
+
+    pub fn initial_evaluate(&mut self) {
+        self.request_evaluation();
+    }
+
+    // Evaluate the watched file in the background, or queue another run if one is already going
+    pub fn request_evaluation(&mut self) {
+        if self.eval_task.is_some() {
+            self.rerun_requested = true;
+        } else {
+            self.start_evaluation();
+        }
+    }
+
+    // Re-read the watched file and spawn its evaluation on the local task set
+    fn start_evaluation(&mut self) {
+        let src = match fs::read_to_string(&self.file_path) {
+            Ok(src) => src,
+            Err(e) => {
+                self.last_error = Some(format!("Could not read {}: {}", self.file_path.display(), e));
+                return;
+            }
+        };
+
+        // Only a task that ended without handing the evaluator back leaves none
+        let Some(mut evaluator) = self.evaluator.take() else {
+            self.last_error = Some("The evaluator was lost with a failed evaluation; restart to evaluate again".to_string());
+            return;
+        };
+        let root_nodes = parser::parse(&src).unwrap_or_default();
+        self.in_flight = uncached_definitions(&evaluator, &root_nodes);
+        self.pending_requests = uncached_requests(&evaluator, &root_nodes);
+        let show_prelude = self.show_prelude;
+        self.eval_task = Some(tokio::task::spawn_local(async move {
+            // A panic is reported like an error, so the evaluator and its cache and settings
+            // come back either way
+            let result = AssertUnwindSafe(evaluate_source(&src, &mut evaluator))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|panic| Err(Error::EvalError(format!("Evaluation panicked: {}", panic_message(&*panic)))));
+            if show_prelude {
+                evaluator.evaluate_prelude().await;
+            }
+            (evaluator, src, result)
+        }));
+    }
+
+    // Collect the background evaluation if it has finished, starting a queued rerun
+    pub async fn poll_evaluation(&mut self) {
+        if !self.eval_task.as_ref().is_some_and(|task| task.is_finished()) {
+            return;
+        }
+        let Some(task) = self.eval_task.take() else {
+            return;
+        };
+        match task.await {
+            Ok(outcome) => self.finish_evaluation(outcome),
+            Err(e) => self.last_error = Some(format!("Evaluation task failed: {}", e)),
+        }
+        self.in_flight.clear();
+        self.pending_requests = 0;
+        // A download that failed never reports that it finished
+        self.download = None;
+
+        if self.rerun_requested {
+            self.rerun_requested = false;
+            self.start_evaluation();
+        }
+    }
+
+    // Apply the result of a background evaluation, refreshing the context
+    fn finish_evaluation(&mut self, (evaluator, src, result): EvalOutcome) {
+        self.error_line = None;
+        match result {
+            Ok((root_nodes, error)) => {
+                let mut context = evaluator.context(&root_nodes);
+                if self.show_prelude {
+                    // The file's definitions come last, overriding prelude ones of the same name
+                    let mut with_prelude = evaluator.prelude_context();
+                    with_prelude.retain(|name, _| !context.contains_key(name));
+                    with_prelude.append(&mut context);
+                    context = with_prelude;
+                }
+                self.previous_context = std::mem::replace(&mut self.context, context);
+                if let Some(broadcaster) = &self.broadcaster {
+                    broadcaster.publish(&self.file_path, &self.context);
+                }
+                self.record_history();
+                self.definition_lines = definition_lines(&root_nodes);
+                if let Some(timings) = evaluator.timings() {
+                    self.timings = definition_timings(&root_nodes, timings);
+                }
+                if error.is_some() {
+                    // The first root whose cached result is an error is where evaluation stopped
+                    self.error_line = root_nodes
+                        .iter()
+                        .find(|node| matches!(evaluator.get_cached_result(node.id()), Some(Err(_))))
+                        .and_then(|node| node_line(node));
+                }
+                self.last_error = error.map(|e| e.to_string());
+                if let Err(e) = evaluator.save_cache(&self.cache_path) {
+                    self.last_error = Some(format!("Could not save cache: {}", e));
+                }
+            }
+            Err(e) => {
+                self.error_line = e.location().map(|(line, _)| line);
+                self.last_error = Some(e.to_string());
+            }
+        }
+        self.source_text = src;
+        self.evaluator = Some(evaluator);
+
+        self.clamp_selection();
+        self.refresh_inspector();
+    }
+
+    // Write the current context as pretty-printed JSON to <name>.output.json next to the file
+    fn export(&mut self) {
+        let path = self.file_path.with_extension("output.json");
+        let context: IndexMap<&str, JsonValue> = self
+            .context
+            .iter()
+            .map(|(name, value)| (name.as_str(), export_json(value)))
+            .collect();
+
+        let result = File::create(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| serde_json::to_writer_pretty(BufWriter::new(file), &context).map_err(|e| e.to_string()));
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let message = match result {
+            Ok(()) => format!("Exported to {}", file_name),
+            Err(e) => format!("Could not export to {}: {}", file_name, e),
+        };
+        self.export_message = Some((message, Instant::now()));
+    }
+
+    // Take the latest report from a running http.download
+    fn check_downloads(&mut self) {
+        if let Some(progress) = self.downloads.try_iter().last() {
+            self.download = Some(progress).filter(|progress| !progress.finished);
+        }
+    }
+
+    fn spinner(&self) -> char {
+        SPINNER[self.spinner_frame % SPINNER.len()]
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if self.filter_active {
+            self.handle_filter_key(key);
+            return;
+        }
+
+        // The history overlay takes focus while open
+        if self.show_history {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('h') => self.show_history = false,
+                KeyCode::Char('q') => self.should_quit = true,
+                KeyCode::Up => self.history_offset = self.history_offset.saturating_sub(1),
+                KeyCode::Down => self.history_offset += 1,
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => self.should_quit = true,
+            KeyCode::Esc if self.show_inspector => self.toggle_inspector(),
+            KeyCode::Esc => self.should_quit = true,
+            KeyCode::Enter => self.toggle_inspector(),
+            KeyCode::Char('/') => self.filter_active = true,
+            KeyCode::Char('p') => self.show_source = !self.show_source,
+            KeyCode::Char('e') => self.export(),
+            KeyCode::Char('h') if self.selected_name().is_some() => {
+                self.show_history = true;
+                self.history_offset = 0;
+            }
+            KeyCode::Up => self.select_previous(1),
+            KeyCode::Down => self.select_next(1),
+            KeyCode::PageUp => self.select_previous(self.page_size),
+            KeyCode::PageDown => self.select_next(self.page_size),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = self.visible_indices().len().saturating_sub(1),
+            _ => {}
+        }
+        self.refresh_inspector();
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if self.show_history {
+            return;
+        }
+        let position = (mouse.column, mouse.row).into();
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) if self.status_area.contains(position) => self.filter_active = true,
+            MouseEventKind::Down(MouseButton::Left) if self.list_area.contains(position) => {
+                // The list's top border takes the first row
+                if let Some(row) = mouse.row.checked_sub(self.list_area.y + 1) {
+                    let index = self.scroll_offset + row as usize;
+                    if index < self.visible_indices().len() {
+                        self.selected = index;
+                    }
+                }
+            }
+            MouseEventKind::ScrollDown => self.select_next(1),
+            MouseEventKind::ScrollUp => self.select_previous(1),
+            _ => {}
+        }
+        self.refresh_inspector();
+    }
+
+    // Keystrokes while the search bar is open edit the filter
+    fn handle_filter_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char(c) => self.filter_text.push(c),
+            KeyCode::Backspace => {
+                self.filter_text.pop();
+            }
+            KeyCode::Esc => {
+                self.filter_text.clear();
+                self.filter_active = false;
+            }
+            KeyCode::Enter => self.filter_active = false,
+            _ => {}
+        }
+        self.clamp_selection();
+        self.refresh_inspector();
+    }
+
+    // Indices into `context` of the entries matching the filter, in source order
+    fn visible_indices(&self) -> Vec<usize> {
+        let needle = self.filter_text.to_lowercase();
+        self.context
+            .iter()
+            .enumerate()
+            .filter(|(_, (name, value))| {
+                needle.is_empty()
+                    || name.to_lowercase().contains(&needle)
+                    || value.to_string().to_lowercase().contains(&needle)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Name of the variable under the cursor
+    fn selected_name(&self) -> Option<String> {
+        self.visible_indices()
+            .get(self.selected)
+            .and_then(|&i| self.context.get_index(i))
+            .map(|(name, _)| name.clone())
+    }
+
+    // Append the current value of every variable to its history
+    fn record_history(&mut self) {
+        let now = Utc::now();
+        for (name, value) in &self.context {
+            self.history.entry(name.clone()).or_default().push((now, value.clone()));
+        }
+    }
+
+    fn toggle_inspector(&mut self) {
+        self.show_inspector = !self.show_inspector && !self.visible_indices().is_empty();
+    }
+
+    // Keep the inspector showing the currently selected value
+    fn refresh_inspector(&mut self) {
+        self.inspector_value = if self.show_inspector {
+            self.visible_indices()
+                .get(self.selected)
+                .and_then(|&i| self.context.get_index(i))
+                .map(|(_, value)| value.clone())
+        } else {
+            None
+        };
+    }
+
+    fn select_next(&mut self, step: usize) {
+        self.selected = self.selected.saturating_add(step);
+        self.clamp_selection();
+    }
+
+    fn select_previous(&mut self, step: usize) {
+        self.selected = self.selected.saturating_sub(step);
+    }
+
+    // Keep `selected` inside the visible entries after moves, filtering or re-evaluation
+    fn clamp_selection(&mut self) {
+        self.selected = self.selected.min(self.visible_indices().len().saturating_sub(1));
+    }
+
+    // Adjust `scroll_offset` so the selected row is within `height` visible rows
+    fn scroll_to_selected(&mut self, height: usize) {
+        self.page_size = height.max(1);
+        if self.selected < self.scroll_offset {
+            self.scroll_offset = self.selected;
+        } else if self.selected >= self.scroll_offset + self.page_size {
+            self.scroll_offset = self.selected + 1 - self.page_size;
+        }
+    }
+}
+
+// Colours used throughout the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    name: &'static str,
+    error: Color,
+    // New values
+    changed: Color,
+    // History entries that differ from the one before
+    history_changed: Color,
+    // Old values, repeated history entries and other de-emphasised text
+    unchanged: Color,
+    // Variable names, labels and the header
+    header: Color,
+    status_ok: Color,
+    // Spinners, the search prompt and other transient notices
+    accent: Color,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            name: "dark",
+            error: Color::Red,
+            changed: Color::Green,
+            history_changed: Color::Yellow,
+            unchanged: Color::DarkGray,
+            header: Color::Cyan,
+            status_ok: Color::Green,
+            accent: Color::Yellow,
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            name: "light",
+            error: Color::Red,
+            changed: Color::Blue,
+            // A darker yellow, readable on a light background
+            history_changed: Color::Rgb(175, 135, 0),
+            unchanged: Color::Gray,
+            header: Color::Magenta,
+            status_ok: Color::Green,
+            accent: Color::LightRed,
+        }
+    }
+
+    fn toggled(&self) -> Self {
+        if *self == Self::dark() {
+            Self::light()
+        } else {
+            Self::dark()
+        }
+    }
+
+    fn path() -> PathBuf {
+        std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default().join(THEME_FILE)
+    }
+
+    // The theme set in .garden.toml, else the one saved by the last toggle, defaulting to dark
+    pub fn load(config: &GardenConfig) -> Self {
+        let name = config.display.theme.clone().or_else(|| fs::read_to_string(Self::path()).ok());
+        match name.as_deref().map(str::trim) {
+            Some("light") => Self::light(),
+            _ => Self::dark(),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(Self::path(), self.name)
+    }
+}
+
+// Several watched files, one per tab
+pub struct MultiApp {
+    files: Vec<FileApp>,
+    // Directory being watched for new .expr files, when started with --watch-dir
+    dir_watcher: Option<(RecommendedWatcher, mpsc::Receiver<notify::Result<notify::Event>>)>,
+    broadcaster: Option<Broadcaster>,
+    show_prelude: bool,
+    // Time evaluations and show how long each definition took
+    benchmark: bool,
+    // Index of the tab being shown
+    active: usize,
+    theme: Theme,
+}
+
+impl MultiApp {
+    pub fn new(file_paths: &[PathBuf], broadcaster: Option<Broadcaster>, show_prelude: bool, benchmark: bool) -> Self {
+        Self {
+            files: file_paths
+                .iter()
+                .map(|path| {
+                    let mut file = FileApp {
+                        broadcaster: broadcaster.clone(),
+                        show_prelude,
+                        ..FileApp::new(path)
+                    };
+                    if let Some(evaluator) = file.evaluator.as_mut().filter(|_| benchmark) {
+                        evaluator.enable_timings();
+                    }
+                    file
+                })
+                .collect(),
+            dir_watcher: None,
+            broadcaster,
+            show_prelude,
+            benchmark,
+            active: 0,
+            theme: Theme::load(&GardenConfig::for_file(&file_paths[0])),
+        }
+    }
+
+    // Add a tab for every .expr file that appears in `dir` from now on
+    pub fn watch_dir(&mut self, dir: &Path, recursive: bool) -> notify::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = recommended_watcher(tx)?;
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(dir, mode)?;
+        self.dir_watcher = Some((watcher, rx));
+        Ok(())
+    }
+
+    // Open tabs for .expr files created in the watched directory since the last check
+    fn check_for_new_files(&mut self) -> notify::Result<()> {
+        let Some((_, rx)) = &self.dir_watcher else {
+            return Ok(());
+        };
+        let mut new_files = Vec::new();
+        while let Ok(res) = rx.try_recv() {
+            let Ok(event) = res else {
+                continue;
+            };
+            for path in event.paths {
+                let is_expr = path.extension().is_some_and(|ext| ext == "expr") && path.is_file();
+                let known = self.files.iter().any(|file| file.file_path == path) || new_files.contains(&path);
+                if is_expr && !known {
+                    new_files.push(path);
+                }
+            }
+        }
+        for path in new_files {
+            let mut file = FileApp {
+                broadcaster: self.broadcaster.clone(),
+                show_prelude: self.show_prelude,
+                ..FileApp::new(&path)
+            };
+            if let Some(evaluator) = file.evaluator.as_mut().filter(|_| self.benchmark) {
+                evaluator.enable_timings();
+            }
+            file.watch()?;
+            file.initial_evaluate();
+            self.files.push(file);
+        }
+        Ok(())
+    }
+
+    fn active_file(&mut self) -> &mut FileApp {
+        &mut self.files[self.active]
+    }
+
+    fn should_quit(&self) -> bool {
+        self.files[self.active].should_quit
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        // Tab switches files unless the search bar or history panel has focus
+        let file = &self.files[self.active];
+        if !file.filter_active && !file.show_history {
+            match key.code {
+                KeyCode::Tab => {
+                    self.active = (self.active + 1) % self.files.len();
+                    return;
+                }
+                KeyCode::BackTab => {
+                    self.active = (self.active + self.files.len() - 1) % self.files.len();
+                    return;
+                }
+                KeyCode::Char('t') => {
+                    self.theme = self.theme.toggled();
+                    if let Err(e) = self.theme.save() {
+                        self.active_file().last_error = Some(format!("Could not save theme: {}", e));
+                    }
+                    return;
+                }
+                _ => {}
+            }
+        }
+        self.active_file().handle_key(key);
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        self.active_file().handle_mouse(mouse);
+    }
+
+    // Names defined in more than one file, sorted
+    fn shared_definitions(&self) -> Vec<String> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for file in &self.files {
+            for name in file.definition_lines.keys() {
+                *counts.entry(name.as_str()).or_default() += 1;
+            }
+        }
+        let mut shared: Vec<String> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        shared.sort();
+        shared
+    }
+}
+
+// Run the TUI for `file_paths`, one tab per file, until the user quits
+// With `watch_dir`, .expr files created in that directory later get tabs too.
+// With `show_prelude`, the variable list includes the prelude's definitions, and with
+// `benchmark` it shows how long each definition took to evaluate.
+pub async fn run(
+    file_paths: &[PathBuf],
+    broadcaster: Option<Broadcaster>,
+    watch_dir: Option<(&Path, bool)>,
+    show_prelude: bool,
+    benchmark: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Evaluators hold Rc nodes, so evaluation tasks run on a single-threaded LocalSet
+    tokio::task::LocalSet::new().run_until(run_local(file_paths, broadcaster, watch_dir, show_prelude, benchmark)).await
+}
+
+async fn run_local(
+    file_paths: &[PathBuf],
+    broadcaster: Option<Broadcaster>,
+    watch_dir: Option<(&Path, bool)>,
+    show_prelude: bool,
+    benchmark: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = MultiApp::new(file_paths, broadcaster, show_prelude, benchmark);
+    if let Some((dir, recursive)) = watch_dir {
+        app.watch_dir(dir, recursive)?;
+    }
+    for file in &mut app.files {
+        file.watch()?;
+        file.initial_evaluate();
+    }
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = run_app(&mut terminal, &mut app).await;
+
+    // Always restore the terminal, even if the loop failed
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut MultiApp) -> Result<(), Box<dyn std::error::Error>> {
+    let mut last_tick = Instant::now();
+    loop {
+        terminal.draw(|f| ui(f, app))?;
+
+        // Never block on input: the evaluation tasks share this thread
+        if event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => app.handle_key(key),
+                Event::Mouse(mouse) => app.handle_mouse(mouse),
+                _ => {}
+            }
+        } else {
+            tokio::time::sleep(INPUT_POLL).await;
+        }
+
+        if app.should_quit() {
+            return Ok(());
+        }
+
+        app.check_for_new_files()?;
+
+        let tick = last_tick.elapsed() >= TICK;
+        if tick {
+            last_tick = Instant::now();
+        }
+        for file in &mut app.files {
+            if file.export_message.as_ref().is_some_and(|(_, at)| at.elapsed() >= EXPORT_MESSAGE_TIMEOUT) {
+                file.export_message = None;
+            }
+            if tick {
+                file.spinner_frame = file.spinner_frame.wrapping_add(1);
+            }
+            file.check_for_changes();
+            file.check_downloads();
+            file.poll_evaluation().await;
+        }
+    }
+}
+
+fn ui(f: &mut Frame, multi: &mut MultiApp) {
+    let shared = multi.shared_definitions();
+    let theme = multi.theme;
+    // With several files the header becomes a tab bar
+    let tabs = (multi.files.len() > 1).then(|| {
+        let titles: Vec<Line> = multi
+            .files
+            .iter()
+            .map(|file| {
+                let name = file.file_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                match (&file.eval_task, &file.last_error) {
+                    (Some(_), _) => Line::from(format!("{} {}", name, file.spinner())),
+                    (None, Some(_)) => Line::from(Span::styled(format!("{} !", name), Style::default().fg(theme.error))),
+                    (None, None) => Line::from(name),
+                }
+            })
+            .collect();
+        Tabs::new(titles)
+            .select(multi.active)
+            .style(Style::default().fg(theme.header))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    });
+    let app = multi.active_file();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(3), Constraint::Length(1)])
+        .split(f.area());
+
+    // The source preview takes the bottom of the middle panel when open
+    let (main_area, source_area) = if app.show_source {
+        let panes = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(chunks[1]);
+        (panes[0], Some(panes[1]))
+    } else {
+        (chunks[1], None)
+    };
+
+    // The inspector takes the right half of the middle panel when open
+    let (list_area, inspector_area) = match &app.inspector_value {
+        Some(_) => {
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(main_area);
+            (panes[0], Some(panes[1]))
+        }
+        None => (main_area, None),
+    };
+
+    let mut header = vec![
+        Span::styled("garden ", Style::default().fg(theme.header).add_modifier(Modifier::BOLD)),
+        Span::raw(app.file_path.display().to_string()),
+    ];
+    if app.eval_task.is_some() {
+        // Definitions that aren't in the context yet only show up here
+        let pending = app.in_flight.iter().filter(|name| !app.context.contains_key(*name)).count();
+        let mut label = match pending {
+            0 => format!("  {} evaluating", app.spinner()),
+            n => format!("  {} evaluating ({} new)", app.spinner(), n),
+        };
+        match app.pending_requests {
+            0 => {}
+            1 => label.push_str(", 1 request pending"),
+            n => label.push_str(&format!(", {} requests pending", n)),
+        }
+        header.push(Span::styled(label, Style::default().fg(theme.accent)));
+    }
+    match tabs {
+        Some(tabs) => f.render_widget(tabs, chunks[0]),
+        None => f.render_widget(Paragraph::new(Line::from(header)), chunks[0]),
+    }
+
+    // Borders take one row above and below the list
+    app.scroll_to_selected(list_area.height.saturating_sub(2) as usize);
+    app.list_area = list_area;
+    app.status_area = chunks[2];
+
+    let visible = app.visible_indices();
+    let items: Vec<ListItem> = visible
+        .iter()
+        .filter_map(|&i| app.context.get_index(i))
+        .map(|(name, value)| {
+            let mut spans = vec![
+                Span::styled(name.clone(), Style::default().fg(theme.header)),
+                Span::raw(" = "),
+            ];
+            match app.previous_context.get(name) {
+                Some(old) if old != value => spans.extend(diff_spans(old, value, &theme)),
+                _ => spans.push(Span::raw(value.to_string())),
+            }
+            if app.in_flight.contains(name) {
+                spans.push(Span::styled(format!(" {}", app.spinner()), Style::default().fg(theme.accent)));
+            } else if let Some(elapsed) = app.timings.get(name) {
+                spans.push(Span::styled(format!("  {:.1}ms", elapsed.as_secs_f64() * 1000.0), Style::default().fg(theme.unchanged)));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    let title = if app.filter_text.is_empty() {
+        "Values".to_string()
+    } else {
+        format!("Values matching \"{}\" ({}/{})", app.filter_text, visible.len(), app.context.len())
+    };
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default()
+        .with_offset(app.scroll_offset)
+        .with_selected((!visible.is_empty()).then_some(app.selected));
+    f.render_stateful_widget(list, list_area, &mut state);
+
+    if let (Some(area), Some(value)) = (inspector_area, &app.inspector_value) {
+        let inspector = List::new(inspector_lines(value, &theme))
+            .block(Block::default().borders(Borders::ALL).title("Inspector"));
+        f.render_widget(inspector, area);
+    }
+
+    if let Some(area) = source_area {
+        render_source(f, app, &theme, area);
+    }
+
+    if app.show_history {
+        if let Some(name) = app.selected_name() {
+            render_history(f, app, &theme, &name, chunks[1]);
+        }
+    }
+
+    // The search bar replaces the status bar while filtering
+    let status = match (&app.last_error, &app.export_message) {
+        _ if app.filter_active => Line::from(vec![
+            Span::styled("/", Style::default().fg(theme.accent)),
+            Span::raw(app.filter_text.clone()),
+        ]),
+        _ if app.download.is_some() => Line::from(Span::styled(
+            app.download.as_ref().map(download_label).unwrap_or_default(),
+            Style::default().fg(theme.accent),
+        )),
+        (_, Some((message, _))) => Line::from(Span::styled(message.clone(), Style::default().fg(theme.accent))),
+        (Some(error), None) => Line::from(Span::styled(error.clone(), Style::default().fg(theme.error))),
+        (None, None) if !shared.is_empty() => Line::from(Span::styled(
+            format!("Defined in several files: {}", shared.join(", ")),
+            Style::default().fg(theme.accent),
+        )),
+        (None, None) => Line::from(Span::styled(
+            format!("{} values  ↑/↓ PgUp/PgDn Home/End move  Enter inspect  / filter  h history  p source  e export  t theme  Tab file  q quit", app.context.len()),
+            Style::default().fg(theme.status_ok),
+        )),
+    };
+    f.render_widget(Paragraph::new(status), chunks[2]);
+}
+
+// Inline rendering of a value change: the old value struck through in red followed by
+// the new one in green, with a numeric delta, a character-level string diff, or a
+// size summary for JSON containers
+fn diff_spans(old: &Value, new: &Value, theme: &Theme) -> Vec<Span<'static>> {
+    let removed = Style::default().fg(theme.error).add_modifier(Modifier::CROSSED_OUT);
+    let added = Style::default().fg(theme.changed);
+    let note = Style::default().fg(theme.accent);
+
+    match (old, new) {
+        (Value::Number(a), Value::Number(b)) => vec![
+            Span::styled(a.to_string(), removed),
+            Span::raw(" → "),
+            Span::styled(b.to_string(), added),
+            Span::styled(format!(" ({:+})", b.wrapping_sub(*a)), note),
+        ],
+        (Value::String(a), Value::String(b)) => string_diff_spans(a, b, removed, added, theme),
+        (Value::Json(a), Value::Json(b)) => match (json_size(a), json_size(b)) {
+            (Some((kind_a, size_a)), Some((kind_b, size_b))) if kind_a == kind_b => vec![
+                Span::styled(new.to_string(), added),
+                Span::styled(format!("  ({}: {} → {})", kind_a, size_a, size_b), note),
+            ],
+            _ => vec![Span::styled(old.to_string(), removed), Span::raw(" → "), Span::styled(new.to_string(), added)],
+        },
+        _ => vec![Span::styled(old.to_string(), removed), Span::raw(" → "), Span::styled(new.to_string(), added)],
+    }
+}
+
+// A value as it is written by export. Functions, even inside lists and maps, are written
+// as "<function>", and other values without a JSON form as their display form.
+fn export_json(value: &Value) -> JsonValue {
+    match value {
+        Value::Lambda(_) => JsonValue::String("<function>".into()),
+        Value::List(items) => JsonValue::Array(items.iter().map(export_json).collect()),
+        Value::Map(entries) => JsonValue::Object(entries.iter().map(|(key, value)| (key.clone(), export_json(value))).collect()),
+        other => other.to_json_value().unwrap_or_else(|_| JsonValue::String(other.to_string())),
+    }
+}
+
+// Kind and size summary for JSON containers, e.g. ("object", "3 keys")
+fn json_size(json: &JsonValue) -> Option<(&'static str, String)> {
+    match json {
+        JsonValue::Object(map) => Some(("object", format!("{} keys", map.len()))),
+        JsonValue::Array(items) => Some(("array", format!("{} items", items.len()))),
+        _ => None,
+    }
+}
+
+// Above this many LCS table cells, strings are diffed as a whole instead
+const MAX_LCS_CELLS: usize = 250_000;
+
+// Render the old string with deleted characters struck through, then the new string
+// with inserted characters highlighted, using a longest-common-subsequence diff
+fn string_diff_spans(old: &str, new: &str, removed: Style, added: Style, theme: &Theme) -> Vec<Span<'static>> {
+    let a: Vec<char> = old.chars().collect();
+    let b: Vec<char> = new.chars().collect();
+    if a.len().saturating_mul(b.len()) > MAX_LCS_CELLS {
+        return vec![Span::styled(format!("{:?}", old), removed), Span::raw(" → "), Span::styled(format!("{:?}", new), added)];
+    }
+
+    // lcs[i][j] = length of the LCS of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    // Walk the table marking which characters of each side are kept
+    let (mut kept_a, mut kept_b) = (vec![false; a.len()], vec![false; b.len()]);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            kept_a[i] = true;
+            kept_b[j] = true;
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    let mut spans = vec![Span::styled("\"", removed)];
+    spans.extend(styled_runs(&a, &kept_a, Style::default().fg(theme.unchanged).add_modifier(Modifier::CROSSED_OUT), removed));
+    spans.push(Span::styled("\"", removed));
+    spans.push(Span::raw(" → "));
+    spans.push(Span::styled("\"", added));
+    spans.extend(styled_runs(&b, &kept_b, Style::default(), added.add_modifier(Modifier::BOLD)));
+    spans.push(Span::styled("\"", added));
+    spans
+}
+
+// "Downloading <url>: 1.5 of 10.0 MB (15%)", or just the amount when the size isn't known
+fn download_label(progress: &DownloadProgress) -> String {
+    let mb = |bytes: u64| bytes as f64 / 1_000_000.0;
+    match progress.total {
+        Some(total) if total > 0 => format!(
+            "Downloading {}: {:.1} of {:.1} MB ({}%)",
+            progress.url,
+            mb(progress.written),
+            mb(total),
+            progress.written * 100 / total
+        ),
+        _ => format!("Downloading {}: {:.1} MB", progress.url, mb(progress.written)),
+    }
+}
+
+// Group consecutive characters with the same kept/changed status into spans
+fn styled_runs(chars: &[char], kept: &[bool], kept_style: Style, changed_style: Style) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start;
+        while end < chars.len() && kept[end] == kept[start] {
+            end += 1;
+        }
+        let text: String = chars[start..end].iter().collect();
+        spans.push(Span::styled(text, if kept[start] { kept_style } else { changed_style }));
+        start = end;
+    }
+    spans
+}
+
+// Scrollable view of the source file. On error the offending line is centred and
+// shown on red; otherwise the text is dimmed and the selected definition is cyan.
+fn render_source(f: &mut Frame, app: &FileApp, theme: &Theme, area: Rect) {
+    let focus_line = match app.error_line {
+        Some(line) => Some(line),
+        None if app.last_error.is_none() => app
+            .selected_name()
+            .and_then(|name| app.definition_lines.get(&name).copied()),
+        None => None,
+    };
+
+    let lines: Vec<Line> = app
+        .source_text
+        .lines()
+        .enumerate()
+        .map(|(i, text)| {
+            let line_no = i + 1;
+            let style = if Some(line_no) == app.error_line {
+                Style::default().bg(theme.error).fg(Color::White)
+            } else if Some(line_no) == focus_line {
+                Style::default().fg(theme.header)
+            } else if app.last_error.is_none() {
+                Style::default().add_modifier(Modifier::DIM)
+            } else {
+                Style::default()
+            };
+            Line::from(vec![
+                Span::styled(format!("{:>3}| ", line_no), Style::default().fg(theme.unchanged)),
+                Span::styled(text.to_string(), style),
+            ])
+        })
+        .collect();
+
+    // Scroll so the focused line sits in the middle of the pane
+    let height = area.height.saturating_sub(2) as usize;
+    let scroll = focus_line
+        .map(|line| line.saturating_sub(1).saturating_sub(height / 2))
+        .unwrap_or(0);
+
+    let source = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Source"))
+        .scroll((scroll as u16, 0));
+    f.render_widget(source, area);
+}
+
+// Map each top-level definition name to the line it starts on
+fn definition_lines(root_nodes: &[Rc<Node>]) -> HashMap<String, usize> {
+    root_nodes
+        .iter()
+        .filter(|node| matches!(node.kind(), NodeKind::Definition | NodeKind::LetStatement))
+        .filter_map(|node| match node.children().get(1).map(|child| child.kind()) {
+            Some(NodeKind::Symbol(name)) => node_line(node).map(|line| (name.clone(), line)),
+            _ => None,
+        })
+        .collect()
+}
+
+// How long each definition in `root_nodes` took to evaluate. Definitions whose
+// results came from the cache are left out.
+fn definition_timings(root_nodes: &[Rc<Node>], timings: &EvalTimings) -> HashMap<String, Duration> {
+    root_nodes
+        .iter()
+        .filter(|node| matches!(node.kind(), NodeKind::Definition | NodeKind::LetStatement))
+        .filter_map(|node| match (node.children().get(1).map(|child| child.kind()), timings.get(node.id())) {
+            (Some(NodeKind::Symbol(name)), Some(elapsed)) => Some((name.clone(), elapsed)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Names defined in `root_nodes` whose definition nodes have no cached result, so they
+// will actually be computed by the next evaluation
+fn uncached_definitions(evaluator: &Evaluator, root_nodes: &[Rc<Node>]) -> HashSet<String> {
+    definition_lines(root_nodes)
+        .into_keys()
+        .filter(|name| {
+            root_nodes.iter().any(|node| {
+                matches!(node.children().get(1).map(|child| child.kind()), Some(NodeKind::Symbol(n)) if n == name)
+                    && evaluator.get_cached_result(node.id()).is_none()
+            })
+        })
+        .collect()
+}
+
+// How many http.get calls in `root_nodes` have no cached response, so the next
+// evaluation will wait on them
+fn uncached_requests(evaluator: &Evaluator, root_nodes: &[Rc<Node>]) -> usize {
+    root_nodes
+        .iter()
+        .flat_map(|node| find_by_kind(node, &NodeKind::HttpGet))
+        .filter(|node| evaluator.get_cached_result(node.id()).is_none())
+        .count()
+}
+
+// What a panic said, when it said it with a string
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+fn node_line(node: &Node) -> Option<usize> {
+    node.metadata().get("line").and_then(|line| line.parse().ok())
+}
+
+// Full-width overlay listing a variable's values, newest first. Entries that differ
+// from the value before them are yellow (darker in the light theme), repeats are grey.
+fn render_history(f: &mut Frame, app: &mut FileApp, theme: &Theme, name: &str, area: Rect) {
+    let entries = app.history.get(name).map(Vec::as_slice).unwrap_or_default();
+    app.history_offset = app.history_offset.min(entries.len().saturating_sub(1));
+
+    let items: Vec<ListItem> = entries
+        .iter()
+        .enumerate()
+        .rev()
+        .skip(app.history_offset)
+        .map(|(i, (timestamp, value))| {
+            let changed = i == 0 || entries[i - 1].1 != *value;
+            let color = if changed { theme.history_changed } else { theme.unchanged };
+            ListItem::new(Line::from(vec![
+                Span::raw(timestamp.format("%H:%M:%S").to_string()),
+                Span::raw(" → "),
+                Span::raw(value.to_string()),
+            ]))
+            .style(Style::default().fg(color))
+        })
+        .collect();
+
+    let history = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("History of {} ({} entries, Esc to close)", name, entries.len())),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(history, area);
+}
+
+// Expand a value into indented lines: objects become key/value pairs, arrays become
+// indexed entries and primitives are leaves
+fn inspector_lines(value: &Value, theme: &Theme) -> Vec<ListItem<'static>> {
+    let mut lines = Vec::new();
+    match value {
+        Value::Json(json) => json_lines(None, json, 0, theme, &mut lines),
+        Value::List(_) | Value::Map(_) => match value.to_json_value() {
+            Ok(json) => json_lines(None, &json, 0, theme, &mut lines),
+            // Holds a function, which has no JSON form
+            Err(_) => lines.push(ListItem::new(value.to_string())),
+        },
+        other => lines.push(ListItem::new(other.to_string())),
+    }
+    lines
+}
+
+fn json_lines(label: Option<String>, json: &JsonValue, depth: usize, theme: &Theme, lines: &mut Vec<ListItem<'static>>) {
+    let indent = "  ".repeat(depth);
+    let label_span = |suffix: &str| match &label {
+        Some(label) => Span::styled(format!("{}{}{}", indent, label, suffix), Style::default().fg(theme.header)),
+        None => Span::raw(indent.clone()),
+    };
+
+    match json {
+        JsonValue::Object(map) => {
+            lines.push(ListItem::new(Line::from(vec![label_span(": "), Span::raw(format!("{{{} keys}}", map.len()))])));
+            for (key, child) in map {
+                json_lines(Some(key.clone()), child, depth + 1, theme, lines);
+            }
+        }
+        JsonValue::Array(items) => {
+            lines.push(ListItem::new(Line::from(vec![label_span(": "), Span::raw(format!("[{} items]", items.len()))])));
+            for (i, child) in items.iter().enumerate() {
+                json_lines(Some(format!("[{}]", i)), child, depth + 1, theme, lines);
+            }
+        }
+        leaf => {
+            lines.push(ListItem::new(Line::from(vec![label_span(": "), Span::raw(leaf.to_string())])));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ratatui::backend::TestBackend;
+
+    // A new .expr file holding `src`, in a directory of its own
+    fn file_for(src: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("garden-tui-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.expr");
+        fs::write(&file, src).unwrap();
+        file
+    }
+
+    fn app_for(src: &str) -> FileApp {
+        FileApp::new(&file_for(src))
+    }
+
+    // A one-tab TUI for `src`, after its first evaluation. Must run on a LocalSet.
+    async fn evaluated(src: &str) -> MultiApp {
+        evaluated_tabs(&[src]).await
+    }
+
+    // A TUI with a tab for each of `srcs`, after their first evaluations
+    async fn evaluated_tabs(srcs: &[&str]) -> MultiApp {
+        let files: Vec<PathBuf> = srcs.iter().map(|src| file_for(src)).collect();
+        let mut multi = MultiApp::new(&files, None, false, false);
+        for file in &mut multi.files {
+            file.initial_evaluate();
+            settle(file).await;
+        }
+        multi
+    }
+
+    fn press(multi: &mut MultiApp, code: KeyCode) {
+        multi.handle_key(KeyEvent::from(code));
+    }
+
+    // What the TUI draws at `width` by `height`
+    fn draw(multi: &mut MultiApp, width: u16, height: u16) -> ratatui::buffer::Buffer {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| ui(f, multi)).unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    // The rows of the screen the TUI draws at `width` by `height`
+    fn screen(multi: &mut MultiApp, width: u16, height: u16) -> Vec<String> {
+        let buffer = draw(multi, width, height);
+        (0..height)
+            .map(|y| (0..width).map(|x| buffer[(x, y)].symbol()).collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    // Wait for the running evaluation, and any rerun it queued, to finish
+    async fn settle(app: &mut FileApp) {
+        while app.eval_task.is_some() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            app.poll_evaluation().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluates_the_file_on_startup_without_blocking() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut app = app_for("(sleep 100) (def x (+ 1 2))");
+                app.initial_evaluate();
+                // The evaluation runs in the background, so the UI can draw meanwhile
+                assert!(app.eval_task.is_some());
+                assert!(app.context.is_empty());
+                settle(&mut app).await;
+                assert_eq!(app.context.get("x"), Some(&Value::Number(3)));
+                assert_eq!(app.last_error, None);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn re_evaluation_shows_the_files_new_values() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut app = app_for("(def x 1) (def y (* x 10))");
+                app.initial_evaluate();
+                settle(&mut app).await;
+                assert_eq!(app.context.get("y"), Some(&Value::Number(10)));
+
+                fs::write(&app.file_path, "(def x 4) (def y (* x 10))").unwrap();
+                app.request_evaluation();
+                settle(&mut app).await;
+                assert_eq!(app.context.get("y"), Some(&Value::Number(40)));
+                assert_eq!(app.previous_context.get("y"), Some(&Value::Number(10)));
+            })
+            .await;
+    }
+
+    // A built-in that panics when called
+    struct Explode;
+
+    impl crate::BuiltinFn for Explode {
+        fn name(&self) -> &str {
+            "explode"
+        }
+
+        fn eval(&self, _args: Vec<Value>, _context: &mut crate::EvalContext) -> futures::future::BoxFuture<'_, Result<Value, Error>> {
+            Box::pin(async { panic!("boom") })
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicking_evaluation_hands_the_evaluator_back() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut app = app_for("(def x (+ 1 2))");
+                app.evaluator.as_mut().unwrap().enable_timings();
+                app.initial_evaluate();
+                settle(&mut app).await;
+                let x = *parser::parse("(def x (+ 1 2))").unwrap()[0].id();
+
+                app.evaluator.as_mut().unwrap().register_builtin(Box::new(Explode));
+                fs::write(&app.file_path, "(def x (+ 1 2)) (def y (explode))").unwrap();
+                app.request_evaluation();
+                settle(&mut app).await;
+                assert_eq!(app.last_error.as_deref(), Some("Evaluation Error: Evaluation panicked: boom"));
+
+                // The same evaluator, with its cache and settings, evaluates the next save
+                let evaluator = app.evaluator.as_ref().expect("the evaluator is handed back");
+                assert!(evaluator.timings().is_some());
+                assert!(evaluator.get_cached_result(&x).is_some());
+                fs::write(&app.file_path, "(def x (+ 1 2)) (def y 5)").unwrap();
+                app.request_evaluation();
+                settle(&mut app).await;
+                assert_eq!(app.last_error, None);
+                assert_eq!(app.context.get("y"), Some(&Value::Number(5)));
+            })
+            .await;
+    }
+
+    #[test]
+    fn requests_without_a_cached_response_are_pending() {
+        let app = app_for("");
+        let root_nodes = parser::parse(r#"(def a (http.get "http://a.invalid/")) (def b (+ 1 (http.get "http://b.invalid/"))) (def c 3)"#).unwrap();
+        assert_eq!(uncached_requests(app.evaluator.as_ref().unwrap(), &root_nodes), 2);
+    }
+
+    #[tokio::test]
+    async fn cursor_stays_within_the_variable_list() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let src: String = (0..30).map(|i| format!("(def v{} {})\n", i, i)).collect();
+                let mut multi = evaluated(&src).await;
+                let last = multi.files[0].context.len() - 1;
+
+                press(&mut multi, KeyCode::Up);
+                assert_eq!(multi.files[0].selected, 0);
+                press(&mut multi, KeyCode::Down);
+                assert_eq!(multi.files[0].selected, 1);
+                press(&mut multi, KeyCode::End);
+                assert_eq!(multi.files[0].selected, last);
+                press(&mut multi, KeyCode::Down);
+                press(&mut multi, KeyCode::PageDown);
+                assert_eq!(multi.files[0].selected, last);
+                press(&mut multi, KeyCode::Home);
+                assert_eq!(multi.files[0].selected, 0);
+                press(&mut multi, KeyCode::PageUp);
+                assert_eq!(multi.files[0].selected, 0);
+
+                // The list scrolls to keep the cursor on screen
+                press(&mut multi, KeyCode::End);
+                let rows = screen(&mut multi, 60, 12);
+                assert!(rows.iter().any(|row| row.contains("v29")), "{:#?}", rows);
+                assert!(!rows.iter().any(|row| row.contains("v0 ")), "{:#?}", rows);
+
+                // Only the selected row is drawn reversed
+                let buffer = draw(&mut multi, 60, 12);
+                let reversed: Vec<&String> = (0..12u16)
+                    .filter(|&y| buffer[(1, y)].modifier.contains(Modifier::REVERSED))
+                    .map(|y| &rows[y as usize])
+                    .collect();
+                assert_eq!(reversed.len(), 1, "{:#?}", rows);
+                assert!(reversed[0].contains("v29"), "{:#?}", reversed);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn inspector_expands_nested_json() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let src = r#"(def user (json.parse "{\"name\": \"ada\", \"address\": {\"city\": \"paris\", \"tags\": [\"a\", 7]}}"))"#;
+                let mut multi = evaluated(src).await;
+                press(&mut multi, KeyCode::Enter);
+                assert!(multi.files[0].show_inspector);
+
+                let rows = screen(&mut multi, 80, 12);
+                let inspector: Vec<String> = rows.iter().map(|row| row.chars().skip(41).collect()).collect();
+                let expected = ["{2 keys}", "  address: {2 keys}", "    city: \"paris\"", "    tags: [2 items]", "      [0]: \"a\"", "      [1]: 7", "  name: \"ada\""];
+                for (row, line) in inspector.iter().skip(2).zip(expected) {
+                    assert!(row.starts_with(line), "{:?} should start with {:?} in {:#?}", row, line, rows);
+                }
+
+                press(&mut multi, KeyCode::Esc);
+                assert!(!multi.files[0].show_inspector);
+                assert!(!multi.files[0].should_quit);
+            })
+            .await;
+    }
+
+    fn type_text(multi: &mut MultiApp, text: &str) {
+        for c in text.chars() {
+            press(multi, KeyCode::Char(c));
+        }
+    }
+
+    fn visible_names(app: &FileApp) -> Vec<&str> {
+        app.visible_indices().iter().map(|&i| app.context.get_index(i).unwrap().0.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn filter_matches_names_and_values_ignoring_case() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut multi = evaluated(r#"(def apple 1) (def Apricot 2) (def berry "APPLE pie")"#).await;
+                press(&mut multi, KeyCode::Char('/'));
+                assert!(multi.files[0].filter_active);
+
+                type_text(&mut multi, "ap");
+                assert_eq!(visible_names(&multi.files[0]), vec!["apple", "Apricot", "berry"]);
+                type_text(&mut multi, "r");
+                assert_eq!(visible_names(&multi.files[0]), vec!["Apricot"]);
+                type_text(&mut multi, "x");
+                assert!(visible_names(&multi.files[0]).is_empty());
+                assert_eq!(multi.files[0].selected, 0);
+
+                press(&mut multi, KeyCode::Backspace);
+                press(&mut multi, KeyCode::Backspace);
+                type_text(&mut multi, "ple");
+                assert_eq!(visible_names(&multi.files[0]), vec!["apple", "berry"]);
+                let rows = screen(&mut multi, 60, 8);
+                assert!(rows.last().unwrap().contains("apple"), "{:#?}", rows);
+
+                // Enter keeps the filter and gives keys back to the list
+                press(&mut multi, KeyCode::Enter);
+                assert!(!multi.files[0].filter_active);
+                press(&mut multi, KeyCode::Down);
+                assert_eq!(multi.files[0].selected, 1);
+                assert_eq!(visible_names(&multi.files[0]).len(), 2);
+
+                press(&mut multi, KeyCode::Char('/'));
+                press(&mut multi, KeyCode::Esc);
+                assert_eq!(multi.files[0].filter_text, "");
+                assert_eq!(visible_names(&multi.files[0]).len(), 3);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn history_keeps_an_entry_per_evaluation() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut multi = evaluated("(def x 1)").await;
+                for src in ["(def x 1)", "(def x 2)"] {
+                    fs::write(&multi.files[0].file_path, src).unwrap();
+                    multi.files[0].request_evaluation();
+                    settle(&mut multi.files[0]).await;
+                }
+                let values: Vec<&Value> = multi.files[0].history["x"].iter().map(|(_, value)| value).collect();
+                assert_eq!(values, vec![&Value::Number(1), &Value::Number(1), &Value::Number(2)]);
+
+                press(&mut multi, KeyCode::Char('h'));
+                let rows = screen(&mut multi, 60, 10);
+                assert!(rows[1].contains("History of x (3 entries"), "{:#?}", rows);
+                // Newest first
+                let entries: Vec<&String> = rows.iter().filter(|row| row.contains(" → ")).collect();
+                assert_eq!(entries.len(), 3, "{:#?}", rows);
+                assert!(entries[0].contains("→ 2"));
+
+                // Changes are yellow and repeats grey
+                multi.theme = Theme::dark();
+                let buffer = draw(&mut multi, 60, 10);
+                let colors: Vec<Color> = (0..10u16)
+                    .filter(|&y| rows[y as usize].contains(" → "))
+                    .map(|y| buffer[(1, y)].fg)
+                    .collect();
+                assert_eq!(colors, vec![Color::Yellow, Color::DarkGray, Color::Yellow]);
+
+                press(&mut multi, KeyCode::Esc);
+                assert!(!multi.files[0].show_history);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn each_tab_has_its_own_context() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut multi = evaluated_tabs(&["(def rate 2) (def a (* rate 10))", "(def rate 5) (def b 1)"]).await;
+                assert_eq!(multi.files[0].context.get("a"), Some(&Value::Number(20)));
+                assert_eq!(multi.files[0].context.get("b"), None);
+                assert_eq!(multi.files[1].context.get("rate"), Some(&Value::Number(5)));
+                assert_eq!(multi.files[1].context.get("a"), None);
+                assert_eq!(multi.shared_definitions(), vec!["rate"]);
+
+                press(&mut multi, KeyCode::Tab);
+                assert_eq!(multi.active, 1);
+                press(&mut multi, KeyCode::Down);
+                assert_eq!((multi.files[0].selected, multi.files[1].selected), (0, 1));
+                let rows = screen(&mut multi, 100, 8);
+                assert_eq!(rows.last().unwrap(), "Defined in several files: rate", "{:#?}", rows);
+                press(&mut multi, KeyCode::BackTab);
+                assert_eq!(multi.active, 0);
+            })
+            .await;
+    }
+
+    fn mouse(multi: &mut MultiApp, kind: MouseEventKind, column: u16, row: u16) {
+        multi.handle_mouse(MouseEvent { kind, column, row, modifiers: event::KeyModifiers::NONE });
+    }
+
+    #[tokio::test]
+    async fn clicking_a_row_selects_it() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let src: String = (0..20).map(|i| format!("(def v{} {})\n", i, i)).collect();
+                let mut multi = evaluated(&src).await;
+                // Drawing records where the list is
+                screen(&mut multi, 60, 12);
+                let list = multi.files[0].list_area;
+                let click = MouseEventKind::Down(MouseButton::Left);
+
+                mouse(&mut multi, click, 5, list.y + 1 + 3);
+                assert_eq!(multi.files[0].selected, 3);
+                mouse(&mut multi, MouseEventKind::ScrollDown, 5, list.y + 1);
+                assert_eq!(multi.files[0].selected, 4);
+                mouse(&mut multi, MouseEventKind::ScrollUp, 5, list.y + 1);
+                mouse(&mut multi, MouseEventKind::ScrollUp, 5, list.y + 1);
+                assert_eq!(multi.files[0].selected, 2);
+
+                // Rows are counted from the top of the scrolled list
+                press(&mut multi, KeyCode::End);
+                screen(&mut multi, 60, 12);
+                mouse(&mut multi, click, 5, list.y + 1);
+                assert_eq!(multi.files[0].selected, multi.files[0].scroll_offset);
+                assert!(multi.files[0].scroll_offset > 0);
+
+                // The border and the status bar aren't rows
+                let selected = multi.files[0].selected;
+                mouse(&mut multi, click, 5, list.y);
+                assert_eq!(multi.files[0].selected, selected);
+                let status = multi.files[0].status_area;
+                mouse(&mut multi, click, 5, status.y);
+                assert!(multi.files[0].filter_active);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn themes_change_colours_but_not_text() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut multi = evaluated("(def x 1) (def y (/ 1 0))").await;
+                multi.theme = Theme::dark();
+                let dark = draw(&mut multi, 60, 8);
+                multi.theme = multi.theme.toggled();
+                assert_eq!(multi.theme, Theme::light());
+                let light = draw(&mut multi, 60, 8);
+
+                let cells = |buffer: &ratatui::buffer::Buffer| buffer.content().iter().map(|cell| cell.symbol().to_string()).collect::<Vec<_>>();
+                assert_eq!(cells(&dark), cells(&light));
+                let styles = |buffer: &ratatui::buffer::Buffer| buffer.content().iter().map(|cell| (cell.fg, cell.bg)).collect::<Vec<_>>();
+                assert_ne!(styles(&dark), styles(&light));
+                assert_eq!(multi.theme.toggled(), Theme::dark());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn e_exports_the_context_as_json() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let mut multi = evaluated(
+                    r#"(def n 1) (def names ["a" "b"]) (def twice (fn [x] (* x 2))) (def fns [twice (assoc (json.parse "{}") "f" twice)])"#,
+                )
+                .await;
+                press(&mut multi, KeyCode::Char('e'));
+                let path = multi.files[0].file_path.with_extension("output.json");
+                let exported: JsonValue = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+                assert_eq!(exported["n"], serde_json::json!(1));
+                assert_eq!(exported["names"], serde_json::json!(["a", "b"]));
+                assert_eq!(exported["twice"], serde_json::json!("<function>"));
+                assert_eq!(exported["fns"], serde_json::json!(["<function>", {"f": "<function>"}]));
+                let keys: Vec<&String> = exported.as_object().unwrap().keys().collect();
+                assert_eq!(keys.len(), 4);
+
+                let rows = screen(&mut multi, 60, 8);
+                assert_eq!(rows.last().unwrap(), "Exported to main.output.json", "{:#?}", rows);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+use futures::{SinkExt, StreamExt};
+use indexmap::IndexMap;
+use serde_json::{json, Value as JsonValue};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::Value;
+
+// Page served at / that connects back over WebSocket and renders a live table
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+// Updates a slow client can fall behind by before it starts missing some
+const CHANNEL_CAPACITY: usize = 64;
+
+// A file's last published context and the update message built from it
+type Published = (IndexMap<String, Value>, String);
+
+// Sends evaluation results to every connected dashboard. Cheap to clone; all clones
+// publish to the same clients.
+#[derive(Clone)]
+pub struct Broadcaster {
+    sender: broadcast::Sender<String>,
+    // Last published context per file, to work out what changed and to greet new clients
+    last: Arc<Mutex<HashMap<PathBuf, Published>>>,
+}
+
+impl Broadcaster {
+    fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            last: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Broadcast `context` for `file`, listing the variables that changed since the last
+    // update for that file
+    pub fn publish(&self, file: &Path, context: &IndexMap<String, Value>) {
+        let mut last = self.last.lock().unwrap();
+        let previous = last.get(file).map(|(context, _)| context);
+        let changed: Vec<&String> = context
+            .iter()
+            .filter(|(name, value)| previous.and_then(|previous| previous.get(*name)) != Some(*value))
+            .map(|(name, _)| name)
+            .collect();
+        // Values without a JSON form, like functions, are sent as their source
+        let values: IndexMap<&str, JsonValue> = context
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_json_value().unwrap_or_else(|_| JsonValue::String(value.to_string()))))
+            .collect();
+
+        let message = json!({
+            "event": "update",
+            "file": file.display().to_string(),
+            "context": values,
+            "changed": changed,
+        })
+        .to_string();
+        last.insert(file.to_path_buf(), (context.clone(), message.clone()));
+        // No receivers just means no dashboard is open
+        let _ = self.sender.send(message);
+    }
+
+    // The latest update for every file, for a client that just connected
+    fn snapshot(&self) -> Vec<String> {
+        self.last.lock().unwrap().values().map(|(_, message)| message.clone()).collect()
+    }
+}
+
+// Start the dashboard server on `port` in the background
+pub async fn serve(port: u16) -> Result<Broadcaster, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    tracing::info!("Dashboard at http://localhost:{}/", listener.local_addr()?.port());
+    Ok(accept_clients(listener))
+}
+
+// Serve dashboards connecting to `listener` in the background
+fn accept_clients(listener: TcpListener) -> Broadcaster {
+    let broadcaster = Broadcaster::new();
+    let server = broadcaster.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("Dashboard accept error: {}", e);
+                    continue;
+                }
+            };
+            let broadcaster = server.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, broadcaster).await {
+                    tracing::warn!(client = %addr, "Dashboard client error: {}", e);
+                }
+            });
+        }
+    });
+    broadcaster
+}
+
+// Upgrade WebSocket requests; answer anything else with the dashboard page
+async fn handle_connection(mut stream: TcpStream, broadcaster: Broadcaster) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut head = [0u8; 2048];
+    let len = stream.peek(&mut head).await?;
+    let request = String::from_utf8_lossy(&head[..len]).to_ascii_lowercase();
+
+    if !request.contains("upgrade: websocket") {
+        // Closing with the request unread would reset the connection, losing the page
+        stream.read_exact(&mut head[..len]).await?;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            DASHBOARD_HTML.len(),
+            DASHBOARD_HTML
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        return Ok(());
+    }
+
+    // Subscribed before the handshake completes, so no update published after the client
+    // sees it connected is missed
+    let mut updates = broadcaster.sender.subscribe();
+    let socket = tokio_tungstenite::accept_async(stream).await?;
+    let (mut outgoing, mut incoming) = socket.split();
+
+    for message in broadcaster.snapshot() {
+        outgoing.send(Message::text(message)).await?;
+    }
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(message) => {
+                    // A failed send means the client went away without closing cleanly
+                    if outgoing.send(Message::text(message)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                // Skipped updates are superseded by the next one anyway
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            // The dashboard never sends anything meaningful; just notice when it leaves
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => continue,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_once, Evaluator, OutputFormat};
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>;
+
+    async fn start_server() -> (Broadcaster, String) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        (accept_clients(listener), addr.to_string())
+    }
+
+    async fn connect(addr: &str) -> Client {
+        tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap().0
+    }
+
+    async fn next_update(client: &mut Client) -> JsonValue {
+        loop {
+            if let Message::Text(text) = client.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clients_get_an_update_when_the_file_is_evaluated_again() {
+        let (broadcaster, addr) = start_server().await;
+        let mut client = connect(&addr).await;
+        let dir = std::env::temp_dir().join(format!("garden-ws-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.expr");
+
+        // Evaluators aren't Send, so evaluation stays on this task like the watcher's
+        let local = tokio::task::LocalSet::new();
+        let mut evaluator = Evaluator::new();
+        std::fs::write(&file, "(def x 1) (def y 2)").unwrap();
+        local.run_until(run_once(&file, &mut evaluator, OutputFormat::Json, Some(&broadcaster), None)).await.unwrap();
+        let update = next_update(&mut client).await;
+        assert_eq!(update["event"], "update");
+        assert_eq!(update["context"], json!({"x": 1, "y": 2}));
+        assert_eq!(update["changed"], json!(["x", "y"]));
+
+        std::fs::write(&file, "(def x 1) (def y 3)").unwrap();
+        local.run_until(run_once(&file, &mut evaluator, OutputFormat::Json, Some(&broadcaster), None)).await.unwrap();
+        let update = next_update(&mut client).await;
+        assert_eq!(update["context"]["y"], 3);
+        assert_eq!(update["changed"], json!(["y"]));
+
+        // A dashboard opened later starts from the latest update
+        let mut late = connect(&addr).await;
+        assert_eq!(next_update(&mut late).await["context"]["y"], 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clients_leaving_dont_stop_updates_to_the_rest() {
+        let (broadcaster, addr) = start_server().await;
+        let leaving = connect(&addr).await;
+        let mut staying = connect(&addr).await;
+        drop(leaving);
+
+        for n in 0..3 {
+            broadcaster.publish(Path::new("a.expr"), &IndexMap::from([("n".to_string(), Value::Number(n))]));
+        }
+        for n in 0..3 {
+            assert_eq!(next_update(&mut staying).await["context"]["n"], n);
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_requests_get_the_dashboard_page() {
+        let (_broadcaster, addr) = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(DASHBOARD_HTML));
+    }
+}
@@ -0,0 +1,205 @@
+// Runs the garden binary the way a shell script would
+
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// A fresh directory, so no .garden.toml from the checkout applies
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("garden-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// An empty directory of its own for one test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Run garden with `args` in `dir` until what it has printed satisfies `done`, then stop
+// it, as watching never ends by itself. Returns the output.
+fn watch_until(dir: &Path, args: &[&str], done: impl Fn(&str) -> bool) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_garden"))
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut output = String::new();
+    while !done(&output) {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        // Checked again every so often, as `done` may wait on more than output
+        match rx.recv_timeout(left.min(Duration::from_millis(50))) {
+            Ok(line) => output.push_str(&format!("{}\n", line)),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    output
+}
+
+fn garden(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_garden"))
+        .args(args)
+        .current_dir(temp_dir())
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn eval_prints_the_value() {
+    let output = garden(&["--eval", "(+ 1 2)"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn evals_are_chained_in_order() {
+    let output = garden(&["--eval", "(def x 20)", "--eval", "(+ x 1)"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "20\n21\n");
+}
+
+#[test]
+fn context_file_is_loaded_first() {
+    let context = temp_dir().join("context.expr");
+    std::fs::write(&context, "(def rate 3)").unwrap();
+    let output = garden(&["--context-file", context.to_str().unwrap(), "--eval", "(* rate 2)"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(stdout(&output), "6\n");
+}
+
+#[test]
+fn help_and_version_succeed_and_unknown_flags_fail() {
+    let help = garden(&["--help"]);
+    assert_eq!(help.status.code(), Some(0));
+    for flag in ["--eval", "--format", "--no-cache", "--sandbox", "--benchmark", "--timeout-ms", "--quiet"] {
+        assert!(stdout(&help).contains(flag), "--help doesn't mention {}", flag);
+    }
+
+    let version = garden(&["--version"]);
+    assert_eq!(version.status.code(), Some(0));
+    assert!(stdout(&version).contains(env!("CARGO_PKG_VERSION")));
+
+    let unknown = garden(&["--unknown-flag"]);
+    assert_eq!(unknown.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("--unknown-flag"));
+}
+
+#[test]
+fn errors_go_to_stderr_with_a_failing_exit_code() {
+    let output = garden(&["--eval", "(/ 1 0)"]);
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "");
+    assert!(!output.stderr.is_empty());
+
+    let output = garden(&["--eval", "(+ 1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 1"));
+}
+
+#[test]
+fn watch_dir_evaluates_every_file() {
+    let dir = test_dir("watch-dir");
+    std::fs::write(dir.join("a.expr"), "(def a (+ 1 2))").unwrap();
+    std::fs::write(dir.join("b.expr"), "(def b (* 2 5))").unwrap();
+    std::fs::write(dir.join("notes.txt"), "(def c 1)").unwrap();
+
+    // Once both have been evaluated, a new file is dropped in
+    let added = std::cell::Cell::new(false);
+    let output = watch_until(&dir, &["--watch-dir", "."], |output| {
+        if !added.get() && output.contains("=> Number(3)") && output.contains("=> Number(10)") {
+            std::fs::write(dir.join("c.expr"), "(def c (- 9 2))").unwrap();
+            added.set(true);
+        }
+        output.contains("=> Number(7)")
+    });
+    let line_of = |value: &str| output.lines().find(|line| line.contains(value)).unwrap_or_default().to_string();
+    assert!(line_of("=> Number(3)").contains("[a.expr]"), "{}", output);
+    assert!(line_of("=> Number(10)").contains("[b.expr]"), "{}", output);
+    assert!(line_of("=> Number(7)").contains("[c.expr]"), "{}", output);
+    assert!(!output.contains("notes"), "{}", output);
+}
+
+#[test]
+fn no_cache_never_writes_a_cache_file() {
+    let dir = test_dir("no-cache");
+    std::fs::write(dir.join("main.expr"), "(def x (+ 1 2))").unwrap();
+    let cache = dir.join("main.expr.cache");
+
+    // Left running for a while after evaluating, long enough for a cache to be written
+    let evaluated_at = std::cell::Cell::new(None);
+    let output = watch_until(&dir, &["main.expr", "--no-cache"], |output| {
+        if output.contains("=> Number(3)") && evaluated_at.get().is_none() {
+            evaluated_at.set(Some(Instant::now()));
+        }
+        evaluated_at.get().is_some_and(|at: Instant| at.elapsed() > Duration::from_millis(500))
+    });
+    assert!(output.contains("=> Number(3)"), "{}", output);
+    assert!(!cache.exists());
+
+    // Without the flag the cache appears
+    watch_until(&dir, &["main.expr"], |_| cache.exists());
+    assert!(cache.exists());
+}
+
+#[test]
+fn clear_cache_removes_the_old_cache_before_evaluating() {
+    let dir = test_dir("clear-cache");
+    let file = dir.join("main.expr");
+    let cache = dir.join("main.expr.cache");
+    // memo-def keeps its first value in the cache, even after its code changes
+    std::fs::write(&file, "(memo-def token 1)").unwrap();
+    watch_until(&dir, &["main.expr"], |output| output.contains("=> Number(1)") && cache.exists());
+    std::fs::write(&file, "(memo-def token 2)").unwrap();
+
+    let evaluated = |output: &str| output.contains("=>") || output.contains("No expressions changed");
+    let kept = watch_until(&dir, &["main.expr"], evaluated);
+    assert!(evaluated(&kept) && !kept.contains("Number(2)"), "{}", kept);
+    let cleared = watch_until(&dir, &["main.expr", "--clear-cache"], |output| output.contains("(memo-def token 2)"));
+    assert!(cleared.contains("=> Number(2)"), "{}", cleared);
+}
+
+#[test]
+fn cache_clean_deletes_a_files_cache() {
+    let dir = test_dir("cache-clean");
+    let cache = dir.join("main.expr.cache");
+    std::fs::write(&cache, "{}").unwrap();
+    let clean = || {
+        Command::new(env!("CARGO_BIN_EXE_garden"))
+            .args(["cache", "clean", "main.expr"])
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+    };
+    let output = clean();
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Removed"));
+    assert!(!cache.exists());
+    assert!(stdout(&clean()).starts_with("No cache"));
+}